@@ -0,0 +1,82 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! `cargo bench --features test-util`
+
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use lib_voxels_directories::filesystem::DefaultFsInt;
+use lib_voxels_directories::testing::{fixed_directories, fixed_environment};
+use lib_voxels_directories::voxels::DirectoryKind;
+use lib_voxels_directories::voxels::voxels_xdg::xdg::cache::{CacheDirectory, CacheDirectoryResolver, DefaultCacheVerifier};
+use lib_voxels_directories::voxels::voxels_xdg::xdg::config::{ConfigDirectory, ConfigDirectoryResolver, DefaultConfigVerifier};
+use lib_voxels_directories::voxels::voxels_xdg::xdg::data::{DataDirectory, DataDirectoryResolver, DefaultDataVerifier};
+use lib_voxels_directories::voxels::voxels_xdg::xdg::runtime::{DefaultRuntimeVerifier, RuntimeDirectory, RuntimeDirectoryResolver};
+use lib_voxels_directories::voxels::voxels_xdg::xdg::state::{DefaultStateVerifier, StateDirectory, StateDirectoryResolver};
+
+/// Resolving every base directory from the environment alone, with verification that never touches the disk
+fn resolve_all_fixed(c: &mut Criterion) {
+    let directories = fixed_directories(&PathBuf::from("/bench"));
+
+    c.bench_function("resolve_all/fixed", |b| b.iter(|| {
+        black_box(directories.config.resolve().unwrap());
+        black_box(directories.data.resolve().unwrap());
+        black_box(directories.state.resolve().unwrap());
+        black_box(directories.cache.resolve().unwrap());
+        black_box(directories.runtime.resolve().unwrap());
+    }));
+}
+
+/// As `resolve_all_fixed` but every candidate is verified against real directories
+fn resolve_all_filesystem(c: &mut Criterion) {
+    let root = std::env::temp_dir().join("lib-voxels-directories-bench");
+
+    for kind in DirectoryKind::ALL {
+        std::fs::create_dir_all(root.join(kind.name())).unwrap();
+    }
+
+    let env = fixed_environment(&root);
+
+    let config = ConfigDirectory::new(env.clone(), DefaultConfigVerifier::new(DefaultFsInt));
+    let data = DataDirectory::new(env.clone(), DefaultDataVerifier::new(DefaultFsInt));
+    let state = StateDirectory::new(env.clone(), DefaultStateVerifier::new(DefaultFsInt));
+    let cache = CacheDirectory::new(env.clone(), DefaultCacheVerifier::new(DefaultFsInt));
+    let runtime = RuntimeDirectory::new(env, DefaultRuntimeVerifier::new(DefaultFsInt));
+
+    c.bench_function("resolve_all/filesystem", |b| b.iter(|| {
+        black_box(config.resolve().ok());
+        black_box(data.resolve().ok());
+        black_box(state.resolve().ok());
+        black_box(cache.resolve().ok());
+        black_box(runtime.resolve().ok());
+    }));
+}
+
+/// Every candidate plus the system directories, which previously resolved the write directory twice
+fn overlay(c: &mut Criterion) {
+    let directories = fixed_directories(&PathBuf::from("/bench"));
+
+    c.bench_function("overlay/data", |b| b.iter(|| {
+        black_box(directories.data.overlay(&DefaultFsInt).unwrap());
+    }));
+}
+
+criterion_group!(benches, resolve_all_fixed, resolve_all_filesystem, overlay);
+criterion_main!(benches);
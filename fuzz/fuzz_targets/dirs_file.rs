@@ -0,0 +1,46 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! `cargo fuzz run dirs_file`
+
+#![no_main]
+
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+
+use lib_voxels_directories::pathcheck::{join_within, normalize};
+use lib_voxels_directories::voxels::DirectoryKind;
+use lib_voxels_directories::voxels::voxels_xdg::xdg::dirs_file::DirsFile;
+
+fuzz_target!(|contents: &str| {
+    let dirs_file = DirsFile::parse(contents);
+
+    for kind in DirectoryKind::ALL {
+        // only absolute paths are ever accepted from the file
+        if let Some(path) = dirs_file.get(kind) {
+            assert!(path.is_absolute());
+        }
+    }
+
+    // whatever the file names, joining it beneath a base must stay beneath that base
+    let base = Path::new("/base");
+
+    if let Ok(joined) = join_within(base, Path::new(contents)) {
+        assert!(normalize(&joined).starts_with(base));
+    }
+});
@@ -0,0 +1,58 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! `cargo fuzz run manifest`
+
+#![no_main]
+
+use std::path::{Component, PathBuf};
+
+use libfuzzer_sys::fuzz_target;
+
+use lib_voxels_directories::voxels::DirectoryKind;
+use lib_voxels_directories::voxels::overrides::DirectoryOverrides;
+use lib_voxels_directories::voxels::rdn::{validate_rdn, RdnStrictness};
+
+fuzz_target!(|contents: &str| {
+    // each line is `<kind>=<path>`, as the `[directories]` table of a manifest
+    let entries: Vec<(String, PathBuf)> = contents.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, path)| (key.to_owned(), PathBuf::from(path)))
+        .collect();
+
+    if let Ok(overrides) = DirectoryOverrides::from_table(entries) {
+        for kind in DirectoryKind::ALL {
+            // an accepted relocation is absolute, free of `..` and never the root or a base directory
+            if let Some(path) = overrides.get(kind) {
+                assert!(path.is_absolute());
+                assert!(!path.components().any(|component| component == Component::ParentDir));
+                assert!(!overrides.is_protected(path));
+            }
+        }
+    }
+
+    for name in contents.lines() {
+        for strictness in [RdnStrictness::Alphanumeric, RdnStrictness::DBus, RdnStrictness::Lenient] {
+            // an accepted name is safe to use as path segments beneath a base directory
+            if validate_rdn(name, strictness).is_ok() {
+                assert!(name.split('.').count() >= 2);
+                assert!(name.split('.').all(|element| !element.is_empty() && element != ".."));
+                assert!(!name.contains(['/', '\\', '\0']));
+            }
+        }
+    }
+});
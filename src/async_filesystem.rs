@@ -0,0 +1,105 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use mockall::automock;
+
+use crate::filesystem::{CreateOptions, DefaultFsInt, DirectoryLock, FsInt};
+
+/// The subset of `FsInt` needed to verify and create directories, without blocking the async runtime
+#[automock]
+pub trait AsyncFsInt {
+    async fn exists(&self, path: &Path) -> bool;
+    async fn is_directory(&self, path: &Path) -> bool;
+    async fn create_dir_all(&self, path: &Path) -> std::io::Result<()>;
+    /// Unix permission bits of `path`
+    async fn mode(&self, path: &Path) -> std::io::Result<u32>;
+    /// Take an exclusive advisory lock on the existing directory `path`, waiting until it is available
+    async fn lock_directory(&self, path: &Path) -> std::io::Result<DirectoryLock>;
+}
+
+#[derive(Clone, Default)]
+pub struct DefaultAsyncFsInt;
+
+impl AsyncFsInt for DefaultAsyncFsInt {
+    async fn exists(&self, path: &Path) -> bool {
+        tokio::fs::try_exists(path).await.unwrap_or(false)
+    }
+
+    async fn is_directory(&self, path: &Path) -> bool {
+        tokio::fs::metadata(path).await.is_ok_and(|metadata| metadata.is_dir())
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(path).await
+    }
+
+    #[cfg(unix)]
+    async fn mode(&self, path: &Path) -> std::io::Result<u32> {
+        use std::os::unix::fs::PermissionsExt;
+
+        Ok(tokio::fs::metadata(path).await?.permissions().mode() & 0o7777)
+    }
+
+    #[cfg(not(unix))]
+    async fn mode(&self, _path: &Path) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    async fn lock_directory(&self, path: &Path) -> std::io::Result<DirectoryLock> {
+        let path = path.to_path_buf();
+
+        // flock has no async form, wait for it off the runtime's worker threads
+        tokio::task::spawn_blocking(move || DefaultFsInt.lock_directory(&path))
+            .await
+            .map_err(std::io::Error::other)?
+    }
+}
+
+/// As `filesystem::create_directory`, for use from async resolvers
+pub async fn create_directory<AsyncFsIntT: AsyncFsInt>(fs: &AsyncFsIntT, path: &Path, options: CreateOptions) -> std::io::Result<()> {
+    let _lock = if options.lock_parent {
+        let mut existing: Option<PathBuf> = None;
+
+        for ancestor in path.ancestors().skip(1) {
+            if fs.exists(ancestor).await {
+                existing = Some(ancestor.to_path_buf());
+                break;
+            }
+        }
+
+        match existing {
+            Some(ancestor) => Some(fs.lock_directory(&ancestor).await?),
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    match fs.create_dir_all(path).await {
+        Ok(()) => {},
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {},
+        Err(err) => return Err(err),
+    }
+
+    if !fs.is_directory(path).await {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotADirectory));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::time::SystemTime;
+
+use mockall::automock;
+
+/// Source of the current time for anything that timestamps or ages files
+#[automock]
+pub trait ClockInt {
+    fn now(&self) -> SystemTime;
+}
+
+#[derive(Clone, Default)]
+pub struct DefaultClockInt;
+
+impl ClockInt for DefaultClockInt {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
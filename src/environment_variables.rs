@@ -19,36 +19,123 @@ use std::{env::VarError, path::PathBuf};
 
 use mockall::automock;
 
+/// Every environment variable this crate reads
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum EnvKey {
+    Home,
+    Path,
+    XdgConfigHome,
+    XdgDataHome,
+    XdgStateHome,
+    XdgCacheHome,
+    XdgRuntimeDir,
+    XdgBinHome,
+    XdgConfigDirs,
+    XdgDataDirs,
+    VoxelsConfigHome,
+    VoxelsDataHome,
+    VoxelsStateHome,
+    VoxelsCacheHome,
+    VoxelsRuntimeHome,
+    VoxelsBinHome,
+    VoxelsDirsFile,
+    VoxelsDirectoriesBus,
+    DbusSessionBusAddress,
+    DbusSystemBusAddress,
+    XdgSessionId,
+}
+
+impl EnvKey {
+    pub const ALL: [EnvKey; 21] = [
+        EnvKey::Home,
+        EnvKey::Path,
+        EnvKey::XdgConfigHome,
+        EnvKey::XdgDataHome,
+        EnvKey::XdgStateHome,
+        EnvKey::XdgCacheHome,
+        EnvKey::XdgRuntimeDir,
+        EnvKey::XdgBinHome,
+        EnvKey::XdgConfigDirs,
+        EnvKey::XdgDataDirs,
+        EnvKey::VoxelsConfigHome,
+        EnvKey::VoxelsDataHome,
+        EnvKey::VoxelsStateHome,
+        EnvKey::VoxelsCacheHome,
+        EnvKey::VoxelsRuntimeHome,
+        EnvKey::VoxelsBinHome,
+        EnvKey::VoxelsDirsFile,
+        EnvKey::VoxelsDirectoriesBus,
+        EnvKey::DbusSessionBusAddress,
+        EnvKey::DbusSystemBusAddress,
+        EnvKey::XdgSessionId,
+    ];
+
+    /// The name of the variable in the environment
+    pub const fn name(&self) -> &'static str {
+        match self {
+            EnvKey::Home => "HOME",
+            EnvKey::Path => "PATH",
+            EnvKey::XdgConfigHome => "XDG_CONFIG_HOME",
+            EnvKey::XdgDataHome => "XDG_DATA_HOME",
+            EnvKey::XdgStateHome => "XDG_STATE_HOME",
+            EnvKey::XdgCacheHome => "XDG_CACHE_HOME",
+            EnvKey::XdgRuntimeDir => "XDG_RUNTIME_DIR",
+            EnvKey::XdgBinHome => "XDG_BIN_HOME",
+            EnvKey::XdgConfigDirs => "XDG_CONFIG_DIRS",
+            EnvKey::XdgDataDirs => "XDG_DATA_DIRS",
+            EnvKey::VoxelsConfigHome => "VOXELS_CONFIG_HOME",
+            EnvKey::VoxelsDataHome => "VOXELS_DATA_HOME",
+            EnvKey::VoxelsStateHome => "VOXELS_STATE_HOME",
+            EnvKey::VoxelsCacheHome => "VOXELS_CACHE_HOME",
+            EnvKey::VoxelsRuntimeHome => "VOXELS_RUNTIME_HOME",
+            EnvKey::VoxelsBinHome => "VOXELS_BIN_HOME",
+            EnvKey::VoxelsDirsFile => "VOXELS_DIRS_FILE",
+            EnvKey::VoxelsDirectoriesBus => "VOXELS_DIRECTORIES_BUS",
+            EnvKey::DbusSessionBusAddress => "DBUS_SESSION_BUS_ADDRESS",
+            EnvKey::DbusSystemBusAddress => "DBUS_SYSTEM_BUS_ADDRESS",
+            EnvKey::XdgSessionId => "XDG_SESSION_ID",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        EnvKey::ALL.into_iter().find(|key| key.name() == name)
+    }
+}
+
 #[automock]
 pub trait EnvInt {
-    fn get_path_from_environment(&self, name: String) -> Result<PathBuf, VarError>;
+    fn get_path(&self, key: EnvKey) -> Result<PathBuf, VarError>;
 }
 
 #[derive(Clone, Default)]
 pub struct DefaultEnvInt;
 
 impl EnvInt for DefaultEnvInt {
-    fn get_path_from_environment(&self, name: String) -> Result<PathBuf, VarError> {
-        let rslt = std::env::var(name);
-
-        if rslt.is_ok() {
-            Ok(PathBuf::from(rslt?))
-        } else {
-            Err(rslt.unwrap_err().into())
-        }
+    fn get_path(&self, key: EnvKey) -> Result<PathBuf, VarError> {
+        std::env::var(key.name()).map(PathBuf::from)
     }
 }
 
 impl MockEnvInt {
-    pub fn expect_and_rig(&mut self, name: &str, env_var_value: PathBuf) -> &mut __mock_MockEnvInt_EnvInt::__get_path_from_environment::Expectation {
-        self.expect_get_path_from_environment()
-            .with(mockall::predicate::eq(name.to_owned()))
+    pub fn expect_and_rig(&mut self, key: EnvKey, env_var_value: PathBuf) -> &mut __mock_MockEnvInt_EnvInt::__get_path::Expectation {
+        self.expect_get_path()
+            .with(mockall::predicate::eq(key))
             .return_once(|_| Ok(env_var_value))
     }
 
-    pub fn expect_and_rig_to_fail(&mut self, name: String) -> &mut __mock_MockEnvInt_EnvInt::__get_path_from_environment::Expectation {
-        self.expect_get_path_from_environment()
-            .with(mockall::predicate::eq(name.to_owned()))
+    pub fn expect_and_rig_to_fail(&mut self, key: EnvKey) -> &mut __mock_MockEnvInt_EnvInt::__get_path::Expectation {
+        self.expect_get_path()
+            .with(mockall::predicate::eq(key))
             .return_once(|_| Err(VarError::NotPresent))
     }
 }
+
+#[test]
+fn test_env_key_names() {
+    for key in EnvKey::ALL {
+        assert_eq!(EnvKey::from_name(key.name()), Some(key));
+    }
+
+    assert_eq!(EnvKey::from_name("XDG_RUNTIME_DIR"), Some(EnvKey::XdgRuntimeDir));
+    assert_eq!(EnvKey::from_name("XDG_MUSIC_DIR"), None);
+}
@@ -16,6 +16,7 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use mockall::automock;
 
@@ -25,6 +26,272 @@ pub trait FsInt {
     fn is_directory(&self, path: &Path) -> bool;
     fn is_absolute(&self, path: &Path) -> bool;
     fn read_to_string(&self, path: &Path) -> std::io::Result<String>;
+    fn write(&self, path: &Path, contents: &str) -> std::io::Result<()>;
+    /// Write `contents` to a new file at `path`, failing with `AlreadyExists` if anything is there, as O_EXCL does
+    fn write_new(&self, path: &Path, contents: &str) -> std::io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()>;
+    /// Create the single directory `path` with mode 0700, failing if anything already exists there
+    fn create_private_dir(&self, path: &Path) -> std::io::Result<()>;
+    /// Create the file `path` with mode 0600 and `size` zeroed bytes, failing if anything already exists there
+    fn create_private_file(&self, path: &Path, size: u64) -> std::io::Result<()>;
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>>;
+    fn remove_file(&self, path: &Path) -> std::io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()>;
+    fn file_size(&self, path: &Path) -> std::io::Result<u64>;
+    fn modified(&self, path: &Path) -> std::io::Result<SystemTime>;
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>>;
+    fn write_bytes(&self, path: &Path, contents: &[u8]) -> std::io::Result<()>;
+    /// Append `contents` to `path` in a single write, creating it when missing
+    fn append(&self, path: &Path, contents: &str) -> std::io::Result<()>;
+    /// Device and inode of `path`, equal for two paths exactly when they are the same file
+    fn file_id(&self, path: &Path) -> std::io::Result<(u64, u64)>;
+    /// True for a symlink, whether or not its target exists
+    fn is_symlink(&self, path: &Path) -> bool;
+    /// Unix permission bits of `path`
+    fn mode(&self, path: &Path) -> std::io::Result<u32>;
+    /// Uid of the owner of `path`
+    fn owner(&self, path: &Path) -> std::io::Result<u32>;
+    /// Effective uid of this process, which owns the files it creates
+    fn effective_uid(&self) -> std::io::Result<u32>;
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf>;
+    /// Whether files can be created in the directory `path`, including on read only mounts
+    fn is_writable(&self, path: &Path) -> bool;
+    /// Take an exclusive advisory lock on the existing directory `path`, blocking until it is available
+    fn lock_directory(&self, path: &Path) -> std::io::Result<DirectoryLock>;
+    /// Create a symlink at `link` pointing to `target`
+    fn symlink(&self, target: &Path, link: &Path) -> std::io::Result<()>;
+    fn read_link(&self, path: &Path) -> std::io::Result<PathBuf>;
+    /// Type of the filesystem `path` is on as the kernel names it, such as `ext4` or `nfs4`
+    fn filesystem_type(&self, path: &Path) -> std::io::Result<String>;
+    /// The access the POSIX ACL of `path` gives the group named `group`, `None` when it has no entry for it
+    /// or the entry does not let it pass through
+    fn group_acl(&self, path: &Path, group: &str) -> std::io::Result<Option<AclAccess>>;
+    /// Set the POSIX ACL entry of the group named `group` on `path`, leaving its mode and other entries alone
+    fn set_group_acl(&self, path: &Path, group: &str, access: AclAccess) -> std::io::Result<()>;
+    /// Remove the POSIX ACL entry of the group named `group` from `path`, if it has one
+    fn remove_group_acl(&self, path: &Path, group: &str) -> std::io::Result<()>;
+}
+
+/// What a POSIX ACL entry lets a group do
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum AclAccess {
+    /// Pass through a directory without listing it
+    Search,
+    /// Read a file, or list and pass through a directory
+    Read,
+}
+
+impl AclAccess {
+    /// The permissions of this access as setfacl(1) takes them
+    pub fn permissions(&self) -> &'static str {
+        match self {
+            AclAccess::Search => "--x",
+            AclAccess::Read => "rX",
+        }
+    }
+}
+
+/// The access of `group` in the output of `getfacl --omit-header`, `None` when it has no entry
+/// or its entry lacks `x`, so it cannot pass through a directory. `#effective:` comments are ignored.
+pub fn group_acl_from_getfacl(output: &str, group: &str) -> Option<AclAccess> {
+    let prefix = format!("group:{}:", group);
+
+    let permissions = output.lines()
+        .find_map(|line| line.strip_prefix(&prefix))?
+        .split(|c: char| c == '#' || c.is_whitespace())
+        .next()?
+        .as_bytes();
+
+    match permissions {
+        [b'r', _, b'x'] => Some(AclAccess::Read),
+        [_, _, b'x'] => Some(AclAccess::Search),
+        _ => None,
+    }
+}
+
+/// Run one of the acl(5) tools, which only say they failed through their exit status and stderr
+#[cfg(unix)]
+fn run_acl_tool(command: &mut std::process::Command) -> std::io::Result<String> {
+    let output = command.output()?;
+
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!("{} failed: {}", command.get_program().to_string_lossy(), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// An advisory lock held until this is dropped
+#[derive(Debug, Default)]
+pub struct DirectoryLock {
+    _file: Option<std::fs::File>,
+}
+
+/// How `create_directory` behaves when other processes may be creating the same hierarchy
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct CreateOptions {
+    /// Serialize creation with other processes by locking the deepest existing ancestor
+    pub lock_parent: bool,
+}
+
+/// Create `path` and any missing parents. Another process creating any of them first is not an error,
+/// but `path` must be a directory once this returns `Ok`.
+pub fn create_directory<FsIntT: FsInt>(fs: &FsIntT, path: &Path, options: CreateOptions) -> std::io::Result<()> {
+    let _lock = if options.lock_parent {
+        match path.ancestors().skip(1).find(|ancestor| fs.exists(ancestor)) {
+            Some(ancestor) => Some(fs.lock_directory(ancestor)?),
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    match fs.create_dir_all(path) {
+        Ok(()) => {},
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {},
+        Err(err) => return Err(err),
+    }
+
+    if !fs.is_directory(path) {
+        return Err(std::io::Error::from(std::io::ErrorKind::NotADirectory));
+    }
+
+    Ok(())
+}
+
+/// Filesystem types served over the network, where file locking is unreliable and databases are slow
+const NETWORK_FILESYSTEM_TYPES: [&str; 13] = [
+    "nfs", "nfs4", "cifs", "smb3", "smbfs", "ncpfs", "afs", "9p", "ceph", "lustre", "gpfs",
+    "fuse.sshfs", "fuse.glusterfs",
+];
+
+pub fn is_network_filesystem(filesystem_type: &str) -> bool {
+    NETWORK_FILESYSTEM_TYPES.contains(&filesystem_type)
+}
+
+/// The type of the deepest mount containing the absolute, canonical `path`, from `/proc/self/mounts` style `mounts`
+pub fn filesystem_type_from_mounts(mounts: &str, path: &Path) -> Option<String> {
+    mounts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+
+            let _device = fields.next()?;
+            // spaces in mount points are escaped as \040
+            let mount_point = PathBuf::from(fields.next()?.replace("\\040", " "));
+            let filesystem_type = fields.next()?;
+
+            path.starts_with(&mount_point).then(|| (mount_point, String::from(filesystem_type)))
+        })
+        .max_by_key(|(mount_point, _)| mount_point.components().count())
+        .map(|(_, filesystem_type)| filesystem_type)
+}
+
+/// Copy the tree at `from` into `to`, creating `to`. Symlinks are copied as links, not followed.
+pub fn copy_directory<FsIntT: FsInt>(fs: &FsIntT, from: &Path, to: &Path) -> std::io::Result<()> {
+    create_directory(fs, to, CreateOptions::default())?;
+
+    for entry in fs.read_dir(from)? {
+        let Some(name) = entry.file_name() else {
+            continue;
+        };
+
+        let destination = to.join(name);
+
+        if fs.is_symlink(&entry) {
+            fs.symlink(&fs.read_link(&entry)?, &destination)?;
+        } else if fs.is_directory(&entry) {
+            copy_directory(fs, &entry, &destination)?;
+        } else {
+            fs.write_bytes(&destination, &fs.read(&entry)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rename `from` to `to`, copying then removing `from` when they are on different filesystems.
+/// A copy that does not match the original is removed again and fails with `InvalidData`, leaving `from` untouched.
+pub fn move_path<FsIntT: FsInt>(fs: &FsIntT, from: &Path, to: &Path) -> std::io::Result<()> {
+    match fs.rename(from, to) {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {},
+        Err(err) => return Err(err),
+    }
+
+    if fs.is_directory(from) && !fs.is_symlink(from) {
+        copy_directory(fs, from, to)?;
+
+        if !directories_match(fs, from, to)? {
+            fs.remove_dir_all(to)?;
+
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+        }
+
+        fs.remove_dir_all(from)
+    } else {
+        let contents = fs.read(from)?;
+
+        fs.write_bytes(to, &contents)?;
+
+        if fs.read(to)? != contents {
+            fs.remove_file(to)?;
+
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+        }
+
+        fs.remove_file(from)
+    }
+}
+
+/// Bytes used by the files beneath `path`, or by `path` itself when it is a file. Symlinks are not followed.
+pub fn measure<FsIntT: FsInt>(fs: &FsIntT, path: &Path) -> std::io::Result<u64> {
+    if fs.is_symlink(path) {
+        return Ok(0);
+    }
+
+    if !fs.is_directory(path) {
+        return fs.file_size(path);
+    }
+
+    let mut total = 0;
+
+    for entry in fs.read_dir(path)? {
+        total += measure(fs, &entry)?;
+    }
+
+    Ok(total)
+}
+
+/// Whether the trees at `a` and `b` hold the same names, link targets and file contents
+pub fn directories_match<FsIntT: FsInt>(fs: &FsIntT, a: &Path, b: &Path) -> std::io::Result<bool> {
+    let entries = fs.read_dir(a)?;
+
+    if entries.len() != fs.read_dir(b)?.len() {
+        return Ok(false);
+    }
+
+    for entry in entries {
+        let Some(name) = entry.file_name() else {
+            continue;
+        };
+
+        let other = b.join(name);
+
+        let matches = if fs.is_symlink(&entry) {
+            fs.is_symlink(&other) && fs.read_link(&entry)? == fs.read_link(&other)?
+        } else if fs.is_directory(&entry) {
+            fs.is_directory(&other) && directories_match(fs, &entry, &other)?
+        } else {
+            fs.file_size(&entry)? == fs.file_size(&other)? && fs.read(&entry)? == fs.read(&other)?
+        };
+
+        if !matches {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
 }
 
 #[derive(Clone, Default)]
@@ -32,11 +299,12 @@ pub struct DefaultFsInt;
 
 impl FsInt for DefaultFsInt {
     fn exists(&self, path: &Path) -> bool {
-        std::fs::exists(path).unwrap()
+        // a path we may not look at is as good as missing
+        std::fs::exists(path).unwrap_or(false)
     }
 
     fn is_directory(&self, path: &Path) -> bool {
-        std::fs::metadata(path).unwrap().is_dir()
+        std::fs::metadata(path).is_ok_and(|metadata| metadata.is_dir())
     }
 
     fn is_absolute(&self, path: &Path) -> bool {
@@ -46,6 +314,240 @@ impl FsInt for DefaultFsInt {
     fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
         std::fs::read_to_string(path)
     }
+
+    fn write(&self, path: &Path, contents: &str) -> std::io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn write_new(&self, path: &Path, contents: &str) -> std::io::Result<()> {
+        use std::io::Write;
+
+        std::fs::OpenOptions::new().write(true).create_new(true).open(path)?.write_all(contents.as_bytes())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    #[cfg(unix)]
+    fn create_private_dir(&self, path: &Path) -> std::io::Result<()> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        std::fs::DirBuilder::new().mode(0o700).create(path)
+    }
+
+    #[cfg(not(unix))]
+    fn create_private_dir(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::create_dir(path)
+    }
+
+    #[cfg(unix)]
+    fn create_private_file(&self, path: &Path, size: u64) -> std::io::Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        std::fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?.set_len(size)
+    }
+
+    #[cfg(not(unix))]
+    fn create_private_file(&self, path: &Path, size: u64) -> std::io::Result<()> {
+        std::fs::OpenOptions::new().write(true).create_new(true).open(path)?.set_len(size)
+    }
+
+    fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+
+    fn remove_file(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn file_size(&self, path: &Path) -> std::io::Result<u64> {
+        Ok(std::fs::metadata(path)?.len())
+    }
+
+    fn modified(&self, path: &Path) -> std::io::Result<SystemTime> {
+        std::fs::metadata(path)?.modified()
+    }
+
+    fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn write_bytes(&self, path: &Path, contents: &[u8]) -> std::io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &str) -> std::io::Result<()> {
+        use std::io::Write;
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(contents.as_bytes())
+    }
+
+    #[cfg(unix)]
+    fn file_id(&self, path: &Path) -> std::io::Result<(u64, u64)> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = std::fs::metadata(path)?;
+
+        Ok((metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(unix))]
+    fn file_id(&self, _path: &Path) -> std::io::Result<(u64, u64)> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.is_symlink()
+    }
+
+    #[cfg(unix)]
+    fn mode(&self, path: &Path) -> std::io::Result<u32> {
+        use std::os::unix::fs::PermissionsExt;
+
+        Ok(std::fs::metadata(path)?.permissions().mode() & 0o7777)
+    }
+
+    #[cfg(not(unix))]
+    fn mode(&self, _path: &Path) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(unix)]
+    fn owner(&self, path: &Path) -> std::io::Result<u32> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(std::fs::metadata(path)?.uid())
+    }
+
+    #[cfg(not(unix))]
+    fn owner(&self, _path: &Path) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(unix)]
+    fn effective_uid(&self) -> std::io::Result<u32> {
+        // SAFETY: geteuid cannot fail and touches no memory of ours
+        Ok(unsafe { libc::geteuid() })
+    }
+
+    #[cfg(not(unix))]
+    fn effective_uid(&self) -> std::io::Result<u32> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn canonicalize(&self, path: &Path) -> std::io::Result<PathBuf> {
+        std::fs::canonicalize(path)
+    }
+
+    fn is_writable(&self, path: &Path) -> bool {
+        // permission bits say nothing about read only mounts, so try it
+        let probe = path.join(format!(".voxels-write-probe-{}", std::process::id()));
+
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+            Ok(_) => {
+                let _ = std::fs::remove_file(&probe);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn lock_directory(&self, path: &Path) -> std::io::Result<DirectoryLock> {
+        let file = std::fs::File::open(path)?;
+
+        file.lock()?;
+
+        Ok(DirectoryLock {
+            _file: Some(file)
+        })
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> std::io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(not(unix))]
+    fn symlink(&self, _target: &Path, _link: &Path) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    fn read_link(&self, path: &Path) -> std::io::Result<PathBuf> {
+        std::fs::read_link(path)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn filesystem_type(&self, path: &Path) -> std::io::Result<String> {
+        let mounts = std::fs::read_to_string("/proc/self/mounts")?;
+
+        filesystem_type_from_mounts(&mounts, &std::fs::canonicalize(path)?)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn filesystem_type(&self, _path: &Path) -> std::io::Result<String> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(unix)]
+    fn group_acl(&self, path: &Path, group: &str) -> std::io::Result<Option<AclAccess>> {
+        let output = run_acl_tool(std::process::Command::new("getfacl").arg("--omit-header").arg("--absolute-names").arg(path))?;
+
+        Ok(group_acl_from_getfacl(&output, group))
+    }
+
+    #[cfg(not(unix))]
+    fn group_acl(&self, _path: &Path, _group: &str) -> std::io::Result<Option<AclAccess>> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(unix)]
+    fn set_group_acl(&self, path: &Path, group: &str, access: AclAccess) -> std::io::Result<()> {
+        // setfacl follows symlinks, which would grant whatever they point at
+        if self.is_symlink(path) {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        }
+
+        run_acl_tool(std::process::Command::new("setfacl").arg("-m").arg(format!("g:{}:{}", group, access.permissions())).arg(path))?;
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn set_group_acl(&self, _path: &Path, _group: &str, _access: AclAccess) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
+
+    #[cfg(unix)]
+    fn remove_group_acl(&self, path: &Path, group: &str) -> std::io::Result<()> {
+        if self.is_symlink(path) {
+            return Err(std::io::Error::from(std::io::ErrorKind::InvalidInput));
+        }
+
+        run_acl_tool(std::process::Command::new("setfacl").arg("-x").arg(format!("g:{}", group)).arg(path))?;
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn remove_group_acl(&self, _path: &Path, _group: &str) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+    }
 }
 
 impl MockFsInt {
@@ -60,4 +562,74 @@ impl MockFsInt {
             .with(mockall::predicate::eq(expected_path))
             .return_once(move |_| rigged)
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_create_directory_tolerates_concurrent_creation() {
+    let mut fs = MockFsInt::new();
+
+    let path = PathBuf::from("/home/user/.local/share/voxels");
+
+    fs.expect_exists()
+        .with(mockall::predicate::eq(PathBuf::from("/home/user/.local/share")))
+        .return_once(|_| true);
+
+    fs.expect_lock_directory()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/home/user/.local/share")))
+        .return_once(|_| Ok(DirectoryLock::default()));
+
+    fs.expect_create_dir_all()
+        .once()
+        .return_once(|_| Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+
+    fs.expect_is_directory()
+        .once()
+        .return_once(|_| true);
+
+    assert!(create_directory(&fs, &path, CreateOptions { lock_parent: true }).is_ok());
+}
+
+#[test]
+fn test_filesystem_type_from_mounts() {
+    let mounts = "/dev/sda1 / ext4 rw 0 0\nserver:/home /home nfs4 rw 0 0\ntmpfs /home/user/My\\040Games tmpfs rw 0 0\n";
+
+    assert_eq!(filesystem_type_from_mounts(mounts, Path::new("/home/user/.local/share")), Some(String::from("nfs4")));
+    assert_eq!(filesystem_type_from_mounts(mounts, Path::new("/home/user/My Games/save")), Some(String::from("tmpfs")));
+    assert_eq!(filesystem_type_from_mounts(mounts, Path::new("/var/tmp")), Some(String::from("ext4")));
+    assert!(is_network_filesystem("nfs4"));
+    assert!(!is_network_filesystem("ext4"));
+}
+
+#[test]
+fn test_group_acl_from_getfacl() {
+    let output = "user::rwx\ngroup::r-x\ngroup:voxels-org-voxels-viewer:r-x\t#effective:r--\ngroup:voxels-org-voxels-map:--x\ngroup:voxels-org-voxels-denied:---\ngroup:voxels-org-voxels-listing:r--\nmask::r-x\nother::---\ndefault:group:voxels-org-voxels-other:r-x\n";
+
+    assert_eq!(group_acl_from_getfacl(output, "voxels-org-voxels-viewer"), Some(AclAccess::Read));
+    assert_eq!(group_acl_from_getfacl(output, "voxels-org-voxels-map"), Some(AclAccess::Search));
+    assert_eq!(group_acl_from_getfacl(output, "voxels-org-voxels-denied"), None);
+    assert_eq!(group_acl_from_getfacl(output, "voxels-org-voxels-listing"), None);
+    assert_eq!(group_acl_from_getfacl(output, "voxels-org-voxels-other"), None);
+    assert_eq!(group_acl_from_getfacl(output, "voxels-org-voxels"), None);
+}
+
+#[test]
+fn test_measure() {
+    let mut fs = MockFsInt::new();
+
+    fs.expect_is_symlink()
+        .returning(|path| path.ends_with("link"));
+
+    fs.expect_is_directory()
+        .returning(|path| path.ends_with("data") || path.ends_with("worlds"));
+
+    fs.expect_read_dir()
+        .returning(|path| Ok(match path.to_str().unwrap() {
+            "/data" => vec![PathBuf::from("/data/settings.toml"), PathBuf::from("/data/worlds"), PathBuf::from("/data/link")],
+            _ => vec![PathBuf::from("/data/worlds/a.dat"), PathBuf::from("/data/worlds/b.dat")],
+        }));
+
+    fs.expect_file_size()
+        .returning(|path| Ok(if path.ends_with("settings.toml") { 10 } else { 100 }));
+
+    assert_eq!(measure(&fs, Path::new("/data")).unwrap(), 210);
+}
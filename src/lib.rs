@@ -14,11 +14,19 @@ GNU General Public License for more details.
 You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
-#[allow(dead_code)]
+// the resolver traits are only driven through generics by this crate's own async code
+#![allow(async_fn_in_trait)]
 
+#[allow(dead_code)]
 pub mod voxels;
 pub mod environment_variables;
 pub mod filesystem;
+#[cfg(any(feature = "dbus", feature = "rdn"))]
+pub mod async_filesystem;
+pub mod clock;
+pub mod pathcheck;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testing;
 
-#[cfg(feature = "application")]
+#[cfg(feature = "rdn")]
 pub use lib_voxels_application;
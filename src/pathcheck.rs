@@ -0,0 +1,434 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::filesystem::FsInt;
+
+/// Linux PATH_MAX, including the terminating nul
+pub const PATH_MAX: usize = 4096;
+
+/// NAME_MAX on Linux and the per component limit on NTFS
+pub const NAME_MAX: usize = 255;
+
+/// MAX_PATH for Windows APIs without long path support
+pub const WINDOWS_LEGACY_PATH_MAX: usize = 260;
+
+/// Device names that Windows refuses as file names, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const WINDOWS_RESERVED_CHARACTERS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PathCheckError {
+    PathTooLong { length: usize, limit: usize },
+    NameTooLong { name: String, limit: usize },
+    ReservedName(String),
+    ReservedCharacter { name: String, character: char },
+    /// Joining or following symlinks would leave the directory the path must stay within
+    Escapes(PathBuf),
+    NotAbsolute(PathBuf),
+}
+
+/// The limits a path is checked against
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PathRules {
+    pub path_max: usize,
+    pub name_max: usize,
+    pub windows_names: bool,
+}
+
+impl PathRules {
+    pub const UNIX: PathRules = PathRules {
+        path_max: PATH_MAX,
+        name_max: NAME_MAX,
+        windows_names: false,
+    };
+
+    pub const WINDOWS_LEGACY: PathRules = PathRules {
+        path_max: WINDOWS_LEGACY_PATH_MAX,
+        name_max: NAME_MAX,
+        windows_names: true,
+    };
+
+    /// Paths that pass these are valid on every platform voxels supports
+    pub const PORTABLE: PathRules = PathRules::WINDOWS_LEGACY;
+
+    /// The rules for the platform this crate was compiled for
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            PathRules::WINDOWS_LEGACY
+        } else {
+            PathRules::UNIX
+        }
+    }
+}
+
+pub fn check_file_name(name: &str, rules: PathRules) -> Result<(), PathCheckError> {
+    if name.len() > rules.name_max {
+        return Err(PathCheckError::NameTooLong { name: name.to_owned(), limit: rules.name_max });
+    }
+
+    if !rules.windows_names {
+        return Ok(());
+    }
+
+    if let Some(character) = name.chars().find(|c| WINDOWS_RESERVED_CHARACTERS.contains(c) || c.is_ascii_control()) {
+        return Err(PathCheckError::ReservedCharacter { name: name.to_owned(), character });
+    }
+
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+
+    if WINDOWS_RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        return Err(PathCheckError::ReservedName(name.to_owned()));
+    }
+
+    // windows silently strips these, so `name.` and `name` alias each other
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(PathCheckError::ReservedName(name.to_owned()));
+    }
+
+    Ok(())
+}
+
+pub fn check_path(path: &Path, rules: PathRules) -> Result<(), PathCheckError> {
+    let length = path.as_os_str().len();
+
+    // the limits count the terminating nul
+    if length + 1 > rules.path_max {
+        return Err(PathCheckError::PathTooLong { length, limit: rules.path_max });
+    }
+
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            check_file_name(&name.to_string_lossy(), rules)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check `path` against the limits of the current platform
+pub fn check(path: &Path) -> Result<(), PathCheckError> {
+    check_path(path, PathRules::native())
+}
+
+/// Lexically normalize `path`: drop trailing separators and `.` components and fold `..` into its parent.
+/// Symlinks are not consulted, so this never touches the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                match normalized.components().next_back() {
+                    Some(Component::Normal(_)) => {
+                        normalized.pop();
+                    },
+                    // `..` at the root is the root
+                    Some(Component::RootDir) | Some(Component::Prefix(_)) => {},
+                    _ => normalized.push(".."),
+                }
+            },
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+
+    normalized
+}
+
+/// A directory path that is known to be absolute and lexically normalized
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct AbsoluteDirPath {
+    path: PathBuf,
+}
+
+impl AbsoluteDirPath {
+    pub fn new(path: PathBuf) -> Result<Self, PathCheckError> {
+        if !path.is_absolute() {
+            return Err(PathCheckError::NotAbsolute(path));
+        }
+
+        Ok(Self {
+            path: normalize(&path)
+        })
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl std::ops::Deref for AbsoluteDirPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for AbsoluteDirPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TryFrom<PathBuf> for AbsoluteDirPath {
+    type Error = PathCheckError;
+
+    fn try_from(path: PathBuf) -> Result<Self, PathCheckError> {
+        Self::new(path)
+    }
+}
+
+impl From<AbsoluteDirPath> for PathBuf {
+    fn from(path: AbsoluteDirPath) -> PathBuf {
+        path.path
+    }
+}
+
+impl PartialEq<PathBuf> for AbsoluteDirPath {
+    fn eq(&self, other: &PathBuf) -> bool {
+        self.path == *other
+    }
+}
+
+impl PartialEq<Path> for AbsoluteDirPath {
+    fn eq(&self, other: &Path) -> bool {
+        self.path == other
+    }
+}
+
+/// A path that stays within whatever directory it is joined to: not empty, not absolute and without `..`.
+/// `.` components are dropped. Take this rather than `&Path` wherever a caller names a file inside a managed directory,
+/// so the escape checks happen once, when the path is built.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct RelPath {
+    path: PathBuf,
+}
+
+impl RelPath {
+    pub fn new<PathT: AsRef<Path>>(path: PathT) -> Result<Self, PathCheckError> {
+        let path = path.as_ref();
+
+        let mut relative = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => relative.push(name),
+                Component::CurDir => {},
+                _ => return Err(PathCheckError::Escapes(path.to_path_buf())),
+            }
+        }
+
+        if relative.as_os_str().is_empty() {
+            return Err(PathCheckError::Escapes(path.to_path_buf()));
+        }
+
+        Ok(Self {
+            path: relative
+        })
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_path_buf(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl std::ops::Deref for RelPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for RelPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TryFrom<PathBuf> for RelPath {
+    type Error = PathCheckError;
+
+    fn try_from(path: PathBuf) -> Result<Self, PathCheckError> {
+        Self::new(path)
+    }
+}
+
+impl TryFrom<&Path> for RelPath {
+    type Error = PathCheckError;
+
+    fn try_from(path: &Path) -> Result<Self, PathCheckError> {
+        Self::new(path)
+    }
+}
+
+impl TryFrom<&str> for RelPath {
+    type Error = PathCheckError;
+
+    fn try_from(path: &str) -> Result<Self, PathCheckError> {
+        Self::new(path)
+    }
+}
+
+impl From<RelPath> for PathBuf {
+    fn from(path: RelPath) -> PathBuf {
+        path.path
+    }
+}
+
+/// `base.join(relative)`, rejecting a `relative` that `RelPath` rejects
+pub fn join_within(base: &Path, relative: &Path) -> Result<PathBuf, PathCheckError> {
+    Ok(base.join(RelPath::new(relative)?))
+}
+
+/// Check `path` stays within `base` once symlinks are resolved.
+/// Only the deepest existing ancestor of `path` is canonicalized, so paths yet to be created can be checked.
+pub fn check_contained<FsIntT: FsInt>(fs: &FsIntT, base: &Path, path: &Path) -> Result<(), PathCheckError> {
+    let Ok(canonical_base) = fs.canonicalize(base) else {
+        // nothing exists yet that could be a symlink
+        return Ok(());
+    };
+
+    let mut existing = path;
+    let mut remainder = PathBuf::new();
+
+    let canonical = loop {
+        if let Ok(canonical) = fs.canonicalize(existing) {
+            break canonical.join(&remainder);
+        }
+
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            return Ok(());
+        };
+
+        remainder = Path::new(name).join(remainder);
+        existing = parent;
+    };
+
+    if normalize(&canonical).starts_with(&canonical_base) {
+        Ok(())
+    } else {
+        Err(PathCheckError::Escapes(path.to_path_buf()))
+    }
+}
+
+#[test]
+fn test_absolute_dir_path() {
+    assert_eq!(AbsoluteDirPath::new(PathBuf::from("/home/user/.config/")).unwrap(), PathBuf::from("/home/user/.config"));
+    assert_eq!(AbsoluteDirPath::new(PathBuf::from("config")), Err(PathCheckError::NotAbsolute(PathBuf::from("config"))));
+}
+
+#[test]
+fn test_rel_path() {
+    assert_eq!(RelPath::new("./mods//list.toml").unwrap().as_path(), Path::new("mods/list.toml"));
+    assert!(RelPath::new("mods/../../etc").is_err());
+    assert!(RelPath::new("/etc/passwd").is_err());
+    assert!(RelPath::new(".").is_err());
+    assert!(RelPath::try_from("saves/world.dat").is_ok());
+}
+
+#[test]
+fn test_join_within() {
+    let base = Path::new("/home/user/.local/share/voxels");
+
+    assert_eq!(join_within(base, Path::new("org.voxels.Client")), Ok(base.join("org.voxels.Client")));
+    assert!(join_within(base, Path::new("../../../etc")).is_err());
+    assert!(join_within(base, Path::new("/etc")).is_err());
+    assert!(join_within(base, Path::new("")).is_err());
+}
+
+#[test]
+fn test_check_contained() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    // <base>/evil is a symlink to /etc
+    fs.expect_canonicalize()
+        .returning(|path| {
+            if path.starts_with("/base/evil") {
+                Ok(PathBuf::from("/etc").join(path.strip_prefix("/base/evil").unwrap()))
+            } else if path == Path::new("/base") {
+                Ok(PathBuf::from("/base"))
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            }
+        });
+
+    assert!(check_contained(&fs, Path::new("/base"), Path::new("/base/new/child")).is_ok());
+    assert_eq!(check_contained(&fs, Path::new("/base"), Path::new("/base/evil/passwd")), Err(PathCheckError::Escapes(PathBuf::from("/base/evil/passwd"))));
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize(Path::new("/home/user/.config/")).as_os_str(), "/home/user/.config");
+    assert_eq!(normalize(Path::new("/home/./user/../other//")).as_os_str(), "/home/other");
+    assert_eq!(normalize(Path::new("/../home")).as_os_str(), "/home");
+    assert_eq!(normalize(Path::new("../relative/./dir")).as_os_str(), "../relative/dir");
+}
+
+#[test]
+fn test_check_path_length() {
+    let long = Path::new("/").join("a".repeat(200)).join("b".repeat(100));
+
+    assert!(check_path(&long, PathRules::UNIX).is_ok());
+    assert!(matches!(check_path(&long, PathRules::WINDOWS_LEGACY), Err(PathCheckError::PathTooLong { .. })));
+    assert!(matches!(check_path(&Path::new("/").join("a".repeat(256)), PathRules::UNIX), Err(PathCheckError::NameTooLong { .. })));
+}
+
+#[test]
+fn test_check_reserved_names() {
+    assert!(check_file_name("con.txt", PathRules::PORTABLE).is_err());
+    assert!(check_file_name("LPT1", PathRules::PORTABLE).is_err());
+    assert!(check_file_name("world?", PathRules::PORTABLE).is_err());
+    assert!(check_file_name("trailing.", PathRules::PORTABLE).is_err());
+    assert!(check_file_name("console", PathRules::PORTABLE).is_ok());
+    assert!(check_file_name("con.txt", PathRules::UNIX).is_ok());
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_join_within_never_escapes(
+        base in crate::testing::strategies::candidate_base_path(),
+        relative in crate::testing::strategies::candidate_relative_path()
+    ) {
+        if let Ok(joined) = join_within(&base, &relative) {
+            proptest::prop_assert!(normalize(&joined).starts_with(&base));
+            proptest::prop_assert_ne!(normalize(&joined), base);
+        }
+    }
+
+    #[test]
+    fn test_normalize_is_idempotent(path in crate::testing::strategies::candidate_relative_path()) {
+        proptest::prop_assert_eq!(normalize(&normalize(&path)), normalize(&path));
+    }
+}
@@ -0,0 +1,227 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+//! Deterministic stand-ins for the environment and filesystem, for tests in this crate and downstream
+
+use std::collections::BTreeMap;
+use std::env::VarError;
+use std::path::{Path, PathBuf};
+
+use crate::environment_variables::{EnvInt, EnvKey};
+use crate::voxels::DirectoryKind;
+use crate::voxels::voxels_xdg::xdg::cache::{CacheDirectory, CacheVerifier};
+use crate::voxels::voxels_xdg::xdg::config::{ConfigDirectory, ConfigVerifier};
+use crate::voxels::voxels_xdg::xdg::data::{DataDirectory, DataVerifier};
+use crate::voxels::voxels_xdg::xdg::runtime::{RuntimeDirectory, RuntimeVerifier};
+use crate::voxels::voxels_xdg::xdg::state::{StateDirectory, StateVerifier};
+use crate::voxels::voxels_xdg::xdg::verifier::VerificationError;
+
+// the generated mocks and their `expect_and_rig*` helpers, gathered so downstream tests need one import path
+pub use crate::clock::MockClockInt;
+pub use crate::environment_variables::MockEnvInt;
+pub use crate::filesystem::MockFsInt;
+pub use crate::voxels::voxels_xdg::xdg::cache::{MockCacheDirectoryResolver, MockCacheVerifier};
+pub use crate::voxels::voxels_xdg::xdg::config::{MockConfigDirectoryResolver, MockConfigVerifier};
+pub use crate::voxels::voxels_xdg::xdg::data::{MockDataDirectoryResolver, MockDataVerifier};
+pub use crate::voxels::voxels_xdg::xdg::runtime::{MockRuntimeDirectoryResolver, MockRuntimeVerifier};
+pub use crate::voxels::voxels_xdg::xdg::state::{MockStateDirectoryResolver, MockStateVerifier};
+
+/// An environment holding only the variables set on it
+#[derive(Debug, Clone, Default)]
+pub struct FixedEnvInt {
+    variables: BTreeMap<EnvKey, PathBuf>,
+}
+
+impl FixedEnvInt {
+    pub fn set(&mut self, key: EnvKey, value: PathBuf) {
+        self.variables.insert(key, value);
+    }
+}
+
+impl EnvInt for FixedEnvInt {
+    fn get_path(&self, key: EnvKey) -> Result<PathBuf, VarError> {
+        self.variables.get(&key).cloned().ok_or(VarError::NotPresent)
+    }
+}
+
+/// Accepts exactly the paths beneath `root` without looking at the filesystem
+#[derive(Debug, Clone)]
+pub struct FixedVerifier {
+    root: PathBuf,
+}
+
+impl FixedVerifier {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root
+        }
+    }
+
+    fn contains(&self, path: &Path) -> Result<(), VerificationError> {
+        if path.starts_with(&self.root) {
+            Ok(())
+        } else {
+            Err(VerificationError::NotFound)
+        }
+    }
+}
+
+impl ConfigVerifier for FixedVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        self.contains(path)
+    }
+}
+
+impl DataVerifier for FixedVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        self.contains(path)
+    }
+}
+
+impl StateVerifier for FixedVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        self.contains(path)
+    }
+}
+
+impl CacheVerifier for FixedVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        self.contains(path)
+    }
+}
+
+impl RuntimeVerifier for FixedVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        self.contains(path)
+    }
+}
+
+/// Base directory resolvers that only ever resolve to `<root>/<kind>`, see `fixed_directories`
+pub struct FixedDirectories {
+    pub root: PathBuf,
+    pub config: ConfigDirectory<FixedEnvInt, FixedVerifier>,
+    pub data: DataDirectory<FixedEnvInt, FixedVerifier>,
+    pub state: StateDirectory<FixedEnvInt, FixedVerifier>,
+    pub cache: CacheDirectory<FixedEnvInt, FixedVerifier>,
+    pub runtime: RuntimeDirectory<FixedEnvInt, FixedVerifier>,
+}
+
+/// The environment `fixed_directories` resolves from: `HOME`, the XDG system directories and
+/// every `VOXELS_*_HOME` point beneath `root`, nothing else is set
+pub fn fixed_environment(root: &Path) -> FixedEnvInt {
+    let mut env = FixedEnvInt::default();
+
+    env.set(EnvKey::Home, root.join("home"));
+    env.set(EnvKey::XdgConfigDirs, root.join("etc").join("xdg"));
+    env.set(EnvKey::XdgDataDirs, root.join("usr").join("share"));
+
+    for kind in DirectoryKind::ALL {
+        env.set(kind.home_key(), root.join(kind.name()));
+    }
+
+    env
+}
+
+/// Base directory resolvers rooted entirely under `root`, which never read the real environment,
+/// the filesystem or DBus, so resolution is the same on every machine. `root` should be absolute.
+pub fn fixed_directories(root: &Path) -> FixedDirectories {
+    let env = fixed_environment(root);
+    let verifier = FixedVerifier::new(root.to_path_buf());
+
+    FixedDirectories {
+        root: root.to_path_buf(),
+        config: ConfigDirectory::new(env.clone(), verifier.clone()),
+        data: DataDirectory::new(env.clone(), verifier.clone()),
+        state: StateDirectory::new(env.clone(), verifier.clone()),
+        cache: CacheDirectory::new(env.clone(), verifier.clone()),
+        runtime: RuntimeDirectory::new(env, verifier),
+    }
+}
+
+/// Proptest strategies for the names and paths this crate joins together
+pub mod strategies {
+    use std::path::PathBuf;
+
+    use proptest::prelude::*;
+
+    /// One element of a reverse-DNS name that `validate_rdn` accepts under `RdnStrictness::DBus`
+    pub fn rdn_element() -> impl Strategy<Value = String> {
+        "[a-zA-Z_-][a-zA-Z0-9_-]{0,15}"
+    }
+
+    /// A reverse-DNS application name of two to five elements, valid under `RdnStrictness::DBus`
+    pub fn rdn() -> impl Strategy<Value = String> {
+        prop::collection::vec(rdn_element(), 2..=5).prop_map(|elements| elements.join("."))
+    }
+
+    /// A path component as an attacker would write it, including `.`, `..`, empty and separator laden names
+    pub fn hostile_component() -> impl Strategy<Value = String> {
+        prop_oneof![
+            rdn_element(),
+            Just(String::from(".")),
+            Just(String::from("..")),
+            Just(String::new()),
+            "[a-z./]{1,8}",
+        ]
+    }
+
+    /// A relative path that may try to escape whatever it is joined onto, or be absolute
+    pub fn candidate_relative_path() -> impl Strategy<Value = PathBuf> {
+        (any::<bool>(), prop::collection::vec(hostile_component(), 0..6)).prop_map(|(absolute, components)| {
+            let joined = components.join("/");
+
+            if absolute {
+                PathBuf::from(format!("/{}", joined))
+            } else {
+                PathBuf::from(joined)
+            }
+        })
+    }
+
+    /// An absolute, already normalized base directory
+    pub fn candidate_base_path() -> impl Strategy<Value = PathBuf> {
+        prop::collection::vec(rdn_element(), 1..5).prop_map(|components| {
+            PathBuf::from("/").join(components.join("/"))
+        })
+    }
+}
+
+#[test]
+fn test_fixed_directories() {
+    use crate::voxels::voxels_xdg::xdg::config::ConfigDirectoryResolver;
+    use crate::voxels::voxels_xdg::xdg::runtime::RuntimeDirectoryResolver;
+
+    let directories = fixed_directories(Path::new("/golden"));
+
+    assert_eq!(directories.config.resolve().unwrap().0, PathBuf::from("/golden/config"));
+    assert_eq!(directories.runtime.resolve().unwrap().0, PathBuf::from("/golden/runtime"));
+}
+
+#[test]
+fn test_reexported_mocks() {
+    let mut env = MockEnvInt::new();
+    let mut verifier = MockConfigVerifier::new();
+
+    env.expect_and_rig(EnvKey::VoxelsConfigHome, PathBuf::from("/voxels/config"));
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirsFile);
+
+    verifier.expect_verify().returning(|_| Ok(()));
+
+    let config = ConfigDirectory::new(env, verifier);
+
+    assert_eq!(crate::voxels::voxels_xdg::xdg::config::ConfigDirectoryResolver::resolve(&config).unwrap().0, PathBuf::from("/voxels/config"));
+}
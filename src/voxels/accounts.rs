@@ -0,0 +1,202 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use lib_voxels_application::application::application::Application;
+
+use crate::filesystem::{create_directory, CreateOptions, FsInt};
+
+use super::VoxelsDirectoryError;
+
+use super::data::DataDirectoryResolver;
+use super::state::StateDirectoryResolver;
+
+pub const ACCOUNTS_DIRECTORY_NAME: &str = "accounts";
+
+/// Written to `<state>/voxels/<rdn>/` holding the id of the account used when none is chosen
+pub const DEFAULT_ACCOUNT_FILE_NAME: &str = "default-account";
+
+const MAX_ACCOUNT_ID_LENGTH: usize = 255;
+
+/// An account of an online voxels service, used as a directory name
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct AccountId {
+    id: String,
+}
+
+impl AccountId {
+    pub fn new(id: &str) -> Result<Self, VoxelsDirectoryError> {
+        if id.is_empty() || id.len() > MAX_ACCOUNT_ID_LENGTH || id.starts_with('.') {
+            return Err(VoxelsDirectoryError::InvalidName);
+        }
+
+        if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' || c == '@') {
+            return Err(VoxelsDirectoryError::InvalidName);
+        }
+
+        Ok(Self {
+            id: String::from(id)
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+}
+
+/// Ids of the account directories in `directory`, sorted
+fn list_accounts<FsIntT: FsInt>(fs: &FsIntT, directory: &Path) -> Result<Vec<AccountId>, VoxelsDirectoryError> {
+    if !fs.exists(directory) {
+        return Ok(Vec::new());
+    }
+
+    let mut accounts: Vec<AccountId> = fs.read_dir(directory)?
+        .into_iter()
+        .filter(|path| fs.is_directory(path))
+        .filter_map(|path| path.file_name().and_then(|name| name.to_str()).and_then(|name| AccountId::new(name).ok()))
+        .collect();
+
+    accounts.sort();
+
+    Ok(accounts)
+}
+
+#[mockall::automock]
+pub trait AccountsDirectoryResolver {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn account(&mut self, application: Application, account: AccountId) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn list(&mut self, application: Application) -> Result<Vec<AccountId>, VoxelsDirectoryError>;
+
+    async fn default_account(&mut self, application: Application) -> Result<Option<AccountId>, VoxelsDirectoryError>;
+
+    async fn set_default_account(&mut self, application: Application, account: AccountId) -> Result<(), VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+/// `<data>/voxels/<rdn>/accounts/`, one subdirectory per account, with the default account kept in state
+pub struct AccountsDirectory<DataT: DataDirectoryResolver, StateT: StateDirectoryResolver, FsIntT: FsInt> {
+    path: Option<PathBuf>,
+    pub create_options: CreateOptions,
+    data: DataT,
+    state: StateT,
+    fs: FsIntT,
+}
+
+impl<DataT: DataDirectoryResolver, StateT: StateDirectoryResolver, FsIntT: FsInt> AccountsDirectory<DataT, StateT, FsIntT> {
+    pub fn new(data: DataT, state: StateT, fs: FsIntT) -> Self {
+        Self {
+            path: None,
+            create_options: CreateOptions::default(),
+            data,
+            state,
+            fs
+        }
+    }
+}
+
+impl<DataT: DataDirectoryResolver, StateT: StateDirectoryResolver, FsIntT: FsInt> AccountsDirectoryResolver for AccountsDirectory<DataT, StateT, FsIntT> {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        if self.is_resolved() {
+            return Ok(self.path.clone().unwrap());
+        }
+
+        let data = self.data.resolve(application).await?;
+
+        let path = data.join(ACCOUNTS_DIRECTORY_NAME);
+
+        self.path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    /// `<data>/voxels/<rdn>/accounts/<account>/`, created if missing
+    async fn account(&mut self, application: Application, account: AccountId) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        let account_path = resolved.join(account.as_str());
+
+        create_directory(&self.fs, &account_path, self.create_options)?;
+
+        Ok(account_path)
+    }
+
+    async fn list(&mut self, application: Application) -> Result<Vec<AccountId>, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        list_accounts(&self.fs, &resolved)
+    }
+
+    async fn default_account(&mut self, application: Application) -> Result<Option<AccountId>, VoxelsDirectoryError> {
+        let state = self.state.resolve(application).await?;
+
+        let default_path = state.join(DEFAULT_ACCOUNT_FILE_NAME);
+
+        if !self.fs.exists(&default_path) {
+            return Ok(None);
+        }
+
+        let contents = self.fs.read_to_string(&default_path)?;
+
+        Ok(AccountId::new(contents.trim()).ok())
+    }
+
+    async fn set_default_account(&mut self, application: Application, account: AccountId) -> Result<(), VoxelsDirectoryError> {
+        let state = self.state.resolve_and_create(application).await?;
+
+        self.fs.write(&state.join(DEFAULT_ACCOUNT_FILE_NAME), &format!("{}\n", account.as_str()))?;
+
+        Ok(())
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+#[test]
+fn test_account_id() {
+    assert!(AccountId::new("player@example.org").is_ok());
+    assert!(AccountId::new("..").is_err());
+    assert!(AccountId::new("a/b").is_err());
+    assert!(AccountId::new("").is_err());
+}
+
+#[test]
+fn test_list_accounts() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let directory = PathBuf::from("/accounts");
+
+    fs.expect_exists()
+        .returning(|_| true);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_read_dir()
+        .once()
+        .return_once({
+            let entries = vec![directory.join("zed"), directory.join(".hidden"), directory.join("alice")];
+            move |_| Ok(entries)
+        });
+
+    assert_eq!(list_accounts(&fs, &directory).unwrap(), vec![AccountId::new("alice").unwrap(), AccountId::new("zed").unwrap()]);
+}
@@ -0,0 +1,1120 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lib_voxels_application::application::application::Application;
+use tokio_util::sync::CancellationToken;
+
+use crate::environment_variables::{DefaultEnvInt, EnvInt, EnvKey};
+use crate::filesystem::{create_directory, move_path, CreateOptions, DefaultFsInt, FsInt};
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::audit::{audit, AuditOperation, SharedAuditLog};
+use super::logs::LOGS_DIRECTORY_NAME;
+use super::mods::MODS_DIRECTORY_NAME;
+use super::overrides::DirectoryOverrides;
+use super::rdn::{RdnCase, RdnLayout};
+
+use super::cache::{CacheDirectory, CacheDirectoryResolver};
+use super::config::{ConfigDirectory, ConfigDirectoryResolver};
+use super::data::{DataDirectory, DataDirectoryResolver};
+use super::runtime::{RuntimeDirectory, RuntimeDirectoryResolver};
+use super::state::{StateDirectory, StateDirectoryResolver};
+
+use super::voxels_xdg::{cache, config, data, runtime, state};
+#[cfg(feature = "dbus")]
+use super::voxels_xdg::dbus::ConnectionLossHandler;
+
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
+
+use super::voxels_xdg::xdg::trash::Removal;
+
+/// Implemented by the per-application resolvers so they can be handled without knowing their kind
+pub trait KindResolver {
+    fn kind(&self) -> DirectoryKind;
+
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+}
+
+impl<BaseT: config::ConfigDirectoryResolver> KindResolver for ConfigDirectory<BaseT> {
+    fn kind(&self) -> DirectoryKind {
+        DirectoryKind::Config
+    }
+
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        ConfigDirectoryResolver::resolve(self, application).await
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        ConfigDirectoryResolver::resolve_and_create(self, application).await
+    }
+}
+
+impl<BaseT: data::DataDirectoryResolver> KindResolver for DataDirectory<BaseT> {
+    fn kind(&self) -> DirectoryKind {
+        DirectoryKind::Data
+    }
+
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        DataDirectoryResolver::resolve(self, application).await
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        DataDirectoryResolver::resolve_and_create(self, application).await
+    }
+}
+
+impl<BaseT: state::StateDirectoryResolver> KindResolver for StateDirectory<BaseT> {
+    fn kind(&self) -> DirectoryKind {
+        DirectoryKind::State
+    }
+
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        StateDirectoryResolver::resolve(self, application).await
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        StateDirectoryResolver::resolve_and_create(self, application).await
+    }
+}
+
+impl<BaseT: cache::CacheDirectoryResolver, FsIntT: FsInt> KindResolver for CacheDirectory<BaseT, FsIntT> {
+    fn kind(&self) -> DirectoryKind {
+        DirectoryKind::Cache
+    }
+
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        CacheDirectoryResolver::resolve(self, application).await
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        CacheDirectoryResolver::resolve_and_create(self, application).await
+    }
+}
+
+impl<BaseT: runtime::RuntimeDirectoryResolver> KindResolver for RuntimeDirectory<BaseT> {
+    fn kind(&self) -> DirectoryKind {
+        DirectoryKind::Runtime
+    }
+
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        RuntimeDirectoryResolver::resolve(self, application).await
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        RuntimeDirectoryResolver::resolve_and_create(self, application).await
+    }
+}
+
+/// A per-application resolver that already knows which application it resolves for
+pub struct BoundDirectory<ResolverT: KindResolver> {
+    pub(crate) application: Application,
+    pub resolver: ResolverT,
+    pub(crate) audit: Option<SharedAuditLog>,
+}
+
+impl<ResolverT: KindResolver> BoundDirectory<ResolverT> {
+    pub fn kind(&self) -> DirectoryKind {
+        self.resolver.kind()
+    }
+
+    pub async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        self.resolver.resolve(self.application.clone()).await
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when it is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub async fn resolve_utf8(&mut self) -> Result<camino::Utf8PathBuf, VoxelsDirectoryError> {
+        camino::Utf8PathBuf::from_path_buf(self.resolve().await?).map_err(|_| VoxelsDirectoryError::NotUtf8)
+    }
+
+    pub async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        let created = self.resolver.resolve_and_create(self.application.clone()).await?;
+
+        audit(self.audit.as_ref(), AuditOperation::Create, &self.application.rdn().as_path().to_string_lossy(), &created, None);
+
+        Ok(created)
+    }
+}
+
+/// The base resolvers `ApplicationDirectory::purge` builds the application's resolvers from
+pub struct BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT> {
+    pub config: ConfigT,
+    pub data: DataT,
+    pub state: StateT,
+    pub cache: CacheT,
+    pub runtime: RuntimeT,
+}
+
+impl<ConfigT, DataT, StateT, CacheT, RuntimeT> BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+{
+    /// The voxels root of every kind, `<base>/voxels` with the default layout, beneath which each application has a directory.
+    /// Kinds without a candidate on this system are absent.
+    pub async fn resolve_roots(mut self) -> Result<BTreeMap<DirectoryKind, PathBuf>, VoxelsDirectoryError> {
+        let resolved = [
+            (DirectoryKind::Config, config::ConfigDirectoryResolver::resolve(&mut self.config).await),
+            (DirectoryKind::Data, data::DataDirectoryResolver::resolve(&mut self.data).await),
+            (DirectoryKind::State, state::StateDirectoryResolver::resolve(&mut self.state).await),
+            (DirectoryKind::Cache, cache::CacheDirectoryResolver::resolve(&mut self.cache).await),
+            (DirectoryKind::Runtime, runtime::RuntimeDirectoryResolver::resolve(&mut self.runtime).await),
+        ];
+
+        let mut roots = BTreeMap::new();
+
+        for (kind, root) in resolved {
+            match root {
+                Ok(root) => {
+                    roots.insert(kind, root);
+                },
+                Err(VoxelsDirectoryError::NoCandidate) => {},
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(roots)
+    }
+
+    /// Every environment variable resolving any kind may read, for sandbox wrappers to pass through
+    pub fn consumed_env_vars(&self) -> BTreeSet<EnvKey> {
+        let mut consumed = config::ConfigDirectoryResolver::consumed_env_vars(&self.config);
+
+        consumed.extend(data::DataDirectoryResolver::consumed_env_vars(&self.data));
+        consumed.extend(state::StateDirectoryResolver::consumed_env_vars(&self.state));
+        consumed.extend(cache::CacheDirectoryResolver::consumed_env_vars(&self.cache));
+        consumed.extend(runtime::RuntimeDirectoryResolver::consumed_env_vars(&self.runtime));
+
+        consumed
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct PurgeOptions {
+    /// Leave the config directory in place so a reinstall picks up the user's settings
+    pub keep_config: bool,
+    /// Report what would be removed without touching the filesystem
+    pub dry_run: bool,
+}
+
+/// Refuse to purge `path`, the directory of `kind`, unless it is the application's own: derived from its RDN
+/// within the kind's voxels root, or a relocation that is `trusted` because `relocate` made it or a dirs file
+/// or the audit log records it. The filesystem root, `$HOME` and the base directories are never purged.
+fn check_purgeable(overrides: &DirectoryOverrides, kind: DirectoryKind, path: &Path, trusted: bool) -> Result<(), VoxelsDirectoryError> {
+    if overrides.is_protected(path) {
+        tracing::warn!("Refusing to purge {}, it is or contains a base directory", path.display());
+
+        return Err(VoxelsDirectoryError::InvalidRelocation);
+    }
+
+    if overrides.get(kind).is_some() && !trusted {
+        tracing::warn!("Refusing to purge {}, the {} relocation was not made by relocate", path.display(), kind.name());
+
+        return Err(VoxelsDirectoryError::InvalidRelocation);
+    }
+
+    Ok(())
+}
+
+/// The directory `bound` resolves to, if it exists and `options` do not keep it
+async fn purge_candidate<ResolverT, FsIntT>(bound: &mut BoundDirectory<ResolverT>, fs: &FsIntT, options: PurgeOptions) -> Result<Option<(DirectoryKind, PathBuf)>, VoxelsDirectoryError>
+where
+    ResolverT: KindResolver,
+    FsIntT: FsInt,
+{
+    if options.keep_config && bound.kind() == DirectoryKind::Config {
+        return Ok(None);
+    }
+
+    let resolved = match bound.resolve().await {
+        Ok(resolved) => resolved,
+        // a kind that cannot be resolved on this system cannot have been written to
+        Err(VoxelsDirectoryError::NoCandidate) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    if !fs.exists(&resolved) {
+        return Ok(None);
+    }
+
+    Ok(Some((bound.kind(), resolved)))
+}
+
+/// Remove `directories` as `removal` says once `check` allows every one of them, so a single refusal removes nothing.
+/// Each removal is recorded in `log` as a purge of `rdn`.
+fn purge_directories<FsIntT, CheckT>(fs: &FsIntT, directories: &[(DirectoryKind, PathBuf)], check: &CheckT, removal: Removal<'_>, dry_run: bool, rdn: &str, log: Option<&SharedAuditLog>) -> Result<Vec<PathBuf>, VoxelsDirectoryError>
+where
+    FsIntT: FsInt,
+    CheckT: Fn(DirectoryKind, &Path) -> Result<(), VoxelsDirectoryError>,
+{
+    for (kind, path) in directories {
+        check(*kind, path)?;
+    }
+
+    let purged: Vec<PathBuf> = directories.iter().map(|(_, path)| path.clone()).collect();
+
+    if !dry_run {
+        for path in &purged {
+            removal.remove(fs, path)?;
+
+            audit(log, AuditOperation::Purge, rdn, path, None);
+        }
+    }
+
+    Ok(purged)
+}
+
+/// Pin the `kind` directory of `rdn` to `path` in the dirs file at `dirs_file_path`, creating it when missing
+fn pin_relocation<FsIntT: FsInt>(fs: &FsIntT, dirs_file_path: &Path, rdn: &str, kind: DirectoryKind, path: &Path) -> Result<(), VoxelsDirectoryError> {
+    let mut dirs_file = if fs.exists(dirs_file_path) {
+        DirsFile::parse(&fs.read_to_string(dirs_file_path)?)
+    } else {
+        DirsFile::default()
+    };
+
+    dirs_file.set_for_application(rdn, kind, path.to_path_buf())?;
+
+    fs.write(dirs_file_path, &dirs_file.render())?;
+
+    Ok(())
+}
+
+/// How `ApplicationDirectory::relocate` records and leaves behind a moved directory
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RelocateOptions {
+    /// Dirs file to pin the new location in, usually the one named by `VOXELS_DIRS_FILE`.
+    /// It is created when missing.
+    pub dirs_file: Option<PathBuf>,
+    /// Leave a symlink to the new location where the directory used to be
+    pub leave_symlink: bool,
+}
+
+/// What `ApplicationDirectory::ensure_layout` found, in the order the directories were visited
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct LayoutSummary {
+    pub created: Vec<PathBuf>,
+    pub existing: Vec<PathBuf>,
+    /// Where each kind resolved to, kinds without a candidate are absent
+    pub directories: BTreeMap<DirectoryKind, PathBuf>,
+    /// None of the application's directories existed beforehand
+    pub first_run: bool,
+}
+
+impl LayoutSummary {
+    fn ensure<FsIntT: FsInt>(&mut self, fs: &FsIntT, path: PathBuf) -> Result<PathBuf, VoxelsDirectoryError> {
+        if fs.is_directory(&path) {
+            self.existing.push(path.clone());
+        } else {
+            create_directory(fs, &path, CreateOptions::default())?;
+            self.created.push(path.clone());
+        }
+
+        Ok(path)
+    }
+}
+
+/// What `ApplicationDirectory::resolve_all_until` found before it was cancelled
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ResolvedDirectories {
+    pub resolved: BTreeMap<DirectoryKind, PathBuf>,
+    pub failed: BTreeMap<DirectoryKind, VoxelsDirectoryError>,
+    /// Kinds still resolving when the token was cancelled or the timeout passed
+    pub timed_out: Vec<DirectoryKind>,
+}
+
+impl ResolvedDirectories {
+    /// Record the outcome for `kind`, `None` when it did not finish in time
+    fn record(&mut self, kind: DirectoryKind, outcome: Option<Result<PathBuf, VoxelsDirectoryError>>) {
+        match outcome {
+            Some(Ok(path)) => {
+                self.resolved.insert(kind, path);
+            },
+            Some(Err(err)) => {
+                self.failed.insert(kind, err);
+            },
+            None => self.timed_out.push(kind),
+        }
+    }
+}
+
+/// Resolve `bound` unless `cancel` fires first
+async fn resolve_until<ResolverT: KindResolver>(bound: &mut BoundDirectory<ResolverT>, cancel: &CancellationToken) -> (DirectoryKind, Option<Result<PathBuf, VoxelsDirectoryError>>) {
+    let kind = bound.kind();
+
+    tokio::select! {
+        outcome = bound.resolve() => (kind, Some(outcome)),
+        _ = cancel.cancelled() => (kind, None),
+    }
+}
+
+/// Resolve `bound` and record it in `summary`, returning `None` when the kind has no candidate on this system
+async fn ensure_directory<ResolverT: KindResolver, FsIntT: FsInt>(bound: &mut BoundDirectory<ResolverT>, fs: &FsIntT, summary: &mut LayoutSummary) -> Result<Option<PathBuf>, VoxelsDirectoryError> {
+    let resolved = match bound.resolve().await {
+        Ok(resolved) => resolved,
+        Err(VoxelsDirectoryError::NoCandidate) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    summary.directories.insert(bound.kind(), resolved.clone());
+
+    Ok(Some(summary.ensure(fs, resolved)?))
+}
+
+/// Resolve `bound`, returning `None` when the kind has no candidate on this system
+pub(crate) async fn resolve_if_candidate<ResolverT: KindResolver>(bound: &mut BoundDirectory<ResolverT>) -> Result<Option<PathBuf>, VoxelsDirectoryError> {
+    match bound.resolve().await {
+        Ok(resolved) => Ok(Some(resolved)),
+        Err(VoxelsDirectoryError::NoCandidate) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether the directory `bound` resolves to exists, a kind without a candidate does not
+async fn directory_exists<ResolverT: KindResolver, FsIntT: FsInt>(bound: &mut BoundDirectory<ResolverT>, fs: &FsIntT) -> Result<bool, VoxelsDirectoryError> {
+    Ok(resolve_if_candidate(bound).await?.is_some_and(|resolved| fs.exists(&resolved)))
+}
+
+/// Point the symlink `link` at `target`, replacing it when it points elsewhere and removing it when `target` is `None`.
+/// Returns whether anything changed. A file or directory that is not a symlink is never replaced.
+fn refresh_link<FsIntT: FsInt>(fs: &FsIntT, link: &Path, target: Option<&Path>) -> Result<bool, VoxelsDirectoryError> {
+    if fs.is_symlink(link) {
+        if target.is_some_and(|target| fs.read_link(link).is_ok_and(|current| current == target)) {
+            return Ok(false);
+        }
+
+        fs.remove_file(link)?;
+    } else if fs.exists(link) {
+        return Err(VoxelsDirectoryError::Io(std::io::ErrorKind::AlreadyExists));
+    }
+
+    if let Some(target) = target {
+        fs.symlink(target, link)?;
+    }
+
+    Ok(true)
+}
+
+/// Move `from` to `to`, falling back to copy, compare and remove when they are on different filesystems
+fn move_directory<FsIntT: FsInt>(fs: &FsIntT, from: &Path, to: &Path) -> Result<(), VoxelsDirectoryError> {
+    if fs.exists(to) && !fs.read_dir(to)?.is_empty() {
+        return Err(VoxelsDirectoryError::Io(std::io::ErrorKind::AlreadyExists));
+    }
+
+    if let Some(parent) = to.parent() {
+        create_directory(fs, parent, CreateOptions::default())?;
+    }
+
+    Ok(move_path(fs, from, to)?)
+}
+
+/// Run by `ensure_layout` after the directories of an application are created for the first time
+pub type FirstRunHook = Box<dyn Fn(&LayoutSummary) -> Result<(), VoxelsDirectoryError>>;
+
+/// Hands out the directories of one application, applying its manifest relocations and the RDN case policy
+pub struct ApplicationDirectory {
+    application: Application,
+    pub overrides: DirectoryOverrides,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+    first_run_hooks: Vec<FirstRunHook>,
+    #[cfg(feature = "dbus")]
+    connection_loss: Option<ConnectionLossHandler>,
+    #[cfg(feature = "dbus")]
+    offline: bool,
+    audit: Option<SharedAuditLog>,
+    /// Kinds whose relocation was pinned from a dirs file or made by `relocate`, rather than asked for by the manifest
+    trusted_relocations: BTreeSet<DirectoryKind>,
+    /// Read from `VOXELS_DIRS_FILE` when this was built and kept up to date by `relocate`, handed to every resolver
+    dirs_file: Option<DirsFile>,
+}
+
+impl ApplicationDirectory {
+    pub fn new(application: Application) -> Self {
+        Self::with_env(application, &DefaultEnvInt, &DefaultFsInt)
+    }
+
+    /// As `new`, reading the dirs file `env` names through `fs`
+    pub fn with_env<EnvIntT: EnvInt, FsIntT: FsInt>(application: Application, env: &EnvIntT, fs: &FsIntT) -> Self {
+        Self {
+            application,
+            overrides: DirectoryOverrides::new(),
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+            first_run_hooks: Vec::new(),
+            #[cfg(feature = "dbus")]
+            connection_loss: None,
+            #[cfg(feature = "dbus")]
+            offline: false,
+            audit: None,
+            trusted_relocations: BTreeSet::new(),
+            dirs_file: DirsFile::load(env, fs),
+        }
+    }
+
+    pub fn application(&self) -> &Application {
+        &self.application
+    }
+
+    /// Apply the entries a `VOXELS_DIRS_FILE` pins for this application, replacing manifest relocations
+    pub fn pin_from(&mut self, dirs_file: &DirsFile) -> Result<(), VoxelsDirectoryError> {
+        let rdn = self.rdn();
+
+        for kind in DirectoryKind::ALL {
+            if let Some(path) = dirs_file.get_for_application(&rdn, kind) {
+                self.overrides.set(kind, path.to_path_buf())?;
+                self.trusted_relocations.insert(kind);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Register `hook` to run, in registration order, when `ensure_layout` creates the application's directories
+    /// for the first time. Use it to install default config or seed data.
+    pub fn on_first_run<HookT: Fn(&LayoutSummary) -> Result<(), VoxelsDirectoryError> + 'static>(&mut self, hook: HookT) {
+        self.first_run_hooks.push(Box::new(hook));
+    }
+
+    fn relocation(&self, kind: DirectoryKind) -> Option<PathBuf> {
+        self.overrides.get(kind).map(PathBuf::from)
+    }
+
+    /// Called when the bus drops a connection any of this application's D-Bus backed resolvers opened.
+    /// Applies to base resolvers handed to this afterwards.
+    #[cfg(feature = "dbus")]
+    pub fn set_connection_loss_handler<HandlerT: Fn(dbus_tokio::connection::IOResourceError) + Send + Sync + 'static>(&mut self, handler: HandlerT) {
+        self.connection_loss = Some(std::sync::Arc::new(handler));
+    }
+
+    /// Disable IPC resolution, D-Bus today, for base resolvers handed to this afterwards whatever their priorities.
+    /// For tests, air-gapped machines and command line tools that cannot wait on a bus.
+    #[cfg(feature = "dbus")]
+    pub fn offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Record `resolve_and_create`, `purge`, `relocate` and grants of this application's directories in `log`
+    pub fn set_audit_log(&mut self, log: SharedAuditLog) {
+        self.audit = Some(log);
+    }
+
+    fn rdn(&self) -> String {
+        self.application.rdn().as_path().to_string_lossy().into_owned()
+    }
+
+    fn bind<ResolverT: KindResolver>(&self, resolver: ResolverT) -> BoundDirectory<ResolverT> {
+        BoundDirectory {
+            application: self.application.clone(),
+            resolver,
+            audit: self.audit.clone(),
+        }
+    }
+
+    pub fn config<BaseT: config::ConfigDirectoryResolver>(&self, base: BaseT) -> BoundDirectory<ConfigDirectory<BaseT>> {
+        #[cfg(feature = "dbus")]
+        let base = {
+            let mut base = base;
+
+            if let Some(handler) = &self.connection_loss {
+                base.set_connection_loss_handler(handler.clone());
+            }
+
+            base.set_offline(self.offline);
+            base
+        };
+
+        let mut resolver = ConfigDirectory::new(base);
+        resolver.rdn_case = self.rdn_case;
+        resolver.rdn_layout = self.rdn_layout;
+        resolver.relocation = self.relocation(DirectoryKind::Config);
+        resolver.dirs_file = self.dirs_file.clone();
+
+        self.bind(resolver)
+    }
+
+    pub fn data<BaseT: data::DataDirectoryResolver>(&self, base: BaseT) -> BoundDirectory<DataDirectory<BaseT>> {
+        #[cfg(feature = "dbus")]
+        let base = {
+            let mut base = base;
+
+            if let Some(handler) = &self.connection_loss {
+                base.set_connection_loss_handler(handler.clone());
+            }
+
+            base.set_offline(self.offline);
+            base
+        };
+
+        let mut resolver = DataDirectory::new(base);
+        resolver.rdn_case = self.rdn_case;
+        resolver.rdn_layout = self.rdn_layout;
+        resolver.relocation = self.relocation(DirectoryKind::Data);
+        resolver.dirs_file = self.dirs_file.clone();
+
+        self.bind(resolver)
+    }
+
+    pub fn state<BaseT: state::StateDirectoryResolver>(&self, base: BaseT) -> BoundDirectory<StateDirectory<BaseT>> {
+        #[cfg(feature = "dbus")]
+        let base = {
+            let mut base = base;
+
+            if let Some(handler) = &self.connection_loss {
+                base.set_connection_loss_handler(handler.clone());
+            }
+
+            base.set_offline(self.offline);
+            base
+        };
+
+        let mut resolver = StateDirectory::new(base);
+        resolver.rdn_case = self.rdn_case;
+        resolver.rdn_layout = self.rdn_layout;
+        resolver.relocation = self.relocation(DirectoryKind::State);
+        resolver.dirs_file = self.dirs_file.clone();
+
+        self.bind(resolver)
+    }
+
+    pub fn cache<BaseT: cache::CacheDirectoryResolver, FsIntT: FsInt>(&self, base: BaseT, fs: FsIntT) -> BoundDirectory<CacheDirectory<BaseT, FsIntT>> {
+        #[cfg(feature = "dbus")]
+        let base = {
+            let mut base = base;
+
+            if let Some(handler) = &self.connection_loss {
+                base.set_connection_loss_handler(handler.clone());
+            }
+
+            base.set_offline(self.offline);
+            base
+        };
+
+        let mut resolver = CacheDirectory::new(base, fs);
+        resolver.rdn_case = self.rdn_case;
+        resolver.rdn_layout = self.rdn_layout;
+        resolver.relocation = self.relocation(DirectoryKind::Cache);
+        resolver.dirs_file = self.dirs_file.clone();
+
+        self.bind(resolver)
+    }
+
+    pub fn runtime<BaseT: runtime::RuntimeDirectoryResolver>(&self, base: BaseT) -> BoundDirectory<RuntimeDirectory<BaseT>> {
+        #[cfg(feature = "dbus")]
+        let base = {
+            let mut base = base;
+
+            if let Some(handler) = &self.connection_loss {
+                base.set_connection_loss_handler(handler.clone());
+            }
+
+            base.set_offline(self.offline);
+            base
+        };
+
+        let mut resolver = RuntimeDirectory::new(base);
+        resolver.rdn_case = self.rdn_case;
+        resolver.rdn_layout = self.rdn_layout;
+        resolver.relocation = self.relocation(DirectoryKind::Runtime);
+        resolver.dirs_file = self.dirs_file.clone();
+
+        self.bind(resolver)
+    }
+
+    /// Remove every directory the application may have written to as `removal` says, `Removal::Trash` unless the
+    /// user asked for them to be deleted outright, returning their original paths.
+    /// With `dry_run` the returned paths are those that would have been removed.
+    pub async fn purge<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT, removal: Removal<'_>, options: PurgeOptions) -> Result<Vec<PathBuf>, VoxelsDirectoryError>
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt + Clone,
+    {
+        let rdn = self.rdn();
+
+        let check = |kind: DirectoryKind, path: &Path| -> Result<(), VoxelsDirectoryError> {
+            let trusted = self.trusted_relocations.contains(&kind)
+                || self.dirs_file.as_ref().is_some_and(|dirs_file| dirs_file.get_for_application(&rdn, kind) == Some(path))
+                || self.audit.as_ref().is_some_and(|log| log.recorded_relocation(&rdn, path));
+
+            check_purgeable(&self.overrides, kind, path, trusted)
+        };
+
+        let candidates = vec![
+            purge_candidate(&mut self.config(bases.config), &fs, options).await?,
+            purge_candidate(&mut self.data(bases.data), &fs, options).await?,
+            purge_candidate(&mut self.state(bases.state), &fs, options).await?,
+            purge_candidate(&mut self.cache(bases.cache, fs.clone()), &fs, options).await?,
+            purge_candidate(&mut self.runtime(bases.runtime), &fs, options).await?,
+        ];
+
+        let directories: Vec<(DirectoryKind, PathBuf)> = candidates.into_iter().flatten().collect();
+
+        purge_directories(&fs, &directories, &check, removal, options.dry_run, &rdn, self.audit.as_ref())
+    }
+
+    /// Create every directory of the application in one go: config, data, state, cache, runtime, logs and mods.
+    /// Kinds without a candidate on this system, usually runtime, are skipped.
+    pub async fn ensure_layout<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT) -> Result<LayoutSummary, VoxelsDirectoryError>
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt + Clone,
+    {
+        let mut summary = LayoutSummary::default();
+
+        ensure_directory(&mut self.config(bases.config), &fs, &mut summary).await?;
+
+        let data = ensure_directory(&mut self.data(bases.data), &fs, &mut summary).await?;
+
+        let state = ensure_directory(&mut self.state(bases.state), &fs, &mut summary).await?;
+
+        ensure_directory(&mut self.cache(bases.cache, fs.clone()), &fs, &mut summary).await?;
+
+        ensure_directory(&mut self.runtime(bases.runtime), &fs, &mut summary).await?;
+
+        if let Some(state) = state {
+            summary.ensure(&fs, state.join(LOGS_DIRECTORY_NAME))?;
+        }
+
+        if let Some(data) = data {
+            summary.ensure(&fs, data.join(MODS_DIRECTORY_NAME))?;
+        }
+
+        summary.first_run = summary.existing.is_empty();
+
+        if summary.first_run {
+            for hook in &self.first_run_hooks {
+                hook(&summary)?;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// True when none of the application's directories exist yet
+    pub async fn is_first_run<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT) -> Result<bool, VoxelsDirectoryError>
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt + Clone,
+    {
+        let exists = [
+            directory_exists(&mut self.config(bases.config), &fs).await?,
+            directory_exists(&mut self.data(bases.data), &fs).await?,
+            directory_exists(&mut self.state(bases.state), &fs).await?,
+            directory_exists(&mut self.cache(bases.cache, fs.clone()), &fs).await?,
+            directory_exists(&mut self.runtime(bases.runtime), &fs).await?,
+        ];
+
+        Ok(!exists.contains(&true))
+    }
+
+    /// Resolve every kind concurrently, giving up on those still resolving once `cancel` fires.
+    /// Only awaiting steps such as D-Bus calls can be abandoned, a blocking filesystem lookup still runs to completion.
+    pub async fn resolve_all_until<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT, cancel: &CancellationToken) -> ResolvedDirectories
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt,
+    {
+        let mut config = self.config(bases.config);
+        let mut data = self.data(bases.data);
+        let mut state = self.state(bases.state);
+        let mut cache = self.cache(bases.cache, fs);
+        let mut runtime = self.runtime(bases.runtime);
+
+        let outcomes = tokio::join!(
+            resolve_until(&mut config, cancel),
+            resolve_until(&mut data, cancel),
+            resolve_until(&mut state, cancel),
+            resolve_until(&mut cache, cancel),
+            resolve_until(&mut runtime, cancel),
+        );
+
+        let mut resolved = ResolvedDirectories::default();
+
+        for (kind, outcome) in [outcomes.0, outcomes.1, outcomes.2, outcomes.3, outcomes.4] {
+            resolved.record(kind, outcome);
+        }
+
+        resolved
+    }
+
+    /// `resolve_all_until` with a token cancelled after `timeout`, so a hung bus cannot block startup
+    pub async fn resolve_all_with_timeout<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT, timeout: Duration) -> ResolvedDirectories
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt,
+    {
+        let cancel = CancellationToken::new();
+
+        let deadline = tokio::spawn({
+            let cancel = cancel.clone();
+
+            async move {
+                tokio::time::sleep(timeout).await;
+                cancel.cancel();
+            }
+        });
+
+        let resolved = self.resolve_all_until(bases, fs, &cancel).await;
+
+        deadline.abort();
+
+        resolved
+    }
+
+    /// Make `target` a directory of symlinks named after each kind, `config -> <resolved config>` and so on,
+    /// so all of the application's directories can be found in one place. Calling it again repoints links
+    /// whose resolution changed and removes those of kinds that no longer resolve. Returns where each link points.
+    pub async fn link_farm<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT, target: &Path) -> Result<BTreeMap<DirectoryKind, PathBuf>, VoxelsDirectoryError>
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt + Clone,
+    {
+        create_directory(&fs, target, CreateOptions::default())?;
+
+        let resolved = [
+            (DirectoryKind::Config, resolve_if_candidate(&mut self.config(bases.config)).await?),
+            (DirectoryKind::Data, resolve_if_candidate(&mut self.data(bases.data)).await?),
+            (DirectoryKind::State, resolve_if_candidate(&mut self.state(bases.state)).await?),
+            (DirectoryKind::Cache, resolve_if_candidate(&mut self.cache(bases.cache, fs.clone())).await?),
+            (DirectoryKind::Runtime, resolve_if_candidate(&mut self.runtime(bases.runtime)).await?),
+        ];
+
+        let mut links = BTreeMap::new();
+
+        for (kind, path) in resolved {
+            refresh_link(&fs, &target.join(kind.name()), path.as_deref())?;
+
+            if let Some(path) = path {
+                links.insert(kind, path);
+            }
+        }
+
+        Ok(links)
+    }
+
+    /// Move the `kind` directory of the application to `new_path` and relocate it there from now on.
+    /// A move across filesystems is copied, compared with the original and only then is the original removed.
+    /// `new_path` must be absolute and either missing or an empty directory.
+    pub async fn relocate<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(&mut self, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: FsIntT, kind: DirectoryKind, new_path: &Path, options: RelocateOptions) -> Result<PathBuf, VoxelsDirectoryError>
+    where
+        ConfigT: config::ConfigDirectoryResolver,
+        DataT: data::DataDirectoryResolver,
+        StateT: state::StateDirectoryResolver,
+        CacheT: cache::CacheDirectoryResolver,
+        RuntimeT: runtime::RuntimeDirectoryResolver,
+        FsIntT: FsInt + Clone,
+    {
+        if !new_path.is_absolute() || self.overrides.is_protected(new_path) {
+            return Err(VoxelsDirectoryError::InvalidRelocation);
+        }
+
+        let current = match kind {
+            DirectoryKind::Config => resolve_if_candidate(&mut self.config(bases.config)).await?,
+            DirectoryKind::Data => resolve_if_candidate(&mut self.data(bases.data)).await?,
+            DirectoryKind::State => resolve_if_candidate(&mut self.state(bases.state)).await?,
+            DirectoryKind::Cache => resolve_if_candidate(&mut self.cache(bases.cache, fs.clone())).await?,
+            DirectoryKind::Runtime => resolve_if_candidate(&mut self.runtime(bases.runtime)).await?,
+        };
+
+        let moved = match current {
+            Some(current) if current != new_path && fs.exists(&current) => {
+                move_directory(&fs, &current, new_path)?;
+                Some(current)
+            },
+            _ => None,
+        };
+
+        let rdn = self.rdn();
+
+        if let Some(dirs_file_path) = &options.dirs_file {
+            pin_relocation(&fs, dirs_file_path, &rdn, kind, new_path)?;
+        }
+
+        // the resolvers prefer a pinned path, so the one this was built with must not keep the old location
+        if let Some(dirs_file) = &mut self.dirs_file {
+            dirs_file.set_for_application(&rdn, kind, new_path.to_path_buf())?;
+        }
+
+        self.overrides.set(kind, new_path.to_path_buf())?;
+        self.trusted_relocations.insert(kind);
+
+        if let Some(old) = &moved
+            && options.leave_symlink {
+            fs.symlink(new_path, old)?;
+        }
+
+        audit(self.audit.as_ref(), AuditOperation::Relocate, &rdn, new_path, moved.as_deref());
+
+        Ok(new_path.to_path_buf())
+    }
+}
+
+#[test]
+fn test_layout_summary_records_created_and_existing() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let existing = PathBuf::from("/home/user/.config/voxels/org.voxels.Client");
+    let created = PathBuf::from("/home/user/.local/state/voxels/org.voxels.Client/logs");
+
+    let mut seen = false;
+
+    fs.expect_is_directory()
+        .returning({
+            let existing = existing.clone();
+            let created = created.clone();
+            move |path| {
+                if path == created {
+                    // missing the first time it is checked, present once created
+                    let present = seen;
+                    seen = true;
+                    present
+                } else {
+                    path == existing
+                }
+            }
+        });
+
+    fs.expect_create_dir_all()
+        .once()
+        .with(mockall::predicate::eq(created.clone()))
+        .return_once(|_| Ok(()));
+
+    let mut summary = LayoutSummary::default();
+
+    summary.ensure(&fs, existing.clone()).unwrap();
+    summary.ensure(&fs, created.clone()).unwrap();
+
+    assert_eq!(summary.created, vec![created]);
+    assert_eq!(summary.existing, vec![existing]);
+}
+
+#[test]
+fn test_purge_refuses_hostile_relocation() {
+    let mut overrides = DirectoryOverrides::default();
+
+    overrides.protect([PathBuf::from("/home/user"), PathBuf::from("/home/user/.local/share")]);
+
+    // a manifest cannot relocate onto a base directory at all
+    assert_eq!(overrides.set(DirectoryKind::Data, PathBuf::from("/home/user")), Err(VoxelsDirectoryError::InvalidRelocation));
+
+    // and what it may relocate to is only purged once relocate, a dirs file or the audit log vouches for it
+    overrides.set(DirectoryKind::Data, PathBuf::from("/home/user/Documents")).unwrap();
+
+    assert_eq!(check_purgeable(&overrides, DirectoryKind::Data, Path::new("/home/user/Documents"), false), Err(VoxelsDirectoryError::InvalidRelocation));
+    assert_eq!(check_purgeable(&overrides, DirectoryKind::Data, Path::new("/home/user/Documents"), true), Ok(()));
+    assert_eq!(check_purgeable(&overrides, DirectoryKind::State, Path::new("/home/user/.local/state/voxels/org.voxels.Client"), false), Ok(()));
+    assert_eq!(check_purgeable(&overrides, DirectoryKind::State, Path::new("/home/user"), true), Err(VoxelsDirectoryError::InvalidRelocation));
+}
+
+#[test]
+fn test_purge_removes_nothing_when_one_directory_is_refused() {
+    use std::sync::Mutex;
+
+    use super::voxels_xdg::xdg::BaseDirectoryError;
+    use super::voxels_xdg::xdg::trash::TrashCan;
+
+    #[derive(Default)]
+    struct RecordingTrash {
+        trashed: Mutex<Vec<PathBuf>>,
+    }
+
+    impl TrashCan for RecordingTrash {
+        fn trash(&self, path: &Path) -> Result<PathBuf, BaseDirectoryError> {
+            self.trashed.lock().unwrap().push(path.to_path_buf());
+
+            Ok(PathBuf::from("/home/user/.local/share/Trash/files").join(path.file_name().unwrap()))
+        }
+    }
+
+    let mut overrides = DirectoryOverrides::default();
+
+    // the manifest relocated state, and nothing vouches for it
+    overrides.set(DirectoryKind::State, PathBuf::from("/home/user/Documents")).unwrap();
+
+    let check = |kind: DirectoryKind, path: &Path| check_purgeable(&overrides, kind, path, false);
+
+    let directories = vec![
+        (DirectoryKind::Config, PathBuf::from("/home/user/.config/voxels/org.voxels.Client")),
+        (DirectoryKind::Data, PathBuf::from("/home/user/.local/share/voxels/org.voxels.Client")),
+        (DirectoryKind::State, PathBuf::from("/home/user/Documents")),
+    ];
+
+    let fs = crate::filesystem::MockFsInt::new();
+    let trash = RecordingTrash::default();
+
+    assert_eq!(purge_directories(&fs, &directories, &check, Removal::Trash(&trash), false, "org.voxels.Client", None), Err(VoxelsDirectoryError::InvalidRelocation));
+    assert!(trash.trashed.lock().unwrap().is_empty());
+
+    assert_eq!(purge_directories(&fs, &directories[..2], &check, Removal::Trash(&trash), false, "org.voxels.Client", None).unwrap().len(), 2);
+    assert_eq!(trash.trashed.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_relocation_is_pinned_for_later_resolvers() {
+    use std::sync::{Arc, Mutex};
+
+    use mockall::predicate::eq;
+
+    let dirs_file_path = PathBuf::from("/home/user/.config/voxels/dirs.toml");
+    let new_path = PathBuf::from("/mnt/games/org.voxels.Client");
+
+    let written = Arc::new(Mutex::new(String::new()));
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_exists()
+        .with(eq(dirs_file_path.clone()))
+        .return_const(false);
+
+    fs.expect_write()
+        .with(eq(dirs_file_path.clone()), mockall::predicate::always())
+        .returning({
+            let written = written.clone();
+            move |_, contents| {
+                *written.lock().unwrap() = String::from(contents);
+                Ok(())
+            }
+        });
+
+    pin_relocation(&fs, &dirs_file_path, "org.voxels.Client", DirectoryKind::Data, &new_path).unwrap();
+
+    // what a later process, and every resolver it builds, reads back through VOXELS_DIRS_FILE
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_and_rig(EnvKey::VoxelsDirsFile, dirs_file_path.clone());
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_read_to_string()
+        .with(eq(dirs_file_path))
+        .returning(move |_| Ok(written.lock().unwrap().clone()));
+
+    let dirs_file = DirsFile::load(&env, &fs).unwrap();
+
+    assert_eq!(dirs_file.get_for_application("org.voxels.Client", DirectoryKind::Data), Some(new_path.as_path()));
+    assert_eq!(dirs_file.get_for_application("org.voxels.Client", DirectoryKind::Config), None);
+}
+
+#[test]
+fn test_refresh_link() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let link = PathBuf::from("/farm/data");
+
+    fs.expect_is_symlink()
+        .returning(|_| true);
+
+    fs.expect_read_link()
+        .returning(|_| Ok(PathBuf::from("/old/data")));
+
+    fs.expect_remove_file()
+        .times(2)
+        .returning(|_| Ok(()));
+
+    fs.expect_symlink()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/new/data")), mockall::predicate::eq(link.clone()))
+        .return_once(|_, _| Ok(()));
+
+    assert_eq!(refresh_link(&fs, &link, Some(Path::new("/old/data"))), Ok(false));
+    assert_eq!(refresh_link(&fs, &link, Some(Path::new("/new/data"))), Ok(true));
+    assert_eq!(refresh_link(&fs, &link, None), Ok(true));
+}
+
+#[test]
+fn test_move_directory_refuses_non_empty_target() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_exists()
+        .returning(|_| true);
+
+    fs.expect_read_dir()
+        .returning(|_| Ok(vec![PathBuf::from("/mnt/games/world")]));
+
+    assert_eq!(move_directory(&fs, Path::new("/home/user/.local/share/voxels/org.voxels.Client"), Path::new("/mnt/games")), Err(VoxelsDirectoryError::Io(std::io::ErrorKind::AlreadyExists)));
+}
+
+#[test]
+fn test_resolved_directories_record() {
+    let mut resolved = ResolvedDirectories::default();
+
+    resolved.record(DirectoryKind::Config, Some(Ok(PathBuf::from("/home/user/.config/voxels/org.voxels.Client"))));
+    resolved.record(DirectoryKind::Runtime, Some(Err(VoxelsDirectoryError::NoCandidate)));
+    resolved.record(DirectoryKind::Data, None);
+
+    assert_eq!(resolved.resolved.get(&DirectoryKind::Config), Some(&PathBuf::from("/home/user/.config/voxels/org.voxels.Client")));
+    assert_eq!(resolved.failed.get(&DirectoryKind::Runtime), Some(&VoxelsDirectoryError::NoCandidate));
+    assert_eq!(resolved.timed_out, vec![DirectoryKind::Data]);
+}
+
+#[test]
+fn test_consumed_env_vars() {
+    let mut config = config::MockConfigDirectoryResolver::new();
+    let mut data = data::MockDataDirectoryResolver::new();
+    let mut state = state::MockStateDirectoryResolver::new();
+    let mut cache = cache::MockCacheDirectoryResolver::new();
+    let mut runtime = runtime::MockRuntimeDirectoryResolver::new();
+
+    config.expect_consumed_env_vars().returning(|| [EnvKey::Home, EnvKey::XdgConfigHome].into());
+    data.expect_consumed_env_vars().returning(|| [EnvKey::Home, EnvKey::XdgDataHome].into());
+    state.expect_consumed_env_vars().returning(BTreeSet::new);
+    cache.expect_consumed_env_vars().returning(BTreeSet::new);
+    runtime.expect_consumed_env_vars().returning(|| [EnvKey::XdgRuntimeDir, EnvKey::DbusSessionBusAddress].into());
+
+    let bases = BaseDirectories { config, data, state, cache, runtime };
+
+    assert_eq!(bases.consumed_env_vars(), [
+        EnvKey::Home,
+        EnvKey::XdgConfigHome,
+        EnvKey::XdgDataHome,
+        EnvKey::XdgRuntimeDir,
+        EnvKey::DbusSessionBusAddress,
+    ].into());
+}
@@ -0,0 +1,346 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock::{ClockInt, DefaultClockInt};
+use crate::filesystem::FsInt;
+
+use super::VoxelsDirectoryError;
+
+/// Kept directly beneath `<state>/voxels/`, shared by every application
+pub const AUDIT_LOG_FILE_NAME: &str = "audit.log";
+
+/// Operations that change where an application's data lives
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum AuditOperation {
+    Create,
+    Purge,
+    Relocate,
+    /// Recorded by callers that move data between layouts
+    Migrate,
+    /// Another application was given read access to a path
+    Grant,
+    /// Another application's read access to a path was taken away
+    Revoke,
+}
+
+impl AuditOperation {
+    pub const ALL: [AuditOperation; 6] = [
+        AuditOperation::Create,
+        AuditOperation::Purge,
+        AuditOperation::Relocate,
+        AuditOperation::Migrate,
+        AuditOperation::Grant,
+        AuditOperation::Revoke,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditOperation::Create => "create",
+            AuditOperation::Purge => "purge",
+            AuditOperation::Relocate => "relocate",
+            AuditOperation::Migrate => "migrate",
+            AuditOperation::Grant => "grant",
+            AuditOperation::Revoke => "revoke",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        AuditOperation::ALL.into_iter().find(|operation| operation.name() == name)
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AuditEntry {
+    pub time: SystemTime,
+    pub operation: AuditOperation,
+    /// The application the operation was requested for
+    pub rdn: String,
+    pub path: PathBuf,
+    /// Where the directory was before a relocation or migration
+    pub previous: Option<PathBuf>,
+    /// The application a grant or revoke was for
+    pub grantee: Option<String>,
+}
+
+/// `field` with backslashes, tabs and line breaks escaped, so it cannot split or end a log line
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Inverse of `escape_field`, `None` for an unknown or unfinished escape
+fn unescape_field(field: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        unescaped.push(match chars.next()? {
+            '\\' => '\\',
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+
+    Some(unescaped)
+}
+
+impl AuditEntry {
+    /// `<unix seconds>\t<operation>\t<rdn>\t<path>\t<previous>\n`, `previous` is empty when there is none.
+    /// Grants and revokes add `\t<grantee>` before the newline. Fields are escaped with `escape_field`.
+    pub fn render(&self) -> String {
+        let since_epoch = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        format!(
+            "{}\t{}\t{}\t{}\t{}{}\n",
+            since_epoch.as_secs(),
+            self.operation.name(),
+            escape_field(&self.rdn),
+            escape_field(&self.path.to_string_lossy()),
+            self.previous.as_ref().map(|previous| escape_field(&previous.to_string_lossy())).unwrap_or_default(),
+            self.grantee.as_ref().map(|grantee| format!("\t{}", escape_field(grantee))).unwrap_or_default()
+        )
+    }
+
+    /// Inverse of render, `None` for a malformed line
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim_end_matches('\n').split('\t');
+
+        let time = UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?);
+        let operation = AuditOperation::from_name(fields.next()?)?;
+        let rdn = unescape_field(fields.next()?)?;
+        let path = PathBuf::from(unescape_field(fields.next()?)?);
+        let previous = match fields.next().filter(|previous| !previous.is_empty()) {
+            Some(previous) => Some(PathBuf::from(unescape_field(previous)?)),
+            None => None,
+        };
+        let grantee = match fields.next().filter(|grantee| !grantee.is_empty()) {
+            Some(grantee) => Some(unescape_field(grantee)?),
+            None => None,
+        };
+
+        Some(Self {
+            time,
+            operation,
+            rdn,
+            path,
+            previous,
+            grantee
+        })
+    }
+}
+
+/// Where audited operations are recorded, implemented by `AuditLog`
+pub trait AuditRecorder {
+    fn record(&self, operation: AuditOperation, rdn: &str, path: &Path, previous: Option<&Path>) -> Result<(), VoxelsDirectoryError>;
+
+    /// Record that `grantee` was given or refused access to `path` of `rdn`
+    fn record_grant(&self, operation: AuditOperation, rdn: &str, path: &Path, grantee: &str) -> Result<(), VoxelsDirectoryError>;
+
+    /// Whether a relocation of `rdn` to `path` was recorded, false for recorders that cannot be read back
+    fn recorded_relocation(&self, _rdn: &str, _path: &Path) -> bool {
+        false
+    }
+}
+
+/// Shared so every directory handed out by one `ApplicationDirectory` records to the same log
+pub type SharedAuditLog = Arc<dyn AuditRecorder + Send + Sync>;
+
+/// Record an operation, a log that cannot be written never fails the operation it describes
+pub(crate) fn audit(log: Option<&SharedAuditLog>, operation: AuditOperation, rdn: &str, path: &Path, previous: Option<&Path>) {
+    if let Some(log) = log
+        && let Err(err) = log.record(operation, rdn, path, previous) {
+        tracing::warn!("Could not record {} of {} in the audit log: {:?}", operation.name(), path.display(), err);
+    }
+}
+
+/// As `audit`, for a grant or revoke of access for `grantee`
+pub(crate) fn audit_grant(log: Option<&SharedAuditLog>, operation: AuditOperation, rdn: &str, path: &Path, grantee: &str) {
+    if let Some(log) = log
+        && let Err(err) = log.record_grant(operation, rdn, path, grantee) {
+        tracing::warn!("Could not record {} of {} to {} in the audit log: {:?}", operation.name(), path.display(), grantee, err);
+    }
+}
+
+/// An append-only log of directory creations, deletions and moves
+pub struct AuditLog<FsIntT: FsInt, ClockIntT: ClockInt = DefaultClockInt> {
+    path: PathBuf,
+    fs: FsIntT,
+    clock: ClockIntT,
+}
+
+impl<FsIntT: FsInt> AuditLog<FsIntT> {
+    /// The log in `state`, the base voxels state directory such as `~/.local/state/voxels`
+    pub fn new(state: &Path, fs: FsIntT) -> Self {
+        Self::with_clock(state, fs, DefaultClockInt)
+    }
+}
+
+impl<FsIntT: FsInt, ClockIntT: ClockInt> AuditLog<FsIntT, ClockIntT> {
+    /// As `new`, with entries timestamped by `clock`
+    pub fn with_clock(state: &Path, fs: FsIntT, clock: ClockIntT) -> Self {
+        Self {
+            path: state.join(AUDIT_LOG_FILE_NAME),
+            fs,
+            clock
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every entry in the order it was recorded, malformed lines are skipped. Empty when nothing was recorded yet.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, VoxelsDirectoryError> {
+        if !self.fs.exists(&self.path) {
+            return Ok(Vec::new());
+        }
+
+        Ok(self.fs.read_to_string(&self.path)?
+            .lines()
+            .filter_map(AuditEntry::parse)
+            .collect())
+    }
+
+    /// The entries recorded for the application `rdn`
+    pub fn entries_for(&self, rdn: &str) -> Result<Vec<AuditEntry>, VoxelsDirectoryError> {
+        Ok(self.entries()?
+            .into_iter()
+            .filter(|entry| entry.rdn == rdn)
+            .collect())
+    }
+}
+
+impl<FsIntT: FsInt, ClockIntT: ClockInt> AuditLog<FsIntT, ClockIntT> {
+    fn append(&self, operation: AuditOperation, rdn: &str, path: &Path, previous: Option<&Path>, grantee: Option<&str>) -> Result<(), VoxelsDirectoryError> {
+        let entry = AuditEntry {
+            time: self.clock.now(),
+            operation,
+            rdn: String::from(rdn),
+            path: path.to_path_buf(),
+            previous: previous.map(Path::to_path_buf),
+            grantee: grantee.map(String::from),
+        };
+
+        Ok(self.fs.append(&self.path, &entry.render())?)
+    }
+}
+
+impl<FsIntT: FsInt, ClockIntT: ClockInt> AuditRecorder for AuditLog<FsIntT, ClockIntT> {
+    fn record(&self, operation: AuditOperation, rdn: &str, path: &Path, previous: Option<&Path>) -> Result<(), VoxelsDirectoryError> {
+        self.append(operation, rdn, path, previous, None)
+    }
+
+    fn record_grant(&self, operation: AuditOperation, rdn: &str, path: &Path, grantee: &str) -> Result<(), VoxelsDirectoryError> {
+        self.append(operation, rdn, path, None, Some(grantee))
+    }
+
+    fn recorded_relocation(&self, rdn: &str, path: &Path) -> bool {
+        self.entries_for(rdn)
+            .is_ok_and(|entries| entries.iter().any(|entry| entry.operation == AuditOperation::Relocate && entry.path == path))
+    }
+}
+
+#[test]
+fn test_audit_entry_round_trip() {
+    let entry = AuditEntry {
+        time: UNIX_EPOCH + Duration::from_secs(1700000000),
+        operation: AuditOperation::Relocate,
+        rdn: String::from("org.voxels.Client"),
+        path: PathBuf::from("/mnt/games/org.voxels.Client"),
+        previous: Some(PathBuf::from("/home/user/.local/share/voxels/org.voxels.Client")),
+        grantee: None,
+    };
+
+    assert_eq!(AuditEntry::parse(&entry.render()), Some(entry));
+    assert_eq!(AuditEntry::parse("1700000000\tcreate\torg.voxels.Client\t/data\t"), Some(AuditEntry {
+        time: UNIX_EPOCH + Duration::from_secs(1700000000),
+        operation: AuditOperation::Create,
+        rdn: String::from("org.voxels.Client"),
+        path: PathBuf::from("/data"),
+        previous: None,
+        grantee: None,
+    }));
+    assert_eq!(AuditEntry::parse("1700000000\tgrant\torg.voxels.Editor\t/data/org.voxels.Editor/maps\t\torg.voxels.Viewer\n"), Some(AuditEntry {
+        time: UNIX_EPOCH + Duration::from_secs(1700000000),
+        operation: AuditOperation::Grant,
+        rdn: String::from("org.voxels.Editor"),
+        path: PathBuf::from("/data/org.voxels.Editor/maps"),
+        previous: None,
+        grantee: Some(String::from("org.voxels.Viewer")),
+    }));
+    assert_eq!(AuditEntry::parse("bogus"), None);
+}
+
+#[test]
+fn test_audit_entry_escapes_hostile_fields() {
+    let entry = AuditEntry {
+        time: UNIX_EPOCH + Duration::from_secs(1700000000),
+        operation: AuditOperation::Grant,
+        rdn: String::from("org.voxels.Client\tpurge"),
+        path: PathBuf::from("/data/evil\t/\n1700000001\tpurge\torg.voxels.Other\t/home/user\t\\n"),
+        previous: Some(PathBuf::from("/data/old\\t")),
+        grantee: Some(String::from("org.voxels.Viewer\n")),
+    };
+
+    let rendered = entry.render();
+
+    assert_eq!(rendered.lines().count(), 1);
+    assert_eq!(rendered.trim_end_matches('\n').split('\t').count(), 6);
+    assert_eq!(AuditEntry::parse(&rendered), Some(entry));
+    assert_eq!(AuditEntry::parse("1700000000\tcreate\torg.voxels.Client\t/data\\x\t"), None);
+}
+
+#[test]
+fn test_audit_log_appends() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+    let mut clock = crate::clock::MockClockInt::new();
+
+    clock.expect_now()
+        .return_const(UNIX_EPOCH + Duration::from_secs(10));
+
+    fs.expect_append()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/state/voxels/audit.log")), mockall::predicate::eq(String::from("10\tpurge\torg.voxels.Client\t/data/org.voxels.Client\t\n")))
+        .return_once(|_, _| Ok(()));
+
+    let log = AuditLog::with_clock(Path::new("/state/voxels"), fs, clock);
+
+    assert!(log.record(AuditOperation::Purge, "org.voxels.Client", Path::new("/data/org.voxels.Client"), None).is_ok());
+}
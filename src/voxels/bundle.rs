@@ -0,0 +1,244 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Component, PathBuf};
+
+use crate::filesystem::{create_directory, CreateOptions, FsInt};
+use crate::pathcheck::{check_contained, join_within};
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::application_directory::{resolve_if_candidate, ApplicationDirectory, BaseDirectories};
+
+use super::voxels_xdg::{cache, config, data, runtime, state};
+
+/// Name of the manifest stored first in every bundle
+pub const BUNDLE_MANIFEST_NAME: &str = "voxels-bundle";
+
+/// The kinds a bundle carries, cache and runtime are rebuilt on the new machine
+pub const BUNDLED_KINDS: [DirectoryKind; 3] = [DirectoryKind::Config, DirectoryKind::Data, DirectoryKind::State];
+
+/// Which application a bundle belongs to and where each of its directories came from
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct BundleManifest {
+    pub rdn: String,
+    pub origins: BTreeMap<DirectoryKind, PathBuf>,
+}
+
+impl BundleManifest {
+    pub fn render(&self) -> String {
+        let mut out = format!("rdn={}\n", self.rdn);
+
+        for (kind, origin) in &self.origins {
+            out.push_str(&format!("{}={}\n", kind.name(), origin.display()));
+        }
+
+        out
+    }
+
+    /// Inverse of render, unknown or malformed lines are ignored
+    pub fn parse(contents: &str) -> Self {
+        let mut manifest = BundleManifest::default();
+
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            if key == "rdn" {
+                manifest.rdn = String::from(value);
+            } else if let Some(kind) = DirectoryKind::from_name(key) {
+                manifest.origins.insert(kind, PathBuf::from(value));
+            }
+        }
+
+        manifest
+    }
+}
+
+fn rdn_of(directory: &ApplicationDirectory) -> String {
+    directory.application().rdn().as_path().to_string_lossy().into_owned()
+}
+
+/// Resolve the bundled kinds of `directory`, leaving out kinds without a candidate
+async fn resolve_bundled<ConfigT, DataT, StateT, CacheT, RuntimeT>(directory: &ApplicationDirectory, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>) -> Result<BTreeMap<DirectoryKind, PathBuf>, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+{
+    let resolved = [
+        (DirectoryKind::Config, resolve_if_candidate(&mut directory.config(bases.config)).await?),
+        (DirectoryKind::Data, resolve_if_candidate(&mut directory.data(bases.data)).await?),
+        (DirectoryKind::State, resolve_if_candidate(&mut directory.state(bases.state)).await?),
+    ];
+
+    Ok(resolved.into_iter()
+        .filter_map(|(kind, path)| path.map(|path| (kind, path)))
+        .collect())
+}
+
+/// Write the config, data and state directories of `directory` to `writer` as a tar archive,
+/// one top level directory per kind after the manifest. Symlinks are stored as links, not followed.
+pub async fn export_bundle<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT, WriterT>(directory: &ApplicationDirectory, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: &FsIntT, writer: WriterT) -> Result<BundleManifest, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+    FsIntT: FsInt,
+    WriterT: Write,
+{
+    let mut manifest = BundleManifest {
+        rdn: rdn_of(directory),
+        origins: BTreeMap::new(),
+    };
+
+    for (kind, path) in resolve_bundled(directory, bases).await? {
+        if fs.is_directory(&path) {
+            manifest.origins.insert(kind, path);
+        }
+    }
+
+    let mut builder = tar::Builder::new(writer);
+
+    builder.follow_symlinks(false);
+
+    let rendered = manifest.render();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(rendered.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    builder.append_data(&mut header, BUNDLE_MANIFEST_NAME, rendered.as_bytes())?;
+
+    for (kind, origin) in &manifest.origins {
+        builder.append_dir_all(kind.name(), origin)?;
+    }
+
+    builder.into_inner()?.flush()?;
+
+    Ok(manifest)
+}
+
+/// Unpack a bundle written by `export_bundle` into where `directory` resolves on this machine.
+/// The bundle must be for the same application. Only regular files and directories are restored,
+/// entries that would land outside their kind's directory are rejected.
+pub async fn import_bundle<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT, ReaderT>(directory: &ApplicationDirectory, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: &FsIntT, reader: ReaderT) -> Result<BundleManifest, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+    FsIntT: FsInt,
+    ReaderT: Read,
+{
+    let destinations = resolve_bundled(directory, bases).await?;
+
+    let mut archive = tar::Archive::new(reader);
+
+    let mut entries = archive.entries()?;
+
+    let mut manifest_entry = entries.next().ok_or(VoxelsDirectoryError::InvalidName)??;
+
+    if manifest_entry.path()?.as_os_str() != BUNDLE_MANIFEST_NAME {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    let mut contents = String::new();
+    manifest_entry.read_to_string(&mut contents)?;
+
+    let manifest = BundleManifest::parse(&contents);
+
+    if manifest.rdn != rdn_of(directory) {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    for entry in entries {
+        let mut entry = entry?;
+
+        let entry_type = entry.header().entry_type();
+
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
+
+        let path = entry.path()?.into_owned();
+
+        let mut components = path.components();
+
+        let Some(Component::Normal(kind)) = components.next() else {
+            return Err(VoxelsDirectoryError::InvalidName);
+        };
+
+        let kind = DirectoryKind::from_name(&kind.to_string_lossy())
+            .filter(|kind| BUNDLED_KINDS.contains(kind))
+            .ok_or(VoxelsDirectoryError::InvalidName)?;
+
+        let Some(base) = destinations.get(&kind) else {
+            return Err(VoxelsDirectoryError::NoCandidate);
+        };
+
+        let relative = components.as_path();
+
+        if relative.as_os_str().is_empty() {
+            create_directory(fs, base, CreateOptions::default())?;
+            continue;
+        }
+
+        let target = join_within(base, relative)?;
+
+        check_contained(fs, base, &target)?;
+
+        if entry_type.is_dir() {
+            create_directory(fs, &target, CreateOptions::default())?;
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            create_directory(fs, parent, CreateOptions::default())?;
+        }
+
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+
+        fs.write_bytes(&target, &bytes)?;
+    }
+
+    Ok(manifest)
+}
+
+#[test]
+fn test_bundle_manifest_round_trip() {
+    let mut manifest = BundleManifest {
+        rdn: String::from("org.voxels.Client"),
+        origins: BTreeMap::new(),
+    };
+
+    manifest.origins.insert(DirectoryKind::Data, PathBuf::from("/home/user/.local/share/voxels/org.voxels.Client"));
+    manifest.origins.insert(DirectoryKind::Config, PathBuf::from("/home/user/.config/voxels/org.voxels.Client"));
+
+    assert_eq!(BundleManifest::parse(&manifest.render()), manifest);
+    assert_eq!(BundleManifest::parse("bogus\nworlds=/mnt\n"), BundleManifest::default());
+}
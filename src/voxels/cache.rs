@@ -0,0 +1,317 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lib_voxels_application::application::application::Application;
+
+use crate::async_filesystem::{create_directory, AsyncFsInt, DefaultAsyncFsInt};
+use crate::clock::ClockInt;
+use crate::environment_variables::DefaultEnvInt;
+use crate::filesystem::{measure, CreateOptions, FsInt};
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::rdn::{pinned_path, rdn_path_within, RdnCase, RdnLayout};
+
+use super::voxels_xdg::cache as base;
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
+use super::voxels_xdg::xdg::trash::Removal;
+
+#[cfg(feature = "dbus")]
+pub const DBUS_STANDARD_APPS_CACHE_METHOD_NAME: &str = "cache";
+
+pub const SHADER_CACHE_DIRECTORY_NAME: &str = "shaders";
+
+pub const ASSET_CACHE_DIRECTORY_NAME: &str = "assets";
+
+/// Written into every standardized cache subdirectory so pruning tools know what may be removed
+pub const CACHE_RETENTION_FILE_NAME: &str = ".retention";
+
+/// How long entries in a cache subdirectory are worth keeping and how large it may grow
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct CacheRetention {
+    pub max_age: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+impl CacheRetention {
+    pub const SHADERS: CacheRetention = CacheRetention {
+        max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        max_size: Some(512 * 1024 * 1024),
+    };
+
+    pub const ASSETS: CacheRetention = CacheRetention {
+        max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+        max_size: Some(1024 * 1024 * 1024),
+    };
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        if let Some(max_age) = self.max_age {
+            out.push_str(&format!("max_age_secs={}\n", max_age.as_secs()));
+        }
+
+        if let Some(max_size) = self.max_size {
+            out.push_str(&format!("max_size_bytes={}\n", max_size));
+        }
+
+        out
+    }
+
+    /// Inverse of render, unknown or malformed lines are ignored
+    pub fn parse(contents: &str) -> Self {
+        let mut retention = CacheRetention::default();
+
+        for line in contents.lines() {
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let Ok(value) = value.trim().parse::<u64>() else {
+                continue;
+            };
+
+            match key.trim() {
+                "max_age_secs" => retention.max_age = Some(Duration::from_secs(value)),
+                "max_size_bytes" => retention.max_size = Some(value),
+                _ => {}
+            }
+        }
+
+        retention
+    }
+}
+
+/// Remove the entries of `subcache` its retention file no longer allows as `removal` says, returning them.
+/// Entries last modified more than `max_age` ago go first, then the oldest until the rest fit in `max_size`.
+/// Pass `Removal::Trash` unless the user asked for the cache to be deleted outright.
+pub fn prune_subcache<FsIntT: FsInt, ClockIntT: ClockInt>(fs: &FsIntT, clock: &ClockIntT, subcache: &Path, removal: Removal<'_>) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+    let retention = match fs.read_to_string(&subcache.join(CACHE_RETENTION_FILE_NAME)) {
+        Ok(contents) => CacheRetention::parse(&contents),
+        // nothing says what may be removed from a directory subcache did not create
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let now = clock.now();
+
+    let mut entries = Vec::new();
+
+    for entry in fs.read_dir(subcache)? {
+        if entry.file_name() == Some(OsStr::new(CACHE_RETENTION_FILE_NAME)) {
+            continue;
+        }
+
+        entries.push((fs.modified(&entry)?, measure(fs, &entry)?, entry));
+    }
+
+    // oldest first
+    entries.sort();
+
+    let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+
+    let mut pruned = Vec::new();
+
+    for (modified, size, entry) in entries {
+        let expired = retention.max_age.is_some_and(|max_age| now.duration_since(modified).is_ok_and(|age| age > max_age));
+        let too_large = retention.max_size.is_some_and(|max_size| total > max_size);
+
+        if !expired && !too_large {
+            continue;
+        }
+
+        removal.remove(fs, &entry)?;
+
+        total -= size;
+        pruned.push(entry);
+    }
+
+    Ok(pruned)
+}
+
+#[mockall::automock]
+pub trait CacheDirectoryResolver {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn subcache(&mut self, application: Application, name: &'static str, retention: CacheRetention) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn shader_cache(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn asset_cache(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+pub struct CacheDirectory<BaseT: base::CacheDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    cache_path: Option<PathBuf>,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+    /// Replaces `<cache>/voxels/<rdn>` when the manifest relocates this application's cache
+    pub relocation: Option<PathBuf>,
+    /// Pins read from `VOXELS_DIRS_FILE` when this was built, the entry for the application wins over `relocation`
+    pub dirs_file: Option<DirsFile>,
+    pub create_options: CreateOptions,
+    base: BaseT,
+    fs: FsIntT,
+    async_fs: AsyncFsIntT,
+}
+
+impl<BaseT: base::CacheDirectoryResolver, FsIntT: FsInt> CacheDirectory<BaseT, FsIntT> {
+    pub fn new(base: BaseT, fs: FsIntT) -> Self {
+        Self::with_async_fs(base, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::CacheDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> CacheDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// As `new`, creating directories through `async_fs`
+    pub fn with_async_fs(base: BaseT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        let dirs_file = DirsFile::load(&DefaultEnvInt, &fs);
+
+        Self {
+            cache_path: None,
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+            relocation: None,
+            dirs_file,
+            create_options: CreateOptions::default(),
+            base,
+            fs,
+            async_fs
+        }
+    }
+}
+
+impl<BaseT: base::CacheDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> CacheDirectoryResolver for CacheDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        // if resolve has been called previously we update this objects path
+        if self.is_resolved() {
+            return Ok(self.cache_path.clone().unwrap());
+        }
+
+        if let Some(pinned) = pinned_path(self.dirs_file.as_ref(), &application, DirectoryKind::Cache) {
+            return Ok(pinned);
+        }
+
+        if let Some(relocation) = &self.relocation {
+            if !relocation.is_absolute() {
+                return Err(VoxelsDirectoryError::InvalidRelocation);
+            }
+
+            return Ok(relocation.clone());
+        }
+
+        let base = self.base.resolve().await?;
+
+        let cache_path = rdn_path_within(&self.fs, &base, &application, self.rdn_case, self.rdn_layout)?;
+
+        self.cache_path = Some(cache_path.clone());
+
+        Ok(cache_path)
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        create_directory(&self.async_fs, &resolved, self.create_options).await?;
+
+        Ok(resolved)
+    }
+
+    /// Creates `<cache>/voxels/<rdn>/<name>` and records its retention policy alongside the entries
+    async fn subcache(&mut self, application: Application, name: &'static str, retention: CacheRetention) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        let subcache_path = resolved.join(name);
+
+        create_directory(&self.async_fs, &subcache_path, CreateOptions::default()).await?;
+
+        self.fs.write(&subcache_path.join(CACHE_RETENTION_FILE_NAME), &retention.render())?;
+
+        Ok(subcache_path)
+    }
+
+    async fn shader_cache(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        self.subcache(application, SHADER_CACHE_DIRECTORY_NAME, CacheRetention::SHADERS).await
+    }
+
+    async fn asset_cache(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        self.subcache(application, ASSET_CACHE_DIRECTORY_NAME, CacheRetention::ASSETS).await
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.cache_path.is_some()
+    }
+}
+
+#[test]
+fn test_cache_retention_round_trip() {
+    assert_eq!(CacheRetention::parse(&CacheRetention::SHADERS.render()), CacheRetention::SHADERS);
+    assert_eq!(CacheRetention::parse("max_size_bytes=10\nbogus\n"), CacheRetention { max_age: None, max_size: Some(10) });
+}
+
+#[test]
+fn test_prune_subcache() {
+    use std::time::UNIX_EPOCH;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+    let mut clock = crate::clock::MockClockInt::new();
+
+    let subcache = PathBuf::from("/home/user/.cache/voxels/org.voxels.Client/shaders");
+
+    let day = 24 * 60 * 60;
+
+    clock.expect_now()
+        .return_const(UNIX_EPOCH + Duration::from_secs(100 * day));
+
+    fs.expect_read_to_string()
+        .returning(|_| Ok(String::from("max_age_secs=864000\nmax_size_bytes=150\n")));
+
+    fs.expect_read_dir()
+        .returning({
+            let subcache = subcache.clone();
+            move |_| Ok(vec![subcache.join(".retention"), subcache.join("stale"), subcache.join("old"), subcache.join("new")])
+        });
+
+    fs.expect_modified()
+        .returning(move |path| Ok(UNIX_EPOCH + Duration::from_secs(match path.file_name().unwrap().to_str().unwrap() {
+            "stale" => 80 * day,
+            "old" => 95 * day,
+            _ => 99 * day,
+        })));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| false);
+
+    fs.expect_file_size()
+        .returning(|_| Ok(100));
+
+    fs.expect_remove_file()
+        .times(2)
+        .returning(|_| Ok(()));
+
+    // stale is past max_age, old has to go for the rest to fit in max_size
+    assert_eq!(prune_subcache(&fs, &clock, &subcache, Removal::Permanent).unwrap(), vec![subcache.join("stale"), subcache.join("old")]);
+}
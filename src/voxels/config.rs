@@ -15,17 +15,52 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use lib_voxels_application::application::application::Application;
 
-use super::VoxelsDirectoryError;
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::rdn::{pinned_path, rdn_path_within, RdnCase, RdnLayout};
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::environment_variables::DefaultEnvInt;
+use crate::filesystem::{create_directory, CreateOptions, DefaultFsInt, FsInt};
 
 use super::voxels_xdg::config as base;
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_APPS_CONFIG_METHOD_NAME: &str = "config";
 
+/// Packages ship default config in `<data>/voxels/<rdn>/default-config`
+pub const DEFAULT_CONFIG_DIRECTORY_NAME: &str = "default-config";
+
+/// Recursively copy the files of `template` into `target` that `target` does not have yet, returning the copies.
+/// Existing files are never overwritten, so user edits survive reseeding.
+pub fn seed_directory<FsIntT: FsInt>(fs: &FsIntT, template: &Path, target: &Path) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+    let mut copied = Vec::new();
+
+    for entry in fs.read_dir(template)? {
+        let Some(name) = entry.file_name() else {
+            continue;
+        };
+
+        let destination = target.join(name);
+
+        if fs.is_directory(&entry) {
+            create_directory(fs, &destination, CreateOptions::default())?;
+
+            copied.append(&mut seed_directory(fs, &entry, &destination)?);
+        } else if !fs.exists(&destination) {
+            fs.write_bytes(&destination, &fs.read(&entry)?)?;
+
+            copied.push(destination);
+        }
+    }
+
+    Ok(copied)
+}
+
 #[mockall::automock]
 pub trait ConfigDirectoryResolver {
     async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
@@ -35,36 +70,88 @@ pub trait ConfigDirectoryResolver {
     fn is_resolved(&self) -> bool;
 }
 
-pub struct ConfigDirectory<BaseT: base::ConfigDirectoryResolver> {
+pub struct ConfigDirectory<BaseT: base::ConfigDirectoryResolver, FsIntT: FsInt = DefaultFsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
     data_path: Option<PathBuf>,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+    /// Replaces `<config>/voxels/<rdn>` when the manifest relocates this application's config
+    pub relocation: Option<PathBuf>,
+    /// Pins read from `VOXELS_DIRS_FILE` when this was built, the entry for the application wins over `relocation`
+    pub dirs_file: Option<DirsFile>,
+    pub create_options: CreateOptions,
     base: BaseT,
+    fs: FsIntT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::ConfigDirectoryResolver> ConfigDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_fs(base, DefaultFsInt)
+    }
+}
+
+impl<BaseT: base::ConfigDirectoryResolver, FsIntT: FsInt> ConfigDirectory<BaseT, FsIntT> {
+    pub fn with_fs(base: BaseT, fs: FsIntT) -> Self {
+        Self::with_async_fs(base, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::ConfigDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> ConfigDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// As `with_fs`, creating the directory through `async_fs` from `resolve_and_create`
+    pub fn with_async_fs(base: BaseT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        let dirs_file = DirsFile::load(&DefaultEnvInt, &fs);
+
         Self {
             data_path: None,
-            base
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+            relocation: None,
+            dirs_file,
+            create_options: CreateOptions::default(),
+            base,
+            fs,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::ConfigDirectoryResolver> ConfigDirectoryResolver for ConfigDirectory<BaseT> {
+impl<BaseT: base::ConfigDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> ConfigDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// Create the config directory and copy in any files from `template` it is missing, see `seed_directory`
+    pub async fn seed_from(&mut self, application: Application, template: &Path) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+        let resolved = self.resolve_and_create(application).await?;
+
+        seed_directory(&self.fs, template, &resolved)
+    }
+}
+
+impl<BaseT: base::ConfigDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> ConfigDirectoryResolver for ConfigDirectory<BaseT, FsIntT, AsyncFsIntT> {
     async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         // if resolve has been called previously we update this objects path
         if self.is_resolved() {
             return Ok(self.data_path.clone().unwrap());
         }
 
+        if let Some(pinned) = pinned_path(self.dirs_file.as_ref(), &application, DirectoryKind::Config) {
+            return Ok(pinned);
+        }
+
+        if let Some(relocation) = &self.relocation {
+            if !relocation.is_absolute() {
+                return Err(VoxelsDirectoryError::InvalidRelocation);
+            }
+
+            return Ok(relocation.clone());
+        }
+
         let base = self.base.resolve().await?;
 
-        Ok(base.join(application.rdn().as_path()))
+        rdn_path_within(&self.fs, &base, &application, self.rdn_case, self.rdn_layout)
     }
 
     async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve(application).await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
     }
@@ -72,4 +159,42 @@ impl<BaseT: base::ConfigDirectoryResolver> ConfigDirectoryResolver for ConfigDir
     fn is_resolved(&self) -> bool {
         self.data_path.is_some()
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_seed_directory_never_overwrites() {
+    use mockall::predicate::eq;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let template = PathBuf::from("/usr/share/voxels/org.voxels.Client/default-config");
+    let target = PathBuf::from("/home/user/.config/voxels/org.voxels.Client");
+
+    fs.expect_read_dir()
+        .once()
+        .with(eq(template.clone()))
+        .return_once({
+            let entries = vec![template.join("settings.toml"), template.join("keybinds.toml")];
+            move |_| Ok(entries)
+        });
+
+    fs.expect_is_directory()
+        .returning(|_| false);
+
+    fs.expect_exists()
+        .returning({
+            let edited = target.join("settings.toml");
+            move |path| path == edited
+        });
+
+    fs.expect_read()
+        .once()
+        .with(eq(template.join("keybinds.toml")))
+        .return_once(|_| Ok(b"jump = \"space\"".to_vec()));
+
+    fs.expect_write_bytes()
+        .once()
+        .with(eq(target.join("keybinds.toml")), mockall::predicate::always())
+        .return_once(|_, _| Ok(()));
+
+    assert_eq!(seed_directory(&fs, &template, &target).unwrap(), vec![target.join("keybinds.toml")]);
+}
@@ -0,0 +1,226 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use lib_voxels_application::application::application::Application;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use toml::{Table, Value};
+use tracing::warn;
+
+use crate::filesystem::FsInt;
+use crate::pathcheck::join_within;
+
+use super::VoxelsDirectoryError;
+
+use super::rdn::{rdn_path, RdnCase, RdnLayout};
+
+use super::voxels_xdg::{LayoutStrategy, Namespace};
+use super::voxels_xdg::xdg::config::ConfigDirectoryResolver;
+
+pub const DEFAULT_SETTINGS_FILE_NAME: &str = "settings.toml";
+
+/// Appended to a settings file while it is written, then renamed over it
+const TEMPORARY_EXTENSION: &str = "tmp";
+
+/// Settings merged from every layer, with the file each leaf value came from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergedConfig {
+    pub value: Table,
+    /// Dotted key, such as `graphics.vsync`, to the settings file that set it
+    pub provenance: BTreeMap<String, PathBuf>,
+}
+
+impl MergedConfig {
+    pub fn source_of(&self, key: &str) -> Option<&Path> {
+        self.provenance.get(key).map(PathBuf::as_path)
+    }
+
+    /// Overlay `layer` read from `source`. Tables are merged key by key, any other value replaces what was there.
+    pub fn merge(&mut self, layer: Table, source: &Path) {
+        merge_table(&mut self.value, layer, source, "", &mut self.provenance);
+    }
+}
+
+fn dotted(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        String::from(key)
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+fn record_leaves(value: &Value, key: String, source: &Path, provenance: &mut BTreeMap<String, PathBuf>) {
+    match value {
+        Value::Table(table) => {
+            for (child, value) in table {
+                record_leaves(value, dotted(&key, child), source, provenance);
+            }
+        },
+        _ => {
+            provenance.insert(key, source.to_path_buf());
+        }
+    }
+}
+
+fn merge_table(into: &mut Table, layer: Table, source: &Path, prefix: &str, provenance: &mut BTreeMap<String, PathBuf>) {
+    for (key, value) in layer {
+        let key_path = dotted(prefix, &key);
+
+        if let (Some(Value::Table(existing)), Value::Table(table)) = (into.get_mut(&key), &value) {
+            merge_table(existing, table.clone(), source, &key_path, provenance);
+            continue;
+        }
+
+        // whatever was beneath this key has been replaced
+        let nested = format!("{}.", key_path);
+        provenance.retain(|recorded, _| *recorded != key_path && !recorded.starts_with(&nested));
+
+        record_leaves(&value, key_path, source, provenance);
+
+        into.insert(key, value);
+    }
+}
+
+/// Reads an application's settings file from every candidate config directory and layers them,
+/// the highest priority candidate winning
+pub struct ConfigStack<BaseT: ConfigDirectoryResolver, FsIntT: FsInt + 'static> {
+    base: BaseT,
+    fs: FsIntT,
+    pub file_name: String,
+    pub namespace: Namespace,
+    pub layout: LayoutStrategy,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+}
+
+impl<BaseT: ConfigDirectoryResolver, FsIntT: FsInt + 'static> ConfigStack<BaseT, FsIntT> {
+    pub fn new(base: BaseT, fs: FsIntT) -> Self {
+        Self {
+            base,
+            fs,
+            file_name: String::from(DEFAULT_SETTINGS_FILE_NAME),
+            namespace: Namespace::default(),
+            layout: LayoutStrategy::default(),
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+        }
+    }
+
+    /// `file_name` in each candidate config directory, highest priority first
+    fn layers_named(&self, application: &Application, file_name: &str) -> Vec<PathBuf> {
+        self.base.resolve_candidates(&self.fs)
+            .into_iter()
+            .filter_map(|(base, _how)| {
+                let application_path = join_within(&self.layout.root(&base, &self.namespace), &rdn_path(application, self.rdn_case, self.rdn_layout)).ok()?;
+
+                join_within(&application_path, Path::new(file_name)).ok()
+            })
+            .collect()
+    }
+
+    /// The settings file in each candidate config directory, highest priority first
+    pub fn layers(&self, application: &Application) -> Vec<PathBuf> {
+        self.layers_named(application, &self.file_name)
+    }
+
+    fn load_named(&self, application: &Application, file_name: &str) -> Result<MergedConfig, VoxelsDirectoryError> {
+        let mut merged = MergedConfig::default();
+
+        for layer in self.layers_named(application, file_name).into_iter().rev() {
+            if !self.fs.exists(&layer) {
+                continue;
+            }
+
+            let contents = self.fs.read_to_string(&layer)?;
+
+            let table: Table = contents.parse().map_err(|err| {
+                warn!("failed to parse settings file {:?}: {}", layer, err);
+                VoxelsDirectoryError::Io(std::io::ErrorKind::InvalidData)
+            })?;
+
+            merged.merge(table, &layer);
+        }
+
+        Ok(merged)
+    }
+
+    pub fn load(&self, application: &Application) -> Result<MergedConfig, VoxelsDirectoryError> {
+        self.load_named(application, &self.file_name)
+    }
+
+    /// Deserialize the merged layers of `file_name` into `T`
+    pub fn load_settings<T: DeserializeOwned>(&self, application: &Application, file_name: &str) -> Result<T, VoxelsDirectoryError> {
+        let merged = self.load_named(application, file_name)?;
+
+        Value::Table(merged.value).try_into().map_err(|err| {
+            warn!("settings file {} does not match the expected shape: {}", file_name, err);
+            VoxelsDirectoryError::Io(std::io::ErrorKind::InvalidData)
+        })
+    }
+
+    /// Write `settings` to `file_name` in the highest priority config directory, returning the written path.
+    /// The file is written beside its destination and renamed over it so readers never see a partial file.
+    pub fn save_settings<T: Serialize>(&self, application: &Application, file_name: &str, settings: &T) -> Result<PathBuf, VoxelsDirectoryError> {
+        let destination = self.layers_named(application, file_name)
+            .into_iter()
+            .next()
+            .ok_or(VoxelsDirectoryError::NoCandidate)?;
+
+        let contents = toml::to_string(settings).map_err(|_| VoxelsDirectoryError::Io(std::io::ErrorKind::InvalidInput))?;
+
+        if let Some(parent) = destination.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+
+        let mut temporary = destination.as_os_str().to_owned();
+        temporary.push(".");
+        temporary.push(TEMPORARY_EXTENSION);
+
+        let temporary = PathBuf::from(temporary);
+
+        self.fs.write(&temporary, &contents)?;
+
+        if let Err(err) = self.fs.rename(&temporary, &destination) {
+            let _ = self.fs.remove_file(&temporary);
+
+            return Err(err.into());
+        }
+
+        Ok(destination)
+    }
+}
+
+#[test]
+fn test_merge_layers() {
+    let system = Path::new("/etc/xdg/voxels/org.voxels.Client/settings.toml");
+    let user = Path::new("/home/user/.config/voxels/org.voxels.Client/settings.toml");
+
+    let mut merged = MergedConfig::default();
+
+    merged.merge("[graphics]\nvsync = true\nscale = 1\n[audio]\nvolume = 50".parse().unwrap(), system);
+    merged.merge("[graphics]\nscale = 2\n[audio]\nvolume = { master = 80 }".parse().unwrap(), user);
+
+    assert_eq!(merged.value["graphics"]["vsync"].as_bool(), Some(true));
+    assert_eq!(merged.value["graphics"]["scale"].as_integer(), Some(2));
+    assert_eq!(merged.source_of("graphics.vsync"), Some(system));
+    assert_eq!(merged.source_of("graphics.scale"), Some(user));
+    assert_eq!(merged.source_of("audio.volume"), None);
+    assert_eq!(merged.source_of("audio.volume.master"), Some(user));
+}
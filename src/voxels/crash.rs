@@ -0,0 +1,197 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lib_voxels_application::application::application::Application;
+
+use crate::clock::{ClockInt, DefaultClockInt};
+use crate::async_filesystem::{create_directory, AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::{CreateOptions, FsInt};
+
+use super::VoxelsDirectoryError;
+
+use super::state::StateDirectoryResolver;
+
+pub const CRASH_DIRECTORY_NAME: &str = "crashes";
+
+pub const CRASH_DUMP_PREFIX: &str = "crash-";
+
+pub const CRASH_DUMP_EXTENSION: &str = "dmp";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CrashRotationPolicy {
+    /// Number of dumps kept, including the one about to be written
+    pub max_count: usize,
+}
+
+impl Default for CrashRotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_count: 10
+        }
+    }
+}
+
+/// Zero padded so that lexical order of the file names is chronological order
+fn dump_file_name(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    format!("{}{:020}-{:09}.{}", CRASH_DUMP_PREFIX, since_epoch.as_secs(), since_epoch.subsec_nanos(), CRASH_DUMP_EXTENSION)
+}
+
+fn is_dump(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+
+    name.starts_with(CRASH_DUMP_PREFIX) && path.extension().is_some_and(|ext| ext == CRASH_DUMP_EXTENSION)
+}
+
+/// Removes the oldest dumps in `directory` so that one more can be written without exceeding `policy`
+fn rotate<FsIntT: FsInt>(fs: &FsIntT, directory: &Path, policy: CrashRotationPolicy) -> Result<(), VoxelsDirectoryError> {
+    let mut dumps: Vec<PathBuf> = fs.read_dir(directory)?
+        .into_iter()
+        .filter(|path| is_dump(path))
+        .collect();
+
+    dumps.sort();
+
+    let keep = policy.max_count.saturating_sub(1);
+
+    if dumps.len() > keep {
+        for dump in &dumps[..dumps.len() - keep] {
+            fs.remove_file(dump)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[mockall::automock]
+pub trait CrashDirectoryResolver {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn new_dump_path(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+/// `<state>/voxels/<rdn>/crashes/`, where crash reporters write their dumps
+pub struct CrashDirectory<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt = DefaultClockInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: Option<PathBuf>,
+    state: StateT,
+    fs: FsIntT,
+    clock: ClockIntT,
+    async_fs: AsyncFsIntT,
+    pub policy: CrashRotationPolicy,
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt> CrashDirectory<StateT, FsIntT> {
+    pub fn new(state: StateT, fs: FsIntT) -> Self {
+        Self::with_clock(state, fs, DefaultClockInt)
+    }
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt> CrashDirectory<StateT, FsIntT, ClockIntT> {
+    /// As `new`, with dump names timestamped by `clock`
+    pub fn with_clock(state: StateT, fs: FsIntT, clock: ClockIntT) -> Self {
+        Self::with_async_fs(state, fs, clock, DefaultAsyncFsInt)
+    }
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt, AsyncFsIntT: AsyncFsInt> CrashDirectory<StateT, FsIntT, ClockIntT, AsyncFsIntT> {
+    /// As `with_clock`, creating the directory through `async_fs`
+    pub fn with_async_fs(state: StateT, fs: FsIntT, clock: ClockIntT, async_fs: AsyncFsIntT) -> Self {
+        Self {
+            path: None,
+            state,
+            fs,
+            clock,
+            async_fs,
+            policy: CrashRotationPolicy::default()
+        }
+    }
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt, AsyncFsIntT: AsyncFsInt> CrashDirectoryResolver for CrashDirectory<StateT, FsIntT, ClockIntT, AsyncFsIntT> {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        if self.is_resolved() {
+            return Ok(self.path.clone().unwrap());
+        }
+
+        let state = self.state.resolve(application).await?;
+
+        let path = state.join(CRASH_DIRECTORY_NAME);
+
+        self.path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        create_directory(&self.async_fs, &resolved, CreateOptions::default()).await?;
+
+        Ok(resolved)
+    }
+
+    /// A fresh, timestamped path for a dump. Old dumps are rotated out first so the
+    /// directory never holds more than `policy.max_count` once the dump is written.
+    async fn new_dump_path(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve_and_create(application).await?;
+
+        rotate(&self.fs, &resolved, self.policy)?;
+
+        Ok(resolved.join(dump_file_name(self.clock.now())))
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+#[test]
+fn test_rotate_removes_oldest() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let directory = PathBuf::from("/state/voxels/org.voxels.Client/crashes");
+
+    let oldest = directory.join(dump_file_name(UNIX_EPOCH + std::time::Duration::from_secs(1)));
+    let middle = directory.join(dump_file_name(UNIX_EPOCH + std::time::Duration::from_secs(2)));
+    let newest = directory.join(dump_file_name(UNIX_EPOCH + std::time::Duration::from_secs(3)));
+
+    fs.expect_read_dir()
+        .once()
+        .return_once({
+            let dumps = vec![newest.clone(), directory.join("notes.txt"), oldest.clone(), middle.clone()];
+            move |_| Ok(dumps)
+        });
+
+    fs.expect_remove_file()
+        .once()
+        .with(mockall::predicate::eq(oldest))
+        .return_once(|_| Ok(()));
+
+    let res = rotate(&fs, &directory, CrashRotationPolicy { max_count: 3 });
+
+    assert!(res.is_ok());
+}
@@ -17,6 +17,20 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::path::PathBuf;
 use crate::voxels::VoxelsDirectoryError;
+use crate::voxels::rdn::{pinned_path, rdn_path_within, RdnCase, RdnLayout};
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::{CreateOptions, DefaultFsInt};
+
+use crate::environment_variables::{DefaultEnvInt, EnvInt};
+use crate::filesystem::FsInt;
+
+use super::DirectoryKind;
+use super::voxels_xdg::{LayoutStrategy, Namespace};
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
+use super::voxels_xdg::xdg::overlay::system_dirs;
+
+#[cfg(feature = "integrity")]
+use super::integrity::{ChecksumManifest, IntegrityReport, INTEGRITY_FILE_NAME};
 
 use super::voxels_xdg::data as base;
 
@@ -34,36 +48,125 @@ pub trait DataDirectoryResolver {
     fn is_resolved(&self) -> bool;
 }
 
-pub struct DataDirectory<BaseT: base::DataDirectoryResolver> {
+pub struct DataDirectory<BaseT: base::DataDirectoryResolver, FsIntT: FsInt = DefaultFsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
     data_path: Option<PathBuf>,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+    /// Replaces `<data>/voxels/<rdn>` when the manifest relocates this application's data
+    pub relocation: Option<PathBuf>,
+    /// Pins read from `VOXELS_DIRS_FILE` when this was built, the entry for the application wins over `relocation`
+    pub dirs_file: Option<DirsFile>,
+    pub create_options: CreateOptions,
     base: BaseT,
+    fs: FsIntT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::DataDirectoryResolver> DataDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_fs(base, DefaultFsInt)
+    }
+}
+
+impl<BaseT: base::DataDirectoryResolver, FsIntT: FsInt> DataDirectory<BaseT, FsIntT> {
+    pub fn with_fs(base: BaseT, fs: FsIntT) -> Self {
+        Self::with_async_fs(base, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> DataDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// As `with_fs`, creating the directory through `async_fs` from `resolve_and_create`
+    pub fn with_async_fs(base: BaseT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        let dirs_file = DirsFile::load(&DefaultEnvInt, &fs);
+
         Self {
             data_path: None,
-            base
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+            relocation: None,
+            dirs_file,
+            create_options: CreateOptions::default(),
+            base,
+            fs,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::DataDirectoryResolver> DataDirectoryResolver for DataDirectory<BaseT> {
+impl<BaseT: base::DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> DataDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// Every directory the application's data is read from, most important first. The first is `resolve`
+    /// and the only one that may be written to, the rest are `<dir>/voxels/<rdn>` for each `XDG_DATA_DIRS`
+    /// entry that exists, such as `/usr/share/voxels/<rdn>` holding worlds and assets packaged by a distribution.
+    pub async fn read_dirs<EnvIntT: EnvInt, ReadFsIntT: FsInt>(&mut self, application: Application, env: &EnvIntT, fs: &ReadFsIntT) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+        let mut dirs = vec![self.resolve(application.clone()).await?];
+
+        for system in system_dirs(env, DirectoryKind::Data) {
+            let root = LayoutStrategy::Flat.root(&system, &Namespace::default());
+
+            let Ok(path) = rdn_path_within(fs, &root, &application, self.rdn_case, self.rdn_layout) else {
+                continue;
+            };
+
+            if fs.is_directory(&path) && !dirs.contains(&path) {
+                dirs.push(path);
+            }
+        }
+
+        Ok(dirs)
+    }
+}
+
+#[cfg(feature = "integrity")]
+impl<BaseT: base::DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> DataDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// Hash every file in the application's data directory into `.voxels-integrity` at its root
+    pub async fn generate_checksums<ReadFsIntT: FsInt>(&mut self, application: Application, fs: &ReadFsIntT) -> Result<ChecksumManifest, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        let manifest = ChecksumManifest::generate(fs, &resolved)?;
+
+        fs.write(&resolved.join(INTEGRITY_FILE_NAME), &manifest.render())?;
+
+        Ok(manifest)
+    }
+
+    /// Compare the application's data directory with the checksums `generate_checksums` last wrote
+    pub async fn verify_checksums<ReadFsIntT: FsInt>(&mut self, application: Application, fs: &ReadFsIntT) -> Result<IntegrityReport, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        let manifest = ChecksumManifest::parse(&fs.read_to_string(&resolved.join(INTEGRITY_FILE_NAME))?);
+
+        Ok(manifest.verify(fs, &resolved)?)
+    }
+}
+
+impl<BaseT: base::DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> DataDirectoryResolver for DataDirectory<BaseT, FsIntT, AsyncFsIntT> {
     async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         // if resolve has been called previously we update this objects path
         if self.is_resolved() {
             return Ok(self.data_path.clone().unwrap());
         }
 
+        if let Some(pinned) = pinned_path(self.dirs_file.as_ref(), &application, DirectoryKind::Data) {
+            return Ok(pinned);
+        }
+
+        if let Some(relocation) = &self.relocation {
+            if !relocation.is_absolute() {
+                return Err(VoxelsDirectoryError::InvalidRelocation);
+            }
+
+            return Ok(relocation.clone());
+        }
+
         let base = self.base.resolve().await?;
 
-        Ok(base.join(application.rdn().as_path()))
+        rdn_path_within(&self.fs, &base, &application, self.rdn_case, self.rdn_layout)
     }
 
     async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve(application).await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
     }
@@ -0,0 +1,243 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::environment_variables::{EnvInt, EnvKey};
+use crate::filesystem::{is_network_filesystem, FsInt};
+
+#[cfg(feature = "dbus")]
+use super::voxels_xdg::dbus::{is_service_available, is_service_registered, service_version, DbusConnection, DbusOptions};
+
+/// Base directory variables the doctor inspects, with the severity of each one being unset
+const ENVIRONMENT_VARIABLES: [(EnvKey, Severity); 10] = [
+    (EnvKey::Home, Severity::Error),
+    (EnvKey::XdgRuntimeDir, Severity::Warning),
+    (EnvKey::XdgConfigHome, Severity::Info),
+    (EnvKey::XdgDataHome, Severity::Info),
+    (EnvKey::XdgStateHome, Severity::Info),
+    (EnvKey::XdgCacheHome, Severity::Info),
+    (EnvKey::VoxelsConfigHome, Severity::Info),
+    (EnvKey::VoxelsDataHome, Severity::Info),
+    (EnvKey::VoxelsStateHome, Severity::Info),
+    (EnvKey::VoxelsCacheHome, Severity::Info),
+];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum Finding {
+    MissingEnvironmentVariable(String),
+    /// The base directory specification requires these to be absolute, relative values are ignored
+    RelativeEnvironmentVariable { variable: String, value: PathBuf },
+    Permissions { path: PathBuf, mode: u32 },
+    DanglingSymlink(PathBuf),
+    /// A variable names a file or other non-directory, so nothing can be resolved beneath it
+    NotADirectory(PathBuf),
+    OwnershipMismatch { path: PathBuf, owner: u32, expected: u32 },
+    /// World databases are slow and locking is unreliable over the network,
+    /// point the cache and runtime directories at local storage
+    NetworkFilesystem { path: PathBuf, filesystem: String },
+    /// Nothing owns the directories service's name on the session bus
+    DBusUnavailable,
+    /// The directories service owns its name but does not answer or report its version
+    DBusServiceBroken,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Issue {
+    pub severity: Severity,
+    pub finding: Finding,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DoctorReport {
+    pub issues: Vec<Issue>,
+}
+
+impl DoctorReport {
+    pub fn worst(&self) -> Option<Severity> {
+        self.issues.iter().map(|issue| issue.severity).max()
+    }
+
+    /// Nothing worse than informational findings
+    pub fn is_healthy(&self) -> bool {
+        self.worst().is_none_or(|severity| severity == Severity::Info)
+    }
+
+    fn push(&mut self, severity: Severity, finding: Finding) {
+        self.issues.push(Issue { severity, finding });
+    }
+}
+
+/// Diagnoses why directories fail to resolve on a system
+pub struct Doctor<EnvIntT: EnvInt, FsIntT: FsInt> {
+    env: EnvIntT,
+    fs: FsIntT,
+}
+
+impl<EnvIntT: EnvInt, FsIntT: FsInt> Doctor<EnvIntT, FsIntT> {
+    pub fn new(env: EnvIntT, fs: FsIntT) -> Self {
+        Self {
+            env,
+            fs
+        }
+    }
+
+    /// Check the environment and every directory it names
+    pub fn run(&self) -> DoctorReport {
+        let mut report = DoctorReport::default();
+
+        let expected_owner = self.env.get_path(EnvKey::Home).ok()
+            .and_then(|home| self.fs.owner(&home).ok());
+
+        for (variable, missing_severity) in ENVIRONMENT_VARIABLES {
+            let Ok(value) = self.env.get_path(variable) else {
+                report.push(missing_severity, Finding::MissingEnvironmentVariable(String::from(variable.name())));
+                continue;
+            };
+
+            if !value.is_absolute() {
+                report.push(Severity::Warning, Finding::RelativeEnvironmentVariable { variable: String::from(variable.name()), value });
+                continue;
+            }
+
+            self.check_directory(&mut report, &value, variable == EnvKey::XdgRuntimeDir, expected_owner);
+        }
+
+        report
+    }
+
+    fn check_directory(&self, report: &mut DoctorReport, path: &Path, runtime: bool, expected_owner: Option<u32>) {
+        if self.fs.is_symlink(path) && !self.fs.exists(path) {
+            report.push(Severity::Error, Finding::DanglingSymlink(path.to_path_buf()));
+            return;
+        }
+
+        if !self.fs.exists(path) {
+            return;
+        }
+
+        if !self.fs.is_directory(path) {
+            report.push(Severity::Error, Finding::NotADirectory(path.to_path_buf()));
+            return;
+        }
+
+        if let Ok(mode) = self.fs.mode(path) {
+            // the runtime directory must be 0700, the others only must not be writable by other users
+            if runtime && mode & 0o777 != 0o700 {
+                report.push(Severity::Error, Finding::Permissions { path: path.to_path_buf(), mode });
+            } else if !runtime && mode & 0o022 != 0 {
+                report.push(Severity::Warning, Finding::Permissions { path: path.to_path_buf(), mode });
+            }
+        }
+
+        if let (Some(expected), Ok(owner)) = (expected_owner, self.fs.owner(path))
+            && owner != expected {
+            let severity = if runtime { Severity::Error } else { Severity::Warning };
+
+            report.push(severity, Finding::OwnershipMismatch { path: path.to_path_buf(), owner, expected });
+        }
+
+        if let Ok(filesystem) = self.fs.filesystem_type(path)
+            && is_network_filesystem(&filesystem) {
+            report.push(Severity::Warning, Finding::NetworkFilesystem { path: path.to_path_buf(), filesystem });
+        }
+    }
+
+    /// As `run`, also checking the directories service is reachable and answering on the session bus
+    #[cfg(feature = "dbus")]
+    pub async fn run_with_dbus(&self) -> DoctorReport {
+        let mut report = self.run();
+
+        if let Some(finding) = directories_service_finding().await {
+            report.push(Severity::Warning, finding);
+        }
+
+        report
+    }
+}
+
+/// `None` when the directories service is healthy, otherwise whether it is missing or broken
+#[cfg(feature = "dbus")]
+async fn directories_service_finding() -> Option<Finding> {
+    let options = DbusOptions::default();
+
+    let Ok(bus) = DbusConnection::open(options, None) else {
+        return Some(Finding::DBusUnavailable);
+    };
+
+    let connection = bus.connection();
+
+    if !is_service_registered(connection.clone(), options).await {
+        Some(Finding::DBusUnavailable)
+    } else if !is_service_available(connection.clone(), options).await || service_version(connection, options).await.is_err() {
+        Some(Finding::DBusServiceBroken)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_doctor_report() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    env.expect_get_path()
+        .returning(|name| match name {
+            EnvKey::Home => Ok(PathBuf::from("/home/user")),
+            EnvKey::XdgRuntimeDir => Ok(PathBuf::from("/run/user/1000")),
+            EnvKey::XdgConfigHome => Ok(PathBuf::from("config")),
+            EnvKey::VoxelsDataHome => Ok(PathBuf::from("/mnt/voxels")),
+            EnvKey::XdgCacheHome => Ok(PathBuf::from("/home/user/.cache")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    fs.expect_is_symlink()
+        .returning(|path| path == Path::new("/mnt/voxels"));
+
+    fs.expect_exists()
+        .returning(|path| path != Path::new("/mnt/voxels"));
+
+    // a file where the cache directory should be
+    fs.expect_is_directory()
+        .returning(|path| path != Path::new("/home/user/.cache"));
+
+    fs.expect_mode()
+        .returning(|path| Ok(if path == Path::new("/run/user/1000") { 0o755 } else { 0o700 }));
+
+    fs.expect_owner()
+        .returning(|_| Ok(1000));
+
+    fs.expect_filesystem_type()
+        .returning(|path| Ok(String::from(if path.starts_with("/home") { "nfs4" } else { "ext4" })));
+
+    let report = Doctor::new(env, fs).run();
+
+    assert_eq!(report.worst(), Some(Severity::Error));
+    assert!(report.issues.contains(&Issue { severity: Severity::Error, finding: Finding::Permissions { path: PathBuf::from("/run/user/1000"), mode: 0o755 } }));
+    assert!(report.issues.contains(&Issue { severity: Severity::Error, finding: Finding::DanglingSymlink(PathBuf::from("/mnt/voxels")) }));
+    assert!(report.issues.contains(&Issue { severity: Severity::Error, finding: Finding::NotADirectory(PathBuf::from("/home/user/.cache")) }));
+    assert!(report.issues.contains(&Issue { severity: Severity::Warning, finding: Finding::RelativeEnvironmentVariable { variable: String::from("XDG_CONFIG_HOME"), value: PathBuf::from("config") } }));
+    assert!(report.issues.contains(&Issue { severity: Severity::Info, finding: Finding::MissingEnvironmentVariable(String::from("XDG_DATA_HOME")) }));
+    assert!(report.issues.contains(&Issue { severity: Severity::Warning, finding: Finding::NetworkFilesystem { path: PathBuf::from("/home/user"), filesystem: String::from("nfs4") } }));
+}
@@ -0,0 +1,118 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::pathcheck::normalize;
+
+use super::DirectoryKind;
+
+/// Where a path sits in the voxels hierarchy
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Explanation {
+    pub kind: DirectoryKind,
+    /// The RDN of the application the path belongs to, `None` for the voxels directory itself
+    pub application: Option<String>,
+    /// The rest of the path beneath the application's directory
+    pub relative: PathBuf,
+}
+
+impl Explanation {
+    /// `key=value` lines for tools that classify files
+    pub fn render(&self) -> String {
+        let mut out = format!("kind={}\n", self.kind.name());
+
+        if let Some(application) = &self.application {
+            out.push_str(&format!("application={}\n", application));
+        }
+
+        out.push_str(&format!("relative={}\n", self.relative.display()));
+
+        out
+    }
+}
+
+/// The resolved voxels directories a path is explained against
+#[derive(Debug, Clone, Default)]
+pub struct Hierarchy {
+    roots: BTreeMap<DirectoryKind, PathBuf>,
+    relocations: Vec<(DirectoryKind, String, PathBuf)>,
+}
+
+impl Hierarchy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `<base>/voxels` as resolved for `kind`
+    pub fn insert(&mut self, kind: DirectoryKind, root: &Path) {
+        self.roots.insert(kind, normalize(root));
+    }
+
+    /// Record that `application` relocated its `kind` directory to `path`
+    pub fn insert_relocation(&mut self, kind: DirectoryKind, application: String, path: &Path) {
+        self.relocations.push((kind, application, normalize(path)));
+    }
+
+    /// Which directory kind and application `path` belongs to, `None` when it is outside every known directory.
+    /// When directories nest the deepest one wins.
+    pub fn explain(&self, path: &Path) -> Option<Explanation> {
+        let path = normalize(path);
+
+        let relocated = self.relocations.iter()
+            .filter_map(|(kind, application, root)| {
+                let relative = path.strip_prefix(root).ok()?;
+
+                Some((root.components().count(), Explanation { kind: *kind, application: Some(application.clone()), relative: relative.to_path_buf() }))
+            });
+
+        let rooted = self.roots.iter()
+            .filter_map(|(kind, root)| {
+                let relative = path.strip_prefix(root).ok()?;
+
+                let mut components = relative.components();
+
+                let application = components.next().map(|rdn| rdn.as_os_str().to_string_lossy().into_owned());
+
+                Some((root.components().count(), Explanation { kind: *kind, application, relative: components.as_path().to_path_buf() }))
+            });
+
+        relocated.chain(rooted)
+            .max_by_key(|(depth, _)| *depth)
+            .map(|(_, explanation)| explanation)
+    }
+}
+
+#[test]
+fn test_explain() {
+    let mut hierarchy = Hierarchy::new();
+
+    hierarchy.insert(DirectoryKind::Config, Path::new("/home/user/.config/voxels/"));
+    hierarchy.insert(DirectoryKind::Data, Path::new("/home/user/.local/share/voxels"));
+    hierarchy.insert_relocation(DirectoryKind::Data, String::from("org.voxels.Client"), Path::new("/home/user/.local/share/voxels/worlds"));
+
+    assert_eq!(hierarchy.explain(Path::new("/home/user/.config/voxels/org.voxels.Client/keys.toml")), Some(Explanation {
+        kind: DirectoryKind::Config,
+        application: Some(String::from("org.voxels.Client")),
+        relative: PathBuf::from("keys.toml"),
+    }));
+
+    assert_eq!(hierarchy.explain(Path::new("/home/user/.local/share/voxels/worlds/a/level.dat")).unwrap().relative, PathBuf::from("a/level.dat"));
+    assert_eq!(hierarchy.explain(Path::new("/home/user/.config/voxels")).unwrap().application, None);
+    assert_eq!(hierarchy.explain(Path::new("/etc/passwd")), None);
+}
@@ -0,0 +1,239 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use lib_voxels_application::application::application::Application;
+
+use crate::filesystem::{AclAccess, FsInt};
+use crate::pathcheck::{check_contained, join_within};
+
+use super::VoxelsDirectoryError;
+
+use super::application_directory::{BoundDirectory, KindResolver};
+use super::audit::{audit_grant, AuditOperation};
+
+/// Begins the name of the Unix group an application runs with to be granted access to other applications' directories
+pub const APPLICATION_GROUP_PREFIX: &str = "voxels-";
+
+/// Longest group name groupadd(8) accepts
+pub const GROUP_NAME_MAX_LENGTH: usize = 32;
+
+/// Hex digits of the RDN hash ending every application group
+const APPLICATION_GROUP_HASH_LENGTH: usize = 8;
+
+/// FNV-1a, stable across builds and platforms unlike `std::hash`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3))
+}
+
+/// The group the application `rdn` must run with to receive grants: `voxels-`, the lowercased RDN with anything
+/// other than ASCII letters and digits as `-` cut to fit, then a hash of the exact RDN. RDNs differing only in case or
+/// punctuation, or in what was cut, get different groups, and the name always fits in `GROUP_NAME_MAX_LENGTH`.
+pub fn application_group(rdn: &str) -> String {
+    let readable_length = GROUP_NAME_MAX_LENGTH - APPLICATION_GROUP_PREFIX.len() - APPLICATION_GROUP_HASH_LENGTH - 1;
+
+    let mut group = String::from(APPLICATION_GROUP_PREFIX);
+
+    group.extend(rdn.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .take(readable_length));
+
+    group.push_str(&format!("-{:08x}", fnv1a(rdn.as_bytes()) as u32));
+
+    group
+}
+
+/// Reject `group` unless groupadd(8) would accept it: at most `GROUP_NAME_MAX_LENGTH` lowercase ASCII letters,
+/// digits, `-` and `_`, not starting with `-`
+pub fn validate_group(group: &str) -> Result<(), VoxelsDirectoryError> {
+    if group.is_empty() || group.len() > GROUP_NAME_MAX_LENGTH || group.starts_with('-') {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    if !group.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    Ok(())
+}
+
+/// `relative` within `root`, which must exist and must not leave `root` lexically or through a symlink
+pub fn exported_path<FsIntT: FsInt>(fs: &FsIntT, root: &Path, relative: &Path) -> Result<PathBuf, VoxelsDirectoryError> {
+    let path = join_within(root, relative)?;
+
+    check_contained(fs, root, &path)?;
+
+    if !fs.exists(&path) {
+        return Err(VoxelsDirectoryError::NoCandidate);
+    }
+
+    Ok(path)
+}
+
+/// Set, or remove when `access` is `None`, the ACL entry of `group` on `path` and everything beneath it.
+/// Symlinks are skipped, setfacl(1) would change what they point at.
+fn apply_to_tree<FsIntT: FsInt>(fs: &FsIntT, path: &Path, group: &str, access: Option<AclAccess>) -> Result<(), VoxelsDirectoryError> {
+    if fs.is_symlink(path) {
+        return Ok(());
+    }
+
+    match access {
+        Some(access) => fs.set_group_acl(path, group, access)?,
+        None => fs.remove_group_acl(path, group)?,
+    }
+
+    if fs.is_directory(path) {
+        for entry in fs.read_dir(path)? {
+            apply_to_tree(fs, &entry, group, access)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Give `group` read access to `path` and everything beneath it, and search access to the directories from
+/// `root` down to it that it has no entry on yet, so a directory it may already read is not narrowed
+pub fn grant_read<FsIntT: FsInt>(fs: &FsIntT, root: &Path, path: &Path, group: &str) -> Result<(), VoxelsDirectoryError> {
+    validate_group(group)?;
+
+    for ancestor in path.ancestors().skip(1).take_while(|ancestor| ancestor.starts_with(root)) {
+        if fs.group_acl(ancestor, group)?.is_none() {
+            fs.set_group_acl(ancestor, group, AclAccess::Search)?;
+        }
+    }
+
+    apply_to_tree(fs, path, group, Some(AclAccess::Read))
+}
+
+/// Remove the entries of `group` from `path` and everything beneath it. Search access to the directories
+/// above is left, other grants to the same group may pass through them.
+pub fn revoke_read<FsIntT: FsInt>(fs: &FsIntT, path: &Path, group: &str) -> Result<(), VoxelsDirectoryError> {
+    validate_group(group)?;
+
+    apply_to_tree(fs, path, group, None)
+}
+
+impl<ResolverT: KindResolver> BoundDirectory<ResolverT> {
+    /// Let the application `to` read `relative`, a file or directory within this application's directory,
+    /// through POSIX ACLs for its group, see `application_group`. Returns the path shared.
+    /// The directories above this application's are not changed and must already be passable by that group.
+    pub async fn grant_access<FsIntT: FsInt>(&mut self, fs: &FsIntT, to: &Application, relative: &Path) -> Result<PathBuf, VoxelsDirectoryError> {
+        let root = self.resolve().await?;
+        let path = exported_path(fs, &root, relative)?;
+        let grantee = to.rdn().as_path().to_string_lossy().into_owned();
+
+        grant_read(fs, &root, &path, &application_group(&grantee))?;
+
+        audit_grant(self.audit.as_ref(), AuditOperation::Grant, &self.application.rdn().as_path().to_string_lossy(), &path, &grantee);
+
+        Ok(path)
+    }
+
+    /// Take back access `grant_access` gave `to` to `relative`, returning the path no longer shared
+    pub async fn revoke_access<FsIntT: FsInt>(&mut self, fs: &FsIntT, to: &Application, relative: &Path) -> Result<PathBuf, VoxelsDirectoryError> {
+        let root = self.resolve().await?;
+        let path = exported_path(fs, &root, relative)?;
+        let grantee = to.rdn().as_path().to_string_lossy().into_owned();
+
+        revoke_read(fs, &path, &application_group(&grantee))?;
+
+        audit_grant(self.audit.as_ref(), AuditOperation::Revoke, &self.application.rdn().as_path().to_string_lossy(), &path, &grantee);
+
+        Ok(path)
+    }
+}
+
+#[test]
+fn test_application_group() {
+    assert!(application_group("org.voxels.Viewer").starts_with("voxels-org-voxels-vie"));
+    assert_eq!(application_group("org.voxels.Viewer"), application_group("org.voxels.Viewer"));
+
+    // punctuation and case that slugging folds away still give distinct groups
+    assert_ne!(application_group("org.a-b.App"), application_group("org.a_b.App"));
+    assert_ne!(application_group("org.voxels.App"), application_group("org.voxels.app"));
+
+    // as do RDNs that only differ past what fits
+    let long = "org.example.games.voxels.SomeVeryLongApplicationName";
+
+    assert_ne!(application_group(long), application_group(&format!("{}2", long)));
+
+    for rdn in ["io.my_org.App2", long, "org.a-b.App"] {
+        assert!(application_group(rdn).len() <= GROUP_NAME_MAX_LENGTH);
+        assert_eq!(validate_group(&application_group(rdn)), Ok(()));
+    }
+}
+
+#[test]
+fn test_validate_group() {
+    assert_eq!(validate_group("voxels-org-voxels-viewer"), Ok(()));
+    assert_eq!(validate_group("voxels-org-example-games-voxels-someveryl"), Err(VoxelsDirectoryError::InvalidName));
+    assert_eq!(validate_group("Voxels"), Err(VoxelsDirectoryError::InvalidName));
+    assert_eq!(validate_group("-voxels"), Err(VoxelsDirectoryError::InvalidName));
+    assert_eq!(validate_group(""), Err(VoxelsDirectoryError::InvalidName));
+}
+
+#[test]
+fn test_grant_read() {
+    use mockall::predicate::eq;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let root = PathBuf::from("/data/org.voxels.Editor");
+    let maps = root.join("exports/maps");
+    let group = "voxels-org-voxels-viewer";
+
+    fs.expect_is_symlink()
+        .returning({
+            let link = maps.join("link");
+            move |path| path == link
+        });
+
+    fs.expect_is_directory()
+        .returning(|path| path.extension().is_none());
+
+    fs.expect_read_dir()
+        .with(eq(maps.clone()))
+        .return_once({
+            let entries = vec![maps.join("spawn.map"), maps.join("link")];
+            move |_| Ok(entries)
+        });
+
+    // the root already lets the group read it, so only the directory between it and the grant gains search access
+    fs.expect_group_acl()
+        .returning({
+            let root = root.clone();
+            move |path, _| Ok((path == root).then_some(AclAccess::Read))
+        });
+
+    fs.expect_set_group_acl()
+        .once()
+        .with(eq(root.join("exports")), eq(group), eq(AclAccess::Search))
+        .return_once(|_, _, _| Ok(()));
+
+    fs.expect_set_group_acl()
+        .once()
+        .with(eq(maps.clone()), eq(group), eq(AclAccess::Read))
+        .return_once(|_, _, _| Ok(()));
+
+    fs.expect_set_group_acl()
+        .once()
+        .with(eq(maps.join("spawn.map")), eq(group), eq(AclAccess::Read))
+        .return_once(|_, _, _| Ok(()));
+
+    assert_eq!(grant_read(&fs, &root, &maps, group), Ok(()));
+}
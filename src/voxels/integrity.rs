@@ -0,0 +1,167 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::filesystem::FsInt;
+
+/// Written at the root of a checked directory, in the format `b3sum --check` reads
+pub const INTEGRITY_FILE_NAME: &str = ".voxels-integrity";
+
+/// BLAKE3 hashes of every regular file beneath a directory, keyed by path relative to it
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ChecksumManifest {
+    hashes: BTreeMap<PathBuf, String>,
+}
+
+/// Files whose contents differ from a `ChecksumManifest`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct IntegrityReport {
+    pub mismatched: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+    /// Present on disk but not in the manifest, new files are not necessarily corrupt
+    pub unexpected: Vec<PathBuf>,
+}
+
+impl IntegrityReport {
+    /// Nothing recorded was changed or lost
+    pub fn is_intact(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl ChecksumManifest {
+    /// Hash every regular file beneath `root`. Symlinks are not followed and the integrity file itself is skipped.
+    pub fn generate<FsIntT: FsInt>(fs: &FsIntT, root: &Path) -> std::io::Result<Self> {
+        let mut manifest = ChecksumManifest::default();
+
+        manifest.add_tree(fs, root, Path::new(""))?;
+
+        Ok(manifest)
+    }
+
+    fn add_tree<FsIntT: FsInt>(&mut self, fs: &FsIntT, root: &Path, relative: &Path) -> std::io::Result<()> {
+        for entry in fs.read_dir(&root.join(relative))? {
+            let Some(name) = entry.file_name() else {
+                continue;
+            };
+
+            let entry_relative = relative.join(name);
+
+            if fs.is_symlink(&entry) || entry_relative.as_os_str() == INTEGRITY_FILE_NAME {
+                continue;
+            }
+
+            if fs.is_directory(&entry) {
+                self.add_tree(fs, root, &entry_relative)?;
+            } else {
+                self.hashes.insert(entry_relative, blake3::hash(&fs.read(&entry)?).to_hex().to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, relative: &Path) -> Option<&str> {
+        self.hashes.get(relative).map(String::as_str)
+    }
+
+    /// `<hex hash>  <relative path>` per line
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (path, hash) in &self.hashes {
+            out.push_str(&format!("{}  {}\n", hash, path.display()));
+        }
+
+        out
+    }
+
+    /// Inverse of render, malformed lines are ignored
+    pub fn parse(contents: &str) -> Self {
+        let mut manifest = ChecksumManifest::default();
+
+        for line in contents.lines() {
+            let Some((hash, path)) = line.split_once("  ") else {
+                continue;
+            };
+
+            if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) || path.is_empty() {
+                continue;
+            }
+
+            manifest.hashes.insert(PathBuf::from(path), String::from(hash));
+        }
+
+        manifest
+    }
+
+    /// Compare the files beneath `root` with this manifest
+    pub fn verify<FsIntT: FsInt>(&self, fs: &FsIntT, root: &Path) -> std::io::Result<IntegrityReport> {
+        let current = Self::generate(fs, root)?;
+
+        let mut report = IntegrityReport::default();
+
+        for (path, hash) in &self.hashes {
+            match current.hashes.get(path) {
+                Some(current_hash) if current_hash == hash => {},
+                Some(_) => report.mismatched.push(path.clone()),
+                None => report.missing.push(path.clone()),
+            }
+        }
+
+        report.unexpected = current.hashes.into_keys()
+            .filter(|path| !self.hashes.contains_key(path))
+            .collect();
+
+        Ok(report)
+    }
+}
+
+#[test]
+fn test_verify_checksums() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let root = PathBuf::from("/data");
+
+    fs.expect_read_dir()
+        .returning(|_| Ok(vec![PathBuf::from("/data/world.dat"), PathBuf::from("/data/new.dat"), PathBuf::from("/data/.voxels-integrity")]));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| false);
+
+    fs.expect_read()
+        .returning(|path| Ok(path.to_string_lossy().into_owned().into_bytes()));
+
+    let mut manifest = ChecksumManifest::parse(&ChecksumManifest::generate(&fs, &root).unwrap().render());
+
+    assert!(manifest.verify(&fs, &root).unwrap().is_intact());
+
+    manifest.hashes.insert(PathBuf::from("world.dat"), "0".repeat(64));
+    manifest.hashes.insert(PathBuf::from("lost.dat"), "0".repeat(64));
+    manifest.hashes.remove(Path::new("new.dat"));
+
+    assert_eq!(manifest.verify(&fs, &root).unwrap(), IntegrityReport {
+        mismatched: vec![PathBuf::from("world.dat")],
+        missing: vec![PathBuf::from("lost.dat")],
+        unexpected: vec![PathBuf::from("new.dat")],
+    });
+}
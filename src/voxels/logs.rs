@@ -0,0 +1,257 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lib_voxels_application::application::application::Application;
+
+use crate::clock::{ClockInt, DefaultClockInt};
+use crate::async_filesystem::{create_directory, AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::{CreateOptions, FsInt};
+use crate::pathcheck::join_within;
+
+use super::VoxelsDirectoryError;
+
+use super::state::StateDirectoryResolver;
+
+pub const LOGS_DIRECTORY_NAME: &str = "logs";
+
+#[cfg(feature = "compression")]
+pub const COMPRESSED_LOG_EXTENSION: &str = "gz";
+
+/// When the active log file should be rotated and how many rotated files are kept
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct LogRotationPolicy {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_rotated: usize,
+}
+
+impl Default for LogRotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_size: Some(10 * 1024 * 1024),
+            max_age: Some(Duration::from_secs(7 * 24 * 60 * 60)),
+            max_rotated: 5,
+        }
+    }
+}
+
+impl LogRotationPolicy {
+    pub fn needs_rotation(&self, size: u64, age: Duration) -> bool {
+        let too_large = self.max_size.is_some_and(|max_size| size >= max_size);
+
+        let too_old = self.max_age.is_some_and(|max_age| age >= max_age);
+
+        too_large || too_old
+    }
+}
+
+/// `<file_name>.<unix seconds>`, zero padded so lexical order is chronological order
+fn rotated_file_name(file_name: &str, time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    format!("{}.{:020}", file_name, since_epoch.as_secs())
+}
+
+#[cfg(feature = "compression")]
+fn compress<FsIntT: FsInt>(fs: &FsIntT, path: &Path) -> Result<PathBuf, VoxelsDirectoryError> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+
+    encoder.write_all(&fs.read(path)?)?;
+
+    let compressed = encoder.finish()?;
+
+    let mut compressed_path = path.as_os_str().to_owned();
+    compressed_path.push(".");
+    compressed_path.push(COMPRESSED_LOG_EXTENSION);
+
+    let compressed_path = PathBuf::from(compressed_path);
+
+    fs.write_bytes(&compressed_path, &compressed)?;
+
+    fs.remove_file(path)?;
+
+    Ok(compressed_path)
+}
+
+/// Removes the oldest rotated copies of `file_name` until at most `max_rotated` remain
+fn prune_rotated<FsIntT: FsInt>(fs: &FsIntT, directory: &Path, file_name: &str, max_rotated: usize) -> Result<(), VoxelsDirectoryError> {
+    let prefix = format!("{}.", file_name);
+
+    let mut rotated: Vec<PathBuf> = fs.read_dir(directory)?
+        .into_iter()
+        .filter(|path| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.starts_with(&prefix)))
+        .collect();
+
+    rotated.sort();
+
+    if rotated.len() > max_rotated {
+        for old in &rotated[..rotated.len() - max_rotated] {
+            fs.remove_file(old)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[mockall::automock]
+pub trait LogsDirectoryResolver {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn rotate_if_needed(&mut self, application: Application, file_name: String, policy: LogRotationPolicy) -> Result<Option<PathBuf>, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+/// `<state>/voxels/<rdn>/logs/`, the XDG specification places logs under the state directory
+pub struct LogsDirectory<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt = DefaultClockInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: Option<PathBuf>,
+    state: StateT,
+    fs: FsIntT,
+    clock: ClockIntT,
+    async_fs: AsyncFsIntT,
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt> LogsDirectory<StateT, FsIntT> {
+    pub fn new(state: StateT, fs: FsIntT) -> Self {
+        Self::with_clock(state, fs, DefaultClockInt)
+    }
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt> LogsDirectory<StateT, FsIntT, ClockIntT> {
+    /// As `new`, with log ages and rotation timestamps taken from `clock`
+    pub fn with_clock(state: StateT, fs: FsIntT, clock: ClockIntT) -> Self {
+        Self::with_async_fs(state, fs, clock, DefaultAsyncFsInt)
+    }
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt, AsyncFsIntT: AsyncFsInt> LogsDirectory<StateT, FsIntT, ClockIntT, AsyncFsIntT> {
+    /// As `with_clock`, creating the directory through `async_fs`
+    pub fn with_async_fs(state: StateT, fs: FsIntT, clock: ClockIntT, async_fs: AsyncFsIntT) -> Self {
+        Self {
+            path: None,
+            state,
+            fs,
+            clock,
+            async_fs
+        }
+    }
+}
+
+impl<StateT: StateDirectoryResolver, FsIntT: FsInt, ClockIntT: ClockInt, AsyncFsIntT: AsyncFsInt> LogsDirectoryResolver for LogsDirectory<StateT, FsIntT, ClockIntT, AsyncFsIntT> {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        if self.is_resolved() {
+            return Ok(self.path.clone().unwrap());
+        }
+
+        let state = self.state.resolve(application).await?;
+
+        let path = state.join(LOGS_DIRECTORY_NAME);
+
+        self.path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        create_directory(&self.async_fs, &resolved, CreateOptions::default()).await?;
+
+        Ok(resolved)
+    }
+
+    /// Moves `<logs>/<file_name>` aside when it breaches `policy`, returning where it was moved to.
+    /// With the `compression` feature the rotated file is gzipped.
+    async fn rotate_if_needed(&mut self, application: Application, file_name: String, policy: LogRotationPolicy) -> Result<Option<PathBuf>, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        let active = join_within(&resolved, Path::new(&file_name))?;
+
+        if !self.fs.exists(&active) {
+            return Ok(None);
+        }
+
+        let now = self.clock.now();
+
+        let size = self.fs.file_size(&active)?;
+
+        let age = now.duration_since(self.fs.modified(&active)?).unwrap_or_default();
+
+        if !policy.needs_rotation(size, age) {
+            return Ok(None);
+        }
+
+        let rotated = resolved.join(rotated_file_name(&file_name, now));
+
+        self.fs.rename(&active, &rotated)?;
+
+        #[cfg(feature = "compression")]
+        let rotated = compress(&self.fs, &rotated)?;
+
+        prune_rotated(&self.fs, &resolved, &file_name, policy.max_rotated)?;
+
+        Ok(Some(rotated))
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+#[test]
+fn test_needs_rotation() {
+    let policy = LogRotationPolicy {
+        max_size: Some(100),
+        max_age: Some(Duration::from_secs(60)),
+        max_rotated: 1,
+    };
+
+    assert!(!policy.needs_rotation(10, Duration::from_secs(10)));
+    assert!(policy.needs_rotation(100, Duration::from_secs(10)));
+    assert!(policy.needs_rotation(10, Duration::from_secs(60)));
+}
+
+#[test]
+fn test_prune_rotated() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let directory = PathBuf::from("/logs");
+
+    let older = directory.join(rotated_file_name("client.log", UNIX_EPOCH + Duration::from_secs(1)));
+    let newer = directory.join(rotated_file_name("client.log", UNIX_EPOCH + Duration::from_secs(2)));
+
+    fs.expect_read_dir()
+        .once()
+        .return_once({
+            let entries = vec![directory.join("client.log"), newer.clone(), older.clone()];
+            move |_| Ok(entries)
+        });
+
+    fs.expect_remove_file()
+        .once()
+        .with(mockall::predicate::eq(older))
+        .return_once(|_| Ok(()));
+
+    assert!(prune_rotated(&fs, &directory, "client.log", 1).is_ok());
+}
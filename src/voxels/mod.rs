@@ -15,37 +15,211 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
+use crate::environment_variables::EnvKey;
 use voxels_xdg::xdg::BaseDirectoryError;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_APPS_PATH: &str = "/apps";
 
+/// Each installed application is a `<applications>/<rdn>/manifest.toml`
+pub const MANIFEST_FILE_NAME: &str = "manifest.toml";
+
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum DirectoryKind {
+    Config,
+    Data,
+    State,
+    Cache,
+    Runtime
+}
+
+impl DirectoryKind {
+    pub const ALL: [DirectoryKind; 5] = [
+        DirectoryKind::Config,
+        DirectoryKind::Data,
+        DirectoryKind::State,
+        DirectoryKind::Cache,
+        DirectoryKind::Runtime,
+    ];
+
+    /// The name used for this kind in manifests and as the DBus method name
+    pub fn name(&self) -> &'static str {
+        match self {
+            DirectoryKind::Config => "config",
+            DirectoryKind::Data => "data",
+            DirectoryKind::State => "state",
+            DirectoryKind::Cache => "cache",
+            DirectoryKind::Runtime => "runtime",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        DirectoryKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    /// The environment variable that overrides the base directory of this kind
+    pub fn home_key(&self) -> EnvKey {
+        match self {
+            DirectoryKind::Config => EnvKey::VoxelsConfigHome,
+            DirectoryKind::Data => EnvKey::VoxelsDataHome,
+            DirectoryKind::State => EnvKey::VoxelsStateHome,
+            DirectoryKind::Cache => EnvKey::VoxelsCacheHome,
+            DirectoryKind::Runtime => EnvKey::VoxelsRuntimeHome,
+        }
+    }
+
+    pub fn home_variable(&self) -> &'static str {
+        self.home_key().name()
+    }
+}
+
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub enum VoxelsDirectoryError {
-    NoCandidate
+    NoCandidate,
+    Io(std::io::ErrorKind),
+    InvalidName,
+    InvalidRelocation,
+    /// D-Bus resolution was skipped because resolution is offline or no address is known for the bus it would connect to
+    NoBus,
+    /// A candidate exists but is a file or a dangling symlink rather than a directory
+    NotADirectory,
+    /// A UTF-8 path was asked for but the path is not valid UTF-8
+    NotUtf8,
+    /// The verifier refused the only candidate, saying why
+    Rejected(voxels_xdg::xdg::verifier::VerificationError),
+    /// The FHS fallback was asked for but the crate was built with `no-fhs`,
+    /// so only explicitly set variables are honoured
+    FhsDisabled,
+    /// The manifest source lists no installed applications, which would make every application directory look orphaned
+    NothingInstalled,
 }
 
 impl From<BaseDirectoryError> for VoxelsDirectoryError {
     fn from(err: BaseDirectoryError) -> Self {
         match err {
-            BaseDirectoryError::NoCandidate => VoxelsDirectoryError::NoCandidate
+            BaseDirectoryError::NoCandidate => VoxelsDirectoryError::NoCandidate,
+            BaseDirectoryError::FhsDisabled => VoxelsDirectoryError::FhsDisabled,
+            BaseDirectoryError::Io(kind) => VoxelsDirectoryError::Io(kind),
+            BaseDirectoryError::NotADirectory(_) | BaseDirectoryError::DanglingSymlink(_) => VoxelsDirectoryError::NotADirectory,
+            BaseDirectoryError::NotUtf8(_) => VoxelsDirectoryError::NotUtf8,
+            BaseDirectoryError::Rejected(_, reason) => VoxelsDirectoryError::Rejected(reason),
+            BaseDirectoryError::InvalidName(_) => VoxelsDirectoryError::InvalidName,
         }
     }
 }
+
+impl From<crate::pathcheck::PathCheckError> for VoxelsDirectoryError {
+    fn from(_: crate::pathcheck::PathCheckError) -> Self {
+        VoxelsDirectoryError::InvalidName
+    }
+}
+
+impl From<std::io::Error> for VoxelsDirectoryError {
+    fn from(err: std::io::Error) -> Self {
+        VoxelsDirectoryError::Io(err.kind())
+    }
+}
 pub mod voxels_xdg;
 
 #[allow(dead_code)]
-#[cfg(feature = "application")]
+#[cfg(feature = "data")]
 pub mod data;
 
 #[allow(dead_code)]
-#[cfg(feature = "application")]
+#[cfg(feature = "config")]
 pub mod config;
 
 #[allow(dead_code)]
-#[cfg(feature = "application")]
+#[cfg(feature = "state")]
 pub mod state;
 
 #[allow(dead_code)]
-#[cfg(feature = "application")]
+#[cfg(feature = "runtime")]
 pub mod runtime;
+
+#[allow(dead_code)]
+#[cfg(feature = "cache")]
+pub mod cache;
+
+#[allow(dead_code)]
+#[cfg(feature = "data")]
+pub mod mods;
+
+#[allow(dead_code)]
+#[cfg(feature = "state")]
+pub mod crash;
+
+#[allow(dead_code)]
+#[cfg(feature = "state")]
+pub mod logs;
+
+#[allow(dead_code)]
+#[cfg(feature = "rdn")]
+pub mod rdn;
+
+#[allow(dead_code)]
+#[cfg(any(feature = "data", feature = "config"))]
+pub mod shared;
+
+#[allow(dead_code)]
+#[cfg(feature = "rdn")]
+pub mod overrides;
+
+#[allow(dead_code)]
+#[cfg(feature = "rdn")]
+pub mod registry;
+
+#[allow(dead_code)]
+#[cfg(feature = "signing")]
+pub mod signing;
+
+#[allow(dead_code)]
+#[cfg(feature = "watch")]
+pub mod watch;
+
+#[allow(dead_code)]
+#[cfg(feature = "application")]
+pub mod application_directory;
+
+#[allow(dead_code)]
+#[cfg(feature = "rdn")]
+pub mod audit;
+
+#[allow(dead_code)]
+#[cfg(feature = "application")]
+pub mod grants;
+
+#[allow(dead_code)]
+#[cfg(feature = "application")]
+pub mod orphans;
+
+#[allow(dead_code)]
+#[cfg(feature = "application")]
+pub mod usage;
+
+#[allow(dead_code)]
+pub mod doctor;
+
+#[allow(dead_code)]
+pub mod explain;
+
+#[allow(dead_code)]
+#[cfg(all(feature = "data", feature = "state"))]
+pub mod accounts;
+
+#[allow(dead_code)]
+#[cfg(feature = "application")]
+pub mod sandbox;
+
+#[allow(dead_code)]
+#[cfg(feature = "bundle")]
+pub mod bundle;
+
+#[allow(dead_code)]
+#[cfg(feature = "integrity")]
+pub mod integrity;
+
+#[allow(dead_code)]
+#[cfg(feature = "settings")]
+pub mod config_stack;
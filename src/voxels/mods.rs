@@ -0,0 +1,273 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use lib_voxels_application::application::application::Application;
+
+use crate::async_filesystem::{create_directory, AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::{CreateOptions, FsInt};
+
+use super::VoxelsDirectoryError;
+
+use super::data::DataDirectoryResolver;
+
+pub const MODS_DIRECTORY_NAME: &str = "mods";
+
+pub const PLUGINS_DIRECTORY_NAME: &str = "plugins";
+
+const MAX_EXTENSION_NAME_LENGTH: usize = 255;
+
+/// Mod and plugin names are used as directory names, so they must be a single portable path component
+pub fn validate_extension_name(name: &str) -> Result<(), VoxelsDirectoryError> {
+    if name.is_empty() || name.len() > MAX_EXTENSION_NAME_LENGTH {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    if name == "." || name == ".." {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    Ok(())
+}
+
+/// Names of the valid subdirectories of `directory`, sorted
+fn list_extensions<FsIntT: FsInt>(fs: &FsIntT, directory: &Path) -> Result<Vec<String>, VoxelsDirectoryError> {
+    if !fs.exists(directory) {
+        return Ok(Vec::new());
+    }
+
+    let mut names: Vec<String> = fs.read_dir(directory)?
+        .into_iter()
+        .filter(|path| fs.is_directory(path))
+        .filter_map(|path| path.file_name().and_then(|name| name.to_str()).map(String::from))
+        .filter(|name| validate_extension_name(name).is_ok())
+        .collect();
+
+    names.sort();
+
+    Ok(names)
+}
+
+#[mockall::automock]
+pub trait ModsDirectoryResolver {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn list(&mut self, application: Application) -> Result<Vec<String>, VoxelsDirectoryError>;
+
+    async fn create_mod(&mut self, application: Application, name: String) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+/// `<data>/voxels/<rdn>/mods/`, one subdirectory per installed mod
+pub struct ModsDirectory<DataT: DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: Option<PathBuf>,
+    data: DataT,
+    fs: FsIntT,
+    async_fs: AsyncFsIntT,
+}
+
+impl<DataT: DataDirectoryResolver, FsIntT: FsInt> ModsDirectory<DataT, FsIntT> {
+    pub fn new(data: DataT, fs: FsIntT) -> Self {
+        Self::with_async_fs(data, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<DataT: DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> ModsDirectory<DataT, FsIntT, AsyncFsIntT> {
+    /// As `new`, creating the directory through `async_fs`
+    pub fn with_async_fs(data: DataT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        Self {
+            path: None,
+            data,
+            fs,
+            async_fs
+        }
+    }
+}
+
+impl<DataT: DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> ModsDirectoryResolver for ModsDirectory<DataT, FsIntT, AsyncFsIntT> {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        if self.is_resolved() {
+            return Ok(self.path.clone().unwrap());
+        }
+
+        let data = self.data.resolve(application).await?;
+
+        let path = data.join(MODS_DIRECTORY_NAME);
+
+        self.path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        create_directory(&self.async_fs, &resolved, CreateOptions::default()).await?;
+
+        Ok(resolved)
+    }
+
+    async fn list(&mut self, application: Application) -> Result<Vec<String>, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        list_extensions(&self.fs, &resolved)
+    }
+
+    async fn create_mod(&mut self, application: Application, name: String) -> Result<PathBuf, VoxelsDirectoryError> {
+        validate_extension_name(&name)?;
+
+        let resolved = self.resolve(application).await?;
+
+        let mod_path = resolved.join(name);
+
+        self.fs.create_dir_all(&mod_path)?;
+
+        Ok(mod_path)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+#[mockall::automock]
+pub trait PluginsDirectoryResolver {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn list(&mut self, application: Application) -> Result<Vec<String>, VoxelsDirectoryError>;
+
+    async fn create_plugin(&mut self, application: Application, name: String) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+/// `<data>/voxels/<rdn>/plugins/`, one subdirectory per installed plugin
+pub struct PluginsDirectory<DataT: DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: Option<PathBuf>,
+    data: DataT,
+    fs: FsIntT,
+    async_fs: AsyncFsIntT,
+}
+
+impl<DataT: DataDirectoryResolver, FsIntT: FsInt> PluginsDirectory<DataT, FsIntT> {
+    pub fn new(data: DataT, fs: FsIntT) -> Self {
+        Self::with_async_fs(data, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<DataT: DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> PluginsDirectory<DataT, FsIntT, AsyncFsIntT> {
+    /// As `new`, creating the directory through `async_fs`
+    pub fn with_async_fs(data: DataT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        Self {
+            path: None,
+            data,
+            fs,
+            async_fs
+        }
+    }
+}
+
+impl<DataT: DataDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> PluginsDirectoryResolver for PluginsDirectory<DataT, FsIntT, AsyncFsIntT> {
+    async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        if self.is_resolved() {
+            return Ok(self.path.clone().unwrap());
+        }
+
+        let data = self.data.resolve(application).await?;
+
+        let path = data.join(PLUGINS_DIRECTORY_NAME);
+
+        self.path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        create_directory(&self.async_fs, &resolved, CreateOptions::default()).await?;
+
+        Ok(resolved)
+    }
+
+    async fn list(&mut self, application: Application) -> Result<Vec<String>, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        list_extensions(&self.fs, &resolved)
+    }
+
+    async fn create_plugin(&mut self, application: Application, name: String) -> Result<PathBuf, VoxelsDirectoryError> {
+        validate_extension_name(&name)?;
+
+        let resolved = self.resolve(application).await?;
+
+        let plugin_path = resolved.join(name);
+
+        self.fs.create_dir_all(&plugin_path)?;
+
+        Ok(plugin_path)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+#[test]
+fn test_validate_extension_name() {
+    assert!(validate_extension_name("better-terrain_2.0").is_ok());
+    assert!(validate_extension_name("").is_err());
+    assert!(validate_extension_name("..").is_err());
+    assert!(validate_extension_name("../escape").is_err());
+    assert!(validate_extension_name("nested/mod").is_err());
+}
+
+#[test]
+fn test_list_extensions() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let directory = PathBuf::from("/data/voxels/org.voxels.Client/mods");
+
+    fs.expect_exists()
+        .return_const(true);
+
+    fs.expect_read_dir()
+        .once()
+        .with(mockall::predicate::eq(directory.clone()))
+        .return_once(|_| Ok(vec![
+            PathBuf::from("/data/voxels/org.voxels.Client/mods/zeta"),
+            PathBuf::from("/data/voxels/org.voxels.Client/mods/alpha"),
+            PathBuf::from("/data/voxels/org.voxels.Client/mods/readme.txt"),
+        ]));
+
+    fs.expect_is_directory()
+        .returning(|path| !path.ends_with("readme.txt"));
+
+    let names = list_extensions(&fs, &directory);
+
+    assert_eq!(names.unwrap(), vec![String::from("alpha"), String::from("zeta")]);
+}
@@ -0,0 +1,235 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::filesystem::FsInt;
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::audit::{audit, AuditOperation, SharedAuditLog};
+use super::voxels_xdg::xdg::trash::Removal;
+
+use super::application_directory::BaseDirectories;
+use super::rdn::{application_directories, normalize, RdnCase, RdnLayout};
+use super::registry::ManifestSource;
+use super::voxels_xdg::{cache, config, data, runtime, state};
+
+/// The kinds `find_orphans` looks in. Cache and runtime directories are disposable and cleaned up by other means.
+pub const ORPHAN_KINDS: [DirectoryKind; 3] = [DirectoryKind::Config, DirectoryKind::Data, DirectoryKind::State];
+
+/// A per-application directory whose application is no longer installed
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Orphan {
+    pub kind: DirectoryKind,
+    pub rdn: String,
+    pub path: PathBuf,
+}
+
+/// What `clean_orphans` removes
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum OrphanPolicy {
+    /// Report what would be removed without touching the filesystem
+    #[default]
+    DryRun,
+    /// Leave config directories so a reinstall picks up the user's settings
+    KeepConfig,
+    RemoveAll,
+}
+
+/// Directories beneath each voxels root in `roots` named after an RDN `installed` has no manifest for,
+/// as listed by `application_directories`. RDNs are compared case folded to match `RdnCase::Lower` directories.
+/// An empty `installed` is `NothingInstalled`, a missing or unreadable registry must not orphan everything.
+pub fn find_orphans_in<FsIntT: FsInt, SourceT: ManifestSource + ?Sized>(fs: &FsIntT, roots: &BTreeMap<DirectoryKind, PathBuf>, layout: RdnLayout, installed: &SourceT) -> Result<Vec<Orphan>, VoxelsDirectoryError> {
+    let installed: BTreeSet<String> = installed.list()?
+        .iter()
+        .map(|rdn| normalize(rdn, RdnCase::Lower))
+        .collect();
+
+    if installed.is_empty() {
+        tracing::warn!("no applications are installed, refusing to treat every application directory as orphaned");
+
+        return Err(VoxelsDirectoryError::NothingInstalled);
+    }
+
+    let mut orphans = Vec::new();
+
+    for (kind, root) in roots {
+        for (rdn, path) in application_directories(fs, root, layout)? {
+            if !installed.contains(&normalize(&rdn, RdnCase::Lower)) {
+                orphans.push(Orphan {
+                    kind: *kind,
+                    rdn,
+                    path,
+                });
+            }
+        }
+    }
+
+    orphans.sort();
+
+    Ok(orphans)
+}
+
+/// Resolve the config, data and state voxels roots from `bases`, then `find_orphans_in` them
+pub async fn find_orphans<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT, SourceT>(bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: &FsIntT, layout: RdnLayout, installed: &SourceT) -> Result<Vec<Orphan>, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+    FsIntT: FsInt,
+    SourceT: ManifestSource + ?Sized,
+{
+    let roots = bases.resolve_roots().await?
+        .into_iter()
+        .filter(|(kind, _)| ORPHAN_KINDS.contains(kind))
+        .collect();
+
+    find_orphans_in(fs, &roots, layout, installed)
+}
+
+/// Remove `orphans` as `policy` allows and `removal` says, returning the directories removed or, for `DryRun`, that would be.
+/// Each removal is recorded in `log` as a purge.
+pub fn clean_orphans<FsIntT: FsInt>(fs: &FsIntT, orphans: &[Orphan], policy: OrphanPolicy, removal: Removal<'_>, log: Option<&SharedAuditLog>) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+    let mut removed = Vec::new();
+
+    for orphan in orphans {
+        if policy == OrphanPolicy::KeepConfig && orphan.kind == DirectoryKind::Config {
+            continue;
+        }
+
+        if policy != OrphanPolicy::DryRun {
+            tracing::info!("removing {:?}, {} is no longer installed", orphan.path, orphan.rdn);
+
+            removal.remove(fs, &orphan.path)?;
+
+            audit(log, AuditOperation::Purge, &orphan.rdn, &orphan.path, None);
+        }
+
+        removed.push(orphan.path.clone());
+    }
+
+    Ok(removed)
+}
+
+#[test]
+fn test_find_orphans_in() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_read_dir()
+        .returning(|path| Ok(match path.to_str().unwrap() {
+            "/data/voxels" => vec![
+                PathBuf::from("/data/voxels/Org.Voxels.Client"),
+                PathBuf::from("/data/voxels/org.voxels.Removed"),
+                PathBuf::from("/data/voxels/org.voxels.Partial"),
+                PathBuf::from("/data/voxels/launcher"),
+                PathBuf::from("/data/voxels/org.voxels.Linked"),
+            ],
+            _ => Vec::new(),
+        }));
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_is_symlink()
+        .returning(|path| path.ends_with("org.voxels.Linked"));
+
+    let roots = BTreeMap::from([(DirectoryKind::Data, PathBuf::from("/data/voxels"))]);
+
+    let mut installed = super::registry::EmbeddedSource::new();
+    installed.insert("org.voxels.Client", "");
+
+    let orphans = find_orphans_in(&fs, &roots, RdnLayout::Flat, &installed).unwrap();
+
+    assert_eq!(orphans.iter().map(|orphan| orphan.rdn.as_str()).collect::<Vec<&str>>(), vec!["org.voxels.Partial", "org.voxels.Removed"]);
+}
+
+#[test]
+fn test_find_orphans_in_refuses_an_empty_registry() {
+    // no expectations, nothing beneath the roots may be looked at
+    let fs = crate::filesystem::MockFsInt::new();
+
+    let roots = BTreeMap::from([(DirectoryKind::Data, PathBuf::from("/data/voxels"))]);
+
+    let installed = super::registry::EmbeddedSource::new();
+
+    assert_eq!(find_orphans_in(&fs, &roots, RdnLayout::Flat, &installed), Err(VoxelsDirectoryError::NothingInstalled));
+}
+
+#[test]
+fn test_clean_orphans() {
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use super::audit::AuditRecorder;
+    use super::voxels_xdg::xdg::BaseDirectoryError;
+    use super::voxels_xdg::xdg::trash::TrashCan;
+
+    #[derive(Default)]
+    struct Recorder {
+        purged: Mutex<Vec<(String, PathBuf)>>,
+    }
+
+    impl AuditRecorder for Recorder {
+        fn record(&self, operation: AuditOperation, rdn: &str, path: &Path, _previous: Option<&Path>) -> Result<(), VoxelsDirectoryError> {
+            assert_eq!(operation, AuditOperation::Purge);
+
+            self.purged.lock().unwrap().push((rdn.to_owned(), path.to_path_buf()));
+
+            Ok(())
+        }
+
+        fn record_grant(&self, _operation: AuditOperation, _rdn: &str, _path: &Path, _grantee: &str) -> Result<(), VoxelsDirectoryError> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingTrash {
+        trashed: Mutex<Vec<PathBuf>>,
+    }
+
+    impl TrashCan for RecordingTrash {
+        fn trash(&self, path: &Path) -> Result<PathBuf, BaseDirectoryError> {
+            self.trashed.lock().unwrap().push(path.to_path_buf());
+
+            Ok(PathBuf::from("/data/Trash/files").join(path.file_name().unwrap()))
+        }
+    }
+
+    let orphans = vec![
+        Orphan { kind: DirectoryKind::Config, rdn: String::from("org.voxels.Removed"), path: PathBuf::from("/config/voxels/org.voxels.Removed") },
+        Orphan { kind: DirectoryKind::Data, rdn: String::from("org.voxels.Removed"), path: PathBuf::from("/data/voxels/org.voxels.Removed") },
+    ];
+
+    let recorder = Arc::new(Recorder::default());
+    let log: SharedAuditLog = recorder.clone();
+
+    let fs = crate::filesystem::MockFsInt::new();
+    let trash = RecordingTrash::default();
+
+    assert_eq!(clean_orphans(&fs, &orphans, OrphanPolicy::DryRun, Removal::Trash(&trash), Some(&log)).unwrap().len(), 2);
+    assert!(trash.trashed.lock().unwrap().is_empty());
+    assert!(recorder.purged.lock().unwrap().is_empty());
+
+    assert_eq!(clean_orphans(&fs, &orphans, OrphanPolicy::KeepConfig, Removal::Trash(&trash), Some(&log)).unwrap(), vec![PathBuf::from("/data/voxels/org.voxels.Removed")]);
+    assert_eq!(*trash.trashed.lock().unwrap(), vec![PathBuf::from("/data/voxels/org.voxels.Removed")]);
+    assert_eq!(*recorder.purged.lock().unwrap(), vec![(String::from("org.voxels.Removed"), PathBuf::from("/data/voxels/org.voxels.Removed"))]);
+}
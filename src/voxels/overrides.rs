@@ -0,0 +1,169 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::environment_variables::{DefaultEnvInt, EnvInt, EnvKey};
+use crate::pathcheck::normalize;
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+/// The base directories under `$HOME` when no variable names them
+const FHS_BASE_DIRECTORIES: [&str; 6] = [".config", ".local", ".local/share", ".local/state", ".local/bin", ".cache"];
+
+/// Directories a relocation may neither be nor contain: `$HOME`, the base directories the environment names
+/// and the FHS defaults under `$HOME`
+pub fn protected_directories<EnvIntT: EnvInt>(env: &EnvIntT) -> Vec<PathBuf> {
+    let keys = [
+        EnvKey::XdgConfigHome,
+        EnvKey::XdgDataHome,
+        EnvKey::XdgStateHome,
+        EnvKey::XdgCacheHome,
+        EnvKey::XdgRuntimeDir,
+        EnvKey::XdgBinHome,
+        EnvKey::VoxelsConfigHome,
+        EnvKey::VoxelsDataHome,
+        EnvKey::VoxelsStateHome,
+        EnvKey::VoxelsCacheHome,
+        EnvKey::VoxelsRuntimeHome,
+        EnvKey::VoxelsBinHome,
+    ];
+
+    let mut protected: Vec<PathBuf> = keys.into_iter()
+        .filter_map(|key| env.get_path(key).ok())
+        .filter(|path| path.is_absolute())
+        .map(|path| normalize(&path))
+        .collect();
+
+    if let Ok(home) = env.get_path(EnvKey::Home)
+        && home.is_absolute() {
+        let home = normalize(&home);
+
+        protected.extend(FHS_BASE_DIRECTORIES.iter().map(|relative| home.join(relative)));
+        protected.push(home);
+    }
+
+    protected
+}
+
+/// The `[directories]` table of an application manifest, mapping a directory kind
+/// to the absolute path the application wants it relocated to
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DirectoryOverrides {
+    relocations: BTreeMap<DirectoryKind, PathBuf>,
+    /// See `protected_directories`
+    protected: Vec<PathBuf>,
+}
+
+impl DirectoryOverrides {
+    /// No relocations, protecting the directories `protected_directories` finds in the process environment
+    pub fn new() -> Self {
+        let mut overrides = Self::default();
+
+        overrides.protect(protected_directories(&DefaultEnvInt));
+
+        overrides
+    }
+
+    /// Refuse relocations to any of `directories` or to a directory containing one of them
+    pub fn protect<I: IntoIterator<Item = PathBuf>>(&mut self, directories: I) {
+        self.protected.extend(directories.into_iter().map(|directory| normalize(&directory)));
+    }
+
+    /// Build from the raw table entries, rejecting unknown kinds and relocations `set` refuses
+    pub fn from_table<I: IntoIterator<Item = (String, PathBuf)>>(entries: I) -> Result<Self, VoxelsDirectoryError> {
+        let mut overrides = Self::new();
+
+        for (key, path) in entries {
+            let kind = DirectoryKind::from_name(&key).ok_or(VoxelsDirectoryError::InvalidName)?;
+
+            overrides.set(kind, path)?;
+        }
+
+        Ok(overrides)
+    }
+
+    /// Relocate `kind` to `path`, which must be absolute without `..`, and neither the filesystem root
+    /// nor a protected directory or one of their ancestors
+    pub fn set(&mut self, kind: DirectoryKind, path: PathBuf) -> Result<(), VoxelsDirectoryError> {
+        if !path.is_absolute() || path.components().any(|component| component == Component::ParentDir) {
+            return Err(VoxelsDirectoryError::InvalidRelocation);
+        }
+
+        let path = normalize(&path);
+
+        if self.is_protected(&path) {
+            return Err(VoxelsDirectoryError::InvalidRelocation);
+        }
+
+        self.relocations.insert(kind, path);
+
+        Ok(())
+    }
+
+    /// Whether `path` is the filesystem root, a protected directory or an ancestor of one
+    pub fn is_protected(&self, path: &Path) -> bool {
+        let path = normalize(path);
+
+        path.parent().is_none() || self.protected.iter().any(|protected| protected.starts_with(&path))
+    }
+
+    pub fn get(&self, kind: DirectoryKind) -> Option<&Path> {
+        self.relocations.get(&kind).map(PathBuf::as_path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relocations.is_empty()
+    }
+}
+
+#[test]
+fn test_from_table() {
+    let overrides = DirectoryOverrides::from_table(vec![
+        (String::from("data"), PathBuf::from("/mnt/games/voxels")),
+    ]).unwrap();
+
+    assert_eq!(overrides.get(DirectoryKind::Data), Some(Path::new("/mnt/games/voxels")));
+    assert_eq!(overrides.get(DirectoryKind::Config), None);
+
+    assert_eq!(DirectoryOverrides::from_table(vec![(String::from("worlds"), PathBuf::from("/mnt"))]), Err(VoxelsDirectoryError::InvalidName));
+    assert_eq!(DirectoryOverrides::from_table(vec![(String::from("data"), PathBuf::from("games"))]), Err(VoxelsDirectoryError::InvalidRelocation));
+}
+
+#[test]
+fn test_set_refuses_dangerous_relocations() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_get_path()
+        .returning(|key| match key {
+            EnvKey::Home => Ok(PathBuf::from("/home/user")),
+            EnvKey::XdgDataHome => Ok(PathBuf::from("/srv/data/")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    let mut overrides = DirectoryOverrides::default();
+
+    overrides.protect(protected_directories(&env));
+
+    for hostile in ["/", "/home", "/home/user", "/home/user/.config/", "/srv/data", "/srv", "/mnt/games/../../etc", "/home/user/./.local"] {
+        assert_eq!(overrides.set(DirectoryKind::Data, PathBuf::from(hostile)), Err(VoxelsDirectoryError::InvalidRelocation), "{}", hostile);
+    }
+
+    assert!(overrides.set(DirectoryKind::Data, PathBuf::from("/home/user/Games/./voxels/")).is_ok());
+    assert_eq!(overrides.get(DirectoryKind::Data), Some(Path::new("/home/user/Games/voxels")));
+}
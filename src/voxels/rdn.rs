@@ -0,0 +1,470 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use lib_voxels_application::application::application::Application;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::filesystem::{create_directory, CreateOptions, FsInt};
+use crate::pathcheck::{check_contained, join_within};
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+use super::audit::{audit, AuditOperation, SharedAuditLog};
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
+
+/// Maximum length of a D-Bus name, which reverse-DNS application names double as
+pub const RDN_MAX_LENGTH: usize = 255;
+
+/// Reserved beside the application directories of a voxels base directory for directories no single application owns
+pub const SHARED_DIRECTORY_NAME: &str = "shared";
+
+/// How the case of an RDN is treated when it becomes a directory name
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum RdnCase {
+    #[default]
+    Preserve,
+    /// Use on case insensitive filesystems so `Org.Example.App` and `org.example.app` share a directory
+    Lower,
+}
+
+/// NFC normalize `name` and apply `case`
+pub fn normalize(name: &str, case: RdnCase) -> String {
+    let normalized: String = name.nfc().collect();
+
+    match case {
+        RdnCase::Preserve => normalized,
+        RdnCase::Lower => normalized.to_lowercase(),
+    }
+}
+
+/// How an RDN is laid out beneath a voxels base directory
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum RdnLayout {
+    /// One directory named after the whole RDN, `org.example.App`
+    #[default]
+    Flat,
+    /// One directory per RDN element, `org/example/App`, so applications of a vendor sit together
+    Nested,
+}
+
+impl RdnLayout {
+    /// The relative path of `rdn` under this layout
+    pub fn path(&self, rdn: &str) -> PathBuf {
+        match self {
+            RdnLayout::Flat => PathBuf::from(rdn),
+            RdnLayout::Nested => rdn.split('.').collect(),
+        }
+    }
+}
+
+/// The path used for `application` beneath a voxels base directory
+pub fn rdn_path(application: &Application, case: RdnCase, layout: RdnLayout) -> PathBuf {
+    layout.path(&normalize(&application.rdn().as_path().to_string_lossy(), case))
+}
+
+/// The `kind` directory `dirs_file` pins for `application`, see `DirsFile::get_for_application`
+pub fn pinned_path(dirs_file: Option<&DirsFile>, application: &Application, kind: DirectoryKind) -> Option<PathBuf> {
+    dirs_file?.get_for_application(&application.rdn().as_path().to_string_lossy(), kind).map(Path::to_path_buf)
+}
+
+/// `<base>/<rdn>`, rejecting RDNs that would leave `base` lexically or through a symlink,
+/// or that would land inside `SHARED_DIRECTORY_NAME` under `RdnLayout::Nested`
+pub fn rdn_path_within<FsIntT: FsInt>(fs: &FsIntT, base: &Path, application: &Application, case: RdnCase, layout: RdnLayout) -> Result<PathBuf, VoxelsDirectoryError> {
+    let relative = rdn_path(application, case, layout);
+
+    if relative.starts_with(SHARED_DIRECTORY_NAME) {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    let path = join_within(base, &relative)?;
+
+    check_contained(fs, base, &path)?;
+
+    Ok(path)
+}
+
+/// Groups of names that would alias each other on a case insensitive, normalizing filesystem.
+/// Names that are unique after folding are not returned.
+pub fn find_collisions<I: IntoIterator<Item = String>>(names: I) -> Vec<Vec<String>> {
+    let mut folded: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for name in names {
+        folded.entry(normalize(&name, RdnCase::Lower)).or_default().push(name);
+    }
+
+    folded.into_values()
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+/// The real subdirectories of `path` with their names, symlinks and names that are not UTF-8 left out
+fn subdirectories<FsIntT: FsInt>(fs: &FsIntT, path: &Path) -> Result<Vec<(String, PathBuf)>, VoxelsDirectoryError> {
+    Ok(fs.read_dir(path)?
+        .into_iter()
+        .filter(|path| !fs.is_symlink(path) && fs.is_directory(path))
+        .filter_map(|path| Some((path.file_name()?.to_str()?.to_owned(), path)))
+        .collect())
+}
+
+/// The application directories of a voxels root laid out as `layout`, with their RDNs.
+/// Entries that are not RDNs, such as the product directories of `LayoutStrategy::VendorProduct`, `SHARED_DIRECTORY_NAME`
+/// and symlinks are left out. RDNs that `find_collisions` groups are logged, as they share data where names are folded.
+///
+/// A nested directory is taken to be an application's once it is at least two elements deep and is empty
+/// or holds a file; one holding only subdirectories cannot be told apart from an RDN prefix and is descended into.
+pub fn application_directories<FsIntT: FsInt>(fs: &FsIntT, root: &Path, layout: RdnLayout) -> Result<Vec<(String, PathBuf)>, VoxelsDirectoryError> {
+    if !fs.is_directory(root) {
+        return Ok(Vec::new());
+    }
+
+    let mut top_level = subdirectories(fs, root)?;
+
+    top_level.retain(|(name, _)| name != SHARED_DIRECTORY_NAME);
+
+    let mut directories: Vec<(String, PathBuf)> = match layout {
+        RdnLayout::Flat => top_level,
+        RdnLayout::Nested => {
+            let mut found = Vec::new();
+            let mut pending = top_level;
+
+            while let Some((prefix, path)) = pending.pop() {
+                let entries = fs.read_dir(&path)?;
+                let deep_enough = prefix.contains('.');
+
+                if deep_enough && (entries.is_empty() || entries.iter().any(|entry| !fs.is_directory(entry))) {
+                    found.push((prefix, path));
+                    continue;
+                }
+
+                for (name, child) in subdirectories(fs, &path)? {
+                    pending.push((format!("{}.{}", prefix, name), child));
+                }
+            }
+
+            found
+        },
+    };
+
+    directories.retain(|(rdn, _)| validate_rdn(rdn, RdnStrictness::Lenient).is_ok());
+    directories.sort();
+
+    for group in find_collisions(directories.iter().map(|(rdn, _)| rdn.clone())) {
+        tracing::warn!("application directories {} of {} alias each other on case insensitive filesystems", group.join(", "), root.display());
+    }
+
+    Ok(directories)
+}
+
+/// Move every application directory of `root` from the `from` layout to `to`, returning the moves made.
+/// Applications whose directory already exists under `to` are left alone and logged.
+/// Directories left empty by leaving `RdnLayout::Nested` are removed. Each move is recorded in `log` as a migration.
+pub fn migrate_layout<FsIntT: FsInt>(fs: &FsIntT, root: &Path, from: RdnLayout, to: RdnLayout, log: Option<&SharedAuditLog>) -> Result<Vec<(PathBuf, PathBuf)>, VoxelsDirectoryError> {
+    let mut moved = Vec::new();
+
+    if from == to {
+        return Ok(moved);
+    }
+
+    for (rdn, source) in application_directories(fs, root, from)? {
+        let destination = join_within(root, &to.path(&rdn))?;
+
+        if fs.exists(&destination) {
+            tracing::warn!("not migrating {:?}, {:?} already exists", source, destination);
+            continue;
+        }
+
+        if let Some(parent) = destination.parent() {
+            create_directory(fs, parent, CreateOptions::default())?;
+        }
+
+        fs.rename(&source, &destination)?;
+
+        audit(log, AuditOperation::Migrate, &rdn, &destination, Some(&source));
+
+        if from == RdnLayout::Nested {
+            let mut parent = source.parent();
+
+            while let Some(directory) = parent.filter(|directory| *directory != root) {
+                if !fs.read_dir(directory)?.is_empty() {
+                    break;
+                }
+
+                fs.remove_dir_all(directory)?;
+                parent = directory.parent();
+            }
+        }
+
+        moved.push((source, destination));
+    }
+
+    Ok(moved)
+}
+
+/// Which characters `validate_rdn` accepts in an element of a reverse-DNS name
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum RdnStrictness {
+    /// ASCII letters and digits only
+    Alphanumeric,
+    /// D-Bus well-known name rules, also used for desktop file ids:
+    /// letters, digits, `_` and `-`, elements may not start with a digit
+    #[default]
+    DBus,
+    /// As `DBus` but elements may start with a digit, for domains like `3dfx.com`
+    Lenient,
+}
+
+/// Check `name` is a reverse-DNS name of at least two non-empty elements under `strictness`.
+/// Punycode labels (`xn--...`) are accepted by `DBus` and `Lenient`.
+pub fn validate_rdn(name: &str, strictness: RdnStrictness) -> Result<(), VoxelsDirectoryError> {
+    if name.is_empty() || name.len() > RDN_MAX_LENGTH {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    let elements: Vec<&str> = name.split('.').collect();
+
+    if elements.len() < 2 {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    for element in elements {
+        let Some(first) = element.chars().next() else {
+            return Err(VoxelsDirectoryError::InvalidName);
+        };
+
+        let valid = match strictness {
+            RdnStrictness::Alphanumeric => {
+                element.chars().all(|c| c.is_ascii_alphanumeric())
+            },
+            RdnStrictness::DBus => {
+                !first.is_ascii_digit() && element.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            },
+            RdnStrictness::Lenient => {
+                element.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            }
+        };
+
+        if !valid {
+            return Err(VoxelsDirectoryError::InvalidName);
+        }
+    }
+
+    Ok(())
+}
+
+/// Map `rdn` onto a valid D-Bus well-known name. Characters D-Bus does not allow become `_`
+/// and elements starting with a digit are prefixed with `_`, matching flatpak.
+pub fn to_dbus_name(rdn: &str) -> String {
+    rdn.split('.')
+        .map(|element| {
+            let mut escaped: String = element.chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+                .collect();
+
+            if escaped.starts_with(|c: char| c.is_ascii_digit()) {
+                escaped.insert(0, '_');
+            }
+
+            escaped
+        })
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Map `rdn` onto a D-Bus object path, one path element per RDN element.
+/// Elements are escaped like systemd's bus labels so distinct names never share a path:
+/// every byte other than an ASCII letter or a non-leading digit becomes `_xx`.
+pub fn to_object_path(rdn: &str) -> String {
+    let mut path = String::new();
+
+    for element in rdn.split('.') {
+        path.push('/');
+
+        if element.is_empty() {
+            path.push('_');
+            continue;
+        }
+
+        for (index, byte) in element.bytes().enumerate() {
+            if byte.is_ascii_alphabetic() || (index > 0 && byte.is_ascii_digit()) {
+                path.push(byte as char);
+            } else {
+                path.push_str(&format!("_{:02x}", byte));
+            }
+        }
+    }
+
+    path
+}
+
+/// The desktop file id for `rdn`, its D-Bus name with the `.desktop` suffix
+pub fn to_desktop_file_id(rdn: &str) -> String {
+    format!("{}.desktop", to_dbus_name(rdn))
+}
+
+/// Conversions of an application's RDN into the other names it is known by
+pub trait ApplicationNames {
+    fn to_dbus_name(&self) -> String;
+    fn to_object_path(&self) -> String;
+    fn to_desktop_file_id(&self) -> String;
+}
+
+impl ApplicationNames for Application {
+    fn to_dbus_name(&self) -> String {
+        to_dbus_name(&self.rdn().as_path().to_string_lossy())
+    }
+
+    fn to_object_path(&self) -> String {
+        to_object_path(&self.rdn().as_path().to_string_lossy())
+    }
+
+    fn to_desktop_file_id(&self) -> String {
+        to_desktop_file_id(&self.rdn().as_path().to_string_lossy())
+    }
+}
+
+#[test]
+fn test_normalize() {
+    // "e" followed by a combining acute accent composes to a single code point
+    assert_eq!(normalize("org.caf\u{0065}\u{0301}.App", RdnCase::Preserve), "org.caf\u{00e9}.App");
+    assert_eq!(normalize("Org.Example.App", RdnCase::Lower), "org.example.app");
+}
+
+#[test]
+fn test_find_collisions() {
+    let collisions = find_collisions(vec![
+        String::from("Org.Example.App"),
+        String::from("org.example.app"),
+        String::from("org.example.Other"),
+    ]);
+
+    assert_eq!(collisions, vec![vec![String::from("Org.Example.App"), String::from("org.example.app")]]);
+}
+
+#[test]
+fn test_rdn_layout() {
+    assert_eq!(RdnLayout::Flat.path("org.example.App"), PathBuf::from("org.example.App"));
+    assert_eq!(RdnLayout::Nested.path("org.example.App"), PathBuf::from("org/example/App"));
+}
+
+#[test]
+fn test_nested_application_directories() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|path| path.extension().is_none_or(|extension| extension != "toml"));
+
+    fs.expect_read_dir()
+        .returning(|path| Ok(match path.to_str().unwrap() {
+            "/data" => vec![PathBuf::from("/data/org"), PathBuf::from("/data/shared")],
+            "/data/shared" => vec![PathBuf::from("/data/shared/assets")],
+            "/data/shared/assets" => vec![PathBuf::from("/data/shared/assets/atlas.toml")],
+            "/data/org" => vec![PathBuf::from("/data/org/example")],
+            "/data/org/example" => vec![PathBuf::from("/data/org/example/App"), PathBuf::from("/data/org/example/Empty")],
+            "/data/org/example/App" => vec![PathBuf::from("/data/org/example/App/settings.toml")],
+            _ => vec![],
+        }));
+
+    assert_eq!(application_directories(&fs, Path::new("/data"), RdnLayout::Nested).unwrap(), vec![
+        (String::from("org.example.App"), PathBuf::from("/data/org/example/App")),
+        (String::from("org.example.Empty"), PathBuf::from("/data/org/example/Empty")),
+    ]);
+}
+
+#[test]
+fn test_migrate_layout_records_moves() {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::audit::AuditLog;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+    let mut log_fs = crate::filesystem::MockFsInt::new();
+    let mut clock = crate::clock::MockClockInt::new();
+
+    fs.expect_read_dir()
+        .with(mockall::predicate::eq(PathBuf::from("/data")))
+        .returning(|_| Ok(vec![PathBuf::from("/data/org.example.App")]));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_exists()
+        .returning(|_| false);
+
+    fs.expect_create_dir_all()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/data/org/example")))
+        .return_once(|_| Ok(()));
+
+    fs.expect_rename()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/data/org.example.App")), mockall::predicate::eq(PathBuf::from("/data/org/example/App")))
+        .return_once(|_, _| Ok(()));
+
+    clock.expect_now()
+        .return_const(UNIX_EPOCH + Duration::from_secs(10));
+
+    log_fs.expect_append()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/state/voxels/audit.log")), mockall::predicate::eq(String::from("10\tmigrate\torg.example.App\t/data/org/example/App\t/data/org.example.App\n")))
+        .return_once(|_, _| Ok(()));
+
+    let log: SharedAuditLog = Arc::new(AuditLog::with_clock(Path::new("/state/voxels"), log_fs, clock));
+
+    assert_eq!(migrate_layout(&fs, Path::new("/data"), RdnLayout::Flat, RdnLayout::Nested, Some(&log)).unwrap(), vec![
+        (PathBuf::from("/data/org.example.App"), PathBuf::from("/data/org/example/App")),
+    ]);
+}
+
+#[test]
+fn test_validate_rdn() {
+    assert!(validate_rdn("io.my-org.App", RdnStrictness::DBus).is_ok());
+    assert!(validate_rdn("io.my-org.App", RdnStrictness::Alphanumeric).is_err());
+    assert!(validate_rdn("org.xn--bcher-kva.Reader", RdnStrictness::DBus).is_ok());
+    assert!(validate_rdn("com.3dfx.Glide", RdnStrictness::DBus).is_err());
+    assert!(validate_rdn("com.3dfx.Glide", RdnStrictness::Lenient).is_ok());
+    assert!(validate_rdn("org..App", RdnStrictness::Lenient).is_err());
+    assert!(validate_rdn("App", RdnStrictness::Lenient).is_err());
+    assert!(validate_rdn("org.example.My App", RdnStrictness::Lenient).is_err());
+}
+
+#[test]
+fn test_rdn_conversions() {
+    assert_eq!(to_dbus_name("io.my-org.App"), "io.my-org.App");
+    assert_eq!(to_dbus_name("com.3dfx.Glide+"), "com._3dfx.Glide_");
+    assert_eq!(to_object_path("org.my_app.App2"), "/org/my_5fapp/App2");
+    assert_eq!(to_object_path("com.3dfx.my-app"), "/com/_33dfx/my_2dapp");
+    assert_ne!(to_object_path("org.a_b"), to_object_path("org.a-b"));
+    assert_eq!(to_desktop_file_id("org.voxels.Client"), "org.voxels.Client.desktop");
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn test_generated_rdn_is_valid(rdn in crate::testing::strategies::rdn()) {
+        proptest::prop_assert!(validate_rdn(&rdn, RdnStrictness::DBus).is_ok());
+        proptest::prop_assert_eq!(to_dbus_name(&rdn), rdn);
+    }
+}
@@ -0,0 +1,350 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::filesystem::FsInt;
+use crate::pathcheck::join_within;
+
+use super::rdn::RdnLayout;
+use super::{VoxelsDirectoryError, MANIFEST_FILE_NAME};
+
+/// Turns the contents of a `manifest.toml` into the caller's manifest type
+pub type ManifestParser<ManifestT> = Box<dyn Fn(&str) -> Result<ManifestT, VoxelsDirectoryError> + Send + Sync>;
+
+/// Where application manifests come from, so applications installed by system packages,
+/// bundled into a binary or published by a network registry resolve like user installed ones
+pub trait ManifestSource {
+    /// RDNs of every application this source has a manifest for
+    fn list(&self) -> Result<BTreeSet<String>, VoxelsDirectoryError>;
+
+    /// Changes whenever the manifest of `rdn` does, `NoCandidate` when there is none
+    fn modified(&self, rdn: &str) -> Result<SystemTime, VoxelsDirectoryError>;
+
+    fn read(&self, rdn: &str) -> Result<String, VoxelsDirectoryError>;
+}
+
+pub type SharedManifestSource = Arc<dyn ManifestSource + Send + Sync>;
+
+/// `<applications>/<rdn>/manifest.toml` on disk
+pub struct DirectorySource<FsIntT: FsInt> {
+    applications: PathBuf,
+    fs: FsIntT,
+    /// How `<rdn>` is laid out beneath the applications directory
+    pub layout: RdnLayout,
+}
+
+impl<FsIntT: FsInt> DirectorySource<FsIntT> {
+    pub fn new(applications: PathBuf, fs: FsIntT) -> Self {
+        Self {
+            applications,
+            fs,
+            layout: RdnLayout::default()
+        }
+    }
+
+    pub fn applications(&self) -> &Path {
+        &self.applications
+    }
+
+    pub fn manifest_path(&self, rdn: &str) -> Result<PathBuf, VoxelsDirectoryError> {
+        Ok(join_within(&self.applications, &self.layout.path(rdn))?.join(MANIFEST_FILE_NAME))
+    }
+
+    /// The RDNs of the manifests beneath `path`, an `RdnLayout::Nested` directory reached through `prefix`
+    fn list_nested(&self, path: &Path, prefix: &str, rdns: &mut BTreeSet<String>) -> Result<(), VoxelsDirectoryError> {
+        for child in self.fs.read_dir(path)? {
+            if self.fs.is_symlink(&child) || !self.fs.is_directory(&child) {
+                continue;
+            }
+
+            let Some(name) = child.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            let rdn = match prefix {
+                "" => String::from(name),
+                prefix => format!("{}.{}", prefix, name),
+            };
+
+            if self.fs.exists(&child.join(MANIFEST_FILE_NAME)) {
+                rdns.insert(rdn.clone());
+            }
+
+            self.list_nested(&child, &rdn, rdns)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<FsIntT: FsInt> ManifestSource for DirectorySource<FsIntT> {
+    fn list(&self) -> Result<BTreeSet<String>, VoxelsDirectoryError> {
+        if !self.fs.exists(&self.applications) {
+            return Ok(BTreeSet::new());
+        }
+
+        if self.layout == RdnLayout::Nested {
+            let mut rdns = BTreeSet::new();
+            self.list_nested(&self.applications, "", &mut rdns)?;
+            return Ok(rdns);
+        }
+
+        Ok(self.fs.read_dir(&self.applications)?
+            .into_iter()
+            .filter(|path| self.fs.exists(&path.join(MANIFEST_FILE_NAME)))
+            .filter_map(|path| path.file_name().and_then(|name| name.to_str()).map(String::from))
+            .collect())
+    }
+
+    fn modified(&self, rdn: &str) -> Result<SystemTime, VoxelsDirectoryError> {
+        match self.fs.modified(&self.manifest_path(rdn)?) {
+            Ok(modified) => Ok(modified),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(VoxelsDirectoryError::NoCandidate),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn read(&self, rdn: &str) -> Result<String, VoxelsDirectoryError> {
+        Ok(self.fs.read_to_string(&self.manifest_path(rdn)?)?)
+    }
+}
+
+/// Manifests compiled into the binary, which never change
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct EmbeddedSource {
+    manifests: BTreeMap<String, String>,
+}
+
+impl EmbeddedSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, rdn: &str, manifest: &str) {
+        self.manifests.insert(String::from(rdn), String::from(manifest));
+    }
+}
+
+impl ManifestSource for EmbeddedSource {
+    fn list(&self) -> Result<BTreeSet<String>, VoxelsDirectoryError> {
+        Ok(self.manifests.keys().cloned().collect())
+    }
+
+    fn modified(&self, rdn: &str) -> Result<SystemTime, VoxelsDirectoryError> {
+        if self.manifests.contains_key(rdn) {
+            Ok(SystemTime::UNIX_EPOCH)
+        } else {
+            Err(VoxelsDirectoryError::NoCandidate)
+        }
+    }
+
+    fn read(&self, rdn: &str) -> Result<String, VoxelsDirectoryError> {
+        self.manifests.get(rdn).cloned().ok_or(VoxelsDirectoryError::NoCandidate)
+    }
+}
+
+/// Several sources searched in order, the first with a manifest for an RDN wins.
+/// Put the user's applications directory before system ones such as `/usr/share` so user installs shadow packaged ones.
+#[derive(Default)]
+pub struct LayeredSource {
+    sources: Vec<SharedManifestSource>,
+}
+
+impl LayeredSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, source: SharedManifestSource) {
+        self.sources.push(source);
+    }
+
+    /// The first source with a manifest for `rdn` and its modification time
+    fn find(&self, rdn: &str) -> Result<(&SharedManifestSource, SystemTime), VoxelsDirectoryError> {
+        for source in &self.sources {
+            match source.modified(rdn) {
+                Ok(modified) => return Ok((source, modified)),
+                Err(VoxelsDirectoryError::NoCandidate) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(VoxelsDirectoryError::NoCandidate)
+    }
+}
+
+impl ManifestSource for LayeredSource {
+    fn list(&self) -> Result<BTreeSet<String>, VoxelsDirectoryError> {
+        let mut rdns = BTreeSet::new();
+
+        for source in &self.sources {
+            rdns.extend(source.list()?);
+        }
+
+        Ok(rdns)
+    }
+
+    fn modified(&self, rdn: &str) -> Result<SystemTime, VoxelsDirectoryError> {
+        Ok(self.find(rdn)?.1)
+    }
+
+    fn read(&self, rdn: &str) -> Result<String, VoxelsDirectoryError> {
+        self.find(rdn)?.0.read(rdn)
+    }
+}
+
+struct CachedManifest<ManifestT> {
+    modified: SystemTime,
+    manifest: Arc<ManifestT>,
+}
+
+/// Parsed manifests from a `ManifestSource`, keyed by RDN.
+/// A manifest is read on first lookup and again only once its modification time changes,
+/// so callers resolving every frame do not re-read and re-parse it. Share it behind an `Arc`.
+pub struct ApplicationRegistry<ManifestT, SourceT: ManifestSource> {
+    source: SourceT,
+    parse: ManifestParser<ManifestT>,
+    cache: RwLock<HashMap<String, CachedManifest<ManifestT>>>,
+}
+
+impl<ManifestT, FsIntT: FsInt> ApplicationRegistry<ManifestT, DirectorySource<FsIntT>> {
+    /// A registry of the manifests beneath `<applications>`
+    pub fn from_directory<ParseT: Fn(&str) -> Result<ManifestT, VoxelsDirectoryError> + Send + Sync + 'static>(applications: PathBuf, fs: FsIntT, parse: ParseT) -> Self {
+        Self::new(DirectorySource::new(applications, fs), parse)
+    }
+}
+
+impl<ManifestT, SourceT: ManifestSource> ApplicationRegistry<ManifestT, SourceT> {
+    pub fn new<ParseT: Fn(&str) -> Result<ManifestT, VoxelsDirectoryError> + Send + Sync + 'static>(source: SourceT, parse: ParseT) -> Self {
+        Self {
+            source,
+            parse: Box::new(parse),
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn source(&self) -> &SourceT {
+        &self.source
+    }
+
+    /// RDNs of every installed application
+    pub fn list(&self) -> Result<BTreeSet<String>, VoxelsDirectoryError> {
+        self.source.list()
+    }
+
+    /// The parsed manifest of `rdn`, from the cache unless the source changed since it was parsed
+    pub fn get(&self, rdn: &str) -> Result<Arc<ManifestT>, VoxelsDirectoryError> {
+        let modified = match self.source.modified(rdn) {
+            Ok(modified) => modified,
+            Err(err) => {
+                // uninstalled, do not keep serving the old manifest
+                self.invalidate(rdn);
+
+                return Err(err);
+            }
+        };
+
+        if let Some(cached) = self.cache.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(rdn)
+            && cached.modified == modified {
+            return Ok(cached.manifest.clone());
+        }
+
+        let manifest = Arc::new((self.parse)(&self.source.read(rdn)?)?);
+
+        self.cache.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(String::from(rdn), CachedManifest { modified, manifest: manifest.clone() });
+
+        Ok(manifest)
+    }
+
+    /// Forget the cached manifest of `rdn`, the next `get` reads it again
+    pub fn invalidate(&self, rdn: &str) {
+        self.cache.write().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(rdn);
+    }
+
+    pub fn clear(&self) {
+        self.cache.write().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+    }
+}
+
+#[test]
+fn test_registry_reparses_on_change() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let modified = Arc::new(AtomicUsize::new(0));
+
+    let seen = modified.clone();
+    fs.expect_modified()
+        .returning(move |_| Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seen.load(Ordering::SeqCst) as u64)));
+
+    fs.expect_read_to_string()
+        .times(2)
+        .with(mockall::predicate::eq(PathBuf::from("/apps/org.voxels.Client/manifest.toml")))
+        .returning(|_| Ok(String::from("name = \"Client\"")));
+
+    let parses = Arc::new(AtomicUsize::new(0));
+
+    let counted = parses.clone();
+    let registry = ApplicationRegistry::from_directory(PathBuf::from("/apps"), fs, move |contents| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        Ok(contents.len())
+    });
+
+    assert_eq!(*registry.get("org.voxels.Client").unwrap(), 15);
+    assert_eq!(*registry.get("org.voxels.Client").unwrap(), 15);
+    assert_eq!(parses.load(Ordering::SeqCst), 1);
+
+    modified.store(1, Ordering::SeqCst);
+
+    registry.get("org.voxels.Client").unwrap();
+    assert_eq!(parses.load(Ordering::SeqCst), 2);
+
+    assert!(registry.get("../etc").is_err());
+}
+
+#[test]
+fn test_layered_source() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_modified()
+        .returning(|path| if path.starts_with("/usr/share/voxels/applications/org.voxels.Packaged") {
+            Ok(SystemTime::UNIX_EPOCH)
+        } else {
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+        });
+
+    fs.expect_read_to_string()
+        .returning(|_| Ok(String::from("packaged")));
+
+    let mut embedded = EmbeddedSource::new();
+    embedded.insert("org.voxels.Client", "embedded");
+
+    let mut layered = LayeredSource::new();
+    layered.push(Arc::new(embedded));
+    layered.push(Arc::new(DirectorySource::new(PathBuf::from("/usr/share/voxels/applications"), fs)));
+
+    assert_eq!(layered.read("org.voxels.Client").unwrap(), "embedded");
+    assert_eq!(layered.read("org.voxels.Packaged").unwrap(), "packaged");
+    assert_eq!(layered.read("org.voxels.Missing"), Err(VoxelsDirectoryError::NoCandidate));
+}
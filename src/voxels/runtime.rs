@@ -14,61 +14,453 @@ GNU General Public License for more details.
 You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use lib_voxels_application::application::application::Application;
 
-use super::VoxelsDirectoryError;
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::rdn::{pinned_path, rdn_path_within, RdnCase, RdnLayout};
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::environment_variables::{DefaultEnvInt, EnvInt, EnvKey};
+use crate::filesystem::{CreateOptions, DefaultFsInt, FsInt};
 
 use super::voxels_xdg::runtime as base;
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_APPS_RUNTIME_METHOD_NAME: &str = "runtime";
 
+/// Records which login session created a runtime directory
+pub const RUNTIME_SESSION_FILE_NAME: &str = ".session";
+
+/// Changes on every boot, so a runtime directory from before a reboot is always stale
+pub const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// Lock files hold the PID of the process that owns them, as its decimal text
+pub const LOCK_FILE_EXTENSION: &str = "lock";
+
+/// A socket is owned by the process named in the lock file beside it, `<name>.lock` for `<name>.socket`
+pub const SOCKET_FILE_EXTENSION: &str = "socket";
+
+/// Directory beneath an application's runtime directory holding its shared memory files, kept at mode 0700
+pub const SHM_DIRECTORY_NAME: &str = "shm";
+
+/// Holds a directory for each running process
+pub const PROC_PATH: &str = "/proc";
+
+/// `<boot id>:<XDG_SESSION_ID>`, either half may be empty. `None` when neither is known.
+pub fn current_session<EnvIntT: EnvInt, FsIntT: FsInt>(env: &EnvIntT, fs: &FsIntT) -> Option<String> {
+    let boot_id = fs.read_to_string(Path::new(BOOT_ID_PATH)).ok()
+        .map(|boot_id| boot_id.trim().to_owned());
+
+    let session_id = env.get_path(EnvKey::XdgSessionId).ok()
+        .map(|session_id| session_id.to_string_lossy().into_owned());
+
+    if boot_id.is_none() && session_id.is_none() {
+        return None;
+    }
+
+    Some(format!("{}:{}", boot_id.unwrap_or_default(), session_id.unwrap_or_default()))
+}
+
+/// True when `directory` was registered by a session other than the current one.
+/// Unregistered directories, or an unknown current session, are never stale.
+pub fn is_stale_session<EnvIntT: EnvInt, FsIntT: FsInt>(env: &EnvIntT, fs: &FsIntT, directory: &Path) -> bool {
+    let Ok(registered) = fs.read_to_string(&directory.join(RUNTIME_SESSION_FILE_NAME)) else {
+        return false;
+    };
+
+    current_session(env, fs).is_some_and(|current| current != registered.trim())
+}
+
+/// Whether the process `pid` is running, `None` on systems without a `/proc` to ask
+pub fn is_process_alive<FsIntT: FsInt>(fs: &FsIntT, pid: u32) -> Option<bool> {
+    let proc = Path::new(PROC_PATH);
+
+    if !fs.exists(&proc.join("self")) {
+        return None;
+    }
+
+    Some(fs.exists(&proc.join(pid.to_string())))
+}
+
+/// The lock files in `directory` naming a process that is no longer running, each followed by its socket when there is one.
+/// Locks that do not hold a PID are left alone, as is everything when liveness cannot be checked.
+pub fn stale_files<FsIntT: FsInt>(fs: &FsIntT, directory: &Path) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+    let mut stale = Vec::new();
+
+    if !fs.is_directory(directory) {
+        return Ok(stale);
+    }
+
+    let mut locks: Vec<PathBuf> = fs.read_dir(directory)?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|extension| extension == LOCK_FILE_EXTENSION))
+        .collect();
+
+    locks.sort();
+
+    for lock in locks {
+        let Some(pid) = fs.read_to_string(&lock).ok().and_then(|contents| contents.trim().parse::<u32>().ok()) else {
+            continue;
+        };
+
+        if is_process_alive(fs, pid) != Some(false) {
+            continue;
+        }
+
+        let socket = lock.with_extension(SOCKET_FILE_EXTENSION);
+
+        stale.push(lock);
+
+        if fs.exists(&socket) {
+            stale.push(socket);
+        }
+    }
+
+    Ok(stale)
+}
+
+/// Remove the `stale_files` of `directory`, returning what was removed
+pub fn remove_stale_files<FsIntT: FsInt>(fs: &FsIntT, directory: &Path) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+    let stale = stale_files(fs, directory)?;
+
+    for path in &stale {
+        tracing::info!("removing {:?}, its owner is no longer running", path);
+
+        fs.remove_file(path)?;
+    }
+
+    Ok(stale)
+}
+
+/// `<directory>/shm/<name>`, `InvalidName` unless `name` is a single plain file name
+pub fn shm_path_in(directory: &Path, name: &str) -> Result<PathBuf, VoxelsDirectoryError> {
+    if name.is_empty() || name.len() > 255 || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+        return Err(VoxelsDirectoryError::InvalidName);
+    }
+
+    Ok(directory.join(SHM_DIRECTORY_NAME).join(name))
+}
+
+/// Create the shared memory file `name` of `size` bytes beneath `directory`, readable only by the current user.
+/// The `shm` directory is created with mode 0700 and must have that mode when it already exists.
+pub fn create_shm_in<FsIntT: FsInt>(fs: &FsIntT, directory: &Path, name: &str, size: u64) -> Result<PathBuf, VoxelsDirectoryError> {
+    let path = shm_path_in(directory, name)?;
+    let shm = directory.join(SHM_DIRECTORY_NAME);
+
+    match fs.create_private_dir(&shm) {
+        Ok(()) => {},
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+            if fs.is_symlink(&shm) || !fs.is_directory(&shm) {
+                return Err(VoxelsDirectoryError::NotADirectory);
+            }
+
+            if fs.mode(&shm)? != 0o700 {
+                tracing::warn!("refusing to create {:?}, {:?} is not private", path, shm);
+                return Err(VoxelsDirectoryError::Io(std::io::ErrorKind::PermissionDenied));
+            }
+        },
+        Err(error) => return Err(error.into()),
+    }
+
+    fs.create_private_file(&path, size)?;
+
+    Ok(path)
+}
+
+/// Removes the runtime directory when dropped, hold it for as long as the session lasts.
+/// Runtime directories on a tmpfs vanish at logout anyway, this covers `VOXELS_RUNTIME_HOME` on disk.
+pub struct RuntimeCleanup {
+    path: Option<PathBuf>,
+    fs: Arc<dyn FsInt + Send + Sync>,
+}
+
+impl RuntimeCleanup {
+    /// Remove `path` through `fs` when dropped
+    pub fn new(path: PathBuf, fs: Arc<dyn FsInt + Send + Sync>) -> Self {
+        Self {
+            path: Some(path),
+            fs
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Keep the directory after all
+    pub fn disarm(&mut self) {
+        self.path = None;
+    }
+}
+
+impl std::fmt::Debug for RuntimeCleanup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RuntimeCleanup").field("path", &self.path).finish_non_exhaustive()
+    }
+}
+
+impl Drop for RuntimeCleanup {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+
+        if let Err(error) = self.fs.remove_dir_all(&path) {
+            tracing::warn!("failed to clean up runtime directory {:?}: {}", path, error);
+        }
+    }
+}
+
 #[mockall::automock]
 pub trait RuntimeDirectoryResolver {
     async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
 
     async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError>;
 
+    /// Create the directory, mark it as belonging to the current session and remove it when the returned handle drops
+    async fn register_cleanup(&mut self, application: Application) -> Result<RuntimeCleanup, VoxelsDirectoryError>;
+
+    /// Whether the directory holds leftover sockets and locks from a previous session, see `is_stale_session`
+    async fn is_stale(&mut self, application: Application) -> Result<bool, VoxelsDirectoryError>;
+
+    /// Where the shared memory file `name` lives, see `shm_path_in`. Nothing is created.
+    async fn shm_path(&mut self, application: Application, name: &str) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        shm_path_in(&resolved, name)
+    }
+
+    /// Create the shared memory file `name` of `size` bytes for other processes of the application to map, see `create_shm_in`
+    async fn create_shm(&mut self, application: Application, name: &str, size: u64) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    /// Remove locks and sockets left by processes that crashed, see `stale_files`. Call at startup before binding sockets.
+    async fn cleanup_stale(&mut self, application: Application) -> Result<Vec<PathBuf>, VoxelsDirectoryError>;
+
     fn is_resolved(&self) -> bool;
 }
 
-pub struct RuntimeDirectory<BaseT: base::RuntimeDirectoryResolver> {
+pub struct RuntimeDirectory<BaseT: base::RuntimeDirectoryResolver, FsIntT: FsInt = DefaultFsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt, EnvIntT: EnvInt = DefaultEnvInt> {
     data_path: Option<PathBuf>,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+    /// Replaces `<runtime>/voxels/<rdn>` when the manifest relocates this application's runtime
+    pub relocation: Option<PathBuf>,
+    /// Pins read from `VOXELS_DIRS_FILE` when this was built, the entry for the application wins over `relocation`
+    pub dirs_file: Option<DirsFile>,
+    pub create_options: CreateOptions,
     base: BaseT,
+    fs: Arc<FsIntT>,
+    async_fs: AsyncFsIntT,
+    env: EnvIntT,
 }
 
 impl<BaseT: base::RuntimeDirectoryResolver> RuntimeDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_fs(base, DefaultFsInt)
+    }
+}
+
+impl<BaseT: base::RuntimeDirectoryResolver, FsIntT: FsInt> RuntimeDirectory<BaseT, FsIntT> {
+    pub fn with_fs(base: BaseT, fs: FsIntT) -> Self {
+        Self::with_async_fs(base, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::RuntimeDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> RuntimeDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// As `with_fs`, creating the directory through `async_fs` from `resolve_and_create`
+    pub fn with_async_fs(base: BaseT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        Self::with_env(base, fs, async_fs, DefaultEnvInt)
+    }
+}
+
+impl<BaseT: base::RuntimeDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt, EnvIntT: EnvInt> RuntimeDirectory<BaseT, FsIntT, AsyncFsIntT, EnvIntT> {
+    /// As `with_async_fs`, with the login session read from `env`
+    pub fn with_env(base: BaseT, fs: FsIntT, async_fs: AsyncFsIntT, env: EnvIntT) -> Self {
+        let dirs_file = DirsFile::load(&env, &fs);
+
         Self {
             data_path: None,
-            base
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+            relocation: None,
+            dirs_file,
+            create_options: CreateOptions::default(),
+            base,
+            fs: Arc::new(fs),
+            async_fs,
+            env
         }
     }
 }
 
-impl<BaseT: base::RuntimeDirectoryResolver> RuntimeDirectoryResolver for RuntimeDirectory<BaseT> {
+impl<BaseT: base::RuntimeDirectoryResolver, FsIntT: FsInt + Send + Sync + 'static, AsyncFsIntT: AsyncFsInt, EnvIntT: EnvInt> RuntimeDirectoryResolver for RuntimeDirectory<BaseT, FsIntT, AsyncFsIntT, EnvIntT> {
     async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         // if resolve has been called previously we update this objects path
         if self.is_resolved() {
             return Ok(self.data_path.clone().unwrap());
         }
 
+        if let Some(pinned) = pinned_path(self.dirs_file.as_ref(), &application, DirectoryKind::Runtime) {
+            return Ok(pinned);
+        }
+
+        if let Some(relocation) = &self.relocation {
+            if !relocation.is_absolute() {
+                return Err(VoxelsDirectoryError::InvalidRelocation);
+            }
+
+            return Ok(relocation.clone());
+        }
+
         let base = self.base.resolve().await?;
 
-        Ok(base.join(application.rdn().as_path()))
+        rdn_path_within(&*self.fs, &base, &application, self.rdn_case, self.rdn_layout)
     }
 
     async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve(application).await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
     }
 
+    async fn register_cleanup(&mut self, application: Application) -> Result<RuntimeCleanup, VoxelsDirectoryError> {
+        let resolved = self.resolve_and_create(application).await?;
+
+        if let Some(session) = current_session(&self.env, &*self.fs) {
+            self.fs.write(&resolved.join(RUNTIME_SESSION_FILE_NAME), &session)?;
+        }
+
+        Ok(RuntimeCleanup::new(resolved, self.fs.clone()))
+    }
+
+    async fn is_stale(&mut self, application: Application) -> Result<bool, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        Ok(is_stale_session(&self.env, &*self.fs, &resolved))
+    }
+
+    async fn cleanup_stale(&mut self, application: Application) -> Result<Vec<PathBuf>, VoxelsDirectoryError> {
+        let resolved = self.resolve(application).await?;
+
+        remove_stale_files(&*self.fs, &resolved)
+    }
+
+    async fn create_shm(&mut self, application: Application, name: &str, size: u64) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve_and_create(application).await?;
+
+        create_shm_in(&*self.fs, &resolved, name, size)
+    }
+
     fn is_resolved(&self) -> bool {
         self.data_path.is_some()
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_is_stale_session() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    env.expect_get_path()
+        .with(mockall::predicate::eq(EnvKey::XdgSessionId))
+        .returning(|_| Ok(PathBuf::from("2")));
+
+    fs.expect_read_to_string()
+        .returning(|path| {
+            if path == Path::new(BOOT_ID_PATH) {
+                Ok(String::from("boot\n"))
+            } else if path == Path::new("/run/previous/.session") {
+                Ok(String::from("boot:1"))
+            } else if path == Path::new("/run/current/.session") {
+                Ok(String::from("boot:2"))
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+            }
+        });
+
+    assert!(is_stale_session(&env, &fs, Path::new("/run/previous")));
+    assert!(!is_stale_session(&env, &fs, Path::new("/run/current")));
+    assert!(!is_stale_session(&env, &fs, Path::new("/run/unregistered")));
+}
+
+#[test]
+fn test_stale_files() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_read_dir()
+        .returning(|_| Ok(vec![
+            PathBuf::from("/run/app/server.lock"),
+            PathBuf::from("/run/app/server.socket"),
+            PathBuf::from("/run/app/worker.lock"),
+            PathBuf::from("/run/app/garbage.lock"),
+        ]));
+
+    fs.expect_read_to_string()
+        .returning(|path| Ok(String::from(match path.to_str().unwrap() {
+            "/run/app/server.lock" => "100\n",
+            "/run/app/worker.lock" => "200",
+            _ => "not a pid",
+        })));
+
+    fs.expect_exists()
+        .returning(|path| matches!(path.to_str().unwrap(), "/proc/self" | "/proc/200" | "/run/app/server.socket"));
+
+    assert_eq!(stale_files(&fs, Path::new("/run/app")).unwrap(), vec![
+        PathBuf::from("/run/app/server.lock"),
+        PathBuf::from("/run/app/server.socket"),
+    ]);
+}
+
+#[test]
+fn test_create_shm_in() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    assert!(shm_path_in(Path::new("/run/app"), "../frames").is_err());
+    assert!(shm_path_in(Path::new("/run/app"), "").is_err());
+
+    fs.expect_create_private_dir()
+        .with(mockall::predicate::eq(PathBuf::from("/run/app/shm")))
+        .returning(|_| Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_mode()
+        .returning(|_| Ok(0o700));
+
+    fs.expect_create_private_file()
+        .with(mockall::predicate::eq(PathBuf::from("/run/app/shm/frames")), mockall::predicate::eq(4096))
+        .return_once(|_, _| Ok(()));
+
+    assert_eq!(create_shm_in(&fs, Path::new("/run/app"), "frames", 4096).unwrap(), PathBuf::from("/run/app/shm/frames"));
+}
+
+#[test]
+fn test_runtime_cleanup_removes_through_fs() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_remove_dir_all()
+        .with(mockall::predicate::eq(PathBuf::from("/run/app")))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    drop(RuntimeCleanup::new(PathBuf::from("/run/app"), Arc::new(fs)));
+
+    // a disarmed handle removes nothing, so the mock without expectations is never called
+    let mut disarmed = RuntimeCleanup::new(PathBuf::from("/run/app"), Arc::new(crate::filesystem::MockFsInt::new()));
+
+    disarmed.disarm();
+
+    drop(disarmed);
+}
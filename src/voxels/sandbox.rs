@@ -0,0 +1,151 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use crate::environment_variables::EnvInt;
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::application_directory::{resolve_if_candidate, ApplicationDirectory, BaseDirectories};
+
+use super::voxels_xdg::{cache, config, data, runtime, state};
+
+use super::voxels_xdg::xdg::overlay::system_dirs;
+
+/// The directories a sandboxed application is allowed to see
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SandboxProfile {
+    pub read_write: Vec<PathBuf>,
+    pub read_only: Vec<PathBuf>,
+}
+
+impl SandboxProfile {
+    pub fn allow_read_write(&mut self, path: PathBuf) {
+        if !self.read_write.contains(&path) {
+            self.read_write.push(path);
+        }
+    }
+
+    pub fn allow_read_only(&mut self, path: PathBuf) {
+        if !self.read_only.contains(&path) {
+            self.read_only.push(path);
+        }
+    }
+
+    /// Arguments for bwrap(1). Read only directories are bound first so a writable directory
+    /// beneath one of them is still writable. Missing read only directories are skipped by bwrap,
+    /// missing writable ones are an error, create them with `ApplicationDirectory::ensure_layout` first.
+    pub fn bwrap_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+
+        for path in &self.read_only {
+            args.push(OsString::from("--ro-bind-try"));
+            args.push(path.clone().into_os_string());
+            args.push(path.clone().into_os_string());
+        }
+
+        for path in &self.read_write {
+            args.push(OsString::from("--bind"));
+            args.push(path.clone().into_os_string());
+            args.push(path.clone().into_os_string());
+        }
+
+        args
+    }
+
+    /// Arguments for firejail(1), whitelisting the writable directories and marking the rest read only
+    pub fn firejail_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+
+        for (flag, paths) in [("--read-only=", &self.read_only), ("--whitelist=", &self.read_write)] {
+            for path in paths {
+                let mut arg = OsString::from(flag);
+                arg.push(path);
+                args.push(arg);
+            }
+        }
+
+        args
+    }
+}
+
+/// The resolved config, data, state and runtime directories of `directory` as writable, and the
+/// system config and data directories from `env` as read only. Kinds without a candidate are left out.
+pub async fn sandbox_profile<ConfigT, DataT, StateT, CacheT, RuntimeT, EnvIntT>(directory: &ApplicationDirectory, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, env: &EnvIntT) -> Result<SandboxProfile, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+    EnvIntT: EnvInt,
+{
+    let mut profile = SandboxProfile::default();
+
+    let writable = [
+        resolve_if_candidate(&mut directory.config(bases.config)).await?,
+        resolve_if_candidate(&mut directory.data(bases.data)).await?,
+        resolve_if_candidate(&mut directory.state(bases.state)).await?,
+        resolve_if_candidate(&mut directory.runtime(bases.runtime)).await?,
+    ];
+
+    for path in writable.into_iter().flatten() {
+        profile.allow_read_write(path);
+    }
+
+    for kind in [DirectoryKind::Config, DirectoryKind::Data] {
+        for path in system_dirs(env, kind) {
+            profile.allow_read_only(path);
+        }
+    }
+
+    Ok(profile)
+}
+
+/// The bwrap(1) bind mounts exposing exactly the directories of `directory`, see `sandbox_profile`
+pub async fn generate_bwrap_args<ConfigT, DataT, StateT, CacheT, RuntimeT, EnvIntT>(directory: &ApplicationDirectory, bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, env: &EnvIntT) -> Result<Vec<OsString>, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+    EnvIntT: EnvInt,
+{
+    Ok(sandbox_profile(directory, bases, env).await?.bwrap_args())
+}
+
+#[test]
+fn test_sandbox_profile_args() {
+    let mut profile = SandboxProfile::default();
+
+    profile.allow_read_only(PathBuf::from("/etc/xdg"));
+    profile.allow_read_write(PathBuf::from("/home/user/.config/voxels/org.voxels.Client"));
+    profile.allow_read_write(PathBuf::from("/home/user/.config/voxels/org.voxels.Client"));
+
+    assert_eq!(profile.bwrap_args(), vec![
+        OsString::from("--ro-bind-try"), OsString::from("/etc/xdg"), OsString::from("/etc/xdg"),
+        OsString::from("--bind"), OsString::from("/home/user/.config/voxels/org.voxels.Client"), OsString::from("/home/user/.config/voxels/org.voxels.Client"),
+    ]);
+
+    assert_eq!(profile.firejail_args(), vec![
+        OsString::from("--read-only=/etc/xdg"),
+        OsString::from("--whitelist=/home/user/.config/voxels/org.voxels.Client"),
+    ]);
+}
@@ -0,0 +1,188 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::PathBuf;
+
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::CreateOptions;
+
+use super::VoxelsDirectoryError;
+use super::voxels_xdg::ResolvedPath;
+
+pub use super::rdn::SHARED_DIRECTORY_NAME;
+
+#[cfg(feature = "data")]
+use super::voxels_xdg::data as data_base;
+
+#[cfg(feature = "config")]
+use super::voxels_xdg::config as config_base;
+
+/// Assets several tools exchange live in `<data>/voxels/shared/assets`
+pub const SHARED_ASSETS_DIRECTORY_NAME: &str = "assets";
+
+/// A voxels directory owned by no single application, so it is resolved without an RDN
+#[mockall::automock]
+pub trait SharedDirectoryResolver {
+    async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+}
+
+/// `<data>/voxels/shared/assets`, for assets exchanged between tools
+#[cfg(feature = "data")]
+pub struct SharedAssetsDirectory<BaseT: data_base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
+    pub create_options: CreateOptions,
+    base: BaseT,
+    async_fs: AsyncFsIntT,
+}
+
+#[cfg(feature = "data")]
+impl<BaseT: data_base::DataDirectoryResolver> SharedAssetsDirectory<BaseT> {
+    pub fn new(base: BaseT) -> Self {
+        Self::with_async_fs(base, DefaultAsyncFsInt)
+    }
+}
+
+#[cfg(feature = "data")]
+impl<BaseT: data_base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt> SharedAssetsDirectory<BaseT, AsyncFsIntT> {
+    /// As `new`, creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, async_fs: AsyncFsIntT) -> Self {
+        Self {
+            path: ResolvedPath::default(),
+            create_options: CreateOptions::default(),
+            base,
+            async_fs
+        }
+    }
+}
+
+#[cfg(feature = "data")]
+impl<BaseT: data_base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt> SharedDirectoryResolver for SharedAssetsDirectory<BaseT, AsyncFsIntT> {
+    async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
+        }
+
+        let base = self.base.resolve().await?;
+
+        let path = base.join(SHARED_DIRECTORY_NAME).join(SHARED_ASSETS_DIRECTORY_NAME);
+
+        self.path.set(path.clone());
+
+        Ok(path)
+    }
+
+    async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve().await?;
+
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
+
+        Ok(resolved)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
+}
+
+/// `<config>/voxels/shared`, for settings every tool of the ecosystem reads
+#[cfg(feature = "config")]
+pub struct SharedConfigDirectory<BaseT: config_base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
+    pub create_options: CreateOptions,
+    base: BaseT,
+    async_fs: AsyncFsIntT,
+}
+
+#[cfg(feature = "config")]
+impl<BaseT: config_base::ConfigDirectoryResolver> SharedConfigDirectory<BaseT> {
+    pub fn new(base: BaseT) -> Self {
+        Self::with_async_fs(base, DefaultAsyncFsInt)
+    }
+}
+
+#[cfg(feature = "config")]
+impl<BaseT: config_base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt> SharedConfigDirectory<BaseT, AsyncFsIntT> {
+    /// As `new`, creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, async_fs: AsyncFsIntT) -> Self {
+        Self {
+            path: ResolvedPath::default(),
+            create_options: CreateOptions::default(),
+            base,
+            async_fs
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl<BaseT: config_base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt> SharedDirectoryResolver for SharedConfigDirectory<BaseT, AsyncFsIntT> {
+    async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
+        }
+
+        let base = self.base.resolve().await?;
+
+        let path = base.join(SHARED_DIRECTORY_NAME);
+
+        self.path.set(path.clone());
+
+        Ok(path)
+    }
+
+    async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve().await?;
+
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
+
+        Ok(resolved)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
+}
+
+#[cfg(all(feature = "data", feature = "config"))]
+#[tokio::test]
+async fn test_shared_directories() {
+    let mut data = data_base::MockDataDirectoryResolver::new();
+
+    data.expect_resolve()
+        .once()
+        .return_once(|| Ok(PathBuf::from("/home/user/.local/share/voxels")));
+
+    let mut assets = SharedAssetsDirectory::new(data);
+
+    assert_eq!(assets.resolve().await, Ok(PathBuf::from("/home/user/.local/share/voxels/shared/assets")));
+    assert!(assets.is_resolved());
+    assert_eq!(assets.resolve().await, Ok(PathBuf::from("/home/user/.local/share/voxels/shared/assets")));
+
+    let mut config = config_base::MockConfigDirectoryResolver::new();
+
+    config.expect_resolve()
+        .once()
+        .return_once(|| Err(VoxelsDirectoryError::NoCandidate));
+
+    let mut shared_config = SharedConfigDirectory::new(config);
+
+    assert_eq!(shared_config.resolve().await, Err(VoxelsDirectoryError::NoCandidate));
+    assert!(!shared_config.is_resolved());
+}
@@ -0,0 +1,135 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+use crate::filesystem::FsInt;
+
+/// Appended to the manifest file name to locate its detached signature, `manifest.toml.sig`
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SignatureError {
+    /// There is no signature next to the manifest
+    Unsigned,
+    /// The signature or a key is not valid ed25519 encoding
+    Malformed,
+    /// No key in the keyring produced the signature over this manifest
+    Untrusted,
+    Io(std::io::ErrorKind),
+}
+
+impl From<std::io::Error> for SignatureError {
+    fn from(err: std::io::Error) -> Self {
+        SignatureError::Io(err.kind())
+    }
+}
+
+/// The publisher keys a manifest may be signed with
+#[derive(Debug, Clone, Default)]
+pub struct Keyring {
+    keys: Vec<VerifyingKey>,
+}
+
+impl Keyring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_key(&mut self, key: &[u8; 32]) -> Result<(), SignatureError> {
+        let key = VerifyingKey::from_bytes(key).map_err(|_| SignatureError::Malformed)?;
+
+        self.keys.push(key);
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        if self.keys.iter().any(|key| key.verify(message, signature).is_ok()) {
+            Ok(())
+        } else {
+            Err(SignatureError::Untrusted)
+        }
+    }
+}
+
+pub fn signature_path(manifest: &Path) -> PathBuf {
+    let mut path = manifest.as_os_str().to_owned();
+    path.push(".");
+    path.push(SIGNATURE_EXTENSION);
+
+    PathBuf::from(path)
+}
+
+/// Read `manifest` only if its detached signature was made by a key in `keyring`.
+/// The returned contents are what lib-voxels-application should parse.
+pub fn read_verified_manifest<FsIntT: FsInt>(fs: &FsIntT, manifest: &Path, keyring: &Keyring) -> Result<String, SignatureError> {
+    let signature_path = signature_path(manifest);
+
+    if !fs.exists(&signature_path) {
+        return Err(SignatureError::Unsigned);
+    }
+
+    let signature = Signature::from_slice(&fs.read(&signature_path)?).map_err(|_| SignatureError::Malformed)?;
+
+    let contents = fs.read(manifest)?;
+
+    keyring.verify(&contents, &signature)?;
+
+    String::from_utf8(contents).map_err(|_| SignatureError::Io(std::io::ErrorKind::InvalidData))
+}
+
+#[test]
+fn test_read_verified_manifest() {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    let publisher = SigningKey::from_bytes(&[7; 32]);
+    let stranger = SigningKey::from_bytes(&[9; 32]);
+
+    let manifest = PathBuf::from("/apps/org.voxels.Client/manifest.toml");
+    let contents = "rdn = \"org.voxels.Client\"\n";
+    let signature = publisher.sign(contents.as_bytes()).to_bytes().to_vec();
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_exists()
+        .returning(|_| true);
+
+    fs.expect_read()
+        .returning(move |path| {
+            if path.extension().is_some_and(|extension| extension == SIGNATURE_EXTENSION) {
+                Ok(signature.clone())
+            } else {
+                Ok(contents.as_bytes().to_vec())
+            }
+        });
+
+    let mut trusted = Keyring::new();
+    trusted.add_key(&publisher.verifying_key().to_bytes()).unwrap();
+
+    let mut untrusted = Keyring::new();
+    untrusted.add_key(&stranger.verifying_key().to_bytes()).unwrap();
+
+    assert_eq!(read_verified_manifest(&fs, &manifest, &trusted), Ok(String::from(contents)));
+    assert_eq!(read_verified_manifest(&fs, &manifest, &untrusted), Err(SignatureError::Untrusted));
+}
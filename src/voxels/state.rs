@@ -18,9 +18,15 @@ use std::path::PathBuf;
 
 use lib_voxels_application::application::application::Application;
 
-use super::VoxelsDirectoryError;
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::rdn::{pinned_path, rdn_path_within, RdnCase, RdnLayout};
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::environment_variables::DefaultEnvInt;
+use crate::filesystem::{CreateOptions, DefaultFsInt, FsInt};
 
 use super::voxels_xdg::state as base;
+use super::voxels_xdg::xdg::dirs_file::DirsFile;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_APPS_STATE_METHOD_NAME: &str = "state";
@@ -34,36 +40,79 @@ pub trait StateDirectoryResolver {
     fn is_resolved(&self) -> bool;
 }
 
-pub struct StateDirectory<BaseT: base::StateDirectoryResolver> {
+pub struct StateDirectory<BaseT: base::StateDirectoryResolver, FsIntT: FsInt = DefaultFsInt, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
     data_path: Option<PathBuf>,
+    pub rdn_case: RdnCase,
+    pub rdn_layout: RdnLayout,
+    /// Replaces `<state>/voxels/<rdn>` when the manifest relocates this application's state
+    pub relocation: Option<PathBuf>,
+    /// Pins read from `VOXELS_DIRS_FILE` when this was built, the entry for the application wins over `relocation`
+    pub dirs_file: Option<DirsFile>,
+    pub create_options: CreateOptions,
     base: BaseT,
+    fs: FsIntT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::StateDirectoryResolver> StateDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_fs(base, DefaultFsInt)
+    }
+}
+
+impl<BaseT: base::StateDirectoryResolver, FsIntT: FsInt> StateDirectory<BaseT, FsIntT> {
+    pub fn with_fs(base: BaseT, fs: FsIntT) -> Self {
+        Self::with_async_fs(base, fs, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::StateDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> StateDirectory<BaseT, FsIntT, AsyncFsIntT> {
+    /// As `with_fs`, creating the directory through `async_fs` from `resolve_and_create`
+    pub fn with_async_fs(base: BaseT, fs: FsIntT, async_fs: AsyncFsIntT) -> Self {
+        let dirs_file = DirsFile::load(&DefaultEnvInt, &fs);
+
         Self {
             data_path: None,
-            base
+            rdn_case: RdnCase::default(),
+            rdn_layout: RdnLayout::default(),
+            relocation: None,
+            dirs_file,
+            create_options: CreateOptions::default(),
+            base,
+            fs,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::StateDirectoryResolver> StateDirectoryResolver for StateDirectory<BaseT> {
+impl<BaseT: base::StateDirectoryResolver, FsIntT: FsInt, AsyncFsIntT: AsyncFsInt> StateDirectoryResolver for StateDirectory<BaseT, FsIntT, AsyncFsIntT> {
     async fn resolve(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         // if resolve has been called previously we update this objects path
         if self.is_resolved() {
             return Ok(self.data_path.clone().unwrap());
         }
 
+        if let Some(pinned) = pinned_path(self.dirs_file.as_ref(), &application, DirectoryKind::State) {
+            return Ok(pinned);
+        }
+
+        if let Some(relocation) = &self.relocation {
+            if !relocation.is_absolute() {
+                return Err(VoxelsDirectoryError::InvalidRelocation);
+            }
+
+            return Ok(relocation.clone());
+        }
+
         let base = self.base.resolve().await?;
 
-        Ok(base.join(application.rdn().as_path()))
+        rdn_path_within(&self.fs, &base, &application, self.rdn_case, self.rdn_layout)
     }
 
     async fn resolve_and_create(&mut self, application: Application) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve(application).await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
     }
@@ -0,0 +1,129 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::filesystem::{measure, FsInt};
+
+use super::{DirectoryKind, VoxelsDirectoryError};
+
+use super::application_directory::BaseDirectories;
+use super::rdn::{application_directories, RdnLayout};
+use super::voxels_xdg::{cache, config, data, runtime, state};
+
+/// The kinds `usage_report` measures. Runtime directories live in memory and are gone at logout.
+pub const USAGE_KINDS: [DirectoryKind; 4] = [DirectoryKind::Config, DirectoryKind::Data, DirectoryKind::State, DirectoryKind::Cache];
+
+/// Bytes used by each application's directories, for a storage settings screen
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsageReport {
+    /// Per RDN, the bytes used by each kind that has a directory
+    pub applications: BTreeMap<String, BTreeMap<DirectoryKind, u64>>,
+}
+
+impl UsageReport {
+    /// Bytes used by every kind of `rdn`
+    pub fn application_total(&self, rdn: &str) -> u64 {
+        self.applications.get(rdn).map_or(0, |kinds| kinds.values().sum())
+    }
+
+    /// Bytes used by `kind` across every application
+    pub fn kind_total(&self, kind: DirectoryKind) -> u64 {
+        self.applications.values().filter_map(|kinds| kinds.get(&kind)).sum()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.applications.values().flat_map(BTreeMap::values).sum()
+    }
+
+    /// RDNs ordered by the space they use, largest first
+    pub fn largest(&self) -> Vec<(&str, u64)> {
+        let mut largest: Vec<(&str, u64)> = self.applications.keys()
+            .map(|rdn| (rdn.as_str(), self.application_total(rdn)))
+            .collect();
+
+        largest.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+        largest
+    }
+}
+
+/// `measure` every application directory beneath each voxels root in `roots`
+pub fn usage_report_in<FsIntT: FsInt>(fs: &FsIntT, roots: &BTreeMap<DirectoryKind, PathBuf>, layout: RdnLayout) -> Result<UsageReport, VoxelsDirectoryError> {
+    let mut report = UsageReport::default();
+
+    for (kind, root) in roots {
+        for (rdn, path) in application_directories(fs, root, layout)? {
+            report.applications.entry(rdn).or_default().insert(*kind, measure(fs, &path)?);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Resolve the config, data, state and cache voxels roots from `bases`, then `usage_report_in` them
+pub async fn usage_report<ConfigT, DataT, StateT, CacheT, RuntimeT, FsIntT>(bases: BaseDirectories<ConfigT, DataT, StateT, CacheT, RuntimeT>, fs: &FsIntT, layout: RdnLayout) -> Result<UsageReport, VoxelsDirectoryError>
+where
+    ConfigT: config::ConfigDirectoryResolver,
+    DataT: data::DataDirectoryResolver,
+    StateT: state::StateDirectoryResolver,
+    CacheT: cache::CacheDirectoryResolver,
+    RuntimeT: runtime::RuntimeDirectoryResolver,
+    FsIntT: FsInt,
+{
+    let roots = bases.resolve_roots().await?
+        .into_iter()
+        .filter(|(kind, _)| USAGE_KINDS.contains(kind))
+        .collect();
+
+    usage_report_in(fs, &roots, layout)
+}
+
+#[test]
+fn test_usage_report_in() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|path| !path.ends_with("blob"));
+
+    fs.expect_read_dir()
+        .returning(|path| Ok(match path.to_str().unwrap() {
+            "/data/voxels" => vec![PathBuf::from("/data/voxels/org.voxels.Client"), PathBuf::from("/data/voxels/org.voxels.Server")],
+            "/cache/voxels" => vec![PathBuf::from("/cache/voxels/org.voxels.Client")],
+            other => vec![PathBuf::from(other).join("blob")],
+        }));
+
+    fs.expect_file_size()
+        .returning(|path| Ok(if path.starts_with("/cache") { 1000 } else { 10 }));
+
+    let roots = BTreeMap::from([
+        (DirectoryKind::Data, PathBuf::from("/data/voxels")),
+        (DirectoryKind::Cache, PathBuf::from("/cache/voxels")),
+    ]);
+
+    let report = usage_report_in(&fs, &roots, RdnLayout::Flat).unwrap();
+
+    assert_eq!(report.application_total("org.voxels.Client"), 1010);
+    assert_eq!(report.kind_total(DirectoryKind::Data), 20);
+    assert_eq!(report.total(), 1020);
+    assert_eq!(report.largest(), vec![("org.voxels.Client", 1010), ("org.voxels.Server", 10)]);
+}
@@ -0,0 +1,396 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+use crate::voxels::voxels_xdg::xdg::{cache as base};
+use crate::voxels::voxels_xdg::xdg::ordering;
+use crate::voxels::voxels_xdg::xdg::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+
+use super::{LayoutStrategy, Namespace, ResolvedPath, VoxelsDirectoryError};
+#[cfg(feature = "dbus")]
+use super::DBUS_STANDARD_VOXELS_XDG_PATH;
+
+use std::collections::BTreeSet;
+use std::path::{PathBuf};
+use crate::environment_variables::EnvKey;
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::CreateOptions;
+use tracing::trace;
+
+#[cfg(feature = "dbus")]
+use std::sync::Arc;
+#[cfg(feature = "dbus")]
+use dbus::nonblock::SyncConnection;
+#[cfg(feature = "dbus")]
+use super::dbus::ConnectionLossHandler;
+
+#[cfg(feature = "dbus")]
+pub const DBUS_STANDARD_VOXELS_XDG_CACHE_METHOD_NAME: &str = "cache";
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
+pub enum CacheDirectoryResolutionMethods {
+    FromXDG,
+    #[cfg(feature = "dbus")]
+    FromDBus,
+}
+
+impl CacheDirectoryResolutionMethods {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CacheDirectoryResolutionMethods::FromXDG => "xdg",
+            #[cfg(feature = "dbus")]
+            CacheDirectoryResolutionMethods::FromDBus => "dbus",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdg" => Some(CacheDirectoryResolutionMethods::FromXDG),
+            #[cfg(feature = "dbus")]
+            "dbus" => Some(CacheDirectoryResolutionMethods::FromDBus),
+            _ => None,
+        }
+    }
+}
+
+impl ResolutionMethod for CacheDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        CacheDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<CacheDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: CacheDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for CacheDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<CacheDirectoryResolutionMethods>", from = "Vec<CacheDirectoryResolutionMethods>"))]
+pub struct CacheDirectoryPriority {
+    order: std::collections::BTreeMap<usize, CacheDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<CacheDirectoryResolutionMethods>,
+}
+
+impl Default for CacheDirectoryPriority {
+    #[cfg(feature = "dbus")]
+    fn default() -> Self {
+        let mut order = std::collections::BTreeMap::new();
+        order.insert(0, CacheDirectoryResolutionMethods::FromDBus);
+        order.insert(1, CacheDirectoryResolutionMethods::FromXDG);
+        Self {
+            order,
+            sources: ordering::PrioritySources::new()
+        }
+    }
+
+    #[cfg(not(feature = "dbus"))]
+    fn default() -> Self {
+        let mut order = std::collections::BTreeMap::new();
+        order.insert(0, CacheDirectoryResolutionMethods::FromXDG);
+        Self {
+            order,
+            sources: ordering::PrioritySources::new()
+        }
+    }
+}
+
+impl CacheDirectoryPriority {
+    #[cfg(feature = "dbus")]
+    pub fn set_all(&mut self, new_order: [CacheDirectoryResolutionMethods; 2]) {
+        self.order = std::collections::BTreeMap::new();
+        self.order.insert(0, new_order[0].clone());
+        self.order.insert(1, new_order[1].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    #[cfg(not(feature = "dbus"))]
+    pub fn set_all(&mut self, new_order: [CacheDirectoryResolutionMethods; 1]) {
+        self.order = std::collections::BTreeMap::new();
+        self.order.insert(0, new_order[0].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, CacheDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, CacheDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for CacheDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, CacheDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<CacheDirectoryPriority> for Vec<CacheDirectoryResolutionMethods> {
+    fn from(priority: CacheDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<CacheDirectoryResolutionMethods>> for CacheDirectoryPriority {
+    fn from(methods: Vec<CacheDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
+    }
+}
+
+#[mockall::automock]
+pub trait CacheDirectoryResolver {
+
+    #[cfg(feature = "dbus")]
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    /// Called when the bus drops a connection this resolver opened
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler);
+
+    /// Skip D-Bus resolution whatever the priority, see `DbusOptions::offline`
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool);
+
+    #[cfg(feature = "dbus")]
+    async fn resolve_using_dbus_with_connection(&mut self, connection: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    fn is_resolved(&self) -> bool;
+
+    /// Forget the resolved path so the next call resolves again, after the base directory moved
+    fn invalidate(&mut self);
+
+    /// Every environment variable resolution may read, those of the base directory and of the bus when D-Bus is in the priority
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey>;
+}
+
+pub struct CacheDirectory<BaseT: base::CacheDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
+    pub priority: CacheDirectoryPriority,
+    namespace: Namespace,
+    pub layout: LayoutStrategy,
+    pub create_options: CreateOptions,
+    #[cfg(feature = "dbus")]
+    pub dbus: super::dbus::DbusOptions,
+    #[cfg(feature = "dbus")]
+    connection_loss: Option<ConnectionLossHandler>,
+    base: BaseT,
+    async_fs: AsyncFsIntT,
+}
+
+impl<BaseT: base::CacheDirectoryResolver> CacheDirectory<BaseT> {
+    pub fn new(base: BaseT) -> Self {
+        Self::with_namespace(base, Namespace::default())
+    }
+
+    /// Resolve beneath `namespace` instead of `voxels`, for white-label builds and test isolation
+    pub fn with_namespace(base: BaseT, namespace: Namespace) -> Self {
+        Self::with_async_fs(base, namespace, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::CacheDirectoryResolver, AsyncFsIntT: AsyncFsInt> CacheDirectory<BaseT, AsyncFsIntT> {
+    /// As `with_namespace`, verifying D-Bus paths and creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, namespace: Namespace, async_fs: AsyncFsIntT) -> Self {
+        let priority = CacheDirectoryPriority::default();
+
+        Self {
+            path: ResolvedPath::default(),
+            priority,
+            namespace,
+            layout: LayoutStrategy::default(),
+            create_options: CreateOptions::default(),
+            #[cfg(feature = "dbus")]
+            dbus: super::dbus::DbusOptions::default(),
+            #[cfg(feature = "dbus")]
+            connection_loss: None,
+            base,
+            async_fs
+        }
+    }
+}
+
+impl<BaseT: base::CacheDirectoryResolver, AsyncFsIntT: AsyncFsInt> CacheDirectoryResolver for CacheDirectory<BaseT, AsyncFsIntT> {
+
+    #[cfg(feature = "dbus")]
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        trace!("Resolving cache directory from DBus");
+
+        // if resolve has been called previously we update this objects path
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
+        }
+
+        let connection = super::dbus::DbusConnection::open(self.dbus, self.connection_loss.clone())?;
+
+        let con = connection.connection();
+
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
+
+        let proxy = dbus::nonblock::Proxy::new(interface, DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
+
+        let cache_path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_CACHE_METHOD_NAME).await?;
+
+        let cache_path = super::dbus::verify_returned_path(&self.async_fs, cache_path, self.dbus.verifier).await?;
+
+        self.path.set(cache_path.clone());
+
+        Ok(cache_path)
+    }
+
+    #[cfg(feature = "dbus")]
+    async fn resolve_using_dbus_with_connection(&mut self, con: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError> {
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
+
+        let proxy = dbus::nonblock::Proxy::new(interface, DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
+
+        let cache_path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_CACHE_METHOD_NAME).await?;
+
+        let cache_path = super::dbus::verify_returned_path(&self.async_fs, cache_path, self.dbus.verifier).await?;
+
+        self.path.set(cache_path.clone());
+
+        Ok(cache_path)
+    }
+
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler) {
+        self.connection_loss = Some(handler);
+    }
+
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool) {
+        self.dbus.offline = offline;
+    }
+
+    fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        trace!("Resolving cache directory from XDG");
+
+        // if resolve has been called previously we update this objects path
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
+        }
+
+        let (base, _how) = self.base.resolve()?;
+
+        let cache_path = self.layout.root(&base, &self.namespace);
+
+        self.path.set(cache_path.clone());
+
+        Ok(cache_path)
+    }
+
+    async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        let methods: Vec<CacheDirectoryResolutionMethods> = self.priority.order.values().cloned().collect();
+
+        super::resolve_in_order(self, &methods).await
+    }
+
+    async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
+        let resolved = self.resolve().await?;
+
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
+
+        Ok(resolved)
+    }
+
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
+
+    fn invalidate(&mut self) {
+        self.path.clear();
+    }
+
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey> {
+        #[allow(unused_mut)]
+        let mut consumed = self.base.consumed_env_vars();
+
+        #[cfg(feature = "dbus")]
+        if self.priority.order.values().any(|method| *method == CacheDirectoryResolutionMethods::FromDBus) {
+            consumed.extend(super::dbus::consumed_env_vars(self.dbus));
+        }
+
+        consumed
+    }
+}
+
+impl<BaseT: base::CacheDirectoryResolver, AsyncFsIntT: AsyncFsInt> super::PriorityResolver for CacheDirectory<BaseT, AsyncFsIntT> {
+    type Method = CacheDirectoryResolutionMethods;
+
+    async fn resolve_using(&mut self, method: &CacheDirectoryResolutionMethods) -> Result<PathBuf, VoxelsDirectoryError> {
+        match method {
+            #[cfg(feature = "dbus")]
+            CacheDirectoryResolutionMethods::FromDBus => self.resolve_using_dbus().await,
+            CacheDirectoryResolutionMethods::FromXDG => self.resolve_using_xdg(),
+        }
+    }
+}
+
+impl<BaseT: base::CacheDirectoryResolver, AsyncFsIntT: AsyncFsInt> From<CacheDirectory<BaseT, AsyncFsIntT>> for Option<PathBuf> {
+    fn from(directory: CacheDirectory<BaseT, AsyncFsIntT>) -> Self {
+        directory.path.into_inner()
+    }
+}
+
+#[test]
+fn test_resolve_using_xdg_is_cached() {
+    let mut base = base::MockCacheDirectoryResolver::new();
+
+    // once for the first resolution and once after `invalidate`, never for the cached call between
+    base.expect_resolve()
+        .times(2)
+        .returning(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/user/.cache")).unwrap(), base::CacheDirectoryResolutionMethods::FromXDG)));
+
+    let mut cache = CacheDirectory::new(base);
+
+    assert!(!cache.is_resolved());
+    assert_eq!(cache.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.cache/voxels"));
+    assert!(cache.is_resolved());
+    assert_eq!(cache.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.cache/voxels"));
+
+    cache.invalidate();
+
+    assert!(!cache.is_resolved());
+    assert_eq!(cache.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.cache/voxels"));
+}
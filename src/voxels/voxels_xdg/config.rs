@@ -15,30 +15,86 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 use crate::voxels::voxels_xdg::xdg::{config as base};
+use crate::voxels::voxels_xdg::xdg::ordering;
+use crate::voxels::voxels_xdg::xdg::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
 
-use super::{VoxelsDirectoryError, DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_PATH};
+use super::{LayoutStrategy, Namespace, ResolvedPath, VoxelsDirectoryError};
+#[cfg(feature = "dbus")]
+use super::DBUS_STANDARD_VOXELS_XDG_PATH;
 
+use std::collections::BTreeSet;
 use std::path::{PathBuf};
+use crate::environment_variables::EnvKey;
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::CreateOptions;
 use tracing::trace;
 
+#[cfg(feature = "dbus")]
 use std::sync::Arc;
-use std::time::Duration;
+#[cfg(feature = "dbus")]
 use dbus::nonblock::SyncConnection;
-use dbus_tokio::connection::IOResourceError;
-use tokio_util::sync::CancellationToken;
+#[cfg(feature = "dbus")]
+use super::dbus::ConnectionLossHandler;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_VOXELS_XDG_CONFIG_METHOD_NAME: &str = "config";
 
-#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum ConfigDirectoryResolutionMethods {
     FromXDG,
     #[cfg(feature = "dbus")]
     FromDBus,
 }
 
+impl ConfigDirectoryResolutionMethods {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigDirectoryResolutionMethods::FromXDG => "xdg",
+            #[cfg(feature = "dbus")]
+            ConfigDirectoryResolutionMethods::FromDBus => "dbus",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdg" => Some(ConfigDirectoryResolutionMethods::FromXDG),
+            #[cfg(feature = "dbus")]
+            "dbus" => Some(ConfigDirectoryResolutionMethods::FromDBus),
+            _ => None,
+        }
+    }
+}
+
+impl ResolutionMethod for ConfigDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        ConfigDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<ConfigDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: ConfigDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for ConfigDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<ConfigDirectoryResolutionMethods>", from = "Vec<ConfigDirectoryResolutionMethods>"))]
 pub struct ConfigDirectoryPriority {
     order: std::collections::BTreeMap<usize, ConfigDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<ConfigDirectoryResolutionMethods>,
 }
 
 impl Default for ConfigDirectoryPriority {
@@ -48,7 +104,8 @@ impl Default for ConfigDirectoryPriority {
         order.insert(0, ConfigDirectoryResolutionMethods::FromDBus);
         order.insert(1, ConfigDirectoryResolutionMethods::FromXDG);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 
@@ -57,7 +114,8 @@ impl Default for ConfigDirectoryPriority {
         let mut order = std::collections::BTreeMap::new();
         order.insert(0, ConfigDirectoryResolutionMethods::FromXDG);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
@@ -68,16 +126,49 @@ impl ConfigDirectoryPriority {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
         self.order.insert(1, new_order[1].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
     }
 
     #[cfg(not(feature = "dbus"))]
     pub fn set_all(&mut self, new_order: [ConfigDirectoryResolutionMethods; 1]) {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, ConfigDirectoryResolutionMethods> {
+        &self.order
     }
 
-    pub fn get(&self) -> std::collections::BTreeMap<usize, ConfigDirectoryResolutionMethods> {
-        self.order.clone()
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, ConfigDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for ConfigDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, ConfigDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<ConfigDirectoryPriority> for Vec<ConfigDirectoryResolutionMethods> {
+    fn from(priority: ConfigDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<ConfigDirectoryResolutionMethods>> for ConfigDirectoryPriority {
+    fn from(methods: Vec<ConfigDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
@@ -85,174 +176,237 @@ impl ConfigDirectoryPriority {
 pub trait ConfigDirectoryResolver {
 
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F: FnOnce(IOResourceError) + Send + 'static>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>;
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    /// Called when the bus drops a connection this resolver opened
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler);
+
+    /// Skip D-Bus resolution whatever the priority, see `DbusOptions::offline`
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool);
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, connection: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError>;
 
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
     fn is_resolved(&self) -> bool;
+
+    /// Forget the resolved path so the next call resolves again, after the base directory moved
+    fn invalidate(&mut self);
+
+    /// Every environment variable resolution may read, those of the base directory and of the bus when D-Bus is in the priority
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey>;
 }
 
-pub struct ConfigDirectory<BaseT: base::ConfigDirectoryResolver> {
-    path: Option<PathBuf>,
+pub struct ConfigDirectory<BaseT: base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
     pub priority: ConfigDirectoryPriority,
+    namespace: Namespace,
+    pub layout: LayoutStrategy,
+    pub create_options: CreateOptions,
+    #[cfg(feature = "dbus")]
+    pub dbus: super::dbus::DbusOptions,
+    #[cfg(feature = "dbus")]
+    connection_loss: Option<ConnectionLossHandler>,
     base: BaseT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::ConfigDirectoryResolver> ConfigDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_namespace(base, Namespace::default())
+    }
+
+    /// Resolve beneath `namespace` instead of `voxels`, for white-label builds and test isolation
+    pub fn with_namespace(base: BaseT, namespace: Namespace) -> Self {
+        Self::with_async_fs(base, namespace, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt> ConfigDirectory<BaseT, AsyncFsIntT> {
+    /// As `with_namespace`, verifying D-Bus paths and creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, namespace: Namespace, async_fs: AsyncFsIntT) -> Self {
         let priority = ConfigDirectoryPriority::default();
 
         Self {
-            path: None,
+            path: ResolvedPath::default(),
             priority,
-            base
+            namespace,
+            layout: LayoutStrategy::default(),
+            create_options: CreateOptions::default(),
+            #[cfg(feature = "dbus")]
+            dbus: super::dbus::DbusOptions::default(),
+            #[cfg(feature = "dbus")]
+            connection_loss: None,
+            base,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::ConfigDirectoryResolver> ConfigDirectoryResolver for ConfigDirectory<BaseT> {
+impl<BaseT: base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt> ConfigDirectoryResolver for ConfigDirectory<BaseT, AsyncFsIntT> {
 
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>
-    where
-        F: FnOnce(IOResourceError) + Send + 'static
-    {
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         trace!("Resolving config directory from DBus");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
-        let (res, con) =
-            dbus_tokio
-            ::connection
-            ::new_session_sync()
-            .unwrap();
+        let connection = super::dbus::DbusConnection::open(self.dbus, self.connection_loss.clone())?;
 
-        let cancellation_token = CancellationToken::new();
+        let con = connection.connection();
 
-        let child_token = cancellation_token.child_token();
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
 
-        let _ = tokio::task::spawn(async move {
-            tokio::select! {
-                err = res => {
-                    on_connection_loss(err);
-                },
-                _ = child_token.cancelled() => {
-                    return;
-                }
-            }
-        });
+        let proxy = dbus::nonblock::Proxy::new(interface, DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let proxy = dbus::nonblock::Proxy::new(DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let config_path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_CONFIG_METHOD_NAME).await?;
 
-        let (config,): (String,) = proxy.method_call(DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_CONFIG_METHOD_NAME, ()).await.unwrap();
+        let config_path = super::dbus::verify_returned_path(&self.async_fs, config_path, self.dbus.verifier).await?;
 
-        let config_path = PathBuf::from(config);
-
-        self.path = Some(config_path.clone());
+        self.path.set(config_path.clone());
 
         Ok(config_path)
     }
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, con: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError> {
-        let proxy = dbus::nonblock::Proxy::new(DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
+
+        let proxy = dbus::nonblock::Proxy::new(interface, DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let (config,): (String,) = proxy.method_call(DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_CONFIG_METHOD_NAME, ()).await.unwrap();
+        let config_path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_CONFIG_METHOD_NAME).await?;
 
-        let config_path = PathBuf::from(config);
+        let config_path = super::dbus::verify_returned_path(&self.async_fs, config_path, self.dbus.verifier).await?;
 
-        self.path = Some(config_path.clone());
+        self.path.set(config_path.clone());
 
         Ok(config_path)
     }
 
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler) {
+        self.connection_loss = Some(handler);
+    }
+
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool) {
+        self.dbus.offline = offline;
+    }
+
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         trace!("Resolving config directory from XDG");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
         let (base, _how) = self.base.resolve()?;
 
-        let config_path = base.join("voxels");
+        let config_path = self.layout.root(&base, &self.namespace);
 
-        self.path = Some(config_path.clone());
+        self.path.set(config_path.clone());
 
         Ok(config_path)
     }
 
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                ConfigDirectoryResolutionMethods::FromDBus => {
-                    self.resolve_using_dbus(|_| {}).await
-                },
-                ConfigDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
-    }
+        let methods: Vec<ConfigDirectoryResolutionMethods> = self.priority.order.values().cloned().collect();
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                ConfigDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
+        super::resolve_in_order(self, &methods).await
     }
 
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve().await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
     }
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        let resolved = self.resolve()?;
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
+
+    fn invalidate(&mut self) {
+        self.path.clear();
+    }
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey> {
+        #[allow(unused_mut)]
+        let mut consumed = self.base.consumed_env_vars();
 
-        Ok(resolved)
+        #[cfg(feature = "dbus")]
+        if self.priority.order.values().any(|method| *method == ConfigDirectoryResolutionMethods::FromDBus) {
+            consumed.extend(super::dbus::consumed_env_vars(self.dbus));
+        }
+
+        consumed
     }
+}
 
-    fn is_resolved(&self) -> bool {
-        self.path.is_some()
+impl<BaseT: base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt> super::PriorityResolver for ConfigDirectory<BaseT, AsyncFsIntT> {
+    type Method = ConfigDirectoryResolutionMethods;
+
+    async fn resolve_using(&mut self, method: &ConfigDirectoryResolutionMethods) -> Result<PathBuf, VoxelsDirectoryError> {
+        match method {
+            #[cfg(feature = "dbus")]
+            ConfigDirectoryResolutionMethods::FromDBus => self.resolve_using_dbus().await,
+            ConfigDirectoryResolutionMethods::FromXDG => self.resolve_using_xdg(),
+        }
     }
 }
 
-impl<BaseT: base::ConfigDirectoryResolver> Into<Option<PathBuf>> for ConfigDirectory<BaseT> {
-    fn into(self) -> Option<PathBuf> {
-        self.path
+impl<BaseT: base::ConfigDirectoryResolver, AsyncFsIntT: AsyncFsInt> From<ConfigDirectory<BaseT, AsyncFsIntT>> for Option<PathBuf> {
+    fn from(directory: ConfigDirectory<BaseT, AsyncFsIntT>) -> Self {
+        directory.path.into_inner()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "dbus")]
+#[tokio::test]
+async fn test_offline_skips_dbus() {
+    let mut xdg = base::MockConfigDirectoryResolver::new();
+
+    xdg.expect_resolve()
+        .once()
+        .return_once(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/user/.config")).unwrap(), base::ConfigDirectoryResolutionMethods::FromXDG)));
+
+    let mut config = ConfigDirectory::new(xdg);
+
+    config.set_offline(true);
+
+    assert_eq!(config.resolve().await, Ok(PathBuf::from("/home/user/.config/voxels")));
+}
+
+#[test]
+fn test_resolve_using_xdg_is_cached() {
+    let mut base = base::MockConfigDirectoryResolver::new();
+
+    // once for the first resolution and once after `invalidate`, never for the cached call between
+    base.expect_resolve()
+        .times(2)
+        .returning(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/user/.config")).unwrap(), base::ConfigDirectoryResolutionMethods::FromXDG)));
+
+    let mut config = ConfigDirectory::new(base);
+
+    assert!(!config.is_resolved());
+    assert_eq!(config.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.config/voxels"));
+    assert!(config.is_resolved());
+    assert_eq!(config.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.config/voxels"));
+
+    config.invalidate();
+
+    assert!(!config.is_resolved());
+    assert_eq!(config.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.config/voxels"));
+}
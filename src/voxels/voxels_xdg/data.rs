@@ -15,29 +15,83 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 use crate::voxels::voxels_xdg::xdg::{data as base};
+use crate::voxels::voxels_xdg::xdg::ordering;
+use crate::voxels::voxels_xdg::xdg::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
 
-use super::{VoxelsDirectoryError};
+use super::{LayoutStrategy, Namespace, ResolvedPath, VoxelsDirectoryError};
 
+use std::collections::BTreeSet;
 use std::path::{PathBuf};
+use crate::environment_variables::EnvKey;
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::CreateOptions;
+#[cfg(feature = "dbus")]
 use std::sync::Arc;
-use std::time::Duration;
+#[cfg(feature = "dbus")]
 use dbus::nonblock::SyncConnection;
-use dbus_tokio::connection::IOResourceError;
-use tokio_util::sync::CancellationToken;
+#[cfg(feature = "dbus")]
+use super::dbus::ConnectionLossHandler;
 use tracing::trace;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_VOXELS_XDG_DATA_METHOD_NAME: &str = "data";
 
-#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum DataDirectoryResolutionMethods {
     FromXDG,
     #[cfg(feature = "dbus")]
     FromDBus,
 }
 
+impl DataDirectoryResolutionMethods {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataDirectoryResolutionMethods::FromXDG => "xdg",
+            #[cfg(feature = "dbus")]
+            DataDirectoryResolutionMethods::FromDBus => "dbus",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdg" => Some(DataDirectoryResolutionMethods::FromXDG),
+            #[cfg(feature = "dbus")]
+            "dbus" => Some(DataDirectoryResolutionMethods::FromDBus),
+            _ => None,
+        }
+    }
+}
+
+impl ResolutionMethod for DataDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        DataDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<DataDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: DataDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for DataDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<DataDirectoryResolutionMethods>", from = "Vec<DataDirectoryResolutionMethods>"))]
 pub struct DataDirectoryPriority {
     order: std::collections::BTreeMap<usize, DataDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<DataDirectoryResolutionMethods>,
 }
 
 impl Default for DataDirectoryPriority {
@@ -48,7 +102,8 @@ impl Default for DataDirectoryPriority {
         order.insert(0, DataDirectoryResolutionMethods::FromXDG);
 
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 
@@ -60,7 +115,8 @@ impl Default for DataDirectoryPriority {
         order.insert(1, DataDirectoryResolutionMethods::FromXDG);
 
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
@@ -71,193 +127,273 @@ impl DataDirectoryPriority {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
         self.order.insert(1, new_order[1].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
     }
 
     #[cfg(not(feature = "dbus"))]
     pub fn set_all(&mut self, new_order: [DataDirectoryResolutionMethods; 1]) {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, DataDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, DataDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for DataDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, DataDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<DataDirectoryPriority> for Vec<DataDirectoryResolutionMethods> {
+    fn from(priority: DataDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
     }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<DataDirectoryResolutionMethods>> for DataDirectoryPriority {
+    fn from(methods: Vec<DataDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
 
-    pub fn get(&self) -> std::collections::BTreeMap<usize, DataDirectoryResolutionMethods> {
-        self.order.clone()
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
 #[mockall::automock]
 pub trait DataDirectoryResolver {
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F: FnOnce(IOResourceError) + Send + 'static>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>;
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    /// Called when the bus drops a connection this resolver opened
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler);
+
+    /// Skip D-Bus resolution whatever the priority, see `DbusOptions::offline`
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool);
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, connection: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError>;
 
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn  resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
     fn is_resolved(&self) -> bool;
+
+    /// Forget the resolved path so the next call resolves again, after the base directory moved
+    fn invalidate(&mut self);
+
+    /// Every environment variable resolution may read, those of the base directory and of the bus when D-Bus is in the priority
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey>;
 }
 
-pub struct DataDirectory<BaseT: base::DataDirectoryResolver> {
-    path: Option<PathBuf>,
+pub struct DataDirectory<BaseT: base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
     pub priority: DataDirectoryPriority,
+    namespace: Namespace,
+    pub layout: LayoutStrategy,
+    pub create_options: CreateOptions,
+    #[cfg(feature = "dbus")]
+    pub dbus: super::dbus::DbusOptions,
+    #[cfg(feature = "dbus")]
+    connection_loss: Option<ConnectionLossHandler>,
     base: BaseT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::DataDirectoryResolver> DataDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_namespace(base, Namespace::default())
+    }
+
+    /// Resolve beneath `namespace` instead of `voxels`, for white-label builds and test isolation
+    pub fn with_namespace(base: BaseT, namespace: Namespace) -> Self {
+        Self::with_async_fs(base, namespace, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt> DataDirectory<BaseT, AsyncFsIntT> {
+    /// As `with_namespace`, verifying D-Bus paths and creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, namespace: Namespace, async_fs: AsyncFsIntT) -> Self {
         let priority = DataDirectoryPriority::default();
         Self {
-            path: None,
+            path: ResolvedPath::default(),
             priority,
-            base
+            namespace,
+            layout: LayoutStrategy::default(),
+            create_options: CreateOptions::default(),
+            #[cfg(feature = "dbus")]
+            dbus: super::dbus::DbusOptions::default(),
+            #[cfg(feature = "dbus")]
+            connection_loss: None,
+            base,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::DataDirectoryResolver> DataDirectoryResolver for DataDirectory<BaseT> {
+impl<BaseT: base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt> DataDirectoryResolver for DataDirectory<BaseT, AsyncFsIntT> {
 
     /// Find the canonical path by contacting the directories service on the system
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>
-    where
-        F: FnOnce(IOResourceError) + Send + 'static
-    {
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         trace!("Resolving data directory from DBus");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
-        let (res, con) =
-            dbus_tokio
-            ::connection
-            ::new_session_sync()
-                .unwrap();
+        let connection = super::dbus::DbusConnection::open(self.dbus, self.connection_loss.clone())?;
 
-        let cancellation_token = CancellationToken::new();
+        let con = connection.connection();
 
-        let child_token = cancellation_token.child_token();
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
 
-        let _ = tokio::task::spawn(async move {
-            tokio::select! {
-                err = res => {
-                    on_connection_loss(err);
-                },
-                _ = child_token.cancelled() => {
-                    return;
-                }
-            }
-        });
+        let proxy = dbus::nonblock::Proxy::new(interface, super::DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let proxy = dbus::nonblock::Proxy::new(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, super::DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_DATA_METHOD_NAME).await?;
 
-        let (config,): (String,) = proxy.method_call(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_DATA_METHOD_NAME,()).await.unwrap();
+        let path = super::dbus::verify_returned_path(&self.async_fs, path, self.dbus.verifier).await?;
 
-        let path = PathBuf::from(config);
-
-        self.path = Some(path.clone());
+        self.path.set(path.clone());
 
         Ok(path)
     }
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, con: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError> {
-        let proxy = dbus::nonblock::Proxy::new(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, super::DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
+
+        let proxy = dbus::nonblock::Proxy::new(interface, super::DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let (config,): (String,) = proxy.method_call(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_DATA_METHOD_NAME,()).await.unwrap();
+        let path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_DATA_METHOD_NAME).await?;
 
-        let path = PathBuf::from(config);
+        let path = super::dbus::verify_returned_path(&self.async_fs, path, self.dbus.verifier).await?;
 
-        self.path = Some(path.clone());
+        self.path.set(path.clone());
 
         Ok(path)
     }
 
     /// Find the canonical path by following XDG specification
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler) {
+        self.connection_loss = Some(handler);
+    }
+
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool) {
+        self.dbus.offline = offline;
+    }
+
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        trace!("Resolving data directory from DBus");
+        trace!("Resolving data directory from XDG");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
         let (base, _how) = self.base.resolve()?;
 
-        let config_path = base.join("voxels");
+        let config_path = self.layout.root(&base, &self.namespace);
 
-        self.path = Some(config_path.clone());
+        self.path.set(config_path.clone());
 
         Ok(config_path)
     }
 
     /// Utilise the priority ordering given during construction to try each method until one returns valid result
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                DataDirectoryResolutionMethods::FromDBus => {
-                    self.resolve_using_dbus(|_| {}).await
-                },
-                DataDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
-    }
+        let methods: Vec<DataDirectoryResolutionMethods> = self.priority.order.values().cloned().collect();
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                DataDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
+        super::resolve_in_order(self, &methods).await
     }
 
     /// Same as resolve except the path and parents are created if they do not already exist
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve().await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
     }
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&self) -> Result<PathBuf, VoxelsDirectoryError> {
-        let resolved = self.resolve()?;
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+    fn invalidate(&mut self) {
+        self.path.clear();
+    }
 
-        Ok(resolved)
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey> {
+        #[allow(unused_mut)]
+        let mut consumed = self.base.consumed_env_vars();
+
+        #[cfg(feature = "dbus")]
+        if self.priority.order.values().any(|method| *method == DataDirectoryResolutionMethods::FromDBus) {
+            consumed.extend(super::dbus::consumed_env_vars(self.dbus));
+        }
+
+        consumed
     }
+}
 
-    fn is_resolved(&self) -> bool {
-        self.path.is_some()
+impl<BaseT: base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt> super::PriorityResolver for DataDirectory<BaseT, AsyncFsIntT> {
+    type Method = DataDirectoryResolutionMethods;
+
+    async fn resolve_using(&mut self, method: &DataDirectoryResolutionMethods) -> Result<PathBuf, VoxelsDirectoryError> {
+        match method {
+            #[cfg(feature = "dbus")]
+            DataDirectoryResolutionMethods::FromDBus => self.resolve_using_dbus().await,
+            DataDirectoryResolutionMethods::FromXDG => self.resolve_using_xdg(),
+        }
     }
 }
 
-impl<BaseT: base::DataDirectoryResolver> Into<Option<PathBuf>> for DataDirectory<BaseT> {
-    fn into(self) -> Option<PathBuf> {
-        self.path
+impl<BaseT: base::DataDirectoryResolver, AsyncFsIntT: AsyncFsInt> From<DataDirectory<BaseT, AsyncFsIntT>> for Option<PathBuf> {
+    fn from(directory: DataDirectory<BaseT, AsyncFsIntT>) -> Self {
+        directory.path.into_inner()
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_resolve_using_xdg_is_cached() {
+    let mut base = base::MockDataDirectoryResolver::new();
+
+    // once for the first resolution and once after `invalidate`, never for the cached call between
+    base.expect_resolve()
+        .times(2)
+        .returning(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/user/.local/share")).unwrap(), base::DataDirectoryResolutionMethods::FromXDG)));
+
+    let mut data = DataDirectory::new(base);
+
+    assert!(!data.is_resolved());
+    assert_eq!(data.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.local/share/voxels"));
+    assert!(data.is_resolved());
+    assert_eq!(data.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.local/share/voxels"));
+
+    data.invalidate();
+
+    assert!(!data.is_resolved());
+    assert_eq!(data.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.local/share/voxels"));
+}
@@ -0,0 +1,495 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ::dbus::channel::Channel;
+use ::dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use ::dbus::nonblock::{Proxy, SyncConnection};
+use dbus_tokio::connection::{IOResource, IOResourceError};
+use tokio::task::JoinHandle;
+
+use crate::async_filesystem::AsyncFsInt;
+use crate::environment_variables::{DefaultEnvInt, EnvInt, EnvKey};
+use crate::filesystem::{DefaultFsInt, FsInt};
+
+use super::xdg::verifier::VerificationError;
+use super::xdg::{verify_async, VerifierOptions};
+
+use super::{VoxelsDirectoryError, DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_PATH, DBUS_VERSIONED_DIRECTORIES_SERVICE_INTERFACE_V1, DEFAULT_NAMESPACE};
+
+const DBUS_BUS_NAME: &str = "org.freedesktop.DBus";
+
+const DBUS_BUS_PATH: &str = "/org/freedesktop/DBus";
+
+const DBUS_PEER_INTERFACE: &str = "org.freedesktop.DBus.Peer";
+
+/// Property of the directories interface holding the protocol version the service speaks
+pub const DBUS_STANDARD_VERSION_PROPERTY: &str = "Version";
+
+/// Appended to a directory method's name for its lossless variant: `data_bytes` returns the path as `ay`,
+/// so paths that are not valid UTF-8 survive the trip. Services speaking `V1` have it, unversioned ones do not.
+pub const DBUS_BYTES_METHOD_SUFFIX: &str = "_bytes";
+
+/// What a service replies from the `s` typed directory methods when the path is not valid UTF-8
+pub const DBUS_ERROR_NOT_UTF8: &str = "voxels.directories.Error.NotUtf8";
+
+const DBUS_ERROR_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+
+/// The socket the directories daemon listens on for peer to peer connections, beneath `<runtime>/voxels/`
+pub const DIRECTORIES_SOCKET_NAME: &str = "directories.socket";
+
+/// Where libdbus looks for the system bus when `DBUS_SYSTEM_BUS_ADDRESS` is unset
+pub const DBUS_DEFAULT_SYSTEM_BUS_SOCKET: &str = "/var/run/dbus/system_bus_socket";
+
+/// Where libdbus looks for the session bus beneath the runtime directory when `DBUS_SESSION_BUS_ADDRESS` is unset
+pub const DBUS_RUNTIME_SESSION_BUS_NAME: &str = "bus";
+
+/// Versions of the directories interface this client speaks. The service's bus name matches its interface name.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum InterfaceVersion {
+    /// `voxels.directories`, spoken by daemons that predate versioning and report no `Version` property
+    Unversioned,
+    V1,
+}
+
+impl InterfaceVersion {
+    /// Every version this client speaks, newest first, the order they are negotiated in
+    pub const SUPPORTED: [InterfaceVersion; 2] = [InterfaceVersion::V1, InterfaceVersion::Unversioned];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InterfaceVersion::Unversioned => DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE,
+            InterfaceVersion::V1 => DBUS_VERSIONED_DIRECTORIES_SERVICE_INTERFACE_V1,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::SUPPORTED.into_iter().find(|version| version.name() == name)
+    }
+
+    /// The number a service speaking this version reports in its `Version` property
+    pub fn number(&self) -> u32 {
+        match self {
+            InterfaceVersion::Unversioned => 0,
+            InterfaceVersion::V1 => 1,
+        }
+    }
+}
+
+/// Which message bus the directories service is looked up on
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum BusType {
+    /// The user's bus. Any process running as the user may own the service's name, so answers are only as trusted as the user's session.
+    #[default]
+    Session,
+    /// The machine's bus, for system-scope services. Bus policy decides who may own the service's name,
+    /// and activation only starts services installed as system services.
+    System,
+    /// A private connection straight to the daemon, for sandboxes that reach neither bus. The address is read from
+    /// `VOXELS_DIRECTORIES_BUS`, otherwise the daemon's socket beneath the runtime directory is used.
+    /// There is no bus in between, so names are not checked and nothing is activated.
+    Peer,
+}
+
+/// How the directories service is reached over D-Bus
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct DbusOptions {
+    /// The session bus unless the service is system-scope
+    pub bus: BusType,
+    /// Ask the bus to start the service when nothing owns its name yet, so it need not already be running
+    pub activate: bool,
+    /// How long each call to the bus may take
+    pub timeout: Duration,
+    /// Never touch the bus, D-Bus resolution is skipped as though no bus existed
+    pub offline: bool,
+    /// The rules paths returned by the service must pass, as the XDG candidates do
+    pub verifier: VerifierOptions,
+}
+
+impl Default for DbusOptions {
+    fn default() -> Self {
+        Self {
+            bus: BusType::default(),
+            activate: true,
+            timeout: Duration::from_secs(1),
+            offline: false,
+            verifier: VerifierOptions::default(),
+        }
+    }
+}
+
+/// `<runtime_dir>/voxels/directories.socket`, where the directories daemon listens for peer to peer connections
+pub fn socket_path(runtime_dir: &Path) -> PathBuf {
+    runtime_dir.join(DEFAULT_NAMESPACE).join(DIRECTORIES_SOCKET_NAME)
+}
+
+/// The address `BusType::Peer` connects to: `VOXELS_DIRECTORIES_BUS` when set, otherwise a `unix:path=`
+/// address for `socket_path` beneath an absolute `XDG_RUNTIME_DIR`
+pub fn peer_address<EnvIntT: EnvInt>(env: &EnvIntT) -> Option<String> {
+    if let Ok(address) = env.get_path(EnvKey::VoxelsDirectoriesBus) {
+        return Some(address.to_string_lossy().into_owned());
+    }
+
+    let runtime_dir = env.get_path(EnvKey::XdgRuntimeDir).ok().filter(|path| path.is_absolute())?;
+
+    Some(format!("unix:path={}", socket_path(&runtime_dir).display()))
+}
+
+/// The variables reaching the service over `options` may read, none when offline
+pub fn consumed_env_vars(options: DbusOptions) -> std::collections::BTreeSet<EnvKey> {
+    if options.offline {
+        return std::collections::BTreeSet::new();
+    }
+
+    match options.bus {
+        BusType::Session => [EnvKey::DbusSessionBusAddress, EnvKey::XdgRuntimeDir].into(),
+        BusType::System => [EnvKey::DbusSystemBusAddress].into(),
+        BusType::Peer => [EnvKey::VoxelsDirectoriesBus, EnvKey::XdgRuntimeDir].into(),
+    }
+}
+
+/// Whether `bus` has an address to connect to, checked without connecting so headless sessions can skip D-Bus immediately
+pub fn has_bus_address<EnvIntT: EnvInt, FsIntT: FsInt>(env: &EnvIntT, fs: &FsIntT, bus: BusType) -> bool {
+    match bus {
+        BusType::Session => {
+            env.get_path(EnvKey::DbusSessionBusAddress).is_ok()
+                || env.get_path(EnvKey::XdgRuntimeDir).is_ok_and(|runtime_dir| fs.exists(&runtime_dir.join(DBUS_RUNTIME_SESSION_BUS_NAME)))
+        },
+        BusType::System => {
+            env.get_path(EnvKey::DbusSystemBusAddress).is_ok() || fs.exists(Path::new(DBUS_DEFAULT_SYSTEM_BUS_SOCKET))
+        },
+        BusType::Peer => peer_address(env).is_some(),
+    }
+}
+
+/// Connect to the bus `options` select. The resource must be spawned for the connection to make progress.
+/// `NoBus` when offline or the bus has no address, without attempting a connection.
+pub fn connect(options: DbusOptions) -> Result<(IOResource<SyncConnection>, Arc<SyncConnection>), VoxelsDirectoryError> {
+    if options.offline || !has_bus_address(&DefaultEnvInt, &DefaultFsInt, options.bus) {
+        return Err(VoxelsDirectoryError::NoBus);
+    }
+
+    let connection = match options.bus {
+        BusType::Session => dbus_tokio::connection::new_session_sync(),
+        BusType::System => dbus_tokio::connection::new_system_sync(),
+        BusType::Peer => {
+            let address = peer_address(&DefaultEnvInt).ok_or(VoxelsDirectoryError::NoCandidate)?;
+
+            tracing::trace!("Connecting to the directories daemon at {}", address);
+
+            Channel::open_private(&address).and_then(dbus_tokio::connection::from_channel)
+        }
+    };
+
+    connection.map_err(|_| VoxelsDirectoryError::NoCandidate)
+}
+
+/// Told why the bus dropped a connection, shared so one handler can serve every resolver
+pub type ConnectionLossHandler = Arc<dyn Fn(IOResourceError) + Send + Sync>;
+
+/// A connection whose IO resource task lives exactly as long as it does, aborted on drop or `close`
+pub struct DbusConnection {
+    connection: Arc<SyncConnection>,
+    resource: JoinHandle<()>,
+}
+
+impl DbusConnection {
+    /// `connect` and spawn the IO resource, `on_connection_loss` is called if the bus drops the connection
+    /// but not when it is closed from this side
+    pub fn open(options: DbusOptions, on_connection_loss: Option<ConnectionLossHandler>) -> Result<Self, VoxelsDirectoryError> {
+        let (resource, connection) = connect(options)?;
+
+        let resource = tokio::task::spawn(async move {
+            let err = resource.await;
+
+            if let Some(on_connection_loss) = on_connection_loss {
+                on_connection_loss(err);
+            }
+        });
+
+        Ok(Self {
+            connection,
+            resource
+        })
+    }
+
+    pub fn connection(&self) -> Arc<SyncConnection> {
+        self.connection.clone()
+    }
+
+    /// Stop the IO resource task, any calls still in flight on clones of the connection fail
+    pub fn close(self) {}
+}
+
+impl Drop for DbusConnection {
+    fn drop(&mut self) {
+        self.resource.abort();
+    }
+}
+
+fn bus_proxy(connection: Arc<SyncConnection>, options: DbusOptions) -> Proxy<'static, Arc<SyncConnection>> {
+    Proxy::new(DBUS_BUS_NAME, DBUS_BUS_PATH, options.timeout, connection)
+}
+
+fn service_proxy(connection: Arc<SyncConnection>, options: DbusOptions, version: InterfaceVersion) -> Proxy<'static, Arc<SyncConnection>> {
+    Proxy::new(version.name(), DBUS_STANDARD_VOXELS_XDG_PATH, options.timeout, connection)
+}
+
+async fn name_has_owner(connection: Arc<SyncConnection>, options: DbusOptions, version: InterfaceVersion) -> bool {
+    let owned: Result<(bool,), ::dbus::Error> = bus_proxy(connection, options).method_call(DBUS_BUS_NAME, "NameHasOwner", (version.name(),)).await;
+
+    matches!(owned, Ok((true,)))
+}
+
+/// Ask a peer to peer daemon which version it speaks, unversioned daemons have no `Version` property but still answer pings
+async fn peer_version(connection: Arc<SyncConnection>, options: DbusOptions) -> Option<InterfaceVersion> {
+    let latest = InterfaceVersion::SUPPORTED[0];
+
+    let reported: Result<u32, ::dbus::Error> = service_proxy(connection.clone(), options, latest).get(latest.name(), DBUS_STANDARD_VERSION_PROPERTY).await;
+
+    if let Ok(number) = reported {
+        return InterfaceVersion::SUPPORTED.into_iter().find(|version| version.number() == number);
+    }
+
+    let pong: Result<(), ::dbus::Error> = service_proxy(connection, options, InterfaceVersion::Unversioned).method_call(DBUS_PEER_INTERFACE, "Ping", ()).await;
+
+    pong.ok().map(|_| InterfaceVersion::Unversioned)
+}
+
+/// The newest interface version a running service speaks, without starting it
+pub async fn registered_version(connection: Arc<SyncConnection>, options: DbusOptions) -> Option<InterfaceVersion> {
+    if options.bus == BusType::Peer {
+        return peer_version(connection, options).await;
+    }
+
+    for version in InterfaceVersion::SUPPORTED {
+        if name_has_owner(connection.clone(), options, version).await {
+            return Some(version);
+        }
+    }
+
+    None
+}
+
+/// Whether anything owns a directories service name, without starting it
+pub async fn is_service_registered(connection: Arc<SyncConnection>, options: DbusOptions) -> bool {
+    registered_version(connection, options).await.is_some()
+}
+
+/// Whether the directories service is registered and answers a ping.
+/// A registered service that does not answer is broken rather than missing.
+pub async fn is_service_available(connection: Arc<SyncConnection>, options: DbusOptions) -> bool {
+    let Some(version) = registered_version(connection.clone(), options).await else {
+        return false;
+    };
+
+    let pong: Result<(), ::dbus::Error> = service_proxy(connection, options, version).method_call(DBUS_PEER_INTERFACE, "Ping", ()).await;
+
+    pong.is_ok()
+}
+
+/// The protocol version the directories service reports, `NoCandidate` when it cannot be asked.
+/// Unversioned services have no `Version` property and report 0.
+pub async fn service_version(connection: Arc<SyncConnection>, options: DbusOptions) -> Result<u32, VoxelsDirectoryError> {
+    let Some(version) = registered_version(connection.clone(), options).await else {
+        return Err(VoxelsDirectoryError::NoCandidate);
+    };
+
+    if version == InterfaceVersion::Unversioned {
+        return Ok(version.number());
+    }
+
+    service_proxy(connection, options, version).get(version.name(), DBUS_STANDARD_VERSION_PROPERTY).await
+        .map_err(|_| VoxelsDirectoryError::NoCandidate)
+}
+
+/// Pick the interface version to talk to the directories service with. The newest version with a running service wins,
+/// otherwise each version is started with `StartServiceByName`, newest first, when `options` allow.
+/// `NoCandidate` when no version is running and none can be started, so callers fall back, `NoBus` when offline.
+pub async fn negotiate(connection: Arc<SyncConnection>, options: DbusOptions) -> Result<InterfaceVersion, VoxelsDirectoryError> {
+    if options.offline {
+        return Err(VoxelsDirectoryError::NoBus);
+    }
+
+    if let Some(version) = registered_version(connection.clone(), options).await {
+        return Ok(version);
+    }
+
+    if !options.activate || options.bus == BusType::Peer {
+        return Err(VoxelsDirectoryError::NoCandidate);
+    }
+
+    for version in InterfaceVersion::SUPPORTED {
+        tracing::trace!("Activating {}", version.name());
+
+        // the reply is 1 when the service was started and 2 when it was already running, either will do
+        let started: Result<(u32,), ::dbus::Error> = bus_proxy(connection.clone(), options).method_call(DBUS_BUS_NAME, "StartServiceByName", (version.name(), 0u32)).await;
+
+        if started.is_ok() {
+            return Ok(version);
+        }
+    }
+
+    Err(VoxelsDirectoryError::NoCandidate)
+}
+
+/// `path` as the string the `s` typed directory methods carry, `NotUtf8` when it is not valid UTF-8
+pub fn path_to_string(path: &Path) -> Result<String, VoxelsDirectoryError> {
+    path.to_str().map(String::from).ok_or(VoxelsDirectoryError::NotUtf8)
+}
+
+/// `path` as the bytes the `_bytes` directory methods carry
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.as_os_str().as_encoded_bytes().to_vec()
+}
+
+/// The path a `_bytes` directory method returned. Outside unix only UTF-8 can be decoded.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, VoxelsDirectoryError> {
+    use std::os::unix::ffi::OsStringExt;
+
+    Ok(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+pub fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, VoxelsDirectoryError> {
+    String::from_utf8(bytes).map(PathBuf::from).map_err(|_| VoxelsDirectoryError::NotUtf8)
+}
+
+fn path_method_error(err: ::dbus::Error) -> VoxelsDirectoryError {
+    if err.name() == Some(DBUS_ERROR_NOT_UTF8) {
+        VoxelsDirectoryError::NotUtf8
+    } else {
+        VoxelsDirectoryError::NoCandidate
+    }
+}
+
+/// Call the directory method `method`, asking for its `_bytes` variant first so any path round-trips.
+/// Unversioned services, and V1 services from before the variant, are asked for the string instead.
+pub(crate) async fn call_path_method(proxy: &Proxy<'_, Arc<SyncConnection>>, interface: &'static str, method: &str) -> Result<PathBuf, VoxelsDirectoryError> {
+    if interface != InterfaceVersion::Unversioned.name() {
+        let bytes: Result<(Vec<u8>,), ::dbus::Error> = proxy.method_call(interface, format!("{}{}", method, DBUS_BYTES_METHOD_SUFFIX), ()).await;
+
+        match bytes {
+            Ok((bytes,)) => return path_from_bytes(bytes),
+            Err(err) if err.name() == Some(DBUS_ERROR_UNKNOWN_METHOD) => {},
+            Err(err) => return Err(path_method_error(err)),
+        }
+    }
+
+    let path: Result<(String,), ::dbus::Error> = proxy.method_call(interface, method, ()).await;
+
+    path.map(|(path,)| PathBuf::from(path)).map_err(path_method_error)
+}
+
+/// Check a path the service returned with `verify_async`. It need not exist yet, as resolvers create it afterwards.
+pub(crate) async fn verify_returned_path<AsyncFsIntT: AsyncFsInt>(fs: &AsyncFsIntT, path: PathBuf, options: VerifierOptions) -> Result<PathBuf, VoxelsDirectoryError> {
+    let verified = if path.is_absolute() {
+        verify_async(fs, &path, options).await
+    } else {
+        Err(VerificationError::NotAbsolute)
+    };
+
+    match verified {
+        Ok(()) | Err(VerificationError::NotFound) => Ok(path),
+        Err(reason) => {
+            tracing::warn!("rejecting {:?} from the directories service, {}", path, reason);
+            Err(reason.at(&path).into())
+        },
+    }
+}
+
+#[test]
+fn test_interface_version() {
+    assert_eq!(InterfaceVersion::SUPPORTED[0], InterfaceVersion::V1);
+    assert_eq!(InterfaceVersion::from_name("voxels.directories1"), Some(InterfaceVersion::V1));
+    assert_eq!(InterfaceVersion::from_name("voxels.directories"), Some(InterfaceVersion::Unversioned));
+    assert_eq!(InterfaceVersion::from_name("voxels.directories2"), None);
+}
+
+#[tokio::test]
+async fn test_verify_returned_path() {
+    let mut fs = crate::async_filesystem::MockAsyncFsInt::new();
+
+    fs.expect_exists()
+        .returning(|path| path != Path::new("/home/user/.local/state/voxels"));
+
+    fs.expect_is_directory()
+        .returning(|path| path != Path::new("/home/user/file"));
+
+    assert_eq!(verify_returned_path(&fs, PathBuf::from("/home/user/.local/state/voxels"), VerifierOptions::default()).await, Ok(PathBuf::from("/home/user/.local/state/voxels")));
+    assert!(verify_returned_path(&fs, PathBuf::from("/home/user/file"), VerifierOptions::default()).await.is_err());
+    assert!(verify_returned_path(&fs, PathBuf::from("relative"), VerifierOptions::default()).await.is_err());
+}
+
+#[test]
+fn test_consumed_env_vars() {
+    let session = DbusOptions::default();
+
+    assert_eq!(consumed_env_vars(session), [EnvKey::DbusSessionBusAddress, EnvKey::XdgRuntimeDir].into());
+    assert_eq!(consumed_env_vars(DbusOptions { bus: BusType::Peer, ..session }), [EnvKey::VoxelsDirectoriesBus, EnvKey::XdgRuntimeDir].into());
+    assert!(consumed_env_vars(DbusOptions { offline: true, ..session }).is_empty());
+}
+
+#[test]
+fn test_peer_address() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_and_rig(EnvKey::VoxelsDirectoriesBus, PathBuf::from("unix:path=/tmp/directories"));
+
+    assert_eq!(peer_address(&env), Some(String::from("unix:path=/tmp/directories")));
+
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirectoriesBus);
+    env.expect_and_rig(EnvKey::XdgRuntimeDir, PathBuf::from("/run/user/1000"));
+
+    assert_eq!(peer_address(&env), Some(String::from("unix:path=/run/user/1000/voxels/directories.socket")));
+}
+
+#[test]
+fn test_has_bus_address() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    env.expect_and_rig_to_fail(EnvKey::DbusSessionBusAddress);
+    env.expect_and_rig(EnvKey::XdgRuntimeDir, PathBuf::from("/run/user/1000"));
+    fs.expect_and_rig_exists(PathBuf::from("/run/user/1000/bus"), false);
+
+    assert!(!has_bus_address(&env, &fs, BusType::Session));
+
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_and_rig(EnvKey::DbusSessionBusAddress, PathBuf::from("unix:path=/run/user/1000/bus"));
+
+    assert!(has_bus_address(&env, &crate::filesystem::MockFsInt::new(), BusType::Session));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_path_bytes_round_trip() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = PathBuf::from(std::ffi::OsStr::from_bytes(b"/home/\xffuser/.local/share"));
+
+    assert_eq!(path_from_bytes(path_to_bytes(&path)), Ok(path.clone()));
+    assert_eq!(path_to_string(&path), Err(VoxelsDirectoryError::NotUtf8));
+    assert_eq!(path_to_string(Path::new("/home/user")), Ok(String::from("/home/user")));
+}
@@ -1,19 +1,203 @@
 
 use super::VoxelsDirectoryError;
 
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(any(feature = "dbus", feature = "rdn"))]
+use xdg::source::ResolutionMethod;
+
+pub const DEFAULT_NAMESPACE: &str = "voxels";
+
+/// The unversioned interface spoken by daemons that predate interface versioning
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE: &str = "voxels.directories";
 
+#[cfg(feature = "dbus")]
+pub const DBUS_VERSIONED_DIRECTORIES_SERVICE_INTERFACE_V1: &str = "voxels.directories1";
+
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_VOXELS_XDG_PATH: &str = "/base";
 
 #[allow(dead_code)]
+#[cfg(any(feature = "dbus", feature = "rdn"))]
 pub mod config;
 #[allow(dead_code)]
+#[cfg(any(feature = "dbus", feature = "rdn"))]
 pub mod data;
 #[allow(dead_code)]
+#[cfg(any(feature = "dbus", feature = "rdn"))]
 pub mod runtime;
 #[allow(dead_code)]
+#[cfg(any(feature = "dbus", feature = "rdn"))]
 pub mod state;
 #[allow(dead_code)]
-pub mod xdg;
\ No newline at end of file
+#[cfg(any(feature = "dbus", feature = "rdn"))]
+pub mod cache;
+#[allow(dead_code)]
+pub mod xdg;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+/// The vendor segment placed beneath each base directory, `voxels` unless a build chooses otherwise
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct Namespace {
+    segment: String,
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Self {
+            segment: String::from(DEFAULT_NAMESPACE)
+        }
+    }
+}
+
+impl Namespace {
+    /// `segment` must be a single normal path component
+    pub fn new(segment: &str) -> Result<Self, VoxelsDirectoryError> {
+        let mut components = Path::new(segment).components();
+
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(Self { segment: String::from(segment) }),
+            _ => Err(VoxelsDirectoryError::InvalidName),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.segment
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.segment)
+    }
+}
+
+/// A resolver that can try each of its resolution methods on its own, see `resolve_in_order`
+#[cfg(any(feature = "dbus", feature = "rdn"))]
+pub(crate) trait PriorityResolver {
+    type Method: ResolutionMethod;
+
+    async fn resolve_using(&mut self, method: &Self::Method) -> Result<PathBuf, VoxelsDirectoryError>;
+}
+
+/// Try `methods` in order, returning the first path found.
+/// When every method fails the last failure is returned, `NoCandidate` when there were no methods.
+#[cfg(any(feature = "dbus", feature = "rdn"))]
+pub(crate) async fn resolve_in_order<ResolverT: PriorityResolver>(resolver: &mut ResolverT, methods: &[ResolverT::Method]) -> Result<PathBuf, VoxelsDirectoryError> {
+    let mut failure = VoxelsDirectoryError::NoCandidate;
+
+    for method in methods {
+        match resolver.resolve_using(method).await {
+            Ok(path) => return Ok(path),
+            Err(error) => {
+                // headless sessions have no bus, so this is routine for D-Bus
+                tracing::trace!("{} resolution failed with {:?}, trying the next method", method.method_name(), error);
+                failure = error;
+            },
+        }
+    }
+
+    Err(failure)
+}
+
+/// The path a resolver settled on, kept so later calls do not ask the base or the bus again
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct ResolvedPath {
+    path: Option<PathBuf>,
+}
+
+impl ResolvedPath {
+    pub fn get(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn set(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    pub fn clear(&mut self) {
+        self.path = None;
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn into_inner(self) -> Option<PathBuf> {
+        self.path
+    }
+}
+
+/// How the namespace is laid out beneath each base directory
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub enum LayoutStrategy {
+    /// `<base>/<namespace>`
+    #[default]
+    Flat,
+    /// `<base>/<namespace>/<product>`, so several products from one vendor do not share a directory
+    VendorProduct(Namespace),
+}
+
+impl LayoutStrategy {
+    pub fn root(&self, base: &Path, namespace: &Namespace) -> PathBuf {
+        match self {
+            LayoutStrategy::Flat => base.join(namespace.as_path()),
+            LayoutStrategy::VendorProduct(product) => base.join(namespace.as_path()).join(product.as_path()),
+        }
+    }
+}
+
+#[test]
+fn test_layout_strategy() {
+    let base = Path::new("/home/user/.config");
+
+    let launcher = LayoutStrategy::VendorProduct(Namespace::new("launcher").unwrap());
+
+    assert_eq!(LayoutStrategy::Flat.root(base, &Namespace::default()), PathBuf::from("/home/user/.config/voxels"));
+    assert_eq!(launcher.root(base, &Namespace::default()), PathBuf::from("/home/user/.config/voxels/launcher"));
+}
+
+#[test]
+fn test_namespace() {
+    assert_eq!(Namespace::default().as_str(), "voxels");
+    assert!(Namespace::new("acme").is_ok());
+    assert_eq!(Namespace::new("acme/games"), Err(VoxelsDirectoryError::InvalidName));
+    assert_eq!(Namespace::new(".."), Err(VoxelsDirectoryError::InvalidName));
+    assert_eq!(Namespace::new(""), Err(VoxelsDirectoryError::InvalidName));
+}
+
+#[cfg(feature = "dbus")]
+#[tokio::test]
+async fn test_resolve_in_order_falls_through_failing_dbus() {
+    use config::ConfigDirectoryResolutionMethods;
+
+    /// D-Bus answers with an error, as when the daemon does not implement the method
+    struct FailingBus {
+        attempts: Vec<&'static str>,
+        xdg: Result<PathBuf, VoxelsDirectoryError>,
+    }
+
+    impl PriorityResolver for FailingBus {
+        type Method = ConfigDirectoryResolutionMethods;
+
+        async fn resolve_using(&mut self, method: &ConfigDirectoryResolutionMethods) -> Result<PathBuf, VoxelsDirectoryError> {
+            self.attempts.push(method.name());
+
+            match method {
+                ConfigDirectoryResolutionMethods::FromDBus => Err(VoxelsDirectoryError::Io(std::io::ErrorKind::Unsupported)),
+                ConfigDirectoryResolutionMethods::FromXDG => self.xdg.clone(),
+            }
+        }
+    }
+
+    let methods = [ConfigDirectoryResolutionMethods::FromDBus, ConfigDirectoryResolutionMethods::FromXDG];
+
+    let mut resolver = FailingBus { attempts: Vec::new(), xdg: Ok(PathBuf::from("/home/user/.config/voxels")) };
+
+    assert_eq!(resolve_in_order(&mut resolver, &methods).await, Ok(PathBuf::from("/home/user/.config/voxels")));
+    assert_eq!(resolver.attempts, vec!["dbus", "xdg"]);
+
+    let mut resolver = FailingBus { attempts: Vec::new(), xdg: Err(VoxelsDirectoryError::NoCandidate) };
+
+    assert_eq!(resolve_in_order(&mut resolver, &methods).await, Err(VoxelsDirectoryError::NoCandidate));
+    assert_eq!(resolve_in_order(&mut resolver, &[]).await, Err(VoxelsDirectoryError::NoCandidate));
+}
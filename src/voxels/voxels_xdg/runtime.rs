@@ -15,29 +15,83 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 use crate::voxels::voxels_xdg::xdg::{runtime as base};
+use crate::voxels::voxels_xdg::xdg::ordering;
+use crate::voxels::voxels_xdg::xdg::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
 
-use super::{VoxelsDirectoryError};
+use super::{LayoutStrategy, Namespace, ResolvedPath, VoxelsDirectoryError};
 
+use std::collections::BTreeSet;
 use std::path::{PathBuf};
+use crate::environment_variables::EnvKey;
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::CreateOptions;
+#[cfg(feature = "dbus")]
 use std::sync::Arc;
-use std::time::Duration;
+#[cfg(feature = "dbus")]
 use dbus::nonblock::SyncConnection;
-use dbus_tokio::connection::IOResourceError;
-use tokio_util::sync::CancellationToken;
+#[cfg(feature = "dbus")]
+use super::dbus::ConnectionLossHandler;
 use tracing::trace;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_VOXELS_XDG_RUNTIME_METHOD_NAME: &str = "runtime";
 
-#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum RuntimeDirectoryResolutionMethods {
     FromXDG,
     #[cfg(feature = "dbus")]
     FromDBus,
 }
 
+impl RuntimeDirectoryResolutionMethods {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeDirectoryResolutionMethods::FromXDG => "xdg",
+            #[cfg(feature = "dbus")]
+            RuntimeDirectoryResolutionMethods::FromDBus => "dbus",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdg" => Some(RuntimeDirectoryResolutionMethods::FromXDG),
+            #[cfg(feature = "dbus")]
+            "dbus" => Some(RuntimeDirectoryResolutionMethods::FromDBus),
+            _ => None,
+        }
+    }
+}
+
+impl ResolutionMethod for RuntimeDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        RuntimeDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<RuntimeDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: RuntimeDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for RuntimeDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<RuntimeDirectoryResolutionMethods>", from = "Vec<RuntimeDirectoryResolutionMethods>"))]
 pub struct RuntimeDirectoryPriority {
     pub(crate) order: std::collections::BTreeMap<usize, RuntimeDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<RuntimeDirectoryResolutionMethods>,
 }
 
 impl Default for RuntimeDirectoryPriority {
@@ -47,7 +101,8 @@ impl Default for RuntimeDirectoryPriority {
         order.insert(0, RuntimeDirectoryResolutionMethods::FromDBus);
         order.insert(1, RuntimeDirectoryResolutionMethods::FromXDG);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 
@@ -56,7 +111,8 @@ impl Default for RuntimeDirectoryPriority {
         let mut order = std::collections::BTreeMap::new();
         order.insert(0, RuntimeDirectoryResolutionMethods::FromXDG);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
@@ -68,189 +124,269 @@ impl RuntimeDirectoryPriority {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
         self.order.insert(1, new_order[1].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
     }
 
     #[cfg(not(feature = "dbus"))]
     pub fn set_all(&mut self, new_order: [RuntimeDirectoryResolutionMethods; 1]) {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, RuntimeDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, RuntimeDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for RuntimeDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, RuntimeDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<RuntimeDirectoryPriority> for Vec<RuntimeDirectoryResolutionMethods> {
+    fn from(priority: RuntimeDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
     }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<RuntimeDirectoryResolutionMethods>> for RuntimeDirectoryPriority {
+    fn from(methods: Vec<RuntimeDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
 
-    pub fn get(&self) -> std::collections::BTreeMap<usize, RuntimeDirectoryResolutionMethods> {
-        self.order.clone()
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
 #[mockall::automock]
 pub trait RuntimeDirectoryResolver {
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F: FnOnce(IOResourceError) + Send + 'static>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>;
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    /// Called when the bus drops a connection this resolver opened
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler);
+
+    /// Skip D-Bus resolution whatever the priority, see `DbusOptions::offline`
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool);
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, connection: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError>;
 
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
     fn is_resolved(&self) -> bool;
+
+    /// Forget the resolved path so the next call resolves again, after the base directory moved
+    fn invalidate(&mut self);
+
+    /// Every environment variable resolution may read, those of the base directory and of the bus when D-Bus is in the priority
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey>;
 }
 
-pub struct RuntimeDirectory<BaseT: base::RuntimeDirectoryResolver> {
-    path: Option<PathBuf>,
+pub struct RuntimeDirectory<BaseT: base::RuntimeDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
     pub priority: RuntimeDirectoryPriority,
+    namespace: Namespace,
+    pub layout: LayoutStrategy,
+    pub create_options: CreateOptions,
+    #[cfg(feature = "dbus")]
+    pub dbus: super::dbus::DbusOptions,
+    #[cfg(feature = "dbus")]
+    connection_loss: Option<ConnectionLossHandler>,
     base: BaseT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::RuntimeDirectoryResolver> RuntimeDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_namespace(base, Namespace::default())
+    }
+
+    /// Resolve beneath `namespace` instead of `voxels`, for white-label builds and test isolation
+    pub fn with_namespace(base: BaseT, namespace: Namespace) -> Self {
+        Self::with_async_fs(base, namespace, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::RuntimeDirectoryResolver, AsyncFsIntT: AsyncFsInt> RuntimeDirectory<BaseT, AsyncFsIntT> {
+    /// As `with_namespace`, verifying D-Bus paths and creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, namespace: Namespace, async_fs: AsyncFsIntT) -> Self {
         let priority = RuntimeDirectoryPriority::default();
         Self {
-            path: None,
+            path: ResolvedPath::default(),
             priority,
-            base
+            namespace,
+            layout: LayoutStrategy::default(),
+            create_options: CreateOptions::default(),
+            #[cfg(feature = "dbus")]
+            dbus: super::dbus::DbusOptions::default(),
+            #[cfg(feature = "dbus")]
+            connection_loss: None,
+            base,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::RuntimeDirectoryResolver> RuntimeDirectoryResolver for RuntimeDirectory<BaseT> {
+impl<BaseT: base::RuntimeDirectoryResolver, AsyncFsIntT: AsyncFsInt> RuntimeDirectoryResolver for RuntimeDirectory<BaseT, AsyncFsIntT> {
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>
-    where
-        F: FnOnce(IOResourceError) + Send + 'static
-    {
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         trace!("Resolving runtime directory from DBus");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
-        let (res, con) =
-            dbus_tokio
-            ::connection
-            ::new_session_sync()
-                .unwrap();
+        let connection = super::dbus::DbusConnection::open(self.dbus, self.connection_loss.clone())?;
 
-        let cancellation_token = CancellationToken::new();
+        let con = connection.connection();
 
-        let child_token = cancellation_token.child_token();
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
 
-        let _ = tokio::task::spawn(async move {
-            tokio::select! {
-                err = res => {
-                    on_connection_loss(err);
-                },
-                _ = child_token.cancelled() => {
-                    return;
-                }
-            }
-        });
+        let proxy = dbus::nonblock::Proxy::new(interface, super::DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let proxy = dbus::nonblock::Proxy::new(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, super::DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_RUNTIME_METHOD_NAME).await?;
 
-        let (config,): (String,) = proxy.method_call(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_RUNTIME_METHOD_NAME,()).await.unwrap();
+        let path = super::dbus::verify_returned_path(&self.async_fs, path, self.dbus.verifier).await?;
 
-        let path = PathBuf::from(config);
-
-        self.path = Some(path.clone());
+        self.path.set(path.clone());
 
         Ok(path)
     }
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, con: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError> {
-        let proxy = dbus::nonblock::Proxy::new(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, super::DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
+
+        let proxy = dbus::nonblock::Proxy::new(interface, super::DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let (config,): (String,) = proxy.method_call(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_RUNTIME_METHOD_NAME,()).await.unwrap();
+        let path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_RUNTIME_METHOD_NAME).await?;
 
-        let path = PathBuf::from(config);
+        let path = super::dbus::verify_returned_path(&self.async_fs, path, self.dbus.verifier).await?;
 
-        self.path = Some(path.clone());
+        self.path.set(path.clone());
 
         Ok(path)
     }
 
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler) {
+        self.connection_loss = Some(handler);
+    }
+
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool) {
+        self.dbus.offline = offline;
+    }
+
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        trace!("Resolving runtime directory from DBus");
+        trace!("Resolving runtime directory from XDG");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
         let (base, _how) = self.base.resolve()?;
 
-        let config_path = base.join("voxels");
+        let config_path = self.layout.root(&base, &self.namespace);
 
-        self.path = Some(config_path.clone());
+        self.path.set(config_path.clone());
 
         Ok(config_path)
     }
 
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                RuntimeDirectoryResolutionMethods::FromDBus => {
-                    self.resolve_using_dbus(|_| {}).await
-                },
-                RuntimeDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
-    }
+        let methods: Vec<RuntimeDirectoryResolutionMethods> = self.priority.order.values().cloned().collect();
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                RuntimeDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
+        super::resolve_in_order(self, &methods).await
     }
 
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve().await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
 
     }
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        let resolved = self.resolve()?;
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+    fn invalidate(&mut self) {
+        self.path.clear();
+    }
 
-        Ok(resolved)
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey> {
+        #[allow(unused_mut)]
+        let mut consumed = self.base.consumed_env_vars();
+
+        #[cfg(feature = "dbus")]
+        if self.priority.order.values().any(|method| *method == RuntimeDirectoryResolutionMethods::FromDBus) {
+            consumed.extend(super::dbus::consumed_env_vars(self.dbus));
+        }
+
+        consumed
     }
+}
 
-    fn is_resolved(&self) -> bool {
-        self.path.is_some()
+impl<BaseT: base::RuntimeDirectoryResolver, AsyncFsIntT: AsyncFsInt> super::PriorityResolver for RuntimeDirectory<BaseT, AsyncFsIntT> {
+    type Method = RuntimeDirectoryResolutionMethods;
+
+    async fn resolve_using(&mut self, method: &RuntimeDirectoryResolutionMethods) -> Result<PathBuf, VoxelsDirectoryError> {
+        match method {
+            #[cfg(feature = "dbus")]
+            RuntimeDirectoryResolutionMethods::FromDBus => self.resolve_using_dbus().await,
+            RuntimeDirectoryResolutionMethods::FromXDG => self.resolve_using_xdg(),
+        }
     }
 }
 
-impl<BaseT: base::RuntimeDirectoryResolver> Into<Option<PathBuf>> for RuntimeDirectory<BaseT> {
-    fn into(self) -> Option<PathBuf> {
-        self.path
+impl<BaseT: base::RuntimeDirectoryResolver, AsyncFsIntT: AsyncFsInt> From<RuntimeDirectory<BaseT, AsyncFsIntT>> for Option<PathBuf> {
+    fn from(directory: RuntimeDirectory<BaseT, AsyncFsIntT>) -> Self {
+        directory.path.into_inner()
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_resolve_using_xdg_is_cached() {
+    let mut base = base::MockRuntimeDirectoryResolver::new();
+
+    // once for the first resolution and once after `invalidate`, never for the cached call between
+    base.expect_resolve()
+        .times(2)
+        .returning(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/run/user/1000")).unwrap(), base::RuntimeDirectoryResolutionMethods::FromXDG)));
+
+    let mut runtime = RuntimeDirectory::new(base);
+
+    assert!(!runtime.is_resolved());
+    assert_eq!(runtime.resolve_using_xdg().unwrap(), PathBuf::from("/run/user/1000/voxels"));
+    assert!(runtime.is_resolved());
+    assert_eq!(runtime.resolve_using_xdg().unwrap(), PathBuf::from("/run/user/1000/voxels"));
+
+    runtime.invalidate();
+
+    assert!(!runtime.is_resolved());
+    assert_eq!(runtime.resolve_using_xdg().unwrap(), PathBuf::from("/run/user/1000/voxels"));
+}
@@ -15,30 +15,83 @@ You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 use crate::voxels::voxels_xdg::xdg::{state as base};
+use crate::voxels::voxels_xdg::xdg::ordering;
+use crate::voxels::voxels_xdg::xdg::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
 
-use super::{VoxelsDirectoryError};
+use super::{LayoutStrategy, Namespace, ResolvedPath, VoxelsDirectoryError};
 
+use std::collections::BTreeSet;
 use std::path::{PathBuf};
+use crate::environment_variables::EnvKey;
+use crate::async_filesystem::{AsyncFsInt, DefaultAsyncFsInt};
+use crate::filesystem::CreateOptions;
+#[cfg(feature = "dbus")]
 use std::sync::Arc;
-use std::time::Duration;
+#[cfg(feature = "dbus")]
 use dbus::nonblock::SyncConnection;
-use dbus_tokio::connection::IOResourceError;
-use tokio_util::sync::CancellationToken;
+#[cfg(feature = "dbus")]
+use super::dbus::ConnectionLossHandler;
 use tracing::trace;
-use crate::voxels::voxels_xdg::data::DBUS_STANDARD_VOXELS_XDG_DATA_METHOD_NAME;
 
 #[cfg(feature = "dbus")]
 pub const DBUS_STANDARD_VOXELS_XDG_STATE_METHOD_NAME: &str = "state";
 
-#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum StateDirectoryResolutionMethods {
     FromXDG,
     #[cfg(feature = "dbus")]
     FromDBus,
 }
 
+impl StateDirectoryResolutionMethods {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateDirectoryResolutionMethods::FromXDG => "xdg",
+            #[cfg(feature = "dbus")]
+            StateDirectoryResolutionMethods::FromDBus => "dbus",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdg" => Some(StateDirectoryResolutionMethods::FromXDG),
+            #[cfg(feature = "dbus")]
+            "dbus" => Some(StateDirectoryResolutionMethods::FromDBus),
+            _ => None,
+        }
+    }
+}
+
+impl ResolutionMethod for StateDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        StateDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<StateDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: StateDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for StateDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<StateDirectoryResolutionMethods>", from = "Vec<StateDirectoryResolutionMethods>"))]
 pub struct StateDirectoryPriority {
     order: std::collections::BTreeMap<usize, StateDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<StateDirectoryResolutionMethods>,
 }
 
 impl Default for StateDirectoryPriority {
@@ -48,7 +101,8 @@ impl Default for StateDirectoryPriority {
         order.insert(0, StateDirectoryResolutionMethods::FromDBus);
         order.insert(1, StateDirectoryResolutionMethods::FromXDG);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 
@@ -57,7 +111,8 @@ impl Default for StateDirectoryPriority {
         let mut order = std::collections::BTreeMap::new();
         order.insert(0, StateDirectoryResolutionMethods::FromXDG);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
@@ -69,187 +124,284 @@ impl StateDirectoryPriority {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
         self.order.insert(1, new_order[1].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
     }
 
     #[cfg(not(feature = "dbus"))]
     pub fn set_all(&mut self, new_order: [StateDirectoryResolutionMethods; 1]) {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
     }
 
-    pub fn get(&self) -> std::collections::BTreeMap<usize, StateDirectoryResolutionMethods> {
-        self.order.clone()
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, StateDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, StateDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for StateDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, StateDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<StateDirectoryPriority> for Vec<StateDirectoryResolutionMethods> {
+    fn from(priority: StateDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<StateDirectoryResolutionMethods>> for StateDirectoryPriority {
+    fn from(methods: Vec<StateDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
 #[mockall::automock]
 pub trait StateDirectoryResolver {
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F: FnOnce(IOResourceError) + Send + 'static>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>;
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
+
+    /// Called when the bus drops a connection this resolver opened
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler);
+
+    /// Skip D-Bus resolution whatever the priority, see `DbusOptions::offline`
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool);
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, connection: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError>;
 
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError>;
-
     fn is_resolved(&self) -> bool;
+
+    /// Forget the resolved path so the next call resolves again, after the base directory moved
+    fn invalidate(&mut self);
+
+    /// Every environment variable resolution may read, those of the base directory and of the bus when D-Bus is in the priority
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey>;
 }
 
-pub struct StateDirectory<BaseT: base::StateDirectoryResolver> {
-    path: Option<PathBuf>,
+pub struct StateDirectory<BaseT: base::StateDirectoryResolver, AsyncFsIntT: AsyncFsInt = DefaultAsyncFsInt> {
+    path: ResolvedPath,
     pub priority: StateDirectoryPriority,
+    namespace: Namespace,
+    pub layout: LayoutStrategy,
+    pub create_options: CreateOptions,
+    #[cfg(feature = "dbus")]
+    pub dbus: super::dbus::DbusOptions,
+    #[cfg(feature = "dbus")]
+    connection_loss: Option<ConnectionLossHandler>,
     base: BaseT,
+    async_fs: AsyncFsIntT,
 }
 
 impl<BaseT: base::StateDirectoryResolver> StateDirectory<BaseT> {
     pub fn new(base: BaseT) -> Self {
+        Self::with_namespace(base, Namespace::default())
+    }
+
+    /// Resolve beneath `namespace` instead of `voxels`, for white-label builds and test isolation
+    pub fn with_namespace(base: BaseT, namespace: Namespace) -> Self {
+        Self::with_async_fs(base, namespace, DefaultAsyncFsInt)
+    }
+}
+
+impl<BaseT: base::StateDirectoryResolver, AsyncFsIntT: AsyncFsInt> StateDirectory<BaseT, AsyncFsIntT> {
+    /// As `with_namespace`, verifying D-Bus paths and creating the directory through `async_fs`
+    pub fn with_async_fs(base: BaseT, namespace: Namespace, async_fs: AsyncFsIntT) -> Self {
         Self {
-            path: None,
+            path: ResolvedPath::default(),
             priority: Default::default(),
-            base
+            namespace,
+            layout: LayoutStrategy::default(),
+            create_options: CreateOptions::default(),
+            #[cfg(feature = "dbus")]
+            dbus: super::dbus::DbusOptions::default(),
+            #[cfg(feature = "dbus")]
+            connection_loss: None,
+            base,
+            async_fs
         }
     }
 }
 
-impl<BaseT: base::StateDirectoryResolver> StateDirectoryResolver for StateDirectory<BaseT> {
+impl<BaseT: base::StateDirectoryResolver, AsyncFsIntT: AsyncFsInt> StateDirectoryResolver for StateDirectory<BaseT, AsyncFsIntT> {
     #[cfg(feature = "dbus")]
-    async fn resolve_using_dbus<F>(&mut self, on_connection_loss: F) -> Result<PathBuf, VoxelsDirectoryError>
-    where
-        F: FnOnce(IOResourceError) + Send + 'static
-    {
+    async fn resolve_using_dbus(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         trace!("Resolving state directory from DBus");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
-        let (res, con) =
-            dbus_tokio
-            ::connection
-            ::new_session_sync()
-                .unwrap();
-
-        let cancellation_token = CancellationToken::new();
+        let connection = super::dbus::DbusConnection::open(self.dbus, self.connection_loss.clone())?;
 
-        let child_token = cancellation_token.child_token();
+        let con = connection.connection();
 
-        let _ = tokio::task::spawn(async move {
-            tokio::select! {
-                err = res => {
-                    on_connection_loss(err);
-                },
-                _ = child_token.cancelled() => {
-                    return;
-                }
-            }
-        });
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
 
-        let proxy = dbus::nonblock::Proxy::new(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, super::DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let proxy = dbus::nonblock::Proxy::new(interface, super::DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let (config,): (String,) = proxy.method_call(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_STATE_METHOD_NAME,()).await.unwrap();
+        let path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_STATE_METHOD_NAME).await?;
 
-        let path = PathBuf::from(config);
+        let path = super::dbus::verify_returned_path(&self.async_fs, path, self.dbus.verifier).await?;
 
-        self.path = Some(path.clone());
+        self.path.set(path.clone());
 
         Ok(path)
     }
 
     #[cfg(feature = "dbus")]
     async fn resolve_using_dbus_with_connection(&mut self, con: Arc<SyncConnection>) -> Result<PathBuf, VoxelsDirectoryError> {
-        let proxy = dbus::nonblock::Proxy::new(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, super::DBUS_STANDARD_VOXELS_XDG_PATH, Duration::from_secs(1), con);
+        let interface = super::dbus::negotiate(con.clone(), self.dbus).await?.name();
 
-        let (config,): (String,) = proxy.method_call(super::DBUS_STANDARD_DIRECTORIES_SERVICE_INTERFACE, DBUS_STANDARD_VOXELS_XDG_STATE_METHOD_NAME,()).await.unwrap();
+        let proxy = dbus::nonblock::Proxy::new(interface, super::DBUS_STANDARD_VOXELS_XDG_PATH, self.dbus.timeout, con);
 
-        let path = PathBuf::from(config);
+        let path = super::dbus::call_path_method(&proxy, interface, DBUS_STANDARD_VOXELS_XDG_STATE_METHOD_NAME).await?;
 
-        self.path = Some(path.clone());
+        let path = super::dbus::verify_returned_path(&self.async_fs, path, self.dbus.verifier).await?;
+
+        self.path.set(path.clone());
 
         Ok(path)
     }
 
+    #[cfg(feature = "dbus")]
+    fn set_connection_loss_handler(&mut self, handler: ConnectionLossHandler) {
+        self.connection_loss = Some(handler);
+    }
+
+    #[cfg(feature = "dbus")]
+    fn set_offline(&mut self, offline: bool) {
+        self.dbus.offline = offline;
+    }
+
     fn resolve_using_xdg(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        trace!("Resolving state directory from DBus");
+        trace!("Resolving state directory from XDG");
 
         // if resolve has been called previously we update this objects path
-        if self.is_resolved() {
-            return Ok(self.path.clone().unwrap());
+        if let Some(path) = self.path.get() {
+            return Ok(path.to_path_buf());
         }
 
         let (base, _how) = self.base.resolve()?;
 
-        let config_path = base.join("voxels");
+        let config_path = self.layout.root(&base, &self.namespace);
 
-        self.path = Some(config_path.clone());
+        self.path.set(config_path.clone());
 
         Ok(config_path)
     }
 
-    #[cfg(feature = "dbus")]
     async fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                StateDirectoryResolutionMethods::FromDBus => {
-                    self.resolve_using_dbus(|_| {}).await
-                },
-                StateDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
-    }
+        let methods: Vec<StateDirectoryResolutionMethods> = self.priority.order.values().cloned().collect();
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            return match self.priority.order[&index] {
-                StateDirectoryResolutionMethods::FromXDG => {
-                    self.resolve_using_xdg()
-                }
-            }
-        }
-        Err(VoxelsDirectoryError::NoCandidate)
+        super::resolve_in_order(self, &methods).await
     }
 
-    #[cfg(feature = "dbus")]
     async fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
         let resolved = self.resolve().await?;
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+        crate::async_filesystem::create_directory(&self.async_fs, &resolved, self.create_options).await?;
 
         Ok(resolved)
 
     }
 
-    #[cfg(not(feature = "dbus"))]
-    fn resolve_and_create(&mut self) -> Result<PathBuf, VoxelsDirectoryError> {
-        let resolved = self.resolve()?;
+    fn is_resolved(&self) -> bool {
+        self.path.is_resolved()
+    }
+
+    fn invalidate(&mut self) {
+        self.path.clear();
+    }
 
-        std::fs::create_dir_all(resolved.as_path()).expect("Failed to create directory");
+    fn consumed_env_vars(&self) -> BTreeSet<EnvKey> {
+        #[allow(unused_mut)]
+        let mut consumed = self.base.consumed_env_vars();
 
-        Ok(resolved)
+        #[cfg(feature = "dbus")]
+        if self.priority.order.values().any(|method| *method == StateDirectoryResolutionMethods::FromDBus) {
+            consumed.extend(super::dbus::consumed_env_vars(self.dbus));
+        }
+
+        consumed
     }
+}
 
-    fn is_resolved(&self) -> bool {
-        self.path.is_some()
+impl<BaseT: base::StateDirectoryResolver, AsyncFsIntT: AsyncFsInt> super::PriorityResolver for StateDirectory<BaseT, AsyncFsIntT> {
+    type Method = StateDirectoryResolutionMethods;
+
+    async fn resolve_using(&mut self, method: &StateDirectoryResolutionMethods) -> Result<PathBuf, VoxelsDirectoryError> {
+        match method {
+            #[cfg(feature = "dbus")]
+            StateDirectoryResolutionMethods::FromDBus => self.resolve_using_dbus().await,
+            StateDirectoryResolutionMethods::FromXDG => self.resolve_using_xdg(),
+        }
     }
 }
 
-impl<BaseT: base::StateDirectoryResolver> Into<Option<PathBuf>> for StateDirectory<BaseT> {
-    fn into(self) -> Option<PathBuf> {
-        self.path
+impl<BaseT: base::StateDirectoryResolver, AsyncFsIntT: AsyncFsInt> From<StateDirectory<BaseT, AsyncFsIntT>> for Option<PathBuf> {
+    fn from(directory: StateDirectory<BaseT, AsyncFsIntT>) -> Self {
+        directory.path.into_inner()
     }
 }
+
+#[test]
+fn test_resolve_using_xdg_is_cached() {
+    let mut base = base::MockStateDirectoryResolver::new();
+
+    // once for the first resolution and once after `invalidate`, never for the cached call between
+    base.expect_resolve()
+        .times(2)
+        .returning(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/user/.local/state")).unwrap(), base::StateDirectoryResolutionMethods::FromXDG)));
+
+    let mut state = StateDirectory::new(base);
+
+    assert!(!state.is_resolved());
+    assert_eq!(state.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.local/state/voxels"));
+    assert!(state.is_resolved());
+    assert_eq!(state.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.local/state/voxels"));
+
+    state.invalidate();
+
+    assert!(!state.is_resolved());
+    assert_eq!(state.resolve_using_xdg().unwrap(), PathBuf::from("/home/user/.local/state/voxels"));
+}
+
+#[cfg(feature = "dbus")]
+#[test]
+fn test_consumed_env_vars_include_the_bus() {
+    let mut base = base::MockStateDirectoryResolver::new();
+
+    base.expect_consumed_env_vars()
+        .returning(|| [EnvKey::XdgStateHome].into());
+
+    let mut state = StateDirectory::new(base);
+
+    assert_eq!(state.consumed_env_vars(), [EnvKey::XdgStateHome, EnvKey::DbusSessionBusAddress, EnvKey::XdgRuntimeDir].into());
+
+    state.set_offline(true);
+
+    assert_eq!(state.consumed_env_vars(), [EnvKey::XdgStateHome].into());
+}
@@ -0,0 +1,348 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use super::BaseDirectoryError;
+use crate::pathcheck::{normalize, AbsoluteDirPath};
+use super::{EnvInt, EnvKey, FsInt};
+use crate::filesystem::{create_directory, CreateOptions};
+use super::{check_is_directory, check_permissions, VerifierOptions};
+use super::ordering;
+use super::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+use super::verifier::VerificationError;
+
+#[mockall::automock]
+pub trait BinVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError>;
+}
+
+#[derive(Default)]
+pub struct DefaultBinVerifier<FsIntT: FsInt> {
+    fs: FsIntT,
+    pub options: VerifierOptions,
+}
+
+impl<FsIntT: FsInt> BinVerifier for DefaultBinVerifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        crate::pathcheck::check(path).map_err(|_| VerificationError::InvalidPath)?;
+
+        check_is_directory(&self.fs, path)?;
+
+        if !self.fs.is_absolute(path) {
+            return Err(VerificationError::NotAbsolute);
+        }
+
+        if !check_permissions(&self.fs, path, self.options) {
+            return Err(VerificationError::InsecurePermissions);
+        }
+
+        Ok(())
+    }
+}
+
+impl<FsIntT: FsInt> DefaultBinVerifier<FsIntT> {
+    pub fn new(fs: FsIntT) -> Self {
+        Self::with_options(fs, VerifierOptions::default())
+    }
+
+    pub fn with_options(fs: FsIntT, options: VerifierOptions) -> Self {
+        Self {
+            fs,
+            options
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
+pub enum BinDirectoryResolutionMethods {
+    FromXDG,
+    FromFHS,
+    FromVoxels
+}
+
+impl BinDirectoryResolutionMethods {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinDirectoryResolutionMethods::FromXDG => "xdg",
+            BinDirectoryResolutionMethods::FromFHS => "fhs",
+            BinDirectoryResolutionMethods::FromVoxels => "voxels",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "xdg" => Some(BinDirectoryResolutionMethods::FromXDG),
+            "fhs" => Some(BinDirectoryResolutionMethods::FromFHS),
+            "voxels" => Some(BinDirectoryResolutionMethods::FromVoxels),
+            _ => None,
+        }
+    }
+}
+
+impl ResolutionMethod for BinDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        BinDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<BinDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: BinDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for BinDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<BinDirectoryResolutionMethods>", from = "Vec<BinDirectoryResolutionMethods>"))]
+pub struct BinDirectoryPriority {
+    order: std::collections::BTreeMap<usize, BinDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<BinDirectoryResolutionMethods>,
+}
+
+impl Default for BinDirectoryPriority {
+    fn default() -> Self {
+        let mut order = std::collections::BTreeMap::new();
+        order.insert(0, BinDirectoryResolutionMethods::FromVoxels);
+        order.insert(1, BinDirectoryResolutionMethods::FromXDG);
+        #[cfg(not(feature = "no-fhs"))]
+        order.insert(2, BinDirectoryResolutionMethods::FromFHS);
+        Self {
+            order,
+            sources: ordering::PrioritySources::new()
+        }
+    }
+}
+
+impl BinDirectoryPriority {
+    pub fn set_all(&mut self, new_order: [BinDirectoryResolutionMethods; 3]) {
+        self.order = std::collections::BTreeMap::new();
+        self.order.insert(0, new_order[0]);
+        self.order.insert(1, new_order[1]);
+        self.order.insert(2, new_order[2]);
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, BinDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, BinDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for BinDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, BinDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<BinDirectoryPriority> for Vec<BinDirectoryResolutionMethods> {
+    fn from(priority: BinDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<BinDirectoryResolutionMethods>> for BinDirectoryPriority {
+    fn from(methods: Vec<BinDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
+    }
+}
+
+#[mockall::automock]
+pub trait BinDirectoryResolver {
+    fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve(&self) -> Result<(AbsoluteDirPath, BinDirectoryResolutionMethods), BaseDirectoryError>;
+    fn resolve_and_create(&self) -> Result<(AbsoluteDirPath, BinDirectoryResolutionMethods), BaseDirectoryError>;
+    fn is_in_path(&self, path: &Path) -> bool;
+}
+
+pub struct BinDirectory<EnvIntT: EnvInt, VerifierT: BinVerifier, FsIntT: FsInt> {
+    verifier: VerifierT,
+    env: EnvIntT,
+    fs: FsIntT,
+    pub priority: BinDirectoryPriority,
+}
+
+impl<EnvIntT: EnvInt, VerifierT: BinVerifier, FsIntT: FsInt> BinDirectory<EnvIntT, VerifierT, FsIntT> {
+    pub fn new(env: EnvIntT, verifier: VerifierT, fs: FsIntT) -> Self {
+        let priority = BinDirectoryPriority::default();
+        Self {
+            env,
+            verifier,
+            fs,
+            priority
+        }
+    }
+
+    /// The path a method points at, without checking that it exists
+    fn candidate(&self, method: BinDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        match method {
+            BinDirectoryResolutionMethods::FromFHS => super::fhs_candidate(&self.env, ".local/bin"),
+            BinDirectoryResolutionMethods::FromXDG => {
+                Ok(normalize(&self.env.get_path(EnvKey::XdgBinHome)?))
+            },
+            BinDirectoryResolutionMethods::FromVoxels => {
+                Ok(normalize(&self.env.get_path(EnvKey::VoxelsBinHome)?))
+            }
+        }
+    }
+
+    fn verified_candidate(&self, method: BinDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.candidate(method)?;
+
+        self.verifier.verify(&path).map_err(|error| error.at(&path))?;
+
+        Ok(path)
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when the directory's path is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub fn resolve_utf8(&self) -> Result<(camino::Utf8PathBuf, BinDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = self.resolve()?;
+
+        Ok((super::to_utf8(path.into_path_buf())?, method))
+    }
+}
+
+impl<EnvIntT: EnvInt, VerifierT: BinVerifier, FsIntT: FsInt> BinDirectoryResolver for BinDirectory<EnvIntT, VerifierT, FsIntT> {
+    fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError> {
+        self.verified_candidate(BinDirectoryResolutionMethods::FromFHS)
+    }
+
+    fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError> {
+        self.verified_candidate(BinDirectoryResolutionMethods::FromXDG)
+    }
+
+    fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError> {
+        self.verified_candidate(BinDirectoryResolutionMethods::FromVoxels)
+    }
+
+    fn resolve(&self) -> Result<(AbsoluteDirPath, BinDirectoryResolutionMethods), BaseDirectoryError> {
+        for index in 0..self.priority.order.len() {
+            let method = self.priority.order[&index];
+
+            if let Ok(path) = self.verified_candidate(method)
+                && let Ok(path) = AbsoluteDirPath::new(path) {
+                return Ok((path, method));
+            }
+        }
+        Err(BaseDirectoryError::NoCandidate)
+    }
+
+    /// Same as resolve except that when no candidate exists yet the highest priority
+    /// candidate that can be computed is created
+    fn resolve_and_create(&self) -> Result<(AbsoluteDirPath, BinDirectoryResolutionMethods), BaseDirectoryError> {
+        if let Ok(resolved) = self.resolve() {
+            return Ok(resolved);
+        }
+
+        for index in 0..self.priority.order.len() {
+            let method = self.priority.order[&index];
+
+            if let Ok(path) = self.candidate(method)
+                && let Ok(path) = AbsoluteDirPath::new(path) {
+                create_directory(&self.fs, &path, CreateOptions::default())?;
+
+                return Ok((path, method));
+            }
+        }
+        Err(BaseDirectoryError::NoCandidate)
+    }
+
+    /// Whether `path` is one of the entries of the PATH environment variable
+    fn is_in_path(&self, path: &Path) -> bool {
+        let Ok(search_path) = self.env.get_path(EnvKey::Path) else {
+            return false;
+        };
+
+        std::env::split_paths(search_path.as_os_str()).any(|entry| entry == path)
+    }
+}
+
+#[cfg(not(feature = "no-fhs"))]
+#[test]
+fn test_resolve_and_create_falls_back_to_fhs() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+    let mut validator = MockBinVerifier::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let expected = PathBuf::from("/home/.local/bin");
+
+    env.expect_get_path()
+        .with(mockall::predicate::eq(EnvKey::Home))
+        .returning(|_| Ok(PathBuf::from("/home")));
+
+    env.expect_get_path()
+        .returning(|_| Err(std::env::VarError::NotPresent));
+
+    validator.expect_verify()
+        .returning(|_| Err(VerificationError::NotFound));
+
+    fs.expect_create_dir_all()
+        .once()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_once(|_| Ok(()));
+
+    fs.expect_is_directory()
+        .once()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_once(|_| true);
+
+    let bin = BinDirectory::new(env, validator, fs);
+
+    let res = bin.resolve_and_create();
+
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), (crate::pathcheck::AbsoluteDirPath::new(expected).unwrap(), BinDirectoryResolutionMethods::FromFHS));
+}
+
+#[test]
+fn test_is_in_path() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_get_path()
+        .with(mockall::predicate::eq(EnvKey::Path))
+        .returning(|_| Ok(PathBuf::from("/usr/bin:/home/.local/bin")));
+
+    let bin = BinDirectory::new(env, MockBinVerifier::new(), crate::filesystem::MockFsInt::new());
+
+    assert!(bin.is_in_path(Path::new("/home/.local/bin/")));
+    assert!(!bin.is_in_path(Path::new("/opt/bin")));
+}
@@ -0,0 +1,465 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use crate::environment_variables::{EnvInt, EnvKey};
+use crate::filesystem::{create_directory, CreateOptions, DefaultFsInt, FsInt};
+use super::{check_is_directory, check_permissions, VerifierOptions};
+use super::BaseDirectoryError;
+use crate::pathcheck::{normalize, AbsoluteDirPath};
+use super::dedup_candidates;
+use super::ordering::{self, PrioritySource};
+use super::observer::{self, SharedObserver};
+use super::overlay::{system_dirs, Overlay};
+use super::container::ContainerVolumes;
+use super::dirs_file::DirsFile;
+use super::pinning::ResolutionRecord;
+use super::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+use crate::voxels::DirectoryKind;
+use super::verifier::VerificationError;
+
+#[mockall::automock]
+pub trait CacheVerifier {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError>;
+}
+
+#[derive(Default)]
+pub struct DefaultCacheVerifier<FsIntT: FsInt> {
+    fs: FsIntT,
+    pub options: VerifierOptions,
+}
+
+
+impl<FsIntT: FsInt> CacheVerifier for DefaultCacheVerifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        crate::pathcheck::check(path).map_err(|_| VerificationError::InvalidPath)?;
+
+        check_is_directory(&self.fs, path)?;
+
+        if !self.fs.is_absolute(path) {
+            return Err(VerificationError::NotAbsolute);
+        }
+
+        if !check_permissions(&self.fs, path, self.options) {
+            return Err(VerificationError::InsecurePermissions);
+        }
+
+        Ok(())
+    }
+}
+
+impl<FsIntT: FsInt> DefaultCacheVerifier<FsIntT> {
+    pub fn new(fs: FsIntT) -> Self {
+        Self::with_options(fs, VerifierOptions::default())
+    }
+
+    pub fn with_options(fs: FsIntT, options: VerifierOptions) -> Self {
+        Self {
+            fs,
+            options
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
+pub enum CacheDirectoryResolutionMethods {
+    FromFile,
+    FromXDG,
+    FromFHS,
+    FromVoxels,
+    /// A volume mounted at a conventional mount point, see `ContainerVolumes`
+    FromContainerVolume
+}
+
+impl CacheDirectoryResolutionMethods {
+    /// Stable name used when a resolution is recorded, see `ResolutionRecord`
+    pub fn name(&self) -> &'static str {
+        match self {
+            CacheDirectoryResolutionMethods::FromFile => "file",
+            CacheDirectoryResolutionMethods::FromXDG => "xdg",
+            CacheDirectoryResolutionMethods::FromFHS => "fhs",
+            CacheDirectoryResolutionMethods::FromVoxels => "voxels",
+            CacheDirectoryResolutionMethods::FromContainerVolume => "container-volume",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "file" => Some(CacheDirectoryResolutionMethods::FromFile),
+            "xdg" => Some(CacheDirectoryResolutionMethods::FromXDG),
+            "fhs" => Some(CacheDirectoryResolutionMethods::FromFHS),
+            "voxels" => Some(CacheDirectoryResolutionMethods::FromVoxels),
+            "container-volume" => Some(CacheDirectoryResolutionMethods::FromContainerVolume),
+            _ => None,
+        }
+    }
+
+    /// The variable this method reads, `None` when it reads none
+    pub fn env_key(&self) -> Option<EnvKey> {
+        match self {
+            CacheDirectoryResolutionMethods::FromFile => Some(EnvKey::VoxelsDirsFile),
+            CacheDirectoryResolutionMethods::FromXDG => Some(EnvKey::XdgCacheHome),
+            CacheDirectoryResolutionMethods::FromFHS => Some(EnvKey::Home),
+            CacheDirectoryResolutionMethods::FromVoxels => Some(EnvKey::VoxelsCacheHome),
+            CacheDirectoryResolutionMethods::FromContainerVolume => None,
+        }
+    }
+}
+
+impl ResolutionMethod for CacheDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        CacheDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<CacheDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: CacheDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for CacheDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<CacheDirectoryResolutionMethods>", from = "Vec<CacheDirectoryResolutionMethods>"))]
+pub struct CacheDirectoryPriority {
+    order: std::collections::BTreeMap<usize, CacheDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<CacheDirectoryResolutionMethods>,
+}
+
+impl Default for CacheDirectoryPriority {
+    fn default() -> Self {
+        let mut order = std::collections::BTreeMap::new();
+        order.insert(0, CacheDirectoryResolutionMethods::FromFile);
+        order.insert(1, CacheDirectoryResolutionMethods::FromVoxels);
+        order.insert(2, CacheDirectoryResolutionMethods::FromContainerVolume);
+        order.insert(3, CacheDirectoryResolutionMethods::FromXDG);
+        #[cfg(not(feature = "no-fhs"))]
+        order.insert(4, CacheDirectoryResolutionMethods::FromFHS);
+        Self {
+            order,
+            sources: ordering::PrioritySources::new()
+        }
+    }
+}
+
+impl CacheDirectoryPriority {
+    pub fn set_all(&mut self, new_order: [CacheDirectoryResolutionMethods; 5]) {
+        self.order = std::collections::BTreeMap::new();
+        self.order.insert(0, new_order[0]);
+        self.order.insert(1, new_order[1]);
+        self.order.insert(2, new_order[2]);
+        self.order.insert(3, new_order[3]);
+        self.order.insert(4, new_order[4]);
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, CacheDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// Move `method` to the front, keeping the relative order of the rest. Methods not in the order are ignored.
+    pub fn prefer(&mut self, method: CacheDirectoryResolutionMethods) {
+        self.prefer_as(method, PrioritySource::Preferred);
+    }
+
+    fn prefer_as(&mut self, method: CacheDirectoryResolutionMethods, source: PrioritySource) {
+        ordering::prefer(&mut self.order, method);
+
+        if self.order.values().any(|existing| *existing == method) {
+            self.sources.insert(method, source);
+        }
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, CacheDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for CacheDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, CacheDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<CacheDirectoryPriority> for Vec<CacheDirectoryResolutionMethods> {
+    fn from(priority: CacheDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<CacheDirectoryResolutionMethods>> for CacheDirectoryPriority {
+    fn from(methods: Vec<CacheDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
+    }
+}
+
+#[mockall::automock]
+pub trait CacheDirectoryResolver {
+    fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve(&self) -> Result<(AbsoluteDirPath, CacheDirectoryResolutionMethods), BaseDirectoryError>;
+
+    /// Every environment variable resolution may read with the current priority, for sandbox allowlists
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey>;
+
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, CacheDirectoryResolutionMethods)>;
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError>;
+}
+
+#[derive(Default)]
+pub struct CacheDirectory<EnvIntT: EnvInt, VerifierT: CacheVerifier> {
+    cache_path: Option<PathBuf>,
+    verifier: VerifierT,
+    env: EnvIntT,
+    pub priority: CacheDirectoryPriority,
+    /// Pinned paths read from `VOXELS_DIRS_FILE`, see `DirsFile::from_environment`
+    pub dirs_file: Option<DirsFile>,
+    /// Volumes found by `ContainerVolumes::detect`, `None` outside a container
+    pub container: Option<ContainerVolumes>,
+    /// Told about every candidate tried, see `ResolutionObserver`
+    pub observer: Option<SharedObserver>,
+}
+
+impl<EnvIntT: EnvInt, VerifierT: CacheVerifier> CacheDirectory<EnvIntT, VerifierT> {
+    /// Pinned paths are read from the file `VOXELS_DIRS_FILE` names, if any
+    pub fn new(env: EnvIntT, verifier: VerifierT) -> Self {
+        Self::with_fs(env, verifier, &DefaultFsInt)
+    }
+
+    /// As `new`, reading the dirs file through `fs`
+    pub fn with_fs<FsIntT: FsInt>(env: EnvIntT, verifier: VerifierT, fs: &FsIntT) -> Self {
+        let priority = CacheDirectoryPriority::default();
+        let dirs_file = DirsFile::load(&env, fs);
+        Self {
+            cache_path: None,
+            env,
+            verifier,
+            priority,
+            dirs_file,
+            container: None,
+            observer: None
+        }
+    }
+
+    /// The path `method` finds, verified but not yet checked to be absolute
+    fn using(&self, method: &CacheDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        match method {
+            CacheDirectoryResolutionMethods::FromFile => self.using_file(),
+            CacheDirectoryResolutionMethods::FromXDG => self.using_xdg(),
+            CacheDirectoryResolutionMethods::FromVoxels => self.using_voxels(),
+            CacheDirectoryResolutionMethods::FromFHS => self.using_fhs(),
+            CacheDirectoryResolutionMethods::FromContainerVolume => self.using_container_volume(),
+        }
+    }
+
+    /// The path `method` would yield before verification, even when nothing exists there yet
+    fn candidate(&self, method: &CacheDirectoryResolutionMethods) -> Option<PathBuf> {
+        match method {
+            CacheDirectoryResolutionMethods::FromFile => self.dirs_file.as_ref()
+                .and_then(|dirs_file| dirs_file.get(DirectoryKind::Cache))
+                .map(Path::to_path_buf),
+            CacheDirectoryResolutionMethods::FromXDG => self.env.get_path(EnvKey::XdgCacheHome).ok().map(|path| normalize(&path)),
+            CacheDirectoryResolutionMethods::FromVoxels => self.env.get_path(EnvKey::VoxelsCacheHome).ok().map(|path| normalize(&path)),
+            CacheDirectoryResolutionMethods::FromFHS => super::fhs_candidate(&self.env, ".cache").ok(),
+            CacheDirectoryResolutionMethods::FromContainerVolume => self.container.as_ref()
+                .and_then(|container| container.get(DirectoryKind::Cache))
+                .map(Path::to_path_buf),
+        }
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when the directory's path is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub fn resolve_utf8(&self) -> Result<(camino::Utf8PathBuf, CacheDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = self.resolve()?;
+
+        Ok((super::to_utf8(path.into_path_buf())?, method))
+    }
+
+    /// What each method in the priority yields before verification, so installers can choose
+    /// where to create a base directory on a machine where none exists yet
+    pub fn candidate_paths(&self) -> Vec<(PathBuf, CacheDirectoryResolutionMethods)> {
+        self.priority.order.values()
+            .filter_map(|method| Some((self.candidate(method)?, *method)))
+            .collect()
+    }
+
+    /// `resolve`, but when no candidate verifies the specification's default, `${XDG_CACHE_HOME}`
+    /// or else `~/.cache`, is created and used, so fresh accounts and minimal containers still resolve
+    pub fn resolve_or_bootstrap<FsIntT: FsInt>(&self, fs: &FsIntT) -> Result<(AbsoluteDirPath, CacheDirectoryResolutionMethods), BaseDirectoryError> {
+        if let Ok(resolved) = self.resolve() {
+            return Ok(resolved);
+        }
+
+        for method in [CacheDirectoryResolutionMethods::FromXDG, CacheDirectoryResolutionMethods::FromFHS] {
+            if let Some(path) = self.candidate(&method)
+                && let Ok(path) = AbsoluteDirPath::new(path) {
+                tracing::info!("bootstrapping {:?}, no cache directory exists yet", path);
+
+                create_directory(fs, &path, CreateOptions::default())?;
+
+                observer::resolved(self.observer.as_ref(), DirectoryKind::Cache, &method.source(), &path);
+
+                return Ok((path, method));
+            }
+        }
+
+        Err(BaseDirectoryError::NoCandidate)
+    }
+
+    /// `using` for `method`, reported to `observer`
+    fn attempt(&self, method: &CacheDirectoryResolutionMethods) -> (CacheDirectoryResolutionMethods, Option<PathBuf>) {
+        (*method, observer::attempt(self.observer.as_ref(), DirectoryKind::Cache, &method.source(), || self.using(method)))
+    }
+
+    /// Prefer the method recorded in `record` and record the outcome, logging when the directory moved since last time
+    pub fn pin_resolution<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(AbsoluteDirPath, CacheDirectoryResolutionMethods), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        if let Some(method) = resolutions.source(DirectoryKind::Cache).and_then(|source| CacheDirectoryResolutionMethods::from_source(source).ok()) {
+            self.priority.prefer_as(method, PrioritySource::Pinned);
+        }
+
+        let (path, method) = self.resolve()?;
+
+        if resolutions.update(DirectoryKind::Cache, &method.source(), &path) {
+            resolutions.save(fs, record)?;
+        }
+
+        Ok((path, method))
+    }
+
+    /// Forget the recorded resolution and return to the default priority
+    pub fn unpin<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        resolutions.remove(DirectoryKind::Cache);
+
+        self.priority = CacheDirectoryPriority::default();
+
+        resolutions.save(fs, record)
+    }
+}
+
+impl<EnvIntT: EnvInt, VerifierT: CacheVerifier> CacheDirectoryResolver for CacheDirectory<EnvIntT, VerifierT> {
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey> {
+        self.priority.order.values()
+            .filter_map(CacheDirectoryResolutionMethods::env_key)
+            .collect()
+    }
+
+    fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let cache_path = super::fhs_candidate(&self.env, ".cache")?;
+
+        self.verifier.verify(&cache_path).map_err(|error| error.at(&cache_path))?;
+
+        Ok(cache_path)
+    }
+
+    fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let cache_path: PathBuf = normalize(&self.env.get_path(EnvKey::XdgCacheHome)?);
+
+        self.verifier.verify(&cache_path).map_err(|error| error.at(&cache_path))?;
+
+        Ok(cache_path)
+    }
+
+    fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path: PathBuf = normalize(&self.env.get_path(EnvKey::VoxelsCacheHome)?);
+
+        self.verifier.verify(&path).map_err(|error| error.at(&path))?;
+
+        Ok(path)
+    }
+
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.dirs_file.as_ref()
+            .and_then(|dirs_file| dirs_file.get(DirectoryKind::Cache))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.container.as_ref()
+            .and_then(|container| container.get(DirectoryKind::Cache))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn resolve(&self) -> Result<(AbsoluteDirPath, CacheDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method)))
+            .next()
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        observer::resolved(self.observer.as_ref(), DirectoryKind::Cache, &method.source(), &path);
+
+        Ok((path, method))
+    }
+
+    /// Every verified candidate in priority order, with candidates that are the same directory on disk removed
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, CacheDirectoryResolutionMethods)> {
+        let candidates = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method))).collect();
+
+        dedup_candidates(fs, candidates)
+    }
+
+    /// `resolve` as the write directory, then every other candidate and the system directories to read from
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError> {
+        let system = system_dirs(&self.env, DirectoryKind::Cache)
+            .into_iter()
+            .filter(|path| self.verifier.verify(path).is_ok())
+            .filter_map(|path| AbsoluteDirPath::new(path).ok());
+
+        ordering::build_overlay(self.resolve_candidates(fs), system, |_path| true)
+            .ok_or(BaseDirectoryError::NoCandidate)
+    }
+}
+
+impl<EnvIntT: EnvInt, VerifierT: CacheVerifier> From<CacheDirectory<EnvIntT, VerifierT>> for PathBuf {
+    fn from(directory: CacheDirectory<EnvIntT, VerifierT>) -> Self {
+        directory.cache_path.unwrap()
+    }
+}
\ No newline at end of file
@@ -16,39 +16,58 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
 use super::BaseDirectoryError;
+use crate::pathcheck::{normalize, AbsoluteDirPath};
+use super::dedup_candidates;
+use super::ordering::{self, PrioritySource};
+use super::observer::{self, RejectionReason, SharedObserver};
+use super::overlay::{system_dirs, Overlay};
+use super::container::ContainerVolumes;
+use super::dirs_file::DirsFile;
+use super::pinning::ResolutionRecord;
+use super::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+use crate::voxels::DirectoryKind;
 use std::path::{Path, PathBuf};
-use crate::voxels::voxels_xdg::xdg::config::ConfigDirectoryResolutionMethods::{FromFHS, FromVoxels, FromXDG};
+use crate::voxels::voxels_xdg::xdg::config::ConfigDirectoryResolutionMethods::{FromFile, FromVoxels, FromXDG};
 use super::{FsInt};
-use super::{EnvInt};
+use crate::filesystem::{create_directory, CreateOptions, DefaultFsInt};
+use super::{check_is_directory, check_permissions, is_read_only_store, VerifierOptions};
+use super::{EnvInt, EnvKey};
+use super::verifier::VerificationError;
 
 #[mockall::automock]
 pub trait ConfigVerifier {
-    fn verify(&self, path: &Path) -> bool;
+    fn verify(&self, path: &Path) -> Result<(), VerificationError>;
 }
 
 #[derive(Default)]
 pub struct DefaultConfigVerifier<FsIntT: FsInt> {
     fs: FsIntT,
+    pub options: VerifierOptions,
 }
 
 impl<FsIntT: FsInt> ConfigVerifier for DefaultConfigVerifier<FsIntT> {
-    fn verify(&self, path: &Path) -> bool {
-        if !self.fs.exists(path) {
-            return false;
-        }
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        crate::pathcheck::check(path).map_err(|_| VerificationError::InvalidPath)?;
+
+        check_is_directory(&self.fs, path)?;
 
-        if !self.fs.is_directory(path) {
-            return false;
+        if !check_permissions(&self.fs, path, self.options) {
+            return Err(VerificationError::InsecurePermissions);
         }
 
-        true
+        Ok(())
     }
 }
 
 impl<FsIntT: FsInt> DefaultConfigVerifier<FsIntT> {
     pub fn new(fs: FsIntT) -> Self {
+        Self::with_options(fs, VerifierOptions::default())
+    }
+
+    pub fn with_options(fs: FsIntT, options: VerifierOptions) -> Self {
         Self {
-            fs
+            fs,
+            options
         }
     }
 }
@@ -74,52 +93,202 @@ fn test_default_config_verifier() {
 
     let result = validator.verify(test_path);
 
-    assert!(result);
+    assert!(result.is_ok());
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum ConfigDirectoryResolutionMethods {
+    FromFile,
     FromXDG,
     FromFHS,
-    FromVoxels
+    FromVoxels,
+    /// A volume mounted at a conventional mount point, see `ContainerVolumes`
+    FromContainerVolume
+}
+
+impl ConfigDirectoryResolutionMethods {
+    /// Stable name used when a resolution is recorded, see `ResolutionRecord`
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigDirectoryResolutionMethods::FromFile => "file",
+            ConfigDirectoryResolutionMethods::FromXDG => "xdg",
+            ConfigDirectoryResolutionMethods::FromFHS => "fhs",
+            ConfigDirectoryResolutionMethods::FromVoxels => "voxels",
+            ConfigDirectoryResolutionMethods::FromContainerVolume => "container-volume",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "file" => Some(ConfigDirectoryResolutionMethods::FromFile),
+            "xdg" => Some(ConfigDirectoryResolutionMethods::FromXDG),
+            "fhs" => Some(ConfigDirectoryResolutionMethods::FromFHS),
+            "voxels" => Some(ConfigDirectoryResolutionMethods::FromVoxels),
+            "container-volume" => Some(ConfigDirectoryResolutionMethods::FromContainerVolume),
+            _ => None,
+        }
+    }
+
+    /// The variable this method reads, `None` when it reads none
+    pub fn env_key(&self) -> Option<EnvKey> {
+        match self {
+            ConfigDirectoryResolutionMethods::FromFile => Some(EnvKey::VoxelsDirsFile),
+            ConfigDirectoryResolutionMethods::FromXDG => Some(EnvKey::XdgConfigHome),
+            ConfigDirectoryResolutionMethods::FromFHS => Some(EnvKey::Home),
+            ConfigDirectoryResolutionMethods::FromVoxels => Some(EnvKey::VoxelsConfigHome),
+            ConfigDirectoryResolutionMethods::FromContainerVolume => None,
+        }
+    }
+}
+
+impl ResolutionMethod for ConfigDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        ConfigDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<ConfigDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: ConfigDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for ConfigDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<ConfigDirectoryResolutionMethods>", from = "Vec<ConfigDirectoryResolutionMethods>"))]
 pub struct ConfigDirectoryPriority {
     order: std::collections::BTreeMap<usize, ConfigDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<ConfigDirectoryResolutionMethods>,
 }
 
 impl Default for ConfigDirectoryPriority {
     fn default() -> Self {
         let mut order = std::collections::BTreeMap::new();
-        order.insert(0, FromVoxels);
-        order.insert(1, FromXDG);
-        order.insert(2, FromFHS);
+        order.insert(0, FromFile);
+        order.insert(1, FromVoxels);
+        order.insert(2, ConfigDirectoryResolutionMethods::FromContainerVolume);
+        order.insert(3, FromXDG);
+        #[cfg(not(feature = "no-fhs"))]
+        order.insert(4, ConfigDirectoryResolutionMethods::FromFHS);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
 
 impl ConfigDirectoryPriority {
-    pub fn set_all(&mut self, new_order: [ConfigDirectoryResolutionMethods; 3]) {
+    pub fn set_all(&mut self, new_order: [ConfigDirectoryResolutionMethods; 5]) {
         self.order = std::collections::BTreeMap::new();
         self.order.insert(0, new_order[0].clone());
         self.order.insert(1, new_order[1].clone());
         self.order.insert(2, new_order[2].clone());
+        self.order.insert(3, new_order[3].clone());
+        self.order.insert(4, new_order[4].clone());
+
+        self.sources = ordering::custom_sources(&self.order);
     }
 
-    pub fn get(&self) -> std::collections::BTreeMap<usize, ConfigDirectoryResolutionMethods> {
-        self.order.clone()
+    pub fn get(&self) -> &std::collections::BTreeMap<usize, ConfigDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// Move `method` to the front, keeping the relative order of the rest. Methods not in the order are ignored.
+    pub fn prefer(&mut self, method: ConfigDirectoryResolutionMethods) {
+        self.prefer_as(method, PrioritySource::Preferred);
+    }
+
+    fn prefer_as(&mut self, method: ConfigDirectoryResolutionMethods, source: PrioritySource) {
+        ordering::prefer(&mut self.order, method.clone());
+
+        if self.order.values().any(|existing| *existing == method) {
+            self.sources.insert(method, source);
+        }
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, ConfigDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for ConfigDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, ConfigDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<ConfigDirectoryPriority> for Vec<ConfigDirectoryResolutionMethods> {
+    fn from(priority: ConfigDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<ConfigDirectoryResolutionMethods>> for ConfigDirectoryPriority {
+    fn from(methods: Vec<ConfigDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
 
+/// A config candidate as returned by `config_sources`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ConfigSource {
+    pub path: AbsoluteDirPath,
+    pub method: ConfigDirectoryResolutionMethods,
+    /// Inside a package store such as `/nix/store`, never chosen by `resolve`
+    pub read_only: bool,
+}
+
 #[mockall::automock]
 pub trait ConfigDirectoryResolver {
     fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError>;
-    fn resolve(&self) -> Result<(PathBuf, ConfigDirectoryResolutionMethods), BaseDirectoryError>;
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve(&self) -> Result<(AbsoluteDirPath, ConfigDirectoryResolutionMethods), BaseDirectoryError>;
+
+    /// Every environment variable resolution may read with the current priority, for sandbox allowlists
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey>;
+
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, ConfigDirectoryResolutionMethods)>;
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError>;
+
+    /// The directory settings are written to, the same as `resolve`
+    fn writable_config_dir(&self) -> Result<(AbsoluteDirPath, ConfigDirectoryResolutionMethods), BaseDirectoryError> {
+        self.resolve()
+    }
+
+    /// Every candidate settings may be read from, in priority order, including read only store paths
+    fn config_sources<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<ConfigSource> {
+        self.resolve_candidates(fs)
+            .into_iter()
+            .map(|(path, method)| ConfigSource {
+                read_only: is_read_only_store(&path),
+                path,
+                method,
+            })
+            .collect()
+    }
 
 }
 
@@ -129,103 +298,250 @@ pub struct ConfigDirectory<EnvIntT: EnvInt, VerifierT: ConfigVerifier> {
     verifier: VerifierT,
     env: EnvIntT,
     pub priority: ConfigDirectoryPriority,
+    /// Pinned paths read from `VOXELS_DIRS_FILE`, see `DirsFile::from_environment`
+    pub dirs_file: Option<DirsFile>,
+    /// Volumes found by `ContainerVolumes::detect`, `None` outside a container
+    pub container: Option<ContainerVolumes>,
+    /// Told about every candidate tried, see `ResolutionObserver`
+    pub observer: Option<SharedObserver>,
 }
 
 impl<EnvIntT: EnvInt, VerifierT: ConfigVerifier> ConfigDirectory<EnvIntT, VerifierT> {
+    /// Pinned paths are read from the file `VOXELS_DIRS_FILE` names, if any
     pub fn new(env: EnvIntT, verifier: VerifierT) -> Self {
+        Self::with_fs(env, verifier, &DefaultFsInt)
+    }
+
+    /// As `new`, reading the dirs file through `fs`
+    pub fn with_fs<FsIntT: FsInt>(env: EnvIntT, verifier: VerifierT, fs: &FsIntT) -> Self {
         let priority = ConfigDirectoryPriority::default();
+        let dirs_file = DirsFile::load(&env, fs);
         Self {
             config_path: None,
             env,
             verifier,
-            priority
+            priority,
+            dirs_file,
+            container: None,
+            observer: None
+        }
+    }
+
+    /// The path `method` finds, verified but not yet checked to be absolute
+    fn using(&self, method: &ConfigDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        match method {
+            ConfigDirectoryResolutionMethods::FromFile => self.using_file(),
+            ConfigDirectoryResolutionMethods::FromXDG => self.using_xdg(),
+            ConfigDirectoryResolutionMethods::FromVoxels => self.using_voxels(),
+            ConfigDirectoryResolutionMethods::FromFHS => self.using_fhs(),
+            ConfigDirectoryResolutionMethods::FromContainerVolume => self.using_container_volume(),
+        }
+    }
+
+    /// The path `method` would yield before verification, even when nothing exists there yet
+    fn candidate(&self, method: &ConfigDirectoryResolutionMethods) -> Option<PathBuf> {
+        match method {
+            ConfigDirectoryResolutionMethods::FromFile => self.dirs_file.as_ref()
+                .and_then(|dirs_file| dirs_file.get(DirectoryKind::Config))
+                .map(Path::to_path_buf),
+            ConfigDirectoryResolutionMethods::FromXDG => self.env.get_path(EnvKey::XdgConfigHome).ok().map(|path| normalize(&path)),
+            ConfigDirectoryResolutionMethods::FromVoxels => self.env.get_path(EnvKey::VoxelsConfigHome).ok().map(|path| normalize(&path)),
+            ConfigDirectoryResolutionMethods::FromFHS => super::fhs_candidate(&self.env, ".config").ok(),
+            ConfigDirectoryResolutionMethods::FromContainerVolume => self.container.as_ref()
+                .and_then(|container| container.get(DirectoryKind::Config))
+                .map(Path::to_path_buf),
+        }
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when the directory's path is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub fn resolve_utf8(&self) -> Result<(camino::Utf8PathBuf, ConfigDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = self.resolve()?;
+
+        Ok((super::to_utf8(path.into_path_buf())?, method))
+    }
+
+    /// What each method in the priority yields before verification, so installers can choose
+    /// where to create a base directory on a machine where none exists yet
+    pub fn candidate_paths(&self) -> Vec<(PathBuf, ConfigDirectoryResolutionMethods)> {
+        self.priority.order.values()
+            .filter_map(|method| Some((self.candidate(method)?, method.clone())))
+            .collect()
+    }
+
+    /// `resolve`, but when no candidate verifies the specification's default, `${XDG_CONFIG_HOME}`
+    /// or else `~/.config`, is created and used, so fresh accounts and minimal containers still resolve
+    pub fn resolve_or_bootstrap<FsIntT: FsInt>(&self, fs: &FsIntT) -> Result<(AbsoluteDirPath, ConfigDirectoryResolutionMethods), BaseDirectoryError> {
+        if let Ok(resolved) = self.resolve() {
+            return Ok(resolved);
+        }
+
+        for method in [ConfigDirectoryResolutionMethods::FromXDG, ConfigDirectoryResolutionMethods::FromFHS] {
+            if let Some(path) = self.candidate(&method)
+                && let Ok(path) = AbsoluteDirPath::new(path) {
+                tracing::info!("bootstrapping {:?}, no config directory exists yet", path);
+
+                create_directory(fs, &path, CreateOptions::default())?;
+
+                observer::resolved(self.observer.as_ref(), DirectoryKind::Config, &method.source(), &path);
+
+                return Ok((path, method));
+            }
         }
+
+        Err(BaseDirectoryError::NoCandidate)
+    }
+
+    /// `using` for `method`, reported to `observer`
+    fn attempt(&self, method: &ConfigDirectoryResolutionMethods) -> (ConfigDirectoryResolutionMethods, Option<PathBuf>) {
+        (method.clone(), observer::attempt(self.observer.as_ref(), DirectoryKind::Config, &method.source(), || self.using(method)))
+    }
+
+    /// Prefer the method recorded in `record` and record the outcome, logging when the directory moved since last time
+    pub fn pin_resolution<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(AbsoluteDirPath, ConfigDirectoryResolutionMethods), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        if let Some(method) = resolutions.source(DirectoryKind::Config).and_then(|source| ConfigDirectoryResolutionMethods::from_source(source).ok()) {
+            self.priority.prefer_as(method, PrioritySource::Pinned);
+        }
+
+        let (path, method) = self.resolve()?;
+
+        if resolutions.update(DirectoryKind::Config, &method.source(), &path) {
+            resolutions.save(fs, record)?;
+        }
+
+        Ok((path, method))
+    }
+
+    /// Forget the recorded resolution and return to the default priority
+    pub fn unpin<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        resolutions.remove(DirectoryKind::Config);
+
+        self.priority = ConfigDirectoryPriority::default();
+
+        resolutions.save(fs, record)
     }
 }
 
 impl<EnvIntT: EnvInt, VerifierT: ConfigVerifier> ConfigDirectoryResolver for ConfigDirectory<EnvIntT, VerifierT> {
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey> {
+        let mut consumed: std::collections::BTreeSet<EnvKey> = self.priority.order.values()
+            .filter_map(ConfigDirectoryResolutionMethods::env_key)
+            .collect();
+
+        // read by `overlay` for the system wide directories
+        consumed.insert(EnvKey::XdgConfigDirs);
+
+        consumed
+    }
+
     fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("HOME"))?;
+        let config_path = super::fhs_candidate(&self.env, ".config")?;
 
-        let config_path = path.join(".config/");
+        self.verifier.verify(&config_path).map_err(|error| error.at(&config_path))?;
 
-        if self.verifier.verify(&config_path) {
-            Ok(config_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        Ok(config_path)
     }
 
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let config_path: PathBuf = self.env.get_path_from_environment(String::from("XDG_CONFIG_HOME"))?;
+        let config_path: PathBuf = normalize(&self.env.get_path(EnvKey::XdgConfigHome)?);
 
-        if self.verifier.verify(&config_path) {
-            Ok(config_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        self.verifier.verify(&config_path).map_err(|error| error.at(&config_path))?;
+
+        Ok(config_path)
     }
 
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("VOXELS_CONFIG_HOME"))?;
+        let path: PathBuf = normalize(&self.env.get_path(EnvKey::VoxelsConfigHome)?);
 
-        if self.verifier.verify(&path) {
-            Ok(path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        self.verifier.verify(&path).map_err(|error| error.at(&path))?;
+
+        Ok(path)
     }
 
-    fn resolve(&self) -> Result<(PathBuf, ConfigDirectoryResolutionMethods), BaseDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            match self.priority.order[&index] {
-                FromXDG => {
-                    let path = self.using_xdg();
-
-                    if path.is_ok() {
-                        return Ok((path?, FromXDG));
-                    }
-                },
-                FromVoxels => {
-                    let path = self.using_voxels();
-
-                    if path.is_ok() {
-                        return Ok((path?, FromVoxels));
-                    }
-                },
-                FromFHS => {
-                    let path = self.using_fhs();
-
-                    if path.is_ok() {
-                        return Ok((path?, FromFHS));
-                    }
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.dirs_file.as_ref()
+            .and_then(|dirs_file| dirs_file.get(DirectoryKind::Config))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.container.as_ref()
+            .and_then(|container| container.get(DirectoryKind::Config))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn resolve(&self) -> Result<(AbsoluteDirPath, ConfigDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method)))
+            .find(|(path, how)| {
+                let writable = !is_read_only_store(path);
+
+                if !writable {
+                    observer::rejected(self.observer.as_ref(), DirectoryKind::Config, &how.source(), RejectionReason::ReadOnlyStore);
                 }
-            }
-        }
-        Err(BaseDirectoryError::NoCandidate)
+
+                writable
+            })
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        observer::resolved(self.observer.as_ref(), DirectoryKind::Config, &method.source(), &path);
+
+        Ok((path, method))
+    }
+
+    /// Every verified candidate in priority order, with candidates that are the same directory on disk removed
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, ConfigDirectoryResolutionMethods)> {
+        let candidates = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method))).collect();
+
+        dedup_candidates(fs, candidates)
+    }
+
+    /// `resolve` as the write directory, then every other candidate and the system directories to read from
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError> {
+        let system = system_dirs(&self.env, DirectoryKind::Config)
+            .into_iter()
+            .filter(|path| self.verifier.verify(path).is_ok())
+            .filter_map(|path| AbsoluteDirPath::new(path).ok());
+
+        ordering::build_overlay(self.resolve_candidates(fs), system, |path| !is_read_only_store(path))
+            .ok_or(BaseDirectoryError::NoCandidate)
     }
 }
 
-impl<EnvIntT: EnvInt, VerifierT: ConfigVerifier> Into<PathBuf> for ConfigDirectory<EnvIntT, VerifierT> {
-    fn into(self) -> PathBuf {
-        self.config_path.unwrap()
+impl<EnvIntT: EnvInt, VerifierT: ConfigVerifier> From<ConfigDirectory<EnvIntT, VerifierT>> for PathBuf {
+    fn from(directory: ConfigDirectory<EnvIntT, VerifierT>) -> Self {
+        directory.config_path.unwrap()
     }
 }
 
 
+#[cfg(not(feature = "no-fhs"))]
 #[test]
 fn test_from_fhs() {
     let mut env = crate::environment_variables::MockEnvInt::new();
     let mut validator = MockConfigVerifier::new();
 
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirsFile);
+
     let home_env = PathBuf::from("/home");
 
-    let expected_home_path = PathBuf::from("/home/.config/");
+    let expected_home_path = PathBuf::from("/home/.config");
 
 
-    env.expect_get_path_from_environment()
+    env.expect_get_path()
         .once()
-        .with(mockall::predicate::eq(String::from("HOME")))
+        .with(mockall::predicate::eq(EnvKey::Home))
         .return_once({
             let expected_home = home_env.clone();
             |_| Ok(expected_home)
@@ -234,7 +550,7 @@ fn test_from_fhs() {
     validator.expect_verify()
         .once()
         .with(mockall::predicate::eq(expected_home_path.clone()))
-        .return_once(|_| true);
+        .return_once(|_| Ok(()));
 
     let config = ConfigDirectory::new(env, validator);
 
@@ -244,23 +560,35 @@ fn test_from_fhs() {
     assert_eq!(res.unwrap(), expected_home_path);
 }
 
+#[cfg(feature = "no-fhs")]
+#[test]
+fn test_fhs_disabled() {
+    let config = ConfigDirectory::new(crate::environment_variables::MockEnvInt::new(), MockConfigVerifier::new());
+
+    assert!(matches!(config.using_fhs(), Err(BaseDirectoryError::FhsDisabled)));
+    assert_eq!(crate::voxels::VoxelsDirectoryError::from(BaseDirectoryError::FhsDisabled), crate::voxels::VoxelsDirectoryError::FhsDisabled);
+    assert!(!ConfigDirectoryPriority::default().describe().contains("fhs"));
+}
+
 #[test]
 fn test_resolve() {
     // create mocked interfaces to filesystem and environment variables
     let mut env = crate::environment_variables::MockEnvInt::new();
     let mut validator = MockConfigVerifier::new();
 
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirsFile);
+
     // first test setup conditions for voxels environment variable
     // value of VOXELS_CONFIG_HOME environment variable
     let voxels_config_home = PathBuf::from("/home");
 
     let expected_voxels_return = PathBuf::from("/home");
 
-    env.expect_and_rig("VOXELS_CONFIG_HOME", voxels_config_home.clone());
+    env.expect_and_rig(EnvKey::VoxelsConfigHome, voxels_config_home.clone());
 
     validator.expect_verify()
         .with(mockall::predicate::eq(expected_voxels_return.clone()))
-        .returning(|_| true);
+        .returning(|_| Ok(()));
 
     let config = ConfigDirectory::new(env, validator);
 
@@ -276,16 +604,18 @@ fn test_from_xdg() {
     let mut env= crate::environment_variables::MockEnvInt::new();
     let mut validator = MockConfigVerifier::new();
 
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirsFile);
+
     let xdg_home = PathBuf::from("/home");
 
     let expected_home_path = PathBuf::from("/home");
 
-    env.expect_and_rig("XDG_CONFIG_HOME", xdg_home.clone());
+    env.expect_and_rig(EnvKey::XdgConfigHome, xdg_home.clone());
 
     validator.expect_verify()
         .with(mockall::predicate::eq(expected_home_path.clone()))
         .once()
-        .return_once(|_| true);
+        .return_once(|_| Ok(()));
 
     let config = ConfigDirectory::new(env, validator);
 
@@ -300,13 +630,15 @@ fn test_from_voxels() {
     let mut env = crate::environment_variables::MockEnvInt::new();
     let mut validator: MockConfigVerifier = MockConfigVerifier::new();
 
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirsFile);
+
     let voxels_env_home = PathBuf::from("/voxels");
 
     let expected_home_path = PathBuf::from("/voxels");
 
-    env.expect_and_rig("VOXELS_CONFIG_HOME", voxels_env_home.clone());
+    env.expect_and_rig(EnvKey::VoxelsConfigHome, voxels_env_home.clone());
 
-    validator.expect_verify().once().returning(|_| true);
+    validator.expect_verify().once().returning(|_| Ok(()));
 
     let config = ConfigDirectory::new(env, validator);
 
@@ -316,3 +648,129 @@ fn test_from_voxels() {
     assert_eq!(res.unwrap(), expected_home_path);
 
 }
+
+#[test]
+fn test_read_only_store_is_a_source_but_not_writable() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+    let mut validator = MockConfigVerifier::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    env.expect_get_path()
+        .returning(|name| match name {
+            EnvKey::VoxelsConfigHome => Ok(PathBuf::from("/nix/store/0abc-voxels-config")),
+            EnvKey::XdgConfigHome => Ok(PathBuf::from("/home/.config")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    validator.expect_verify()
+        .returning(|_| Ok(()));
+
+    fs.expect_file_id()
+        .returning(|_| Err(std::io::Error::from(std::io::ErrorKind::Unsupported)));
+
+    let config = ConfigDirectory::new(env, validator);
+
+    let (writable, how) = config.writable_config_dir().unwrap();
+
+    assert_eq!(writable, PathBuf::from("/home/.config"));
+    assert_eq!(how, FromXDG);
+
+    let sources = config.config_sources(&fs);
+
+    assert_eq!(sources.len(), 2);
+    assert!(sources[0].read_only);
+    assert!(!sources[1].read_only);
+}
+
+#[cfg(not(feature = "no-fhs"))]
+#[test]
+fn test_consumed_env_vars() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_and_rig_to_fail(EnvKey::VoxelsDirsFile);
+
+    let config = ConfigDirectory::new(env, MockConfigVerifier::new());
+
+    assert_eq!(config.consumed_env_vars().into_iter().collect::<Vec<EnvKey>>(), vec![
+        EnvKey::Home,
+        EnvKey::XdgConfigHome,
+        EnvKey::XdgConfigDirs,
+        EnvKey::VoxelsConfigHome,
+        EnvKey::VoxelsDirsFile,
+    ]);
+}
+
+#[cfg(not(feature = "no-fhs"))]
+#[test]
+fn test_candidate_paths_skip_verification() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_get_path()
+        .returning(|key| match key {
+            EnvKey::Home => Ok(PathBuf::from("/home/user")),
+            EnvKey::XdgConfigHome => Ok(PathBuf::from("/home/user/.config/")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    // nothing exists yet, the verifier is never asked
+    let config = ConfigDirectory::new(env, MockConfigVerifier::new());
+
+    assert_eq!(config.candidate_paths(), vec![
+        (PathBuf::from("/home/user/.config"), ConfigDirectoryResolutionMethods::FromXDG),
+        (PathBuf::from("/home/user/.config"), ConfigDirectoryResolutionMethods::FromFHS),
+    ]);
+}
+
+#[cfg(not(feature = "no-fhs"))]
+#[test]
+fn test_resolve_or_bootstrap_creates_fhs_default() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+    let mut validator = MockConfigVerifier::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let expected = PathBuf::from("/home/user/.config");
+
+    env.expect_get_path()
+        .returning(|key| match key {
+            EnvKey::Home => Ok(PathBuf::from("/home/user")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    validator.expect_verify()
+        .returning(|_| Err(VerificationError::NotFound));
+
+    fs.expect_create_dir_all()
+        .once()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_once(|_| Ok(()));
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    let config = ConfigDirectory::new(env, validator);
+
+    let (path, method) = config.resolve_or_bootstrap(&fs).unwrap();
+
+    assert_eq!(path, expected);
+    assert_eq!(method, ConfigDirectoryResolutionMethods::FromFHS);
+}
+
+#[cfg(all(feature = "serde", not(feature = "no-fhs")))]
+#[test]
+fn test_priority_serde_round_trip() {
+    let mut priority = ConfigDirectoryPriority::default();
+
+    priority.prefer(FromXDG);
+
+    let json = serde_json::to_string(&priority).unwrap();
+
+    assert_eq!(json, r#"["xdg","file","voxels","container-volume","fhs"]"#);
+
+    let read: ConfigDirectoryPriority = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(read.to_string(), priority.to_string());
+    assert!(read.describe().starts_with("1. xdg (custom)\n"));
+
+    // a source config directories cannot come from
+    assert!(serde_json::from_str::<ConfigDirectoryPriority>(r#"["temp"]"#).is_err());
+}
@@ -0,0 +1,111 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::voxels::DirectoryKind;
+
+use super::FsInt;
+
+/// Created by Docker in the root of every container
+pub const DOCKER_ENV_FILE: &str = "/.dockerenv";
+
+/// Created by Podman in every container
+pub const PODMAN_ENV_FILE: &str = "/run/.containerenv";
+
+const INIT_CGROUP_FILE: &str = "/proc/1/cgroup";
+
+/// Substrings of pid 1's cgroup that only appear inside a container
+const CGROUP_MARKERS: [&str; 4] = ["docker", "libpod", "containerd", "kubepods"];
+
+/// The conventional mount point for volumes holding `kind`, runtime directories are never volumes
+pub fn volume_mount_point(kind: DirectoryKind) -> Option<&'static Path> {
+    match kind {
+        DirectoryKind::Config => Some(Path::new("/config")),
+        DirectoryKind::Data => Some(Path::new("/data")),
+        DirectoryKind::State => Some(Path::new("/state")),
+        DirectoryKind::Cache => Some(Path::new("/cache")),
+        DirectoryKind::Runtime => None,
+    }
+}
+
+/// Whether this process runs inside a Docker or Podman container
+pub fn in_container<FsIntT: FsInt>(fs: &FsIntT) -> bool {
+    if fs.exists(Path::new(DOCKER_ENV_FILE)) || fs.exists(Path::new(PODMAN_ENV_FILE)) {
+        return true;
+    }
+
+    fs.read_to_string(Path::new(INIT_CGROUP_FILE))
+        .is_ok_and(|cgroup| CGROUP_MARKERS.iter().any(|marker| cgroup.contains(marker)))
+}
+
+/// The volumes mounted at conventional mount points, used by the `FromContainerVolume` resolution methods
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ContainerVolumes {
+    volumes: BTreeMap<DirectoryKind, PathBuf>,
+}
+
+impl ContainerVolumes {
+    /// `None` outside a container. Inside one, every mount point that is a writable directory is included.
+    pub fn detect<FsIntT: FsInt>(fs: &FsIntT) -> Option<Self> {
+        if !in_container(fs) {
+            return None;
+        }
+
+        let mut volumes = BTreeMap::new();
+
+        for kind in DirectoryKind::ALL {
+            let Some(mount_point) = volume_mount_point(kind) else {
+                continue;
+            };
+
+            if fs.exists(mount_point) && fs.is_directory(mount_point) && fs.is_writable(mount_point) {
+                volumes.insert(kind, mount_point.to_path_buf());
+            }
+        }
+
+        Some(Self {
+            volumes
+        })
+    }
+
+    pub fn get(&self, kind: DirectoryKind) -> Option<&Path> {
+        self.volumes.get(&kind).map(PathBuf::as_path)
+    }
+}
+
+#[test]
+fn test_detect_container_volumes() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_exists()
+        .returning(|path| path == Path::new(PODMAN_ENV_FILE) || path == Path::new("/config") || path == Path::new("/data"));
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    // `/data` is mounted read only
+    fs.expect_is_writable()
+        .returning(|path| path == Path::new("/config"));
+
+    let volumes = ContainerVolumes::detect(&fs).unwrap();
+
+    assert_eq!(volumes.get(DirectoryKind::Config), Some(Path::new("/config")));
+    assert_eq!(volumes.get(DirectoryKind::Data), None);
+    assert_eq!(volumes.get(DirectoryKind::State), None);
+}
@@ -16,80 +16,214 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 use std::path::{Path, PathBuf};
 use crate::voxels::voxels_xdg::xdg::BaseDirectoryError;
-use crate::environment_variables::EnvInt;
-use crate::filesystem::FsInt;
+use crate::environment_variables::{EnvInt, EnvKey};
+use crate::filesystem::{create_directory, CreateOptions, DefaultFsInt, FsInt};
+use super::{check_is_directory, check_permissions, VerifierOptions};
+use crate::pathcheck::{normalize, AbsoluteDirPath, RelPath};
+use super::dedup_candidates;
+use super::ordering::{self, PrioritySource};
+use super::observer::{self, SharedObserver};
+use super::overlay::{system_dirs, Overlay};
+use super::container::ContainerVolumes;
+use super::dirs_file::DirsFile;
+use super::pinning::ResolutionRecord;
+use super::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+use crate::voxels::DirectoryKind;
+use super::verifier::VerificationError;
 
 #[mockall::automock]
 pub trait DataVerifier {
-    fn verify(&self, path: &Path) -> bool;
+    fn verify(&self, path: &Path) -> Result<(), VerificationError>;
 }
 
 #[derive(Default)]
 pub struct DefaultDataVerifier<FsIntT: FsInt> {
     fs: FsIntT,
+    pub options: VerifierOptions,
 }
 
 
 impl<FsIntT: FsInt> DataVerifier for DefaultDataVerifier<FsIntT> {
-    fn verify(&self, path: &Path) -> bool {
-        if !self.fs.exists(path) {
-            return false;
-        }
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        crate::pathcheck::check(path).map_err(|_| VerificationError::InvalidPath)?;
 
-        if !self.fs.is_directory(path) {
-            return false;
-        }
+        check_is_directory(&self.fs, path)?;
 
         if !self.fs.is_absolute(path) {
-            return false;
+            return Err(VerificationError::NotAbsolute);
+        }
+
+        if !check_permissions(&self.fs, path, self.options) {
+            return Err(VerificationError::InsecurePermissions);
         }
 
-        true
+        Ok(())
     }
 }
 
 impl<FsIntT: FsInt> DefaultDataVerifier<FsIntT> {
     pub fn new(fs: FsIntT) -> Self {
+        Self::with_options(fs, VerifierOptions::default())
+    }
+
+    pub fn with_options(fs: FsIntT, options: VerifierOptions) -> Self {
         Self {
-            fs
+            fs,
+            options
         }
     }
 }
 
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum DataDirectoryResolutionMethods {
+    FromFile,
     FromXDG,
     FromFHS,
-    FromVoxels
+    FromVoxels,
+    /// A volume mounted at a conventional mount point, see `ContainerVolumes`
+    FromContainerVolume
+}
+
+impl DataDirectoryResolutionMethods {
+    /// Stable name used when a resolution is recorded, see `ResolutionRecord`
+    pub fn name(&self) -> &'static str {
+        match self {
+            DataDirectoryResolutionMethods::FromFile => "file",
+            DataDirectoryResolutionMethods::FromXDG => "xdg",
+            DataDirectoryResolutionMethods::FromFHS => "fhs",
+            DataDirectoryResolutionMethods::FromVoxels => "voxels",
+            DataDirectoryResolutionMethods::FromContainerVolume => "container-volume",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "file" => Some(DataDirectoryResolutionMethods::FromFile),
+            "xdg" => Some(DataDirectoryResolutionMethods::FromXDG),
+            "fhs" => Some(DataDirectoryResolutionMethods::FromFHS),
+            "voxels" => Some(DataDirectoryResolutionMethods::FromVoxels),
+            "container-volume" => Some(DataDirectoryResolutionMethods::FromContainerVolume),
+            _ => None,
+        }
+    }
+
+    /// The variable this method reads, `None` when it reads none
+    pub fn env_key(&self) -> Option<EnvKey> {
+        match self {
+            DataDirectoryResolutionMethods::FromFile => Some(EnvKey::VoxelsDirsFile),
+            DataDirectoryResolutionMethods::FromXDG => Some(EnvKey::XdgDataHome),
+            DataDirectoryResolutionMethods::FromFHS => Some(EnvKey::Home),
+            DataDirectoryResolutionMethods::FromVoxels => Some(EnvKey::VoxelsDataHome),
+            DataDirectoryResolutionMethods::FromContainerVolume => None,
+        }
+    }
 }
 
+impl ResolutionMethod for DataDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        DataDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<DataDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: DataDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for DataDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<DataDirectoryResolutionMethods>", from = "Vec<DataDirectoryResolutionMethods>"))]
 pub struct DataDirectoryPriority {
     order: std::collections::BTreeMap<usize, DataDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<DataDirectoryResolutionMethods>,
 }
 
 impl Default for DataDirectoryPriority {
     fn default() -> Self {
         let mut order = std::collections::BTreeMap::new();
-        order.insert(0, DataDirectoryResolutionMethods::FromVoxels);
-        order.insert(1, DataDirectoryResolutionMethods::FromXDG);
-        order.insert(2, DataDirectoryResolutionMethods::FromFHS);
+        order.insert(0, DataDirectoryResolutionMethods::FromFile);
+        order.insert(1, DataDirectoryResolutionMethods::FromVoxels);
+        order.insert(2, DataDirectoryResolutionMethods::FromContainerVolume);
+        order.insert(3, DataDirectoryResolutionMethods::FromXDG);
+        #[cfg(not(feature = "no-fhs"))]
+        order.insert(4, DataDirectoryResolutionMethods::FromFHS);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
 
 impl DataDirectoryPriority {
-    fn set_all(&mut self, new_order: [DataDirectoryResolutionMethods; 3]) {
+    fn set_all(&mut self, new_order: [DataDirectoryResolutionMethods; 5]) {
         self.order = std::collections::BTreeMap::new();
-        self.order.insert(0, new_order[0].clone());
-        self.order.insert(1, new_order[1].clone());
-        self.order.insert(2, new_order[2].clone());
+        self.order.insert(0, new_order[0]);
+        self.order.insert(1, new_order[1]);
+        self.order.insert(2, new_order[2]);
+        self.order.insert(3, new_order[3]);
+        self.order.insert(4, new_order[4]);
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    fn get(&self) -> &std::collections::BTreeMap<usize, DataDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// Move `method` to the front, keeping the relative order of the rest. Methods not in the order are ignored.
+    pub fn prefer(&mut self, method: DataDirectoryResolutionMethods) {
+        self.prefer_as(method, PrioritySource::Preferred);
+    }
+
+    fn prefer_as(&mut self, method: DataDirectoryResolutionMethods, source: PrioritySource) {
+        ordering::prefer(&mut self.order, method);
+
+        if self.order.values().any(|existing| *existing == method) {
+            self.sources.insert(method, source);
+        }
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, DataDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for DataDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, DataDirectoryResolutionMethods::name)
     }
+}
 
-    fn get(&self) -> std::collections::BTreeMap<usize, DataDirectoryResolutionMethods> {
-        self.order.clone()
+impl From<DataDirectoryPriority> for Vec<DataDirectoryResolutionMethods> {
+    fn from(priority: DataDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<DataDirectoryResolutionMethods>> for DataDirectoryPriority {
+    fn from(methods: Vec<DataDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
@@ -98,7 +232,15 @@ pub trait DataDirectoryResolver {
     fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError>;
-    fn resolve(&self) -> Result<(PathBuf, DataDirectoryResolutionMethods), BaseDirectoryError>;
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve(&self) -> Result<(AbsoluteDirPath, DataDirectoryResolutionMethods), BaseDirectoryError>;
+
+    /// Every environment variable resolution may read with the current priority, for sandbox allowlists
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey>;
+
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, DataDirectoryResolutionMethods)>;
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError>;
 }
 
 #[derive(Default)]
@@ -107,85 +249,227 @@ pub struct DataDirectory<EnvIntT: EnvInt, VerifierT: DataVerifier> {
     verifier: VerifierT,
     env: EnvIntT,
     pub priority: DataDirectoryPriority,
+    /// Pinned paths read from `VOXELS_DIRS_FILE`, see `DirsFile::from_environment`
+    pub dirs_file: Option<DirsFile>,
+    /// Volumes found by `ContainerVolumes::detect`, `None` outside a container
+    pub container: Option<ContainerVolumes>,
+    /// Told about every candidate tried, see `ResolutionObserver`
+    pub observer: Option<SharedObserver>,
 }
 
 impl<EnvIntT: EnvInt, VerifierT: DataVerifier> DataDirectory<EnvIntT, VerifierT> {
+    /// Pinned paths are read from the file `VOXELS_DIRS_FILE` names, if any
     pub fn new(env: EnvIntT, verifier: VerifierT) -> Self {
+        Self::with_fs(env, verifier, &DefaultFsInt)
+    }
+
+    /// As `new`, reading the dirs file through `fs`
+    pub fn with_fs<FsIntT: FsInt>(env: EnvIntT, verifier: VerifierT, fs: &FsIntT) -> Self {
         let priority = DataDirectoryPriority::default();
+        let dirs_file = DirsFile::load(&env, fs);
         Self {
             data_path: None,
             env,
             verifier,
-            priority
+            priority,
+            dirs_file,
+            container: None,
+            observer: None
+        }
+    }
+
+    /// The path `method` finds, verified but not yet checked to be absolute
+    fn using(&self, method: &DataDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        match method {
+            DataDirectoryResolutionMethods::FromFile => self.using_file(),
+            DataDirectoryResolutionMethods::FromXDG => self.using_xdg(),
+            DataDirectoryResolutionMethods::FromVoxels => self.using_voxels(),
+            DataDirectoryResolutionMethods::FromFHS => self.using_fhs(),
+            DataDirectoryResolutionMethods::FromContainerVolume => self.using_container_volume(),
+        }
+    }
+
+    /// The path `method` would yield before verification, even when nothing exists there yet
+    fn candidate(&self, method: &DataDirectoryResolutionMethods) -> Option<PathBuf> {
+        match method {
+            DataDirectoryResolutionMethods::FromFile => self.dirs_file.as_ref()
+                .and_then(|dirs_file| dirs_file.get(DirectoryKind::Data))
+                .map(Path::to_path_buf),
+            DataDirectoryResolutionMethods::FromXDG => self.env.get_path(EnvKey::XdgDataHome).ok().map(|path| normalize(&path)),
+            DataDirectoryResolutionMethods::FromVoxels => self.env.get_path(EnvKey::VoxelsDataHome).ok().map(|path| normalize(&path)),
+            DataDirectoryResolutionMethods::FromFHS => super::fhs_candidate(&self.env, ".local/share").ok(),
+            DataDirectoryResolutionMethods::FromContainerVolume => self.container.as_ref()
+                .and_then(|container| container.get(DirectoryKind::Data))
+                .map(Path::to_path_buf),
+        }
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when the directory's path is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub fn resolve_utf8(&self) -> Result<(camino::Utf8PathBuf, DataDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = self.resolve()?;
+
+        Ok((super::to_utf8(path.into_path_buf())?, method))
+    }
+
+    /// What each method in the priority yields before verification, so installers can choose
+    /// where to create a base directory on a machine where none exists yet
+    pub fn candidate_paths(&self) -> Vec<(PathBuf, DataDirectoryResolutionMethods)> {
+        self.priority.order.values()
+            .filter_map(|method| Some((self.candidate(method)?, *method)))
+            .collect()
+    }
+
+    /// `resolve`, but when no candidate verifies the specification's default, `${XDG_DATA_HOME}`
+    /// or else `~/.local/share`, is created and used, so fresh accounts and minimal containers still resolve
+    pub fn resolve_or_bootstrap<FsIntT: FsInt>(&self, fs: &FsIntT) -> Result<(AbsoluteDirPath, DataDirectoryResolutionMethods), BaseDirectoryError> {
+        if let Ok(resolved) = self.resolve() {
+            return Ok(resolved);
+        }
+
+        for method in [DataDirectoryResolutionMethods::FromXDG, DataDirectoryResolutionMethods::FromFHS] {
+            if let Some(path) = self.candidate(&method)
+                && let Ok(path) = AbsoluteDirPath::new(path) {
+                tracing::info!("bootstrapping {:?}, no data directory exists yet", path);
+
+                create_directory(fs, &path, CreateOptions::default())?;
+
+                observer::resolved(self.observer.as_ref(), DirectoryKind::Data, &method.source(), &path);
+
+                return Ok((path, method));
+            }
         }
+
+        Err(BaseDirectoryError::NoCandidate)
+    }
+
+    /// The writable path of `relative`, copying it out of a read-only system data directory on first use.
+    /// See `Overlay::materialize`
+    pub fn materialize<FsIntT: FsInt + 'static>(&self, fs: &FsIntT, relative: &RelPath) -> Result<PathBuf, BaseDirectoryError> {
+        self.overlay(fs)?.materialize(fs, relative)
+    }
+
+    /// `using` for `method`, reported to `observer`
+    fn attempt(&self, method: &DataDirectoryResolutionMethods) -> (DataDirectoryResolutionMethods, Option<PathBuf>) {
+        (*method, observer::attempt(self.observer.as_ref(), DirectoryKind::Data, &method.source(), || self.using(method)))
+    }
+
+    /// Prefer the method recorded in `record` and record the outcome, logging when the directory moved since last time
+    pub fn pin_resolution<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(AbsoluteDirPath, DataDirectoryResolutionMethods), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        if let Some(method) = resolutions.source(DirectoryKind::Data).and_then(|source| DataDirectoryResolutionMethods::from_source(source).ok()) {
+            self.priority.prefer_as(method, PrioritySource::Pinned);
+        }
+
+        let (path, method) = self.resolve()?;
+
+        if resolutions.update(DirectoryKind::Data, &method.source(), &path) {
+            resolutions.save(fs, record)?;
+        }
+
+        Ok((path, method))
+    }
+
+    /// Forget the recorded resolution and return to the default priority
+    pub fn unpin<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        resolutions.remove(DirectoryKind::Data);
+
+        self.priority = DataDirectoryPriority::default();
+
+        resolutions.save(fs, record)
     }
 }
 
 impl<EnvIntT: EnvInt, VerifierT: DataVerifier> DataDirectoryResolver for DataDirectory<EnvIntT, VerifierT> {
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey> {
+        let mut consumed: std::collections::BTreeSet<EnvKey> = self.priority.order.values()
+            .filter_map(DataDirectoryResolutionMethods::env_key)
+            .collect();
+
+        // read by `overlay` for the system wide directories
+        consumed.insert(EnvKey::XdgDataDirs);
+
+        consumed
+    }
+
     fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("HOME"))?;
+        let data_path = super::fhs_candidate(&self.env, ".local/share")?;
 
-        let data_path = path.join(".local/share/");
+        self.verifier.verify(&data_path).map_err(|error| error.at(&data_path))?;
 
-        if self.verifier.verify(&data_path) {
-            Ok(data_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        Ok(data_path)
     }
 
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let data_path: PathBuf = self.env.get_path_from_environment(String::from("XDG_DATA_HOME"))?;
+        let data_path: PathBuf = normalize(&self.env.get_path(EnvKey::XdgDataHome)?);
 
-        if self.verifier.verify(&data_path) {
-            Ok(data_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        self.verifier.verify(&data_path).map_err(|error| error.at(&data_path))?;
+
+        Ok(data_path)
     }
 
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("VOXELS_DATA_HOME"))?;
-
-        if self.verifier.verify(&path) {
-            Ok(path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
-    }
-
-    fn resolve(&self) -> Result<(PathBuf, DataDirectoryResolutionMethods), BaseDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            match self.priority.order[&index] {
-                DataDirectoryResolutionMethods::FromXDG => {
-                    let path = self.using_xdg();
-
-                    if path.is_ok() {
-                        return Ok((path?, DataDirectoryResolutionMethods::FromXDG));
-                    }
-                },
-                DataDirectoryResolutionMethods::FromVoxels => {
-                    let path = self.using_voxels();
-
-                    if path.is_ok() {
-                        return Ok((path?, DataDirectoryResolutionMethods::FromVoxels));
-                    }
-                },
-                DataDirectoryResolutionMethods::FromFHS => {
-                    let path = self.using_fhs();
-
-                    if path.is_ok() {
-                        return Ok((path?, DataDirectoryResolutionMethods::FromFHS));
-                    }
-                }
-            }
-        }
-        Err(BaseDirectoryError::NoCandidate)
+        let path: PathBuf = normalize(&self.env.get_path(EnvKey::VoxelsDataHome)?);
+
+        self.verifier.verify(&path).map_err(|error| error.at(&path))?;
+
+        Ok(path)
+    }
+
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.dirs_file.as_ref()
+            .and_then(|dirs_file| dirs_file.get(DirectoryKind::Data))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.container.as_ref()
+            .and_then(|container| container.get(DirectoryKind::Data))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn resolve(&self) -> Result<(AbsoluteDirPath, DataDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method)))
+            .next()
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        observer::resolved(self.observer.as_ref(), DirectoryKind::Data, &method.source(), &path);
+
+        Ok((path, method))
+    }
+
+    /// Every verified candidate in priority order, with candidates that are the same directory on disk removed
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, DataDirectoryResolutionMethods)> {
+        let candidates = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method))).collect();
+
+        dedup_candidates(fs, candidates)
+    }
+
+    /// `resolve` as the write directory, then every other candidate and the system directories to read from
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError> {
+        let system = system_dirs(&self.env, DirectoryKind::Data)
+            .into_iter()
+            .filter(|path| self.verifier.verify(path).is_ok())
+            .filter_map(|path| AbsoluteDirPath::new(path).ok());
+
+        ordering::build_overlay(self.resolve_candidates(fs), system, |_path| true)
+            .ok_or(BaseDirectoryError::NoCandidate)
     }
 }
 
-impl<EnvIntT: EnvInt, VerifierT: DataVerifier> Into<PathBuf> for DataDirectory<EnvIntT, VerifierT> {
-    fn into(self) -> PathBuf {
-        self.data_path.unwrap()
+impl<EnvIntT: EnvInt, VerifierT: DataVerifier> From<DataDirectory<EnvIntT, VerifierT>> for PathBuf {
+    fn from(directory: DataDirectory<EnvIntT, VerifierT>) -> Self {
+        directory.data_path.unwrap()
     }
 }
\ No newline at end of file
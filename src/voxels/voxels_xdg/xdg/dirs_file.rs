@@ -0,0 +1,265 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::pathcheck::PathCheckError;
+use crate::voxels::DirectoryKind;
+
+use super::{BaseDirectoryError, EnvInt, EnvKey, FsInt};
+
+/// Names a file pinning directories, taking priority over every other resolution method
+pub const VOXELS_DIRS_FILE_VARIABLE: &str = EnvKey::VoxelsDirsFile.name();
+
+/// Section of the dirs file holding per-application entries, `[apps."<rdn>"]`
+const APPLICATIONS_SECTION: &str = "apps";
+
+/// Explicit directory paths, read from the file named by `VOXELS_DIRS_FILE`:
+///
+/// ```toml
+/// config = "/pinned/config"
+/// data = "/pinned/data"
+///
+/// [apps."org.voxels.Client"]
+/// data = "/pinned/client"
+/// ```
+///
+/// Only this subset of TOML is understood: string values, comments and `[apps."<rdn>"]` headers.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DirsFile {
+    kinds: BTreeMap<DirectoryKind, PathBuf>,
+    applications: BTreeMap<String, BTreeMap<DirectoryKind, PathBuf>>,
+}
+
+/// The contents of the TOML basic string `value`, `None` when it is not exactly one
+fn unquote(value: &str) -> Option<String> {
+    let mut chars = value.strip_prefix('"')?.chars();
+    let mut unquoted = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return chars.as_str().is_empty().then_some(unquoted),
+            '\\' => {
+                let escaped = match chars.next()? {
+                    'b' => '\u{8}',
+                    't' => '\t',
+                    'n' => '\n',
+                    'f' => '\u{c}',
+                    'r' => '\r',
+                    '"' => '"',
+                    '\\' => '\\',
+                    'u' => char::from_u32(u32::from_str_radix(chars.by_ref().take(4).collect::<String>().as_str(), 16).ok()?)?,
+                    'U' => char::from_u32(u32::from_str_radix(chars.by_ref().take(8).collect::<String>().as_str(), 16).ok()?)?,
+                    _ => return None,
+                };
+
+                unquoted.push(escaped);
+            },
+            c => unquoted.push(c),
+        }
+    }
+
+    None
+}
+
+/// `value` as a TOML basic string, the inverse of `unquote`
+fn quote(value: &str) -> String {
+    let mut quoted = String::from('"');
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\t' => quoted.push_str("\\t"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+
+    quoted
+}
+
+impl DirsFile {
+    /// Relative paths, unknown kinds and malformed lines are ignored
+    pub fn parse(contents: &str) -> Self {
+        let mut dirs_file = DirsFile::default();
+
+        let mut application: Option<String> = None;
+        let mut in_unknown_section = false;
+
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                application = header.trim()
+                    .strip_prefix(APPLICATIONS_SECTION)
+                    .and_then(|rest| rest.strip_prefix('.'))
+                    .map(|rdn| unquote(rdn).unwrap_or_else(|| String::from(rdn)));
+
+                in_unknown_section = application.is_none();
+
+                continue;
+            }
+
+            if in_unknown_section {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let Some(kind) = DirectoryKind::from_name(key.trim()) else {
+                continue;
+            };
+
+            let Some(path) = unquote(value.trim()).map(PathBuf::from) else {
+                continue;
+            };
+
+            if !path.is_absolute() {
+                continue;
+            }
+
+            match &application {
+                Some(rdn) => {
+                    dirs_file.applications.entry(rdn.clone()).or_default().insert(kind, path);
+                },
+                None => {
+                    dirs_file.kinds.insert(kind, path);
+                }
+            }
+        }
+
+        dirs_file
+    }
+
+    /// Read the file named by `VOXELS_DIRS_FILE`, `None` when the variable is unset
+    pub fn from_environment<EnvIntT: EnvInt, FsIntT: FsInt>(env: &EnvIntT, fs: &FsIntT) -> Result<Option<Self>, BaseDirectoryError> {
+        let Ok(path) = env.get_path(EnvKey::VoxelsDirsFile) else {
+            return Ok(None);
+        };
+
+        Ok(Some(Self::parse(&fs.read_to_string(&path)?)))
+    }
+
+    /// As `from_environment` for resolvers being built, a file that cannot be read is logged and ignored
+    pub(crate) fn load<EnvIntT: EnvInt, FsIntT: FsInt>(env: &EnvIntT, fs: &FsIntT) -> Option<Self> {
+        match Self::from_environment(env, fs) {
+            Ok(dirs_file) => dirs_file,
+            Err(error) => {
+                tracing::warn!("Ignoring {}, it could not be read: {:?}", VOXELS_DIRS_FILE_VARIABLE, error);
+
+                None
+            },
+        }
+    }
+
+    /// The pinned base directory for `kind`
+    pub fn get(&self, kind: DirectoryKind) -> Option<&Path> {
+        self.kinds.get(&kind).map(PathBuf::as_path)
+    }
+
+    /// The pinned `kind` directory of the application `rdn`
+    pub fn get_for_application(&self, rdn: &str, kind: DirectoryKind) -> Option<&Path> {
+        self.applications.get(rdn)?.get(&kind).map(PathBuf::as_path)
+    }
+
+    /// Pin the `kind` directory of the application `rdn`, `path` must be absolute
+    pub fn set_for_application(&mut self, rdn: &str, kind: DirectoryKind, path: PathBuf) -> Result<(), PathCheckError> {
+        if !path.is_absolute() {
+            return Err(PathCheckError::NotAbsolute(path));
+        }
+
+        self.applications.entry(String::from(rdn)).or_default().insert(kind, path);
+
+        Ok(())
+    }
+
+    /// Inverse of parse, comments in the file that was parsed are not kept
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (kind, path) in &self.kinds {
+            out.push_str(&format!("{} = {}\n", kind.name(), quote(&path.to_string_lossy())));
+        }
+
+        for (rdn, kinds) in &self.applications {
+            out.push_str(&format!("\n[{}.{}]\n", APPLICATIONS_SECTION, quote(rdn)));
+
+            for (kind, path) in kinds {
+                out.push_str(&format!("{} = {}\n", kind.name(), quote(&path.to_string_lossy())));
+            }
+        }
+
+        out
+    }
+}
+
+#[test]
+fn test_parse_dirs_file() {
+    let dirs_file = DirsFile::parse("\
+# pinned for ci
+config = \"/pinned/config\"
+data = \"relative\"
+worlds = \"/pinned/worlds\"
+
+[apps.\"org.voxels.Client\"]
+data = \"/pinned/client\"
+
+[other]
+cache = \"/ignored\"
+");
+
+    assert_eq!(dirs_file.get(DirectoryKind::Config), Some(Path::new("/pinned/config")));
+    assert_eq!(dirs_file.get(DirectoryKind::Data), None);
+    assert_eq!(dirs_file.get(DirectoryKind::Cache), None);
+    assert_eq!(dirs_file.get_for_application("org.voxels.Client", DirectoryKind::Data), Some(Path::new("/pinned/client")));
+}
+
+#[test]
+fn test_render_dirs_file() {
+    let mut dirs_file = DirsFile::parse("config = \"/pinned/config\"\n");
+
+    dirs_file.set_for_application("org.voxels.Client", DirectoryKind::Data, PathBuf::from("/mnt/games/client")).unwrap();
+
+    assert!(dirs_file.set_for_application("org.voxels.Client", DirectoryKind::Data, PathBuf::from("games")).is_err());
+    assert_eq!(DirsFile::parse(&dirs_file.render()), dirs_file);
+}
+
+#[test]
+fn test_dirs_file_escapes_paths() {
+    let mut dirs_file = DirsFile::default();
+
+    dirs_file.set_for_application("org.voxels.Client", DirectoryKind::Data, PathBuf::from("/mnt/\"quoted\" \\ games\nnext")).unwrap();
+
+    let rendered = dirs_file.render();
+
+    assert!(rendered.contains(r#"data = "/mnt/\"quoted\" \\ games\nnext""#));
+    assert_eq!(DirsFile::parse(&rendered), dirs_file);
+    assert_eq!(DirsFile::parse(r#"config = "/pinned/\u00e9t\u00E9""#).get(DirectoryKind::Config), Some(Path::new("/pinned/été")));
+    assert_eq!(DirsFile::parse(r#"config = "/pinned/"config""#).get(DirectoryKind::Config), None);
+}
@@ -0,0 +1,151 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::voxels::DirectoryKind;
+
+use super::cache::CacheDirectoryResolver;
+use super::config::ConfigDirectoryResolver;
+use super::data::DataDirectoryResolver;
+use super::runtime::RuntimeDirectoryResolver;
+use super::state::StateDirectoryResolver;
+
+/// Shells `EnvVars::render` can write export statements for
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Shell {
+    /// Also correct for any POSIX shell
+    Bash,
+    Fish,
+    PowerShell,
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bash" | "sh" | "zsh" => Some(Shell::Bash),
+            "fish" => Some(Shell::Fish),
+            "powershell" | "pwsh" => Some(Shell::PowerShell),
+            _ => None,
+        }
+    }
+
+    /// A statement setting `variable` to `value` for the rest of the session and its children
+    pub fn export(&self, variable: &str, value: &str) -> String {
+        match self {
+            Shell::Bash => format!("export {}='{}'", variable, value.replace('\'', "'\\''")),
+            Shell::Fish => format!("set -gx {} '{}'", variable, value.replace('\\', "\\\\").replace('\'', "\\'")),
+            Shell::PowerShell => format!("$env:{} = '{}'", variable, value.replace('\'', "''")),
+        }
+    }
+}
+
+/// The `VOXELS_*_HOME` variables that make a child process resolve the same base directories as this one.
+/// Relocations and pins need no variables of their own, the child reads the same manifest and dirs file.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct EnvVars {
+    variables: BTreeMap<DirectoryKind, PathBuf>,
+}
+
+impl EnvVars {
+    /// Resolve every base directory, kinds without a candidate are left out
+    pub fn resolve<ConfigT, DataT, StateT, CacheT, RuntimeT>(config: &ConfigT, data: &DataT, state: &StateT, cache: &CacheT, runtime: &RuntimeT) -> Self
+    where
+        ConfigT: ConfigDirectoryResolver,
+        DataT: DataDirectoryResolver,
+        StateT: StateDirectoryResolver,
+        CacheT: CacheDirectoryResolver,
+        RuntimeT: RuntimeDirectoryResolver,
+    {
+        let mut env_vars = EnvVars::default();
+
+        let resolved = [
+            (DirectoryKind::Config, config.resolve().map(|(path, _how)| path)),
+            (DirectoryKind::Data, data.resolve().map(|(path, _how)| path)),
+            (DirectoryKind::State, state.resolve().map(|(path, _how)| path)),
+            (DirectoryKind::Cache, cache.resolve().map(|(path, _how)| path)),
+            (DirectoryKind::Runtime, runtime.resolve().map(|(path, _how)| path)),
+        ];
+
+        for (kind, path) in resolved {
+            if let Ok(path) = path {
+                env_vars.set(kind, path.into_path_buf());
+            }
+        }
+
+        env_vars
+    }
+
+    pub fn set(&mut self, kind: DirectoryKind, path: PathBuf) {
+        self.variables.insert(kind, path);
+    }
+
+    pub fn get(&self, kind: DirectoryKind) -> Option<&Path> {
+        self.variables.get(&kind).map(PathBuf::as_path)
+    }
+
+    /// `(variable, value)` pairs ordered by kind
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &Path)> {
+        self.variables.iter().map(|(kind, path)| (kind.home_variable(), path.as_path()))
+    }
+
+    /// Export statements for `shell`, one per line, so scripts can `eval "$(...)"` them
+    pub fn render(&self, shell: Shell) -> String {
+        let mut out = String::new();
+
+        for (variable, path) in self.iter() {
+            out.push_str(&shell.export(variable, &path.to_string_lossy()));
+            out.push('\n');
+        }
+
+        out
+    }
+
+    pub fn apply_to(&self, command: &mut Command) {
+        for (variable, path) in self.iter() {
+            command.env(variable, path);
+        }
+    }
+}
+
+#[test]
+fn test_apply_to() {
+    let mut env_vars = EnvVars::default();
+
+    env_vars.set(DirectoryKind::Config, PathBuf::from("/home/user/.config"));
+    env_vars.set(DirectoryKind::Cache, PathBuf::from("/var/cache"));
+
+    let mut command = Command::new("voxels-client");
+
+    env_vars.apply_to(&mut command);
+
+    let applied: Vec<_> = command.get_envs().map(|(key, value)| (key.to_owned(), value.map(|value| value.to_owned()))).collect();
+
+    assert_eq!(applied, vec![
+        ("VOXELS_CACHE_HOME".into(), Some("/var/cache".into())),
+        ("VOXELS_CONFIG_HOME".into(), Some("/home/user/.config".into())),
+    ]);
+}
+
+#[test]
+fn test_shell_export_quoting() {
+    assert_eq!(Shell::Bash.export("VOXELS_DATA_HOME", "/home/o'neil/data"), "export VOXELS_DATA_HOME='/home/o'\\''neil/data'");
+    assert_eq!(Shell::Fish.export("VOXELS_DATA_HOME", "/home/o'neil/data"), "set -gx VOXELS_DATA_HOME '/home/o\\'neil/data'");
+    assert_eq!(Shell::PowerShell.export("VOXELS_DATA_HOME", "C:\\Users\\o'neil"), "$env:VOXELS_DATA_HOME = 'C:\\Users\\o''neil'");
+}
@@ -18,13 +18,41 @@ pub mod config;
 pub mod data;
 pub mod state;
 pub mod runtime;
+pub mod cache;
+pub mod xdg_extras;
+pub mod user_dirs;
+pub mod bin;
+pub mod trash;
+pub mod dirs_file;
+pub mod container;
+pub mod overlay;
+pub mod ordering;
+pub mod observer;
+pub mod pinning;
+pub mod env_export;
+pub mod source;
+pub mod verifier;
 
-use crate::environment_variables::EnvInt;
+use crate::environment_variables::{EnvInt, EnvKey};
 use crate::filesystem::FsInt;
 
 #[derive(Debug)]
 pub enum BaseDirectoryError {
-    NoCandidate
+    NoCandidate,
+    Io(std::io::ErrorKind),
+    /// The candidate exists but is a file or other non-directory
+    NotADirectory(std::path::PathBuf),
+    /// The candidate is a symlink whose target does not exist
+    DanglingSymlink(std::path::PathBuf),
+    /// A UTF-8 path was asked for but the directory's path is not valid UTF-8
+    NotUtf8(std::path::PathBuf),
+    /// The verifier refused the candidate for a reason other than it not being a directory
+    Rejected(std::path::PathBuf, verifier::VerificationError),
+    /// The FHS fallback was asked for but the crate was built with `no-fhs`,
+    /// so only explicitly set variables are honoured
+    FhsDisabled,
+    /// A name or value that would be written into a directory could not be used safely
+    InvalidName(String),
 }
 
 impl From<std::env::VarError> for BaseDirectoryError {
@@ -33,3 +61,281 @@ impl From<std::env::VarError> for BaseDirectoryError {
     }
 }
 
+impl From<crate::pathcheck::PathCheckError> for BaseDirectoryError {
+    fn from(_: crate::pathcheck::PathCheckError) -> Self {
+        BaseDirectoryError::NoCandidate
+    }
+}
+
+impl From<std::io::Error> for BaseDirectoryError {
+    fn from(err: std::io::Error) -> Self {
+        BaseDirectoryError::Io(err.kind())
+    }
+}
+
+/// `path` as a `Utf8PathBuf`, for the `resolve_utf8` variants of the resolvers
+#[cfg(feature = "camino")]
+pub fn to_utf8(path: std::path::PathBuf) -> Result<camino::Utf8PathBuf, BaseDirectoryError> {
+    camino::Utf8PathBuf::from_path_buf(path).map_err(BaseDirectoryError::NotUtf8)
+}
+
+/// Check `path` is an existing directory, saying why when it is not.
+/// A missing path is `NoCandidate`, as nothing was found there.
+pub fn check_is_directory<FsIntT: FsInt>(fs: &FsIntT, path: &std::path::Path) -> Result<(), BaseDirectoryError> {
+    if !fs.exists(path) {
+        if fs.is_symlink(path) {
+            return Err(BaseDirectoryError::DanglingSymlink(path.to_path_buf()));
+        }
+
+        return Err(BaseDirectoryError::NoCandidate);
+    }
+
+    if !fs.is_directory(path) {
+        return Err(BaseDirectoryError::NotADirectory(path.to_path_buf()));
+    }
+
+    Ok(())
+}
+
+/// The FHS style guess `$HOME/<relative>`, used when no variable names the base directory.
+/// Built with `no-fhs` nothing is guessed and this is always `FhsDisabled`.
+#[cfg(not(feature = "no-fhs"))]
+pub fn fhs_candidate<EnvIntT: EnvInt>(env: &EnvIntT, relative: &str) -> Result<std::path::PathBuf, BaseDirectoryError> {
+    Ok(crate::pathcheck::normalize(&env.get_path(EnvKey::Home)?).join(relative))
+}
+
+/// Warns the first time an FHS guess is refused, `fhs_candidate` is called for every candidate listing
+#[cfg(feature = "no-fhs")]
+static FHS_DISABLED_WARNING: std::sync::Once = std::sync::Once::new();
+
+/// The FHS style guess `$HOME/<relative>`, used when no variable names the base directory.
+/// Built with `no-fhs` nothing is guessed and this is always `FhsDisabled`.
+#[cfg(feature = "no-fhs")]
+pub fn fhs_candidate<EnvIntT: EnvInt>(_env: &EnvIntT, _relative: &str) -> Result<std::path::PathBuf, BaseDirectoryError> {
+    FHS_DISABLED_WARNING.call_once(|| {
+        tracing::warn!("not falling back to directories under $HOME, the FHS fallback is disabled by the no-fhs feature and only explicitly set variables are honoured");
+    });
+
+    Err(BaseDirectoryError::FhsDisabled)
+}
+
+/// Package stores that are mounted read only, like Nix's and Guix's
+pub const READ_ONLY_STORES: [&str; 2] = ["/nix/store", "/gnu/store"];
+
+/// Whether `path` lies within one of `READ_ONLY_STORES`, where nothing may be written
+pub fn is_read_only_store(path: &std::path::Path) -> bool {
+    READ_ONLY_STORES.iter().any(|store| path.starts_with(store))
+}
+
+/// Opt-in permission rules for the default verifiers, for hosts shared between users
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct VerifierOptions {
+    /// Reject candidates any user can write to unless the sticky bit is set, as on `/tmp`
+    pub reject_world_writable: bool,
+    /// Log a warning for every ancestor of a candidate that its group can write to
+    pub warn_group_writable_parents: bool,
+}
+
+const WORLD_WRITABLE: u32 = 0o002;
+
+const GROUP_WRITABLE: u32 = 0o020;
+
+const STICKY: u32 = 0o1000;
+
+/// Apply `options` to `path`, returning false when it must be rejected.
+/// Paths whose mode cannot be read are not rejected.
+pub fn check_permissions<FsIntT: FsInt>(fs: &FsIntT, path: &std::path::Path, options: VerifierOptions) -> bool {
+    if options.reject_world_writable
+        && let Ok(mode) = fs.mode(path)
+        && mode & WORLD_WRITABLE != 0
+        && mode & STICKY == 0 {
+        tracing::warn!("rejecting {:?}, it is world writable without the sticky bit", path);
+        return false;
+    }
+
+    if options.warn_group_writable_parents {
+        for ancestor in path.ancestors().skip(1) {
+            if let Ok(mode) = fs.mode(ancestor)
+                && mode & GROUP_WRITABLE != 0 {
+                tracing::warn!("{:?} is group writable, which lets other users replace {:?}", ancestor, path);
+            }
+        }
+    }
+
+    true
+}
+
+/// The checks the default verifiers make, for async callers that must not block on the filesystem
+#[cfg(feature = "dbus")]
+pub async fn verify_async<AsyncFsIntT: crate::async_filesystem::AsyncFsInt>(fs: &AsyncFsIntT, path: &std::path::Path, options: VerifierOptions) -> Result<(), verifier::VerificationError> {
+    crate::pathcheck::check(path).map_err(|_| verifier::VerificationError::InvalidPath)?;
+
+    if !fs.exists(path).await {
+        return Err(verifier::VerificationError::NotFound);
+    }
+
+    if !fs.is_directory(path).await {
+        return Err(verifier::VerificationError::NotADirectory);
+    }
+
+    if !path.is_absolute() {
+        return Err(verifier::VerificationError::NotAbsolute);
+    }
+
+    if options.reject_world_writable
+        && let Ok(mode) = fs.mode(path).await
+        && mode & WORLD_WRITABLE != 0
+        && mode & STICKY == 0 {
+        tracing::warn!("rejecting {:?}, it is world writable without the sticky bit", path);
+        return Err(verifier::VerificationError::InsecurePermissions);
+    }
+
+    if options.warn_group_writable_parents {
+        for ancestor in path.ancestors().skip(1) {
+            if let Ok(mode) = fs.mode(ancestor).await
+                && mode & GROUP_WRITABLE != 0 {
+                tracing::warn!("{:?} is group writable, which lets other users replace {:?}", ancestor, path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_check_permissions() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_mode()
+        .returning(|path| Ok(match path.to_str().unwrap() {
+            "/tmp" => 0o1777,
+            "/shared" => 0o777,
+            _ => 0o775,
+        }));
+
+    let strict = VerifierOptions { reject_world_writable: true, warn_group_writable_parents: true };
+
+    assert!(check_permissions(&fs, std::path::Path::new("/tmp"), strict));
+    assert!(!check_permissions(&fs, std::path::Path::new("/shared"), strict));
+    assert!(check_permissions(&fs, std::path::Path::new("/shared"), VerifierOptions::default()));
+}
+
+#[cfg(feature = "dbus")]
+#[tokio::test]
+async fn test_verify_async() {
+    use verifier::VerificationError;
+
+    let mut fs = crate::async_filesystem::MockAsyncFsInt::new();
+
+    fs.expect_exists()
+        .returning(|path| path != std::path::Path::new("/missing"));
+
+    fs.expect_is_directory()
+        .returning(|path| path != std::path::Path::new("/file"));
+
+    fs.expect_mode()
+        .returning(|path| Ok(if path == std::path::Path::new("/shared") { 0o777 } else { 0o755 }));
+
+    let strict = VerifierOptions { reject_world_writable: true, warn_group_writable_parents: false };
+
+    assert_eq!(verify_async(&fs, std::path::Path::new("/home"), strict).await, Ok(()));
+    assert_eq!(verify_async(&fs, std::path::Path::new(&"/long".repeat(1024)), strict).await, Err(VerificationError::InvalidPath));
+    assert_eq!(verify_async(&fs, std::path::Path::new("/missing"), strict).await, Err(VerificationError::NotFound));
+    assert_eq!(verify_async(&fs, std::path::Path::new("/file"), strict).await, Err(VerificationError::NotADirectory));
+    assert_eq!(verify_async(&fs, std::path::Path::new("/shared"), strict).await, Err(VerificationError::InsecurePermissions));
+}
+
+#[test]
+fn test_check_is_directory() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_exists()
+        .returning(|path| path != std::path::Path::new("/dangling") && path != std::path::Path::new("/missing"));
+
+    fs.expect_is_symlink()
+        .returning(|path| path == std::path::Path::new("/dangling"));
+
+    fs.expect_is_directory()
+        .returning(|path| path != std::path::Path::new("/file"));
+
+    assert!(check_is_directory(&fs, std::path::Path::new("/home")).is_ok());
+    assert!(matches!(check_is_directory(&fs, std::path::Path::new("/file")), Err(BaseDirectoryError::NotADirectory(_))));
+    assert!(matches!(check_is_directory(&fs, std::path::Path::new("/dangling")), Err(BaseDirectoryError::DanglingSymlink(_))));
+    assert!(matches!(check_is_directory(&fs, std::path::Path::new("/missing")), Err(BaseDirectoryError::NoCandidate)));
+}
+
+#[test]
+fn test_default_verifiers_reject_files() {
+    use config::ConfigVerifier;
+    use data::DataVerifier;
+    use state::StateVerifier;
+    use cache::CacheVerifier;
+    use runtime::RuntimeVerifier;
+
+    fn file_fs() -> crate::filesystem::MockFsInt {
+        let mut fs = crate::filesystem::MockFsInt::new();
+
+        fs.expect_exists()
+            .returning(|_| true);
+
+        fs.expect_is_directory()
+            .returning(|_| false);
+
+        fs
+    }
+
+    let path = std::path::Path::new("/home/user/not-a-directory");
+
+    assert_eq!(config::DefaultConfigVerifier::new(file_fs()).verify(path), Err(verifier::VerificationError::NotADirectory));
+    assert!(data::DefaultDataVerifier::new(file_fs()).verify(path).is_err());
+    assert!(state::DefaultStateVerifier::new(file_fs()).verify(path).is_err());
+    assert!(cache::DefaultCacheVerifier::new(file_fs()).verify(path).is_err());
+    assert!(runtime::DefaultRuntimeVerifier::new(file_fs()).verify(path).is_err());
+}
+
+/// Drop candidates that are the same directory as an earlier one, such as `VOXELS_CONFIG_HOME`
+/// pointing at `XDG_CONFIG_HOME`. Identity is the device and inode, falling back to the path
+/// when the filesystem cannot report one.
+pub fn dedup_candidates<FsIntT: FsInt, PathT: AsRef<std::path::Path> + PartialEq, MethodT>(fs: &FsIntT, candidates: Vec<(PathT, MethodT)>) -> Vec<(PathT, MethodT)> {
+    ordering::dedup(candidates, |path| fs.file_id(path.as_ref()).ok())
+}
+
+#[test]
+fn test_dedup_candidates() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_file_id()
+        .returning(|path| {
+            if path.starts_with("/unsupported") {
+                Err(std::io::Error::from(std::io::ErrorKind::Unsupported))
+            } else if path.starts_with("/home/user/.config") || path.starts_with("/voxels/config") {
+                Ok((1, 100))
+            } else {
+                Ok((1, 200))
+            }
+        });
+
+    let candidates = vec![
+        (std::path::PathBuf::from("/voxels/config"), 0),
+        (std::path::PathBuf::from("/home/user/.config"), 1),
+        (std::path::PathBuf::from("/etc/xdg"), 2),
+        (std::path::PathBuf::from("/unsupported"), 3),
+        (std::path::PathBuf::from("/unsupported"), 4),
+    ];
+
+    let deduped: Vec<i32> = dedup_candidates(&fs, candidates).into_iter().map(|(_, method)| method).collect();
+
+    assert_eq!(deduped, vec![0, 2, 3]);
+}
+
+#[cfg(all(feature = "camino", unix))]
+#[test]
+fn test_to_utf8() {
+    use std::os::unix::ffi::OsStrExt;
+
+    assert_eq!(to_utf8(std::path::PathBuf::from("/home/user/.config")).unwrap(), camino::Utf8PathBuf::from("/home/user/.config"));
+
+    let invalid = std::path::PathBuf::from(std::ffi::OsStr::from_bytes(b"/home/\xff"));
+
+    assert!(matches!(to_utf8(invalid), Err(BaseDirectoryError::NotUtf8(_))));
+}
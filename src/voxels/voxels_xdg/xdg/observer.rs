@@ -0,0 +1,152 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::voxels::DirectoryKind;
+
+use super::BaseDirectoryError;
+use super::source::ResolutionSource;
+use super::verifier::VerificationError;
+
+/// Why a resolution method's candidate was passed over
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RejectionReason {
+    /// The method found nothing, or the verifier refused what it found
+    NotFound,
+    NotAbsolute,
+    /// Inside one of `READ_ONLY_STORES`, so it cannot be written to
+    ReadOnlyStore,
+    /// A file or other non-directory is in the way
+    NotADirectory,
+    DanglingSymlink,
+    /// The verifier refused it for another reason
+    Refused(VerificationError),
+}
+
+/// Progress of a resolver as it works through its priority, for installers showing live diagnostics.
+/// Methods are identified by their `ResolutionSource`, as in `ResolutionRecord`, so one observer serves every kind.
+pub trait ResolutionObserver {
+    fn on_candidate_tried(&self, _kind: DirectoryKind, _source: &ResolutionSource) {}
+
+    fn on_candidate_rejected(&self, _kind: DirectoryKind, _source: &ResolutionSource, _reason: RejectionReason) {}
+
+    fn on_resolved(&self, _kind: DirectoryKind, _source: &ResolutionSource, _path: &Path) {}
+}
+
+/// Shared so one observer can watch several resolvers
+pub type SharedObserver = std::sync::Arc<dyn ResolutionObserver + Send + Sync>;
+
+/// Run `lookup` for `source`, telling `observer` it was tried and whether it was rejected
+pub(crate) fn attempt(observer: Option<&SharedObserver>, kind: DirectoryKind, source: &ResolutionSource, lookup: impl FnOnce() -> Result<PathBuf, BaseDirectoryError>) -> Option<PathBuf> {
+    if let Some(observer) = observer {
+        observer.on_candidate_tried(kind, source);
+    }
+
+    let found = lookup();
+
+    let failure = match &found {
+        Err(BaseDirectoryError::NotADirectory(_)) => Some(RejectionReason::NotADirectory),
+        Err(BaseDirectoryError::DanglingSymlink(_)) => Some(RejectionReason::DanglingSymlink),
+        Err(BaseDirectoryError::Rejected(_, reason)) => Some(RejectionReason::Refused(*reason)),
+        _ => None,
+    };
+
+    let path = found.ok();
+
+    let rejection = match &path {
+        None => Some(failure.unwrap_or(RejectionReason::NotFound)),
+        Some(path) if !path.is_absolute() => Some(RejectionReason::NotAbsolute),
+        Some(_) => None,
+    };
+
+    if let (Some(observer), Some(reason)) = (observer, rejection) {
+        observer.on_candidate_rejected(kind, source, reason);
+    }
+
+    path
+}
+
+pub(crate) fn rejected(observer: Option<&SharedObserver>, kind: DirectoryKind, source: &ResolutionSource, reason: RejectionReason) {
+    if let Some(observer) = observer {
+        observer.on_candidate_rejected(kind, source, reason);
+    }
+}
+
+pub(crate) fn resolved(observer: Option<&SharedObserver>, kind: DirectoryKind, source: &ResolutionSource, path: &Path) {
+    if let Some(observer) = observer {
+        observer.on_resolved(kind, source, path);
+    }
+}
+
+#[test]
+fn test_observer_sees_every_attempt() {
+    use std::sync::{Arc, Mutex};
+
+    use super::config::{ConfigDirectory, ConfigDirectoryResolver, MockConfigVerifier};
+    use crate::environment_variables::{EnvKey, MockEnvInt};
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ResolutionObserver for Recorder {
+        fn on_candidate_tried(&self, _kind: DirectoryKind, source: &ResolutionSource) {
+            self.events.lock().unwrap().push(format!("tried {}", source));
+        }
+
+        fn on_candidate_rejected(&self, _kind: DirectoryKind, source: &ResolutionSource, reason: RejectionReason) {
+            self.events.lock().unwrap().push(format!("rejected {} {:?}", source, reason));
+        }
+
+        fn on_resolved(&self, kind: DirectoryKind, source: &ResolutionSource, path: &Path) {
+            self.events.lock().unwrap().push(format!("resolved {} {} {}", kind.name(), source, path.display()));
+        }
+    }
+
+    let mut env = MockEnvInt::new();
+    let mut verifier = MockConfigVerifier::new();
+
+    env.expect_get_path()
+        .returning(|key| match key {
+            EnvKey::VoxelsConfigHome => Ok(PathBuf::from("/nix/store/abc-config")),
+            EnvKey::XdgConfigHome => Ok(PathBuf::from("/home/user/.config")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    verifier.expect_verify().returning(|_| Ok(()));
+
+    let recorder = Arc::new(Recorder::default());
+
+    let mut config = ConfigDirectory::new(env, verifier);
+
+    config.observer = Some(recorder.clone());
+
+    assert_eq!(config.resolve().unwrap().0, PathBuf::from("/home/user/.config"));
+
+    assert_eq!(*recorder.events.lock().unwrap(), vec![
+        String::from("tried file"),
+        String::from("rejected file NotFound"),
+        String::from("tried voxels"),
+        String::from("rejected voxels ReadOnlyStore"),
+        String::from("tried container-volume"),
+        String::from("rejected container-volume NotFound"),
+        String::from("tried xdg"),
+        String::from("resolved config xdg /home/user/.config"),
+    ]);
+}
@@ -0,0 +1,263 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::pathcheck::{normalize, AbsoluteDirPath};
+
+use super::overlay::Overlay;
+
+/// Move `method` to the front of `order`, keeping the relative order of the rest. Methods not in `order` are ignored.
+pub fn prefer<MethodT: PartialEq>(order: &mut BTreeMap<usize, MethodT>, method: MethodT) {
+    if !order.values().any(|existing| *existing == method) {
+        return;
+    }
+
+    let methods: Vec<MethodT> = std::mem::take(order)
+        .into_values()
+        .filter(|existing| *existing != method)
+        .collect();
+
+    *order = std::iter::once(method).chain(methods).enumerate().collect();
+}
+
+/// Where a method's place in a priority came from, shown by `describe`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum PrioritySource {
+    #[default]
+    Default,
+    /// Moved to the front with `prefer`
+    Preferred,
+    /// Moved to the front because a `ResolutionRecord` pinned it
+    Pinned,
+    /// Placed with `set_all`
+    Custom,
+}
+
+impl PrioritySource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrioritySource::Default => "default",
+            PrioritySource::Preferred => "preferred",
+            PrioritySource::Pinned => "pinned",
+            PrioritySource::Custom => "custom",
+        }
+    }
+}
+
+/// The source of each method whose place was changed, every other method keeps `PrioritySource::Default`
+pub type PrioritySources<MethodT> = BTreeMap<MethodT, PrioritySource>;
+
+/// `methods` as a priority order, dropping any method already placed earlier
+pub fn order_from<MethodT: PartialEq>(methods: Vec<MethodT>) -> BTreeMap<usize, MethodT> {
+    let mut unique: Vec<MethodT> = Vec::new();
+
+    for method in methods {
+        if !unique.contains(&method) {
+            unique.push(method);
+        }
+    }
+
+    unique.into_iter().enumerate().collect()
+}
+
+/// Every method in `order` marked as placed by the caller
+pub fn custom_sources<MethodT: Ord + Clone>(order: &BTreeMap<usize, MethodT>) -> PrioritySources<MethodT> {
+    order.values().map(|method| (method.clone(), PrioritySource::Custom)).collect()
+}
+
+/// One numbered line per method in `order` with where its place came from, such as `1. xdg (pinned)`
+pub fn describe<MethodT: Ord>(order: &BTreeMap<usize, MethodT>, sources: &PrioritySources<MethodT>, name: impl Fn(&MethodT) -> &'static str) -> String {
+    order.values()
+        .enumerate()
+        .map(|(index, method)| format!("{}. {} ({})\n", index + 1, name(method), sources.get(method).copied().unwrap_or_default().name()))
+        .collect()
+}
+
+/// The methods of `order` separated by `>`, as priorities are displayed
+pub fn write_order<MethodT>(f: &mut fmt::Formatter<'_>, order: &BTreeMap<usize, MethodT>, name: impl Fn(&MethodT) -> &'static str) -> fmt::Result {
+    let names: Vec<&'static str> = order.values().map(name).collect();
+
+    write!(f, "{}", names.join(" > "))
+}
+
+/// The attempts that produced an absolute path, in the order given.
+/// Lazy, so a resolver mapping its priority through a lookup only looks up as far as the caller consumes.
+pub fn absolute_candidates<MethodT, AttemptsT>(attempts: AttemptsT) -> impl Iterator<Item = (AbsoluteDirPath, MethodT)>
+where
+    AttemptsT: IntoIterator<Item = (MethodT, Option<PathBuf>)>,
+{
+    attempts.into_iter()
+        .filter_map(|(method, path)| Some((AbsoluteDirPath::new(path?).ok()?, method)))
+}
+
+/// Keep the first of every group of candidates sharing an identity, as reported by `identity`.
+/// Candidates without an identity fall back to comparing paths.
+pub fn dedup<PathT: PartialEq, MethodT, IdT: PartialEq>(candidates: Vec<(PathT, MethodT)>, identity: impl Fn(&PathT) -> Option<IdT>) -> Vec<(PathT, MethodT)> {
+    let mut seen_ids = Vec::new();
+    let mut deduped: Vec<(PathT, MethodT)> = Vec::new();
+
+    for (path, method) in candidates {
+        match identity(&path) {
+            Some(id) => {
+                if seen_ids.contains(&id) {
+                    continue;
+                }
+
+                seen_ids.push(id);
+            },
+            None => {
+                if deduped.iter().any(|(seen, _)| *seen == path) {
+                    continue;
+                }
+            }
+        }
+
+        deduped.push((path, method));
+    }
+
+    deduped
+}
+
+/// Split a `XDG_*_DIRS` style `value`, using `default` when it is missing or empty.
+/// Relative entries are ignored as the specification requires.
+pub fn split_search_path(value: Option<&OsStr>, default: &str) -> Vec<PathBuf> {
+    let value = value
+        .filter(|value| !value.is_empty())
+        .unwrap_or(OsStr::new(default));
+
+    std::env::split_paths(value)
+        .filter(|path| path.is_absolute())
+        .map(|path| normalize(&path))
+        .collect()
+}
+
+/// The first of `candidates` that `writable` accepts to write to, then every candidate and `system` to read from.
+/// `None` when no candidate is writable.
+pub fn build_overlay<MethodT>(candidates: Vec<(AbsoluteDirPath, MethodT)>, system: impl IntoIterator<Item = AbsoluteDirPath>, writable: impl Fn(&Path) -> bool) -> Option<Overlay> {
+    let write = candidates.iter()
+        .find(|(path, _how)| writable(path))
+        .map(|(path, _how)| path.clone())?;
+
+    let mut overlay = Overlay::new(write);
+
+    for (path, _how) in candidates {
+        overlay.add_read_dir(path);
+    }
+
+    for path in system {
+        overlay.add_read_dir(path);
+    }
+
+    Some(overlay)
+}
+
+#[test]
+fn test_prefer() {
+    let mut order: BTreeMap<usize, char> = ['a', 'b', 'c', 'd'].into_iter().enumerate().collect();
+
+    prefer(&mut order, 'c');
+    assert_eq!(order.into_values().collect::<String>(), "cabd");
+
+    // every starting position of every method ends up first with the rest untouched
+    for preferred in ['a', 'b', 'c', 'd'] {
+        let mut order: BTreeMap<usize, char> = ['a', 'b', 'c', 'd'].into_iter().enumerate().collect();
+
+        prefer(&mut order, preferred);
+
+        let expected: String = std::iter::once(preferred).chain("abcd".chars().filter(|c| *c != preferred)).collect();
+
+        assert_eq!(order.keys().copied().collect::<Vec<usize>>(), vec![0, 1, 2, 3]);
+        assert_eq!(order.into_values().collect::<String>(), expected);
+    }
+
+    let mut order: BTreeMap<usize, char> = ['a', 'b'].into_iter().enumerate().collect();
+
+    prefer(&mut order, 'z');
+    assert_eq!(order.into_values().collect::<String>(), "ab");
+}
+
+#[test]
+fn test_absolute_candidates() {
+    let attempts = vec![
+        ('a', None),
+        ('b', Some(PathBuf::from("relative"))),
+        ('c', Some(PathBuf::from("/home/user/.config/"))),
+        ('d', Some(PathBuf::from("/etc/xdg"))),
+    ];
+
+    let candidates: Vec<(AbsoluteDirPath, char)> = absolute_candidates(attempts).collect();
+
+    assert_eq!(candidates, vec![
+        (AbsoluteDirPath::new(PathBuf::from("/home/user/.config")).unwrap(), 'c'),
+        (AbsoluteDirPath::new(PathBuf::from("/etc/xdg")).unwrap(), 'd'),
+    ]);
+}
+
+#[test]
+fn test_dedup() {
+    let candidates = vec![("/a", 0), ("/b", 1), ("/c", 2), ("/c", 3), ("/d", 4)];
+
+    let deduped: Vec<i32> = dedup(candidates, |path| match *path {
+        "/a" | "/b" => Some(1),
+        "/d" => Some(2),
+        _ => None,
+    }).into_iter().map(|(_, method)| method).collect();
+
+    assert_eq!(deduped, vec![0, 2, 4]);
+}
+
+#[test]
+fn test_split_search_path() {
+    assert_eq!(split_search_path(Some(OsStr::new("/opt/share/:relative:/usr/share")), "/default"), vec![PathBuf::from("/opt/share"), PathBuf::from("/usr/share")]);
+    assert_eq!(split_search_path(Some(OsStr::new("")), "/default"), vec![PathBuf::from("/default")]);
+    assert_eq!(split_search_path(None, "/default"), vec![PathBuf::from("/default")]);
+}
+
+#[test]
+fn test_build_overlay() {
+    let store = AbsoluteDirPath::new(PathBuf::from("/nix/store/abc-config")).unwrap();
+    let home = AbsoluteDirPath::new(PathBuf::from("/home/user/.config")).unwrap();
+    let system = AbsoluteDirPath::new(PathBuf::from("/etc/xdg")).unwrap();
+
+    let overlay = build_overlay(vec![(store.clone(), 0), (home.clone(), 1)], vec![system.clone(), home.clone()], |path| !super::is_read_only_store(path)).unwrap();
+
+    assert_eq!(overlay.write_dir(), &home);
+    assert_eq!(overlay.read_dirs(), &[home, store.clone(), system]);
+
+    assert!(build_overlay(vec![(store, 0)], Vec::new(), |path| !super::is_read_only_store(path)).is_none());
+}
+
+#[test]
+fn test_describe() {
+    let order: BTreeMap<usize, &'static str> = ["xdg", "fhs"].into_iter().enumerate().collect();
+
+    let mut sources = PrioritySources::new();
+    sources.insert("xdg", PrioritySource::Pinned);
+
+    assert_eq!(describe(&order, &sources, |method| method), "1. xdg (pinned)\n2. fhs (default)\n");
+}
+
+#[test]
+fn test_order_from() {
+    let order = order_from(vec!["xdg", "fhs", "xdg"]);
+
+    assert_eq!(order.into_values().collect::<Vec<_>>(), vec!["xdg", "fhs"]);
+}
@@ -0,0 +1,156 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::filesystem::{copy_directory, create_directory, CreateOptions, FsInt};
+use crate::pathcheck::{AbsoluteDirPath, RelPath};
+use crate::voxels::DirectoryKind;
+
+use super::{BaseDirectoryError, EnvInt, EnvKey};
+use super::ordering::split_search_path;
+
+/// Used when `XDG_CONFIG_DIRS` is unset or empty
+pub const DEFAULT_XDG_CONFIG_DIRS: &str = "/etc/xdg";
+
+/// Used when `XDG_DATA_DIRS` is unset or empty
+pub const DEFAULT_XDG_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+
+/// The system wide directories searched after the user's own for `kind`, most important first.
+/// Only config and data have them. Relative entries are ignored as the specification requires.
+pub fn system_dirs<EnvIntT: EnvInt>(env: &EnvIntT, kind: DirectoryKind) -> Vec<PathBuf> {
+    let (variable, default) = match kind {
+        DirectoryKind::Config => (EnvKey::XdgConfigDirs, DEFAULT_XDG_CONFIG_DIRS),
+        DirectoryKind::Data => (EnvKey::XdgDataDirs, DEFAULT_XDG_DATA_DIRS),
+        _ => return Vec::new(),
+    };
+
+    let value = env.get_path(variable).ok();
+
+    split_search_path(value.as_deref().map(Path::as_os_str), default)
+}
+
+/// One directory to write to and an ordered list to read from, the write directory first.
+/// Later read directories are overridden by earlier ones, as with `XDG_CONFIG_DIRS`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Overlay {
+    read: Vec<AbsoluteDirPath>,
+}
+
+impl Overlay {
+    pub fn new(write: AbsoluteDirPath) -> Self {
+        Self {
+            read: vec![write]
+        }
+    }
+
+    /// Append `path` to the read directories unless it is already one of them
+    pub fn add_read_dir(&mut self, path: AbsoluteDirPath) {
+        if !self.read.contains(&path) {
+            self.read.push(path);
+        }
+    }
+
+    /// The single directory new files are created in
+    pub fn write_dir(&self) -> &AbsoluteDirPath {
+        &self.read[0]
+    }
+
+    pub fn read_dirs(&self) -> &[AbsoluteDirPath] {
+        &self.read
+    }
+
+    /// `relative` in the first read directory that has it
+    pub fn find<FsIntT: FsInt>(&self, fs: &FsIntT, relative: &RelPath) -> Option<PathBuf> {
+        self.read.iter()
+            .map(|dir| dir.join(relative))
+            .find(|path| fs.exists(path))
+    }
+
+    /// `relative` in the write directory, ready to be written.
+    /// When only a read directory has it, it is copied into the write directory first so edits start from the shipped defaults.
+    pub fn materialize<FsIntT: FsInt>(&self, fs: &FsIntT, relative: &RelPath) -> Result<PathBuf, BaseDirectoryError> {
+        let destination = self.write_dir().join(relative);
+
+        if fs.exists(&destination) {
+            return Ok(destination);
+        }
+
+        if let Some(parent) = destination.parent() {
+            create_directory(fs, parent, CreateOptions::default())?;
+        }
+
+        if let Some(source) = self.find(fs, relative) {
+            tracing::debug!("copying {:?} to {:?} before first write", source, destination);
+
+            if fs.is_directory(&source) {
+                copy_directory(fs, &source, &destination)?;
+            } else {
+                fs.write_bytes(&destination, &fs.read(&source)?)?;
+            }
+        }
+
+        Ok(destination)
+    }
+}
+
+#[test]
+fn test_system_dirs() {
+    let mut env = crate::environment_variables::MockEnvInt::new();
+
+    env.expect_get_path()
+        .returning(|name| match name {
+            EnvKey::XdgDataDirs => Ok(PathBuf::from("/var/lib/flatpak/exports/share:relative:/usr/share/")),
+            _ => Err(std::env::VarError::NotPresent),
+        });
+
+    assert_eq!(system_dirs(&env, DirectoryKind::Data), vec![PathBuf::from("/var/lib/flatpak/exports/share"), PathBuf::from("/usr/share")]);
+    assert_eq!(system_dirs(&env, DirectoryKind::Config), vec![PathBuf::from("/etc/xdg")]);
+    assert!(system_dirs(&env, DirectoryKind::Cache).is_empty());
+}
+
+#[test]
+fn test_materialize() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    // the shipped default exists only in the system directory, the user already has their own `edited.toml`
+    fs.expect_exists()
+        .returning(|path| path == Path::new("/usr/share/mods/list.toml") || path == Path::new("/home/user/.local/share/edited.toml"));
+
+    fs.expect_create_dir_all()
+        .returning(|_| Ok(()));
+
+    fs.expect_is_directory()
+        .returning(|path| !path.ends_with("list.toml"));
+
+    fs.expect_read()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/usr/share/mods/list.toml")))
+        .return_once(|_| Ok(b"default".to_vec()));
+
+    fs.expect_write_bytes()
+        .once()
+        .withf(|path, contents| path == Path::new("/home/user/.local/share/mods/list.toml") && contents == b"default")
+        .return_once(|_, _| Ok(()));
+
+    let mut overlay = Overlay::new(AbsoluteDirPath::new(PathBuf::from("/home/user/.local/share")).unwrap());
+    overlay.add_read_dir(AbsoluteDirPath::new(PathBuf::from("/usr/share")).unwrap());
+
+    assert_eq!(overlay.materialize(&fs, &RelPath::new("mods/list.toml").unwrap()).unwrap(), PathBuf::from("/home/user/.local/share/mods/list.toml"));
+    assert_eq!(overlay.materialize(&fs, &RelPath::new("edited.toml").unwrap()).unwrap(), PathBuf::from("/home/user/.local/share/edited.toml"));
+    assert_eq!(overlay.materialize(&fs, &RelPath::new("new.toml").unwrap()).unwrap(), PathBuf::from("/home/user/.local/share/new.toml"));
+}
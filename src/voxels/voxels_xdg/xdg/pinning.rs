@@ -0,0 +1,119 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::voxels::DirectoryKind;
+
+use super::{BaseDirectoryError, FsInt};
+use super::source::ResolutionSource;
+
+/// Suggested name for the record, kept in the state directory
+pub const RESOLUTION_FILE_NAME: &str = "resolution";
+
+/// The source and path each kind last resolved with, one `<kind> <source> <path>` line per kind
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ResolutionRecord {
+    entries: BTreeMap<DirectoryKind, (ResolutionSource, PathBuf)>,
+}
+
+impl ResolutionRecord {
+    /// Malformed lines and unknown kinds are ignored
+    pub fn parse(contents: &str) -> Self {
+        let mut record = ResolutionRecord::default();
+
+        for line in contents.lines() {
+            let mut fields = line.splitn(3, ' ');
+
+            let (Some(kind), Some(source), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+
+            if let Some(kind) = DirectoryKind::from_name(kind) {
+                record.entries.insert(kind, (ResolutionSource::from_name(source), PathBuf::from(path)));
+            }
+        }
+
+        record
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (kind, (source, path)) in &self.entries {
+            out.push_str(&format!("{} {} {}\n", kind.name(), source, path.to_string_lossy()));
+        }
+
+        out
+    }
+
+    /// A missing or unreadable record is empty
+    pub fn load<FsIntT: FsInt>(fs: &FsIntT, path: &Path) -> Self {
+        fs.read_to_string(path)
+            .map(|contents| Self::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    pub fn save<FsIntT: FsInt>(&self, fs: &FsIntT, path: &Path) -> Result<(), BaseDirectoryError> {
+        if let Some(parent) = path.parent() {
+            fs.create_dir_all(parent)?;
+        }
+
+        Ok(fs.write(path, &self.render())?)
+    }
+
+    pub fn source(&self, kind: DirectoryKind) -> Option<&ResolutionSource> {
+        self.entries.get(&kind).map(|(source, _path)| source)
+    }
+
+    pub fn path(&self, kind: DirectoryKind) -> Option<&Path> {
+        self.entries.get(&kind).map(|(_source, path)| path.as_path())
+    }
+
+    /// Record that `kind` resolved to `path` from `source`, returning whether anything changed.
+    /// A change from an earlier record is logged, as settings in the old directory will seem to disappear.
+    pub fn update(&mut self, kind: DirectoryKind, source: &ResolutionSource, path: &Path) -> bool {
+        if let Some((previous_source, previous_path)) = self.entries.get(&kind) {
+            if previous_source == source && previous_path == path {
+                return false;
+            }
+
+            warn!("{} directory moved from {:?} ({}) to {:?} ({})", kind.name(), previous_path, previous_source, path, source);
+        }
+
+        self.entries.insert(kind, (source.clone(), path.to_path_buf()));
+
+        true
+    }
+
+    pub fn remove(&mut self, kind: DirectoryKind) {
+        self.entries.remove(&kind);
+    }
+}
+
+#[test]
+fn test_resolution_record() {
+    let mut record = ResolutionRecord::parse("config xdg /home/user/.config\nbogus\n");
+
+    assert_eq!(record.source(DirectoryKind::Config), Some(&ResolutionSource::XDG));
+    assert!(!record.update(DirectoryKind::Config, &ResolutionSource::XDG, Path::new("/home/user/.config")));
+    assert!(record.update(DirectoryKind::Config, &ResolutionSource::Voxels, Path::new("/srv/voxels/config")));
+    assert_eq!(ResolutionRecord::parse(&record.render()), record);
+}
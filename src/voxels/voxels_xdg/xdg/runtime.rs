@@ -17,63 +17,197 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::path::{Path, PathBuf};
 use crate::voxels::voxels_xdg::xdg::BaseDirectoryError;
-use crate::environment_variables::EnvInt;
-use crate::filesystem::FsInt;
+use crate::environment_variables::{EnvInt, EnvKey};
+use crate::filesystem::{DefaultFsInt, FsInt};
+use super::{check_is_directory, check_permissions, VerifierOptions};
+use crate::pathcheck::{normalize, AbsoluteDirPath};
+use super::dedup_candidates;
+use super::ordering::{self, PrioritySource};
+use super::observer::{self, SharedObserver};
+use super::overlay::{system_dirs, Overlay};
+use super::dirs_file::DirsFile;
+use super::pinning::ResolutionRecord;
+use super::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+use crate::voxels::DirectoryKind;
+use super::verifier::VerificationError;
+
+/// Prefix of the per user directories `RuntimeFallbackPolicy::PerUserTemp` creates in the temp directory
+pub const RUNTIME_FALLBACK_PREFIX: &str = "voxels-runtime-";
+
+/// How many suffixed names are tried when another user already holds the per user temp directory
+pub const RUNTIME_FALLBACK_ATTEMPTS: usize = 16;
+
+/// What happens when neither `XDG_RUNTIME_DIR` nor `VOXELS_RUNTIME_HOME` is set, as under cron or in containers
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum RuntimeFallbackPolicy {
+    #[default]
+    Fail,
+    /// Use `<tmp>/voxels-runtime-<uid>`, created with mode 0700 when missing
+    PerUserTemp,
+}
+
+/// True when `path` is a real 0700 directory owned by `uid` that we can write to
+fn is_private_directory<FsIntT: FsInt>(fs: &FsIntT, path: &Path, uid: u32) -> bool {
+    !fs.is_symlink(path)
+        && fs.is_directory(path)
+        && fs.owner(path).is_ok_and(|owner| owner == uid)
+        && fs.mode(path).is_ok_and(|mode| mode == 0o700)
+        && fs.is_writable(path)
+}
+
+/// A runtime directory for the current user beneath `temp`, reused across calls.
+/// The name is keyed on the effective uid of the process; when another user or a non private
+/// directory already holds a name the next suffixed name is tried, so nothing is ever shared.
+pub fn per_user_temp_dir<FsIntT: FsInt>(fs: &FsIntT, temp: &Path) -> Result<PathBuf, BaseDirectoryError> {
+    let uid = fs.effective_uid()?;
+
+    for attempt in 0..RUNTIME_FALLBACK_ATTEMPTS {
+        let path = match attempt {
+            0 => temp.join(format!("{}{}", RUNTIME_FALLBACK_PREFIX, uid)),
+            _ => temp.join(format!("{}{}-{}", RUNTIME_FALLBACK_PREFIX, uid, attempt)),
+        };
+
+        match fs.create_private_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                if is_private_directory(fs, &path, uid) {
+                    return Ok(path);
+                }
+
+                tracing::warn!("skipping {:?}, it is not a private directory", path);
+            },
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Err(BaseDirectoryError::NoCandidate)
+}
 
 #[mockall::automock]
 pub trait RuntimeVerifier {
-    fn verify(&self, path: &Path) -> bool;
+    fn verify(&self, path: &Path) -> Result<(), VerificationError>;
 }
 
 #[derive(Default)]
 pub struct DefaultRuntimeVerifier<FsIntT: FsInt> {
     fs: FsIntT,
+    pub options: VerifierOptions,
 }
 
 
 impl<FsIntT: FsInt> RuntimeVerifier for DefaultRuntimeVerifier<FsIntT> {
-    fn verify(&self, path: &Path) -> bool {
-        if !self.fs.exists(path) {
-            return false;
-        }
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        crate::pathcheck::check(path).map_err(|_| VerificationError::InvalidPath)?;
 
-        if !self.fs.is_directory(path) {
-            return false;
-        }
+        check_is_directory(&self.fs, path)?;
 
         if !self.fs.is_absolute(path) {
-            return false;
+            return Err(VerificationError::NotAbsolute);
+        }
+
+        if !check_permissions(&self.fs, path, self.options) {
+            return Err(VerificationError::InsecurePermissions);
         }
 
-        true
+        Ok(())
     }
 }
 
 impl<FsIntT: FsInt> DefaultRuntimeVerifier<FsIntT> {
     pub fn new(fs: FsIntT) -> Self {
+        Self::with_options(fs, VerifierOptions::default())
+    }
+
+    pub fn with_options(fs: FsIntT, options: VerifierOptions) -> Self {
         Self {
-            fs
+            fs,
+            options
         }
     }
 }
 
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum RuntimeDirectoryResolutionMethods {
+    FromFile,
     FromXDG,
-    FromVoxels
+    FromVoxels,
+    /// The per user temp directory, only tried when `RuntimeFallbackPolicy` allows it
+    FromTemp
+}
+
+impl RuntimeDirectoryResolutionMethods {
+    /// Stable name used when a resolution is recorded, see `ResolutionRecord`
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeDirectoryResolutionMethods::FromFile => "file",
+            RuntimeDirectoryResolutionMethods::FromXDG => "xdg",
+            RuntimeDirectoryResolutionMethods::FromVoxels => "voxels",
+            RuntimeDirectoryResolutionMethods::FromTemp => "temp",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "file" => Some(RuntimeDirectoryResolutionMethods::FromFile),
+            "xdg" => Some(RuntimeDirectoryResolutionMethods::FromXDG),
+            "voxels" => Some(RuntimeDirectoryResolutionMethods::FromVoxels),
+            "temp" => Some(RuntimeDirectoryResolutionMethods::FromTemp),
+            _ => None,
+        }
+    }
+
+    /// The variable this method reads, `None` when it reads none
+    pub fn env_key(&self) -> Option<EnvKey> {
+        match self {
+            RuntimeDirectoryResolutionMethods::FromFile => Some(EnvKey::VoxelsDirsFile),
+            RuntimeDirectoryResolutionMethods::FromXDG => Some(EnvKey::XdgRuntimeDir),
+            RuntimeDirectoryResolutionMethods::FromVoxels => Some(EnvKey::VoxelsRuntimeHome),
+            RuntimeDirectoryResolutionMethods::FromTemp => None,
+        }
+    }
+}
+
+impl ResolutionMethod for RuntimeDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        RuntimeDirectoryResolutionMethods::from_name(name)
+    }
 }
 
+impl From<RuntimeDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: RuntimeDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for RuntimeDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<RuntimeDirectoryResolutionMethods>", from = "Vec<RuntimeDirectoryResolutionMethods>"))]
 pub struct RuntimeDirectoryPriority {
     order: std::collections::BTreeMap<usize, RuntimeDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<RuntimeDirectoryResolutionMethods>,
 }
 
 impl Default for RuntimeDirectoryPriority {
     fn default() -> Self {
         let mut order = std::collections::BTreeMap::new();
-        order.insert(0, RuntimeDirectoryResolutionMethods::FromVoxels);
+        order.insert(0, RuntimeDirectoryResolutionMethods::FromFile);
+        order.insert(1, RuntimeDirectoryResolutionMethods::FromVoxels);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
@@ -81,13 +215,57 @@ impl Default for RuntimeDirectoryPriority {
 impl RuntimeDirectoryPriority {
     fn set_all(&mut self, new_order: [RuntimeDirectoryResolutionMethods; 3]) {
         self.order = std::collections::BTreeMap::new();
-        self.order.insert(0, new_order[0].clone());
-        self.order.insert(1, new_order[1].clone());
-        self.order.insert(2, new_order[2].clone());
+        self.order.insert(0, new_order[0]);
+        self.order.insert(1, new_order[1]);
+        self.order.insert(2, new_order[2]);
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    fn get(&self) -> &std::collections::BTreeMap<usize, RuntimeDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// Move `method` to the front, keeping the relative order of the rest. Methods not in the order are ignored.
+    pub fn prefer(&mut self, method: RuntimeDirectoryResolutionMethods) {
+        self.prefer_as(method, PrioritySource::Preferred);
+    }
+
+    fn prefer_as(&mut self, method: RuntimeDirectoryResolutionMethods, source: PrioritySource) {
+        ordering::prefer(&mut self.order, method);
+
+        if self.order.values().any(|existing| *existing == method) {
+            self.sources.insert(method, source);
+        }
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, RuntimeDirectoryResolutionMethods::name)
     }
+}
+
+impl std::fmt::Display for RuntimeDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, RuntimeDirectoryResolutionMethods::name)
+    }
+}
+
+impl From<RuntimeDirectoryPriority> for Vec<RuntimeDirectoryResolutionMethods> {
+    fn from(priority: RuntimeDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<RuntimeDirectoryResolutionMethods>> for RuntimeDirectoryPriority {
+    fn from(methods: Vec<RuntimeDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
 
-    fn get(&self) -> std::collections::BTreeMap<usize, RuntimeDirectoryResolutionMethods> {
-        self.order.clone()
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
@@ -95,7 +273,14 @@ impl RuntimeDirectoryPriority {
 pub trait RuntimeDirectoryResolver {
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError>;
-    fn resolve(&self) -> Result<(PathBuf, RuntimeDirectoryResolutionMethods), BaseDirectoryError>;
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve(&self) -> Result<(AbsoluteDirPath, RuntimeDirectoryResolutionMethods), BaseDirectoryError>;
+
+    /// Every environment variable resolution may read with the current priority, for sandbox allowlists
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey>;
+
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, RuntimeDirectoryResolutionMethods)>;
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError>;
 }
 
 #[derive(Default)]
@@ -104,66 +289,258 @@ pub struct RuntimeDirectory<EnvIntT: EnvInt, VerifierT: RuntimeVerifier> {
     verifier: VerifierT,
     env: EnvIntT,
     pub priority: RuntimeDirectoryPriority,
+    /// Pinned paths read from `VOXELS_DIRS_FILE`, see `DirsFile::from_environment`
+    pub dirs_file: Option<DirsFile>,
+    /// Told about every candidate tried, see `ResolutionObserver`
+    pub observer: Option<SharedObserver>,
+    pub fallback: RuntimeFallbackPolicy,
 }
 
 impl<EnvIntT: EnvInt, VerifierT: RuntimeVerifier> RuntimeDirectory<EnvIntT, VerifierT> {
+    /// Pinned paths are read from the file `VOXELS_DIRS_FILE` names, if any
     pub fn new(env: EnvIntT, verifier: VerifierT) -> Self {
+        Self::with_fs(env, verifier, &DefaultFsInt)
+    }
+
+    /// As `new`, reading the dirs file through `fs`
+    pub fn with_fs<FsIntT: FsInt>(env: EnvIntT, verifier: VerifierT, fs: &FsIntT) -> Self {
         let priority = RuntimeDirectoryPriority::default();
+        let dirs_file = DirsFile::load(&env, fs);
         Self {
             data_path: None,
             env,
             verifier,
-            priority
+            priority,
+            dirs_file,
+            observer: None,
+            fallback: RuntimeFallbackPolicy::default()
+        }
+    }
+
+    /// The path `method` finds, verified but not yet checked to be absolute
+    fn using(&self, method: &RuntimeDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        match method {
+            RuntimeDirectoryResolutionMethods::FromFile => self.using_file(),
+            RuntimeDirectoryResolutionMethods::FromXDG => self.using_xdg(),
+            RuntimeDirectoryResolutionMethods::FromVoxels => self.using_voxels(),
+            RuntimeDirectoryResolutionMethods::FromTemp => self.using_temp(),
+        }
+    }
+
+    /// The per user temp directory, when neither runtime variable is set
+    fn using_temp(&self) -> Result<PathBuf, BaseDirectoryError> {
+        if self.env.get_path(EnvKey::XdgRuntimeDir).is_ok() || self.env.get_path(EnvKey::VoxelsRuntimeHome).is_ok() {
+            return Err(BaseDirectoryError::NoCandidate);
+        }
+
+        per_user_temp_dir(&DefaultFsInt, &std::env::temp_dir())
+    }
+
+    /// The priority order, followed by the temp fallback when `fallback` allows it
+    fn methods(&self) -> impl Iterator<Item = &RuntimeDirectoryResolutionMethods> {
+        let fallback = match self.fallback {
+            RuntimeFallbackPolicy::Fail => None,
+            RuntimeFallbackPolicy::PerUserTemp => Some(&RuntimeDirectoryResolutionMethods::FromTemp),
+        };
+
+        self.priority.order.values().chain(fallback)
+    }
+
+    /// The path `method` would yield before verification, even when nothing exists there yet
+    fn candidate(&self, method: &RuntimeDirectoryResolutionMethods) -> Option<PathBuf> {
+        match method {
+            RuntimeDirectoryResolutionMethods::FromFile => self.dirs_file.as_ref()
+                .and_then(|dirs_file| dirs_file.get(DirectoryKind::Runtime))
+                .map(Path::to_path_buf),
+            RuntimeDirectoryResolutionMethods::FromXDG => self.env.get_path(EnvKey::XdgRuntimeDir).ok().map(|path| normalize(&path)),
+            RuntimeDirectoryResolutionMethods::FromVoxels => self.env.get_path(EnvKey::VoxelsRuntimeHome).ok().map(|path| normalize(&path)),
+            // created on first use, so there is nothing to report beforehand
+            RuntimeDirectoryResolutionMethods::FromTemp => None,
+        }
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when the directory's path is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub fn resolve_utf8(&self) -> Result<(camino::Utf8PathBuf, RuntimeDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = self.resolve()?;
+
+        Ok((super::to_utf8(path.into_path_buf())?, method))
+    }
+
+    /// What each method in the priority yields before verification, so installers can choose
+    /// where to create a base directory on a machine where none exists yet
+    pub fn candidate_paths(&self) -> Vec<(PathBuf, RuntimeDirectoryResolutionMethods)> {
+        self.methods()
+            .filter_map(|method| Some((self.candidate(method)?, *method)))
+            .collect()
+    }
+
+    /// `using` for `method`, reported to `observer`
+    fn attempt(&self, method: &RuntimeDirectoryResolutionMethods) -> (RuntimeDirectoryResolutionMethods, Option<PathBuf>) {
+        (*method, observer::attempt(self.observer.as_ref(), DirectoryKind::Runtime, &method.source(), || self.using(method)))
+    }
+
+    /// Prefer the method recorded in `record` and record the outcome, logging when the directory moved since last time
+    pub fn pin_resolution<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(AbsoluteDirPath, RuntimeDirectoryResolutionMethods), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        if let Some(method) = resolutions.source(DirectoryKind::Runtime).and_then(|source| RuntimeDirectoryResolutionMethods::from_source(source).ok()) {
+            self.priority.prefer_as(method, PrioritySource::Pinned);
         }
+
+        let (path, method) = self.resolve()?;
+
+        if resolutions.update(DirectoryKind::Runtime, &method.source(), &path) {
+            resolutions.save(fs, record)?;
+        }
+
+        Ok((path, method))
+    }
+
+    /// Forget the recorded resolution and return to the default priority
+    pub fn unpin<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        resolutions.remove(DirectoryKind::Runtime);
+
+        self.priority = RuntimeDirectoryPriority::default();
+
+        resolutions.save(fs, record)
     }
 }
 
 impl<EnvIntT: EnvInt, VerifierT: RuntimeVerifier> RuntimeDirectoryResolver for RuntimeDirectory<EnvIntT, VerifierT> {
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey> {
+        self.methods()
+            .filter_map(RuntimeDirectoryResolutionMethods::env_key)
+            .collect()
+    }
+
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let data_path: PathBuf = self.env.get_path_from_environment(String::from("XDG_RUNTIME_DIR"))?;
+        let data_path: PathBuf = normalize(&self.env.get_path(EnvKey::XdgRuntimeDir)?);
 
-        if self.verifier.verify(&data_path) {
-            Ok(data_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        self.verifier.verify(&data_path).map_err(|error| error.at(&data_path))?;
+
+        Ok(data_path)
     }
 
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("VOXELS_RUNTIME_HOME"))?;
+        let path: PathBuf = normalize(&self.env.get_path(EnvKey::VoxelsRuntimeHome)?);
 
-        if self.verifier.verify(&path) {
-            Ok(path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        self.verifier.verify(&path).map_err(|error| error.at(&path))?;
+
+        Ok(path)
     }
 
-    fn resolve(&self) -> Result<(PathBuf, RuntimeDirectoryResolutionMethods), BaseDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            match self.priority.order[&index] {
-                RuntimeDirectoryResolutionMethods::FromXDG => {
-                    let path = self.using_xdg();
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.dirs_file.as_ref()
+            .and_then(|dirs_file| dirs_file.get(DirectoryKind::Runtime))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
 
-                    if path.is_ok() {
-                        return Ok((path?, RuntimeDirectoryResolutionMethods::FromXDG));
-                    }
-                },
-                RuntimeDirectoryResolutionMethods::FromVoxels => {
-                    let path = self.using_voxels();
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
 
-                    if path.is_ok() {
-                        return Ok((path?, RuntimeDirectoryResolutionMethods::FromVoxels));
-                    }
-                }
-            }
-        }
-        Err(BaseDirectoryError::NoCandidate)
+        Ok(path.to_path_buf())
+    }
+
+    fn resolve(&self) -> Result<(AbsoluteDirPath, RuntimeDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = ordering::absolute_candidates(self.methods().map(|method| self.attempt(method)))
+            .next()
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        observer::resolved(self.observer.as_ref(), DirectoryKind::Runtime, &method.source(), &path);
+
+        Ok((path, method))
+    }
+
+    /// Every verified candidate in priority order, with candidates that are the same directory on disk removed
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, RuntimeDirectoryResolutionMethods)> {
+        let candidates = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method))).collect();
+
+        dedup_candidates(fs, candidates)
+    }
+
+    /// `resolve` as the write directory, then every other candidate and the system directories to read from
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError> {
+        let system = system_dirs(&self.env, DirectoryKind::Runtime)
+            .into_iter()
+            .filter(|path| self.verifier.verify(path).is_ok())
+            .filter_map(|path| AbsoluteDirPath::new(path).ok());
+
+        ordering::build_overlay(self.resolve_candidates(fs), system, |_path| true)
+            .ok_or(BaseDirectoryError::NoCandidate)
     }
 }
 
-impl<EnvIntT: EnvInt, VerifierT: RuntimeVerifier> Into<PathBuf> for RuntimeDirectory<EnvIntT, VerifierT> {
-    fn into(self) -> PathBuf {
-        self.data_path.unwrap()
+impl<EnvIntT: EnvInt, VerifierT: RuntimeVerifier> From<RuntimeDirectory<EnvIntT, VerifierT>> for PathBuf {
+    fn from(directory: RuntimeDirectory<EnvIntT, VerifierT>) -> Self {
+        directory.data_path.unwrap()
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_per_user_temp_dir_skips_foreign_directories() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let taken = PathBuf::from("/tmp/voxels-runtime-1000");
+    let expected = PathBuf::from("/tmp/voxels-runtime-1000-1");
+
+    fs.expect_effective_uid()
+        .returning(|| Ok(1000));
+
+    // another user got there first, with a directory that looks private
+    fs.expect_create_private_dir()
+        .with(mockall::predicate::eq(taken.clone()))
+        .return_once(|_| Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+
+    fs.expect_owner()
+        .with(mockall::predicate::eq(taken.clone()))
+        .returning(|_| Ok(1001));
+
+    fs.expect_create_private_dir()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_once(|_| Ok(()));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_mode()
+        .returning(|_| Ok(0o700));
+
+    fs.expect_is_writable()
+        .returning(|_| true);
+
+    assert_eq!(per_user_temp_dir(&fs, Path::new("/tmp")).unwrap(), expected);
+}
+
+#[test]
+fn test_per_user_temp_dir_reuses_own_directory() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let expected = PathBuf::from("/tmp/voxels-runtime-1000");
+
+    fs.expect_effective_uid()
+        .returning(|| Ok(1000));
+
+    fs.expect_create_private_dir()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_once(|_| Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+
+    fs.expect_owner()
+        .returning(|_| Ok(1000));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_mode()
+        .returning(|_| Ok(0o700));
+
+    fs.expect_is_writable()
+        .returning(|_| true);
+
+    assert_eq!(per_user_temp_dir(&fs, Path::new("/tmp")).unwrap(), expected);
+}
@@ -0,0 +1,127 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::fmt;
+
+/// Every way a directory can be found, shared by all kinds and layers so code can be written once over them.
+/// Each kind keeps its own `*DirectoryResolutionMethods`, which convert to and from this through `ResolutionMethod`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "String", from = "String"))]
+pub enum ResolutionSource {
+    /// Pinned in the file `VOXELS_DIRS_FILE` names
+    File,
+    XDG,
+    FHS,
+    Voxels,
+    ContainerVolume,
+    Temp,
+    DBus,
+    /// Handed over by a systemd unit, no resolver offers this yet
+    Systemd,
+    /// Any name not known to this crate
+    Custom(String),
+}
+
+impl ResolutionSource {
+    /// The stable name, as used by `ResolutionRecord` and the `name` of every method enum
+    pub fn name(&self) -> &str {
+        match self {
+            ResolutionSource::File => "file",
+            ResolutionSource::XDG => "xdg",
+            ResolutionSource::FHS => "fhs",
+            ResolutionSource::Voxels => "voxels",
+            ResolutionSource::ContainerVolume => "container-volume",
+            ResolutionSource::Temp => "temp",
+            ResolutionSource::DBus => "dbus",
+            ResolutionSource::Systemd => "systemd",
+            ResolutionSource::Custom(name) => name,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "file" => ResolutionSource::File,
+            "xdg" => ResolutionSource::XDG,
+            "fhs" => ResolutionSource::FHS,
+            "voxels" => ResolutionSource::Voxels,
+            "container-volume" => ResolutionSource::ContainerVolume,
+            "temp" => ResolutionSource::Temp,
+            "dbus" => ResolutionSource::DBus,
+            "systemd" => ResolutionSource::Systemd,
+            _ => ResolutionSource::Custom(name.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for ResolutionSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl From<ResolutionSource> for String {
+    fn from(source: ResolutionSource) -> Self {
+        source.name().to_owned()
+    }
+}
+
+impl From<String> for ResolutionSource {
+    fn from(name: String) -> Self {
+        ResolutionSource::from_name(&name)
+    }
+}
+
+/// A source the kind being converted to cannot resolve from
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UnsupportedSource(pub ResolutionSource);
+
+impl fmt::Display for UnsupportedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not a resolution method of this kind", self.0)
+    }
+}
+
+/// Implemented by every kind's resolution method enum
+pub trait ResolutionMethod: Sized {
+    fn method_name(&self) -> &'static str;
+
+    fn from_method_name(name: &str) -> Option<Self>;
+
+    fn source(&self) -> ResolutionSource {
+        ResolutionSource::from_name(self.method_name())
+    }
+
+    /// The method for `source`, failing when this kind does not support it
+    fn from_source(source: &ResolutionSource) -> Result<Self, UnsupportedSource> {
+        Self::from_method_name(source.name()).ok_or_else(|| UnsupportedSource(source.clone()))
+    }
+}
+
+#[test]
+fn test_resolution_source() {
+    use super::config::ConfigDirectoryResolutionMethods;
+    use super::runtime::RuntimeDirectoryResolutionMethods;
+
+    assert_eq!(ConfigDirectoryResolutionMethods::FromFHS.source(), ResolutionSource::FHS);
+    assert_eq!(RuntimeDirectoryResolutionMethods::from_source(&ResolutionSource::Temp), Ok(RuntimeDirectoryResolutionMethods::FromTemp));
+    assert_eq!(RuntimeDirectoryResolutionMethods::from_source(&ResolutionSource::FHS), Err(UnsupportedSource(ResolutionSource::FHS)));
+    assert_eq!(ResolutionSource::from_name("flatpak"), ResolutionSource::Custom(String::from("flatpak")));
+
+    for source in [ResolutionSource::File, ResolutionSource::ContainerVolume, ResolutionSource::DBus, ResolutionSource::Systemd] {
+        assert_eq!(ResolutionSource::from_name(source.name()), source);
+    }
+}
@@ -16,81 +16,215 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
 */
 
 use std::path::{Path, PathBuf};
-use crate::environment_variables::EnvInt;
-use crate::filesystem::FsInt;
+use crate::environment_variables::{EnvInt, EnvKey};
+use crate::filesystem::{create_directory, CreateOptions, DefaultFsInt, FsInt};
+use super::{check_is_directory, check_permissions, VerifierOptions};
 use super::BaseDirectoryError;
+use crate::pathcheck::{normalize, AbsoluteDirPath};
+use super::dedup_candidates;
+use super::ordering::{self, PrioritySource};
+use super::observer::{self, SharedObserver};
+use super::overlay::{system_dirs, Overlay};
+use super::container::ContainerVolumes;
+use super::dirs_file::DirsFile;
+use super::pinning::ResolutionRecord;
+use super::source::{ResolutionMethod, ResolutionSource, UnsupportedSource};
+use crate::voxels::DirectoryKind;
+use super::verifier::VerificationError;
 
 #[mockall::automock]
 pub trait StateVerifier {
-    fn verify(&self, path: &Path) -> bool;
+    fn verify(&self, path: &Path) -> Result<(), VerificationError>;
 }
 
 #[derive(Default)]
 pub struct DefaultStateVerifier<FsIntT: FsInt> {
     fs: FsIntT,
+    pub options: VerifierOptions,
 }
 
 
 impl<FsIntT: FsInt> StateVerifier for DefaultStateVerifier<FsIntT> {
-    fn verify(&self, path: &Path) -> bool {
-        if !self.fs.exists(path) {
-            return false;
-        }
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        crate::pathcheck::check(path).map_err(|_| VerificationError::InvalidPath)?;
 
-        if !self.fs.is_directory(path) {
-            return false;
-        }
+        check_is_directory(&self.fs, path)?;
 
         if !self.fs.is_absolute(path) {
-            return false;
+            return Err(VerificationError::NotAbsolute);
+        }
+
+        if !check_permissions(&self.fs, path, self.options) {
+            return Err(VerificationError::InsecurePermissions);
         }
 
-        true
+        Ok(())
     }
 }
 
 impl<FsIntT: FsInt> DefaultStateVerifier<FsIntT> {
     pub fn new(fs: FsIntT) -> Self {
+        Self::with_options(fs, VerifierOptions::default())
+    }
+
+    pub fn with_options(fs: FsIntT, options: VerifierOptions) -> Self {
         Self {
-            fs
+            fs,
+            options
         }
     }
 }
 
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "ResolutionSource", try_from = "ResolutionSource"))]
 pub enum StateDirectoryResolutionMethods {
+    FromFile,
     FromXDG,
     FromFHS,
-    FromVoxels
+    FromVoxels,
+    /// A volume mounted at a conventional mount point, see `ContainerVolumes`
+    FromContainerVolume
+}
+
+impl StateDirectoryResolutionMethods {
+    /// Stable name used when a resolution is recorded, see `ResolutionRecord`
+    pub fn name(&self) -> &'static str {
+        match self {
+            StateDirectoryResolutionMethods::FromFile => "file",
+            StateDirectoryResolutionMethods::FromXDG => "xdg",
+            StateDirectoryResolutionMethods::FromFHS => "fhs",
+            StateDirectoryResolutionMethods::FromVoxels => "voxels",
+            StateDirectoryResolutionMethods::FromContainerVolume => "container-volume",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "file" => Some(StateDirectoryResolutionMethods::FromFile),
+            "xdg" => Some(StateDirectoryResolutionMethods::FromXDG),
+            "fhs" => Some(StateDirectoryResolutionMethods::FromFHS),
+            "voxels" => Some(StateDirectoryResolutionMethods::FromVoxels),
+            "container-volume" => Some(StateDirectoryResolutionMethods::FromContainerVolume),
+            _ => None,
+        }
+    }
+
+    /// The variable this method reads, `None` when it reads none
+    pub fn env_key(&self) -> Option<EnvKey> {
+        match self {
+            StateDirectoryResolutionMethods::FromFile => Some(EnvKey::VoxelsDirsFile),
+            StateDirectoryResolutionMethods::FromXDG => Some(EnvKey::XdgStateHome),
+            StateDirectoryResolutionMethods::FromFHS => Some(EnvKey::Home),
+            StateDirectoryResolutionMethods::FromVoxels => Some(EnvKey::VoxelsStateHome),
+            StateDirectoryResolutionMethods::FromContainerVolume => None,
+        }
+    }
 }
 
+impl ResolutionMethod for StateDirectoryResolutionMethods {
+    fn method_name(&self) -> &'static str {
+        self.name()
+    }
+
+    fn from_method_name(name: &str) -> Option<Self> {
+        StateDirectoryResolutionMethods::from_name(name)
+    }
+}
+
+impl From<StateDirectoryResolutionMethods> for ResolutionSource {
+    fn from(method: StateDirectoryResolutionMethods) -> Self {
+        method.source()
+    }
+}
+
+impl TryFrom<ResolutionSource> for StateDirectoryResolutionMethods {
+    type Error = UnsupportedSource;
+
+    fn try_from(source: ResolutionSource) -> Result<Self, Self::Error> {
+        Self::from_source(&source)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(into = "Vec<StateDirectoryResolutionMethods>", from = "Vec<StateDirectoryResolutionMethods>"))]
 pub struct StateDirectoryPriority {
     order: std::collections::BTreeMap<usize, StateDirectoryResolutionMethods>,
+    sources: ordering::PrioritySources<StateDirectoryResolutionMethods>,
 }
 
 impl Default for StateDirectoryPriority {
     fn default() -> Self {
         let mut order = std::collections::BTreeMap::new();
-        order.insert(0, StateDirectoryResolutionMethods::FromVoxels);
-        order.insert(1, StateDirectoryResolutionMethods::FromXDG);
-        order.insert(2, StateDirectoryResolutionMethods::FromFHS);
+        order.insert(0, StateDirectoryResolutionMethods::FromFile);
+        order.insert(1, StateDirectoryResolutionMethods::FromVoxels);
+        order.insert(2, StateDirectoryResolutionMethods::FromContainerVolume);
+        order.insert(3, StateDirectoryResolutionMethods::FromXDG);
+        #[cfg(not(feature = "no-fhs"))]
+        order.insert(4, StateDirectoryResolutionMethods::FromFHS);
         Self {
-            order
+            order,
+            sources: ordering::PrioritySources::new()
         }
     }
 }
 
 impl StateDirectoryPriority {
-    fn set_all(&mut self, new_order: [StateDirectoryResolutionMethods; 3]) {
+    fn set_all(&mut self, new_order: [StateDirectoryResolutionMethods; 5]) {
         self.order = std::collections::BTreeMap::new();
-        self.order.insert(0, new_order[0].clone());
-        self.order.insert(1, new_order[1].clone());
-        self.order.insert(2, new_order[2].clone());
+        self.order.insert(0, new_order[0]);
+        self.order.insert(1, new_order[1]);
+        self.order.insert(2, new_order[2]);
+        self.order.insert(3, new_order[3]);
+        self.order.insert(4, new_order[4]);
+
+        self.sources = ordering::custom_sources(&self.order);
+    }
+
+    fn get(&self) -> &std::collections::BTreeMap<usize, StateDirectoryResolutionMethods> {
+        &self.order
+    }
+
+    /// Move `method` to the front, keeping the relative order of the rest. Methods not in the order are ignored.
+    pub fn prefer(&mut self, method: StateDirectoryResolutionMethods) {
+        self.prefer_as(method, PrioritySource::Preferred);
+    }
+
+    fn prefer_as(&mut self, method: StateDirectoryResolutionMethods, source: PrioritySource) {
+        ordering::prefer(&mut self.order, method);
+
+        if self.order.values().any(|existing| *existing == method) {
+            self.sources.insert(method, source);
+        }
+    }
+
+    /// One line per method in priority order, with whether it is there by default or was moved
+    pub fn describe(&self) -> String {
+        ordering::describe(&self.order, &self.sources, StateDirectoryResolutionMethods::name)
+    }
+}
+
+impl std::fmt::Display for StateDirectoryPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ordering::write_order(f, &self.order, StateDirectoryResolutionMethods::name)
     }
+}
 
-    fn get(&self) -> std::collections::BTreeMap<usize, StateDirectoryResolutionMethods> {
-        self.order.clone()
+impl From<StateDirectoryPriority> for Vec<StateDirectoryResolutionMethods> {
+    fn from(priority: StateDirectoryPriority) -> Self {
+        priority.order.into_values().collect()
+    }
+}
+
+/// An order read back from configuration, every method placed as `PrioritySource::Custom`
+impl From<Vec<StateDirectoryResolutionMethods>> for StateDirectoryPriority {
+    fn from(methods: Vec<StateDirectoryResolutionMethods>) -> Self {
+        let order = ordering::order_from(methods);
+
+        Self {
+            sources: ordering::custom_sources(&order),
+            order
+        }
     }
 }
 
@@ -99,7 +233,15 @@ pub trait StateDirectoryResolver {
     fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError>;
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError>;
-    fn resolve(&self) -> Result<(PathBuf, StateDirectoryResolutionMethods), BaseDirectoryError>;
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve(&self) -> Result<(AbsoluteDirPath, StateDirectoryResolutionMethods), BaseDirectoryError>;
+
+    /// Every environment variable resolution may read with the current priority, for sandbox allowlists
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey>;
+
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, StateDirectoryResolutionMethods)>;
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError>;
 }
 
 #[derive(Default)]
@@ -108,85 +250,226 @@ pub struct StateDirectory<EnvIntT: EnvInt, VerifierT: StateVerifier> {
     verifier: VerifierT,
     env: EnvIntT,
     pub priority: StateDirectoryPriority,
+    /// Pinned paths read from `VOXELS_DIRS_FILE`, see `DirsFile::from_environment`
+    pub dirs_file: Option<DirsFile>,
+    /// Volumes found by `ContainerVolumes::detect`, `None` outside a container
+    pub container: Option<ContainerVolumes>,
+    /// Told about every candidate tried, see `ResolutionObserver`
+    pub observer: Option<SharedObserver>,
 }
 
 impl<EnvIntT: EnvInt, VerifierT: StateVerifier> StateDirectory<EnvIntT, VerifierT> {
+    /// Pinned paths are read from the file `VOXELS_DIRS_FILE` names, if any
     pub fn new(env: EnvIntT, verifier: VerifierT) -> Self {
+        Self::with_fs(env, verifier, &DefaultFsInt)
+    }
+
+    /// As `new`, reading the dirs file through `fs`
+    pub fn with_fs<FsIntT: FsInt>(env: EnvIntT, verifier: VerifierT, fs: &FsIntT) -> Self {
         let priority = StateDirectoryPriority::default();
+        let dirs_file = DirsFile::load(&env, fs);
         Self {
             state_path: None,
             env,
             verifier,
-            priority
+            priority,
+            dirs_file,
+            container: None,
+            observer: None
+        }
+    }
+
+    /// The path `method` finds, verified but not yet checked to be absolute
+    fn using(&self, method: &StateDirectoryResolutionMethods) -> Result<PathBuf, BaseDirectoryError> {
+        match method {
+            StateDirectoryResolutionMethods::FromFile => self.using_file(),
+            StateDirectoryResolutionMethods::FromXDG => self.using_xdg(),
+            StateDirectoryResolutionMethods::FromVoxels => self.using_voxels(),
+            StateDirectoryResolutionMethods::FromFHS => self.using_fhs(),
+            StateDirectoryResolutionMethods::FromContainerVolume => self.using_container_volume(),
+        }
+    }
+
+    /// The path `method` would yield before verification, even when nothing exists there yet
+    fn candidate(&self, method: &StateDirectoryResolutionMethods) -> Option<PathBuf> {
+        match method {
+            StateDirectoryResolutionMethods::FromFile => self.dirs_file.as_ref()
+                .and_then(|dirs_file| dirs_file.get(DirectoryKind::State))
+                .map(Path::to_path_buf),
+            StateDirectoryResolutionMethods::FromXDG => self.env.get_path(EnvKey::XdgStateHome).ok().map(|path| normalize(&path)),
+            StateDirectoryResolutionMethods::FromVoxels => self.env.get_path(EnvKey::VoxelsStateHome).ok().map(|path| normalize(&path)),
+            StateDirectoryResolutionMethods::FromFHS => super::fhs_candidate(&self.env, ".local/state").ok(),
+            StateDirectoryResolutionMethods::FromContainerVolume => self.container.as_ref()
+                .and_then(|container| container.get(DirectoryKind::State))
+                .map(Path::to_path_buf),
+        }
+    }
+
+    /// `resolve` as a UTF-8 path, `NotUtf8` when the directory's path is not valid UTF-8
+    #[cfg(feature = "camino")]
+    pub fn resolve_utf8(&self) -> Result<(camino::Utf8PathBuf, StateDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = self.resolve()?;
+
+        Ok((super::to_utf8(path.into_path_buf())?, method))
+    }
+
+    /// What each method in the priority yields before verification, so installers can choose
+    /// where to create a base directory on a machine where none exists yet
+    pub fn candidate_paths(&self) -> Vec<(PathBuf, StateDirectoryResolutionMethods)> {
+        self.priority.order.values()
+            .filter_map(|method| Some((self.candidate(method)?, *method)))
+            .collect()
+    }
+
+    /// `resolve`, but when no candidate verifies the specification's default, `${XDG_STATE_HOME}`
+    /// or else `~/.local/state`, is created and used, so fresh accounts and minimal containers still resolve
+    pub fn resolve_or_bootstrap<FsIntT: FsInt>(&self, fs: &FsIntT) -> Result<(AbsoluteDirPath, StateDirectoryResolutionMethods), BaseDirectoryError> {
+        if let Ok(resolved) = self.resolve() {
+            return Ok(resolved);
+        }
+
+        for method in [StateDirectoryResolutionMethods::FromXDG, StateDirectoryResolutionMethods::FromFHS] {
+            if let Some(path) = self.candidate(&method)
+                && let Ok(path) = AbsoluteDirPath::new(path) {
+                tracing::info!("bootstrapping {:?}, no state directory exists yet", path);
+
+                create_directory(fs, &path, CreateOptions::default())?;
+
+                observer::resolved(self.observer.as_ref(), DirectoryKind::State, &method.source(), &path);
+
+                return Ok((path, method));
+            }
         }
+
+        Err(BaseDirectoryError::NoCandidate)
+    }
+
+    /// `using` for `method`, reported to `observer`
+    fn attempt(&self, method: &StateDirectoryResolutionMethods) -> (StateDirectoryResolutionMethods, Option<PathBuf>) {
+        (*method, observer::attempt(self.observer.as_ref(), DirectoryKind::State, &method.source(), || self.using(method)))
+    }
+
+    /// Prefer the method recorded in `record` and record the outcome, logging when the directory moved since last time
+    pub fn pin_resolution<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(AbsoluteDirPath, StateDirectoryResolutionMethods), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        if let Some(method) = resolutions.source(DirectoryKind::State).and_then(|source| StateDirectoryResolutionMethods::from_source(source).ok()) {
+            self.priority.prefer_as(method, PrioritySource::Pinned);
+        }
+
+        let (path, method) = self.resolve()?;
+
+        if resolutions.update(DirectoryKind::State, &method.source(), &path) {
+            resolutions.save(fs, record)?;
+        }
+
+        Ok((path, method))
+    }
+
+    /// Forget the recorded resolution and return to the default priority
+    pub fn unpin<FsIntT: FsInt>(&mut self, fs: &FsIntT, record: &Path) -> Result<(), BaseDirectoryError> {
+        let mut resolutions = ResolutionRecord::load(fs, record);
+
+        resolutions.remove(DirectoryKind::State);
+
+        self.priority = StateDirectoryPriority::default();
+
+        resolutions.save(fs, record)
     }
 }
 
 impl<EnvIntT: EnvInt, VerifierT: StateVerifier> StateDirectoryResolver for StateDirectory<EnvIntT, VerifierT> {
+    fn consumed_env_vars(&self) -> std::collections::BTreeSet<EnvKey> {
+        self.priority.order.values()
+            .filter_map(StateDirectoryResolutionMethods::env_key)
+            .collect()
+    }
+
     fn using_fhs(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("HOME"))?;
+        let state_path = super::fhs_candidate(&self.env, ".local/state")?;
 
-        let state_path = path.join(".local/state/");
+        self.verifier.verify(&state_path).map_err(|error| error.at(&state_path))?;
 
-        if self.verifier.verify(&state_path) {
-            Ok(state_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        Ok(state_path)
     }
 
     fn using_xdg(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let state_path: PathBuf = self.env.get_path_from_environment(String::from("XDG_STATE_HOME"))?;
+        let state_path: PathBuf = normalize(&self.env.get_path(EnvKey::XdgStateHome)?);
 
-        if self.verifier.verify(&state_path) {
-            Ok(state_path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
+        self.verifier.verify(&state_path).map_err(|error| error.at(&state_path))?;
+
+        Ok(state_path)
     }
 
     fn using_voxels(&self) -> Result<PathBuf, BaseDirectoryError> {
-        let path: PathBuf = self.env.get_path_from_environment(String::from("VOXELS_STATE_HOME"))?;
-
-        if self.verifier.verify(&path) {
-            Ok(path)
-        } else {
-            Err(BaseDirectoryError::NoCandidate)
-        }
-    }
-
-    fn resolve(&self) -> Result<(PathBuf, StateDirectoryResolutionMethods), BaseDirectoryError> {
-        for index in 0..self.priority.order.len() {
-            match self.priority.order[&index] {
-                StateDirectoryResolutionMethods::FromXDG => {
-                    let path = self.using_xdg();
-
-                    if path.is_ok() {
-                        return Ok((path?, StateDirectoryResolutionMethods::FromXDG));
-                    }
-                },
-                StateDirectoryResolutionMethods::FromVoxels => {
-                    let path = self.using_voxels();
-
-                    if path.is_ok() {
-                        return Ok((path?, StateDirectoryResolutionMethods::FromVoxels));
-                    }
-                },
-                StateDirectoryResolutionMethods::FromFHS => {
-                    let path = self.using_fhs();
-
-                    if path.is_ok() {
-                        return Ok((path?, StateDirectoryResolutionMethods::FromFHS));
-                    }
-                }
-            }
-        }
-        Err(BaseDirectoryError::NoCandidate)
+        let path: PathBuf = normalize(&self.env.get_path(EnvKey::VoxelsStateHome)?);
+
+        self.verifier.verify(&path).map_err(|error| error.at(&path))?;
+
+        Ok(path)
+    }
+
+    fn using_file(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.dirs_file.as_ref()
+            .and_then(|dirs_file| dirs_file.get(DirectoryKind::State))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn using_container_volume(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let path = self.container.as_ref()
+            .and_then(|container| container.get(DirectoryKind::State))
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        self.verifier.verify(path).map_err(|error| error.at(path))?;
+
+        Ok(path.to_path_buf())
+    }
+
+    fn resolve(&self) -> Result<(AbsoluteDirPath, StateDirectoryResolutionMethods), BaseDirectoryError> {
+        let (path, method) = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method)))
+            .next()
+            .ok_or(BaseDirectoryError::NoCandidate)?;
+
+        observer::resolved(self.observer.as_ref(), DirectoryKind::State, &method.source(), &path);
+
+        Ok((path, method))
+    }
+
+    /// Every verified candidate in priority order, with candidates that are the same directory on disk removed
+    fn resolve_candidates<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Vec<(AbsoluteDirPath, StateDirectoryResolutionMethods)> {
+        let candidates = ordering::absolute_candidates(self.priority.order.values().map(|method| self.attempt(method))).collect();
+
+        dedup_candidates(fs, candidates)
+    }
+
+    /// `resolve` as the write directory, then every other candidate and the system directories to read from
+    fn overlay<FsIntT: FsInt + 'static>(&self, fs: &FsIntT) -> Result<Overlay, BaseDirectoryError> {
+        let system = system_dirs(&self.env, DirectoryKind::State)
+            .into_iter()
+            .filter(|path| self.verifier.verify(path).is_ok())
+            .filter_map(|path| AbsoluteDirPath::new(path).ok());
+
+        ordering::build_overlay(self.resolve_candidates(fs), system, |_path| true)
+            .ok_or(BaseDirectoryError::NoCandidate)
     }
 }
 
-impl<EnvIntT: EnvInt, VerifierT: StateVerifier> Into<PathBuf> for StateDirectory<EnvIntT, VerifierT> {
-    fn into(self) -> PathBuf {
-        self.state_path.unwrap()
+impl<EnvIntT: EnvInt, VerifierT: StateVerifier> From<StateDirectory<EnvIntT, VerifierT>> for PathBuf {
+    fn from(directory: StateDirectory<EnvIntT, VerifierT>) -> Self {
+        directory.state_path.unwrap()
     }
-}
\ No newline at end of file
+}
+#[cfg(not(feature = "no-fhs"))]
+#[test]
+fn test_priority_describe() {
+    let mut priority = StateDirectoryPriority::default();
+
+    priority.prefer(StateDirectoryResolutionMethods::FromFHS);
+
+    assert_eq!(priority.to_string(), "fhs > file > voxels > container-volume > xdg");
+    assert!(priority.describe().starts_with("1. fhs (preferred)\n2. file (default)\n"));
+}
@@ -0,0 +1,321 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::BaseDirectoryError;
+use super::data::DataDirectoryResolver;
+use super::FsInt;
+use crate::filesystem::move_path;
+use crate::clock::{ClockInt, DefaultClockInt};
+
+pub const TRASH_DIRECTORY_NAME: &str = "Trash";
+
+pub const TRASH_FILES_DIRECTORY_NAME: &str = "files";
+
+pub const TRASH_INFO_DIRECTORY_NAME: &str = "info";
+
+pub const TRASH_INFO_EXTENSION: &str = "trashinfo";
+
+/// `YYYY-MM-DDThh:mm:ss` as required by the `DeletionDate` key.
+/// Times are written in UTC as this crate has no access to the local time zone.
+pub fn format_deletion_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    let days = (secs / 86400) as i64;
+    let secs_of_day = secs % 86400;
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", year, month, day, secs_of_day / 3600, (secs_of_day % 3600) / 60, secs_of_day % 60)
+}
+
+/// Percent encode `path` for the `Path` key, keeping `/` and RFC 3986 unreserved characters
+pub fn encode_path(path: &Path) -> String {
+    let mut encoded = String::new();
+
+    for byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(*byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+pub fn render_trash_info(original: &Path, deleted_at: SystemTime) -> String {
+    format!("[Trash Info]\nPath={}\nDeletionDate={}\n", encode_path(original), format_deletion_date(deleted_at))
+}
+
+/// A trash can as described by the freedesktop.org Trash specification
+pub struct Trash<FsIntT: FsInt, ClockIntT: ClockInt = DefaultClockInt> {
+    root: PathBuf,
+    fs: FsIntT,
+    clock: ClockIntT,
+}
+
+impl<FsIntT: FsInt> Trash<FsIntT> {
+    pub fn new(root: PathBuf, fs: FsIntT) -> Self {
+        Self::with_clock(root, fs, DefaultClockInt)
+    }
+
+    /// The home trash, `$XDG_DATA_HOME/Trash`
+    pub fn home<BaseT: DataDirectoryResolver>(base: &BaseT, fs: FsIntT) -> Result<Self, BaseDirectoryError> {
+        let data = base.using_xdg()?;
+
+        Ok(Self::new(data.join(TRASH_DIRECTORY_NAME), fs))
+    }
+}
+
+impl<FsIntT: FsInt, ClockIntT: ClockInt> Trash<FsIntT, ClockIntT> {
+    /// As `new`, with deletion dates taken from `clock`
+    pub fn with_clock(root: PathBuf, fs: FsIntT, clock: ClockIntT) -> Self {
+        Self {
+            root,
+            fs,
+            clock
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Move `path` into the trash, returning where it now lives.
+    /// A `path` on another filesystem than the trash, such as a tmpfs runtime directory, is copied in and removed.
+    pub fn trash(&self, path: &Path) -> Result<PathBuf, BaseDirectoryError> {
+        self.trash_at(path, self.clock.now())
+    }
+
+    fn trash_at(&self, path: &Path, deleted_at: SystemTime) -> Result<PathBuf, BaseDirectoryError> {
+        if !path.is_absolute() {
+            return Err(BaseDirectoryError::Io(std::io::ErrorKind::InvalidInput));
+        }
+
+        let name = path.file_name().ok_or(BaseDirectoryError::Io(std::io::ErrorKind::InvalidInput))?;
+
+        let files = self.root.join(TRASH_FILES_DIRECTORY_NAME);
+        let info = self.root.join(TRASH_INFO_DIRECTORY_NAME);
+
+        self.fs.create_dir_all(&files)?;
+        self.fs.create_dir_all(&info)?;
+
+        // the info file is what claims a name, so both it and the file must be free
+        let mut candidate = name.to_owned();
+        let mut suffix = 1;
+
+        loop {
+            let mut info_name = candidate.clone();
+            info_name.push(".");
+            info_name.push(TRASH_INFO_EXTENSION);
+
+            let info_path = info.join(info_name);
+            let trashed = files.join(&candidate);
+
+            if !self.fs.exists(&trashed) {
+                // created exclusively, so a name another process claimed first is never reused
+                match self.fs.write_new(&info_path, &render_trash_info(path, deleted_at)) {
+                    Ok(()) => {
+                        if let Err(err) = move_path(&self.fs, path, &trashed) {
+                            let _ = self.fs.remove_file(&info_path);
+
+                            return Err(err.into());
+                        }
+
+                        return Ok(trashed);
+                    },
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {},
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            suffix += 1;
+
+            candidate = name.to_owned();
+            candidate.push(format!(".{}", suffix));
+        }
+    }
+}
+
+/// Somewhere a path can be moved to instead of being deleted, implemented by `Trash`
+pub trait TrashCan {
+    fn trash(&self, path: &Path) -> Result<PathBuf, BaseDirectoryError>;
+}
+
+impl<FsIntT: FsInt, ClockIntT: ClockInt> TrashCan for Trash<FsIntT, ClockIntT> {
+    fn trash(&self, path: &Path) -> Result<PathBuf, BaseDirectoryError> {
+        Trash::trash(self, path)
+    }
+}
+
+/// How destructive operations such as purging an application or pruning a cache get rid of a directory
+#[derive(Clone, Copy)]
+pub enum Removal<'a> {
+    /// Move it into the trash so the user can restore it, the default
+    Trash(&'a dyn TrashCan),
+    /// Delete it outright
+    Permanent,
+}
+
+impl Removal<'_> {
+    pub fn remove<FsIntT: FsInt>(&self, fs: &FsIntT, path: &Path) -> Result<(), BaseDirectoryError> {
+        match self {
+            Removal::Trash(trash) => {
+                trash.trash(path)?;
+            },
+            Removal::Permanent => {
+                if fs.is_directory(path) {
+                    fs.remove_dir_all(path)?;
+                } else {
+                    fs.remove_file(path)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn test_render_trash_info() {
+    let deleted_at = UNIX_EPOCH + std::time::Duration::from_secs(1_093_991_528);
+
+    assert_eq!(
+        render_trash_info(Path::new("/home/user/my world"), deleted_at),
+        "[Trash Info]\nPath=/home/user/my%20world\nDeletionDate=2004-08-31T22:32:08\n"
+    );
+}
+
+#[test]
+fn test_trash_avoids_taken_names() {
+    use mockall::predicate::eq;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let root = PathBuf::from("/home/user/.local/share/Trash");
+
+    fs.expect_create_dir_all()
+        .returning(|_| Ok(()));
+
+    fs.expect_exists()
+        .returning(|_| false);
+
+    // another process claimed the first name between our look and our create
+    fs.expect_write_new()
+        .once()
+        .with(eq(root.join("info").join("saves.trashinfo")), mockall::predicate::always())
+        .return_once(|_, _| Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+
+    fs.expect_write_new()
+        .once()
+        .with(eq(root.join("info").join("saves.2.trashinfo")), mockall::predicate::always())
+        .return_once(|_, _| Ok(()));
+
+    fs.expect_rename()
+        .once()
+        .with(eq(PathBuf::from("/games/saves")), eq(root.join("files").join("saves.2")))
+        .return_once(|_, _| Ok(()));
+
+    let trash = Trash::new(root.clone(), fs);
+
+    assert_eq!(trash.trash(Path::new("/games/saves")).unwrap(), root.join("files").join("saves.2"));
+}
+
+#[test]
+fn test_trash_uses_clock() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+    let mut clock = crate::clock::MockClockInt::new();
+
+    let root = PathBuf::from("/home/user/.local/share/Trash");
+
+    clock.expect_now()
+        .once()
+        .return_once(|| UNIX_EPOCH + std::time::Duration::from_secs(1_093_991_528));
+
+    fs.expect_create_dir_all()
+        .returning(|_| Ok(()));
+
+    fs.expect_exists()
+        .returning(|_| false);
+
+    fs.expect_write_new()
+        .once()
+        .with(mockall::predicate::always(), mockall::predicate::eq(String::from("[Trash Info]\nPath=/games/saves\nDeletionDate=2004-08-31T22:32:08\n")))
+        .return_once(|_, _| Ok(()));
+
+    fs.expect_rename()
+        .once()
+        .return_once(|_, _| Ok(()));
+
+    let trash = Trash::with_clock(root, fs, clock);
+
+    assert!(trash.trash(Path::new("/games/saves")).is_ok());
+}
+
+#[test]
+fn test_trash_across_filesystems() {
+    use mockall::predicate::eq;
+
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let root = PathBuf::from("/home/user/.local/share/Trash");
+    let runtime = PathBuf::from("/run/user/1000/voxels/org.voxels.Client");
+    let trashed = root.join("files").join("org.voxels.Client");
+
+    fs.expect_create_dir_all()
+        .returning(|_| Ok(()));
+
+    fs.expect_exists()
+        .returning(|_| false);
+
+    fs.expect_write_new()
+        .once()
+        .return_once(|_, _| Ok(()));
+
+    fs.expect_rename()
+        .once()
+        .return_once(|_, _| Err(std::io::Error::from(std::io::ErrorKind::CrossesDevices)));
+
+    fs.expect_is_symlink()
+        .returning(|_| false);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_read_dir()
+        .returning(|_| Ok(Vec::new()));
+
+    fs.expect_remove_dir_all()
+        .once()
+        .with(eq(runtime.clone()))
+        .return_once(|_| Ok(()));
+
+    let trash = Trash::new(root, fs);
+
+    assert_eq!(trash.trash(&runtime).unwrap(), trashed);
+}
@@ -0,0 +1,273 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use super::BaseDirectoryError;
+use super::config::ConfigDirectoryResolver;
+use super::{EnvInt, EnvKey, FsInt};
+use crate::voxels::voxels_xdg::Namespace;
+
+#[cfg(feature = "rdn")]
+use lib_voxels_application::application::application::Application;
+
+#[cfg(feature = "rdn")]
+use crate::voxels::rdn::{rdn_path_within, RdnCase, RdnLayout};
+#[cfg(feature = "rdn")]
+use crate::voxels::VoxelsDirectoryError;
+
+pub const USER_DIRS_FILE_NAME: &str = "user-dirs.dirs";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum UserDirectory {
+    Desktop,
+    Download,
+    Templates,
+    PublicShare,
+    Documents,
+    Music,
+    Pictures,
+    Videos,
+}
+
+impl UserDirectory {
+    pub const ALL: [UserDirectory; 8] = [
+        UserDirectory::Desktop,
+        UserDirectory::Download,
+        UserDirectory::Templates,
+        UserDirectory::PublicShare,
+        UserDirectory::Documents,
+        UserDirectory::Music,
+        UserDirectory::Pictures,
+        UserDirectory::Videos,
+    ];
+
+    /// The key used for this directory inside `user-dirs.dirs`
+    pub fn key(&self) -> &'static str {
+        match self {
+            UserDirectory::Desktop => "XDG_DESKTOP_DIR",
+            UserDirectory::Download => "XDG_DOWNLOAD_DIR",
+            UserDirectory::Templates => "XDG_TEMPLATES_DIR",
+            UserDirectory::PublicShare => "XDG_PUBLICSHARE_DIR",
+            UserDirectory::Documents => "XDG_DOCUMENTS_DIR",
+            UserDirectory::Music => "XDG_MUSIC_DIR",
+            UserDirectory::Pictures => "XDG_PICTURES_DIR",
+            UserDirectory::Videos => "XDG_VIDEOS_DIR",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        UserDirectory::ALL.into_iter().find(|dir| dir.key() == key)
+    }
+}
+
+/// The well known user directories as configured by xdg-user-dirs
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct UserDirs {
+    dirs: BTreeMap<UserDirectory, PathBuf>,
+    /// Where xdg-user-dirs puts a directory the file leaves unset
+    home: PathBuf,
+}
+
+impl UserDirs {
+    /// Parse the contents of a `user-dirs.dirs` file, expanding `$HOME` to `home`.
+    /// Lines that do not follow the format written by xdg-user-dirs-update are ignored.
+    pub fn parse(contents: &str, home: &Path) -> Self {
+        let mut dirs = BTreeMap::new();
+
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+
+            let Some(kind) = UserDirectory::from_key(key.trim()) else {
+                continue;
+            };
+
+            let value = value.trim();
+
+            let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+                continue;
+            };
+
+            // `$HOME` is only expanded when unescaped, `\$HOME` names a directory literally called that
+            let path = if value == "$HOME" {
+                home.to_path_buf()
+            } else if let Some(relative) = value.strip_prefix("$HOME/") {
+                let Some(relative) = unescape(relative) else {
+                    continue;
+                };
+
+                home.join(relative)
+            } else if value.starts_with('/') {
+                let Some(value) = unescape(value) else {
+                    continue;
+                };
+
+                PathBuf::from(value)
+            } else {
+                continue;
+            };
+
+            dirs.insert(kind, path);
+        }
+
+        Self {
+            dirs,
+            home: home.to_path_buf(),
+        }
+    }
+
+    /// Read `user-dirs.dirs` from the resolved config directory
+    pub fn load<BaseT: ConfigDirectoryResolver, EnvIntT: EnvInt, FsIntT: FsInt>(base: &BaseT, env: &EnvIntT, fs: &FsIntT) -> Result<Self, BaseDirectoryError> {
+        let home = env.get_path(EnvKey::Home)?;
+
+        let (config, _how) = base.resolve()?;
+
+        let contents = fs.read_to_string(&config.join(USER_DIRS_FILE_NAME))?;
+
+        Ok(Self::parse(&contents, &home))
+    }
+
+    pub fn get(&self, kind: UserDirectory) -> Option<PathBuf> {
+        self.dirs.get(&kind).cloned()
+    }
+
+    pub fn desktop(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Desktop)
+    }
+
+    pub fn downloads(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Download)
+    }
+
+    pub fn templates(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Templates)
+    }
+
+    pub fn public_share(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::PublicShare)
+    }
+
+    pub fn documents(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Documents)
+    }
+
+    pub fn music(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Music)
+    }
+
+    pub fn pictures(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Pictures)
+    }
+
+    pub fn videos(&self) -> Option<PathBuf> {
+        self.get(UserDirectory::Videos)
+    }
+
+    /// `<pictures>/<namespace>`, with pictures falling back to home as xdg-user-dirs does when it is unset
+    pub fn screenshots_base(&self, namespace: &Namespace) -> PathBuf {
+        self.pictures().unwrap_or_else(|| self.home.clone()).join(namespace.as_str())
+    }
+
+    /// Where an application should export screenshots and renders, `<pictures>/<namespace>/<rdn>`
+    #[cfg(feature = "rdn")]
+    pub fn screenshots_dir<FsIntT: FsInt>(&self, fs: &FsIntT, namespace: &Namespace, application: &Application) -> Result<PathBuf, VoxelsDirectoryError> {
+        rdn_path_within(fs, &self.screenshots_base(namespace), application, RdnCase::default(), RdnLayout::default())
+    }
+}
+
+/// Undo the shell escaping xdg-user-dirs-update applies inside double quotes, `None` if a backslash ends the value
+fn unescape(value: &str) -> Option<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut characters = value.chars();
+
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            out.push(character);
+            continue;
+        }
+
+        match characters.next()? {
+            escaped @ ('"' | '\\' | '$' | '`') => out.push(escaped),
+            other => {
+                out.push('\\');
+                out.push(other);
+            },
+        }
+    }
+
+    Some(out)
+}
+
+#[test]
+fn test_parse_user_dirs() {
+    let contents = "\
+# This file is written by xdg-user-dirs-update
+XDG_DESKTOP_DIR=\"$HOME/Desktop\"
+XDG_DOWNLOAD_DIR=\"$HOME/Downloads\"
+XDG_PICTURES_DIR=\"/media/pictures\"
+XDG_MUSIC_DIR=\"$HOME\"
+XDG_VIDEOS_DIR=Videos
+";
+
+    let dirs = UserDirs::parse(contents, Path::new("/home/user"));
+
+    assert_eq!(dirs.desktop(), Some(PathBuf::from("/home/user/Desktop")));
+    assert_eq!(dirs.downloads(), Some(PathBuf::from("/home/user/Downloads")));
+    assert_eq!(dirs.pictures(), Some(PathBuf::from("/media/pictures")));
+    assert_eq!(dirs.music(), Some(PathBuf::from("/home/user")));
+    assert_eq!(dirs.videos(), None);
+    assert_eq!(dirs.documents(), None);
+}
+
+#[test]
+fn test_parse_user_dirs_unescapes() {
+    let contents = r#"
+XDG_DESKTOP_DIR="$HOME/My \"Desktop\""
+XDG_DOWNLOAD_DIR="/media/back\\slash"
+XDG_DOCUMENTS_DIR="$HOME/\$HOME"
+XDG_MUSIC_DIR="$HOME/dangling\"
+"#;
+
+    let dirs = UserDirs::parse(contents, Path::new("/home/user"));
+
+    assert_eq!(dirs.desktop(), Some(PathBuf::from("/home/user/My \"Desktop\"")));
+    assert_eq!(dirs.downloads(), Some(PathBuf::from("/media/back\\slash")));
+    assert_eq!(dirs.documents(), Some(PathBuf::from("/home/user/$HOME")));
+    assert_eq!(dirs.music(), None);
+}
+
+#[test]
+fn test_screenshots_base() {
+    let namespace = Namespace::new("acme").unwrap();
+
+    let dirs = UserDirs::parse("XDG_PICTURES_DIR=\"$HOME/Pictures\"\n", Path::new("/home/user"));
+
+    assert_eq!(dirs.screenshots_base(&namespace), PathBuf::from("/home/user/Pictures/acme"));
+
+    // an unset pictures directory is home, as it is for xdg-user-dirs
+    let dirs = UserDirs::parse("", Path::new("/home/user"));
+
+    assert_eq!(dirs.screenshots_base(&namespace), PathBuf::from("/home/user/acme"));
+}
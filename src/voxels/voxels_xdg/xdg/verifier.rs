@@ -0,0 +1,267 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::Path;
+
+use crate::filesystem::FsInt;
+use super::{check_is_directory, check_permissions, BaseDirectoryError, VerifierOptions};
+use super::bin::BinVerifier;
+use super::cache::CacheVerifier;
+use super::config::ConfigVerifier;
+use super::data::DataVerifier;
+use super::runtime::RuntimeVerifier;
+use super::state::StateVerifier;
+
+/// Why a verifier refused a candidate
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum VerificationError {
+    /// Empty, `.` or `..` components or control characters, see `pathcheck::check`
+    InvalidPath,
+    NotFound,
+    /// A file or other non-directory is in the way
+    NotADirectory,
+    DanglingSymlink,
+    NotAbsolute,
+    /// Refused by the `VerifierOptions` permission rules
+    InsecurePermissions,
+    /// The named `Check` of a composed `Verifier` failed
+    Check(&'static str),
+}
+
+impl VerificationError {
+    /// The resolution error for `path` being refused for this reason
+    pub fn at(self, path: &Path) -> BaseDirectoryError {
+        match self {
+            VerificationError::NotFound => BaseDirectoryError::NoCandidate,
+            VerificationError::NotADirectory => BaseDirectoryError::NotADirectory(path.to_path_buf()),
+            VerificationError::DanglingSymlink => BaseDirectoryError::DanglingSymlink(path.to_path_buf()),
+            reason => BaseDirectoryError::Rejected(path.to_path_buf(), reason),
+        }
+    }
+}
+
+impl std::fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerificationError::InvalidPath => write!(f, "the path is malformed"),
+            VerificationError::NotFound => write!(f, "nothing exists there"),
+            VerificationError::NotADirectory => write!(f, "it is not a directory"),
+            VerificationError::DanglingSymlink => write!(f, "it is a symlink to nothing"),
+            VerificationError::NotAbsolute => write!(f, "the path is relative"),
+            VerificationError::InsecurePermissions => write!(f, "its permissions are unsafe"),
+            VerificationError::Check(name) => write!(f, "it failed the {} check", name),
+        }
+    }
+}
+
+/// What `check_is_directory` found instead of a directory
+impl From<BaseDirectoryError> for VerificationError {
+    fn from(err: BaseDirectoryError) -> Self {
+        match err {
+            BaseDirectoryError::NotADirectory(_) => VerificationError::NotADirectory,
+            BaseDirectoryError::DanglingSymlink(_) => VerificationError::DanglingSymlink,
+            _ => VerificationError::NotFound,
+        }
+    }
+}
+
+/// The test of a `Check`, true when the candidate passes
+pub type CheckFn<FsIntT> = Box<dyn Fn(&FsIntT, &Path) -> bool + Send + Sync>;
+
+/// One named test a candidate directory must pass, given the filesystem of its `Verifier`
+pub struct Check<FsIntT: FsInt> {
+    name: &'static str,
+    test: CheckFn<FsIntT>,
+}
+
+impl<FsIntT: FsInt> Check<FsIntT> {
+    pub fn new<TestT: Fn(&FsIntT, &Path) -> bool + Send + Sync + 'static>(name: &'static str, test: TestT) -> Self {
+        Self {
+            name,
+            test: Box::new(test)
+        }
+    }
+
+    /// Used when a candidate is rejected, to say which check it failed
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn passes(&self, fs: &FsIntT, path: &Path) -> bool {
+        (self.test)(fs, path)
+    }
+}
+
+/// The path has no empty, `.` or `..` components and no control characters, see `pathcheck::check`
+pub fn lexically_valid<FsIntT: FsInt>() -> Check<FsIntT> {
+    Check::new("lexically valid", |_: &FsIntT, path: &Path| crate::pathcheck::check(path).is_ok())
+}
+
+pub fn exists<FsIntT: FsInt>() -> Check<FsIntT> {
+    Check::new("exists", |fs: &FsIntT, path: &Path| fs.exists(path))
+}
+
+/// An existing directory, see `check_is_directory`
+pub fn is_dir<FsIntT: FsInt>() -> Check<FsIntT> {
+    Check::new("is a directory", |fs: &FsIntT, path: &Path| {
+        check_is_directory(fs, path).inspect_err(|error| tracing::debug!("{:?} is not a directory: {:?}", path, error)).is_ok()
+    })
+}
+
+pub fn absolute<FsIntT: FsInt>() -> Check<FsIntT> {
+    Check::new("absolute", |fs: &FsIntT, path: &Path| fs.is_absolute(path))
+}
+
+pub fn writable<FsIntT: FsInt>() -> Check<FsIntT> {
+    Check::new("writable", |fs: &FsIntT, path: &Path| fs.is_writable(path))
+}
+
+/// The permission rules in `options`, see `check_permissions`
+pub fn permissions<FsIntT: FsInt>(options: VerifierOptions) -> Check<FsIntT> {
+    Check::new("permissions", move |fs: &FsIntT, path: &Path| check_permissions(fs, path, options))
+}
+
+/// Not on any of `filesystem_types`, such as `tmpfs` for directories that must survive a reboot.
+/// Paths whose filesystem cannot be read pass.
+pub fn not_on_filesystem<FsIntT: FsInt>(filesystem_types: &[&str]) -> Check<FsIntT> {
+    let filesystem_types: Vec<String> = filesystem_types.iter().map(|filesystem_type| String::from(*filesystem_type)).collect();
+
+    Check::new("not on an excluded filesystem", move |fs: &FsIntT, path: &Path| {
+        fs.filesystem_type(path).map_or(true, |filesystem_type| !filesystem_types.contains(&filesystem_type))
+    })
+}
+
+/// Not on a network filesystem, see `filesystem::is_network_filesystem`
+pub fn not_on_network<FsIntT: FsInt>() -> Check<FsIntT> {
+    Check::new("not on a network filesystem", |fs: &FsIntT, path: &Path| {
+        fs.filesystem_type(path).map_or(true, |filesystem_type| !crate::filesystem::is_network_filesystem(&filesystem_type))
+    })
+}
+
+/// What the `Default*Verifier`s check, to extend rather than rebuild
+pub fn default_checks<FsIntT: FsInt>(options: VerifierOptions) -> Vec<Check<FsIntT>> {
+    vec![lexically_valid(), is_dir(), absolute(), permissions(options)]
+}
+
+/// A verifier built from `Check`s, accepting a candidate when it passes every one in order.
+/// It implements every base directory's verifier trait, so custom rules need no new verifier type.
+pub struct Verifier<FsIntT: FsInt> {
+    fs: FsIntT,
+    checks: Vec<Check<FsIntT>>,
+}
+
+impl<FsIntT: FsInt> Verifier<FsIntT> {
+    pub fn all<ChecksT: IntoIterator<Item = Check<FsIntT>>>(fs: FsIntT, checks: ChecksT) -> Self {
+        Self {
+            fs,
+            checks: checks.into_iter().collect()
+        }
+    }
+
+    /// The checks of the `Default*Verifier`s with `options`
+    pub fn with_defaults(fs: FsIntT, options: VerifierOptions) -> Self {
+        Self::all(fs, default_checks(options))
+    }
+
+    /// Add `check` after the existing ones
+    pub fn with(mut self, check: Check<FsIntT>) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub fn checks(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.checks.iter().map(Check::name)
+    }
+
+    /// The first check `path` fails, `None` when it passes them all
+    pub fn first_failure(&self, path: &Path) -> Option<&'static str> {
+        self.checks.iter()
+            .find(|check| !check.passes(&self.fs, path))
+            .map(Check::name)
+    }
+
+    pub fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        match self.first_failure(path) {
+            Some(check) => {
+                tracing::debug!("rejecting {:?}, it failed the {} check", path, check);
+                Err(VerificationError::Check(check))
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl<FsIntT: FsInt> ConfigVerifier for Verifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        Verifier::verify(self, path)
+    }
+}
+
+impl<FsIntT: FsInt> DataVerifier for Verifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        Verifier::verify(self, path)
+    }
+}
+
+impl<FsIntT: FsInt> StateVerifier for Verifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        Verifier::verify(self, path)
+    }
+}
+
+impl<FsIntT: FsInt> CacheVerifier for Verifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        Verifier::verify(self, path)
+    }
+}
+
+impl<FsIntT: FsInt> RuntimeVerifier for Verifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        Verifier::verify(self, path)
+    }
+}
+
+impl<FsIntT: FsInt> BinVerifier for Verifier<FsIntT> {
+    fn verify(&self, path: &Path) -> Result<(), VerificationError> {
+        Verifier::verify(self, path)
+    }
+}
+
+#[test]
+fn test_verifier_composition() {
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    fs.expect_exists()
+        .returning(|_| true);
+
+    fs.expect_is_directory()
+        .returning(|_| true);
+
+    fs.expect_is_absolute()
+        .returning(|path| path.is_absolute());
+
+    fs.expect_filesystem_type()
+        .returning(|path| Ok(String::from(if path.starts_with("/tmp") { "tmpfs" } else { "ext4" })));
+
+    let verifier = Verifier::all(fs, [exists(), is_dir(), absolute()])
+        .with(not_on_filesystem(&["tmpfs"]));
+
+    assert!(verifier.verify(Path::new("/home/user/.local/state")).is_ok());
+    assert_eq!(verifier.verify(Path::new("/tmp/state")), Err(VerificationError::Check("not on an excluded filesystem")));
+    assert_eq!(verifier.first_failure(Path::new("/tmp/state")), Some("not on an excluded filesystem"));
+    assert_eq!(verifier.first_failure(Path::new("relative")), Some("absolute"));
+}
@@ -0,0 +1,444 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+use super::BaseDirectoryError;
+use super::config::ConfigDirectoryResolver;
+use super::data::{DataDirectoryResolver, DataVerifier};
+use super::FsInt;
+use crate::filesystem::{create_directory, CreateOptions};
+
+#[cfg(feature = "rdn")]
+use lib_voxels_application::application::application::Application;
+
+pub const AUTOSTART_DIRECTORY_NAME: &str = "autostart";
+
+pub const DESKTOP_ENTRY_DIRECTORY_NAME: &str = "applications";
+
+pub const DESKTOP_ENTRY_EXTENSION: &str = "desktop";
+
+pub const FONTS_DIRECTORY_NAME: &str = "fonts";
+
+pub const ICONS_DIRECTORY_NAME: &str = "icons";
+
+/// Contents of a freedesktop.org desktop entry of type Application
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct DesktopEntry {
+    pub name: String,
+    pub exec: String,
+    pub comment: Option<String>,
+    pub icon: Option<String>,
+    pub terminal: bool,
+    pub categories: Vec<String>,
+}
+
+impl DesktopEntry {
+    pub fn new(name: String, exec: String) -> Self {
+        Self {
+            name,
+            exec,
+            ..Default::default()
+        }
+    }
+
+    /// Render the entry in the key file format expected by launchers, refusing values with control characters
+    /// the format cannot carry
+    pub fn render(&self) -> Result<String, BaseDirectoryError> {
+        let mut out = String::from("[Desktop Entry]\nType=Application\n");
+
+        out.push_str(&format!("Name={}\n", escape_value(&self.name)?));
+        out.push_str(&format!("Exec={}\n", escape_value(&self.exec)?));
+
+        if let Some(comment) = &self.comment {
+            out.push_str(&format!("Comment={}\n", escape_value(comment)?));
+        }
+
+        if let Some(icon) = &self.icon {
+            out.push_str(&format!("Icon={}\n", escape_value(icon)?));
+        }
+
+        out.push_str(&format!("Terminal={}\n", self.terminal));
+
+        if !self.categories.is_empty() {
+            let categories = self.categories.iter()
+                .map(|category| escape_value(category).map(|escaped| escaped.replace(';', "\\;")))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            out.push_str(&format!("Categories={};\n", categories.join(";")));
+        }
+
+        Ok(out)
+    }
+}
+
+/// Escape `value` as a desktop entry string, a raw newline would otherwise start a key of its own
+fn escape_value(value: &str) -> Result<String, BaseDirectoryError> {
+    let mut escaped = String::with_capacity(value.len());
+
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            character if character.is_control() => return Err(BaseDirectoryError::InvalidName(String::from(value))),
+            character => escaped.push(character),
+        }
+    }
+
+    Ok(escaped)
+}
+
+/// Check `desktop_file_id` names a `.desktop` file directly inside the directory it is installed into
+fn validate_desktop_file_id(desktop_file_id: &str) -> Result<(), BaseDirectoryError> {
+    let stem = desktop_file_id.strip_suffix(&format!(".{}", DESKTOP_ENTRY_EXTENSION));
+
+    let valid = stem.is_some_and(|stem| !stem.is_empty())
+        && !desktop_file_id.contains('/')
+        && !desktop_file_id.contains("..")
+        && !desktop_file_id.chars().any(char::is_control);
+
+    if valid {
+        Ok(())
+    } else {
+        Err(BaseDirectoryError::InvalidName(String::from(desktop_file_id)))
+    }
+}
+
+/// Writes `entry` to `<directory>/<desktop_file_id>`, creating the directory if needed
+fn install_desktop_entry_into<FsIntT: FsInt>(fs: &FsIntT, directory: &Path, desktop_file_id: &str, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError> {
+    validate_desktop_file_id(desktop_file_id)?;
+
+    let contents = entry.render()?;
+
+    create_directory(fs, directory, CreateOptions::default())?;
+
+    let path = directory.join(desktop_file_id);
+
+    fs.write(&path, &contents)?;
+
+    Ok(path)
+}
+
+#[cfg(feature = "rdn")]
+fn desktop_file_id_of(application: &Application) -> String {
+    use crate::voxels::rdn::ApplicationNames;
+
+    application.to_desktop_file_id()
+}
+
+#[mockall::automock]
+pub trait AutostartDirectoryResolver {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn install_desktop_entry(&self, desktop_file_id: &str, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError>;
+}
+
+/// `$XDG_CONFIG_HOME/autostart`, entries placed here are launched on login
+pub struct AutostartDirectory<BaseT: ConfigDirectoryResolver, FsIntT: FsInt> {
+    base: BaseT,
+    fs: FsIntT,
+}
+
+impl<BaseT: ConfigDirectoryResolver, FsIntT: FsInt> AutostartDirectory<BaseT, FsIntT> {
+    pub fn new(base: BaseT, fs: FsIntT) -> Self {
+        Self {
+            base,
+            fs
+        }
+    }
+
+    #[cfg(feature = "rdn")]
+    pub fn install_for_application(&self, application: &Application, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError> {
+        self.install_desktop_entry(&desktop_file_id_of(application), entry)
+    }
+}
+
+impl<BaseT: ConfigDirectoryResolver, FsIntT: FsInt> AutostartDirectoryResolver for AutostartDirectory<BaseT, FsIntT> {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let (config, _how) = self.base.resolve()?;
+
+        Ok(config.join(AUTOSTART_DIRECTORY_NAME))
+    }
+
+    fn install_desktop_entry(&self, desktop_file_id: &str, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError> {
+        let directory = self.resolve()?;
+
+        install_desktop_entry_into(&self.fs, &directory, desktop_file_id, entry)
+    }
+}
+
+#[mockall::automock]
+pub trait DesktopEntryDirectoryResolver {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn install_desktop_entry(&self, desktop_file_id: &str, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError>;
+}
+
+/// `$XDG_DATA_HOME/applications`, entries placed here show up in application launchers
+pub struct DesktopEntryDirectory<BaseT: DataDirectoryResolver, FsIntT: FsInt> {
+    base: BaseT,
+    fs: FsIntT,
+}
+
+impl<BaseT: DataDirectoryResolver, FsIntT: FsInt> DesktopEntryDirectory<BaseT, FsIntT> {
+    pub fn new(base: BaseT, fs: FsIntT) -> Self {
+        Self {
+            base,
+            fs
+        }
+    }
+
+    #[cfg(feature = "rdn")]
+    pub fn install_for_application(&self, application: &Application, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError> {
+        self.install_desktop_entry(&desktop_file_id_of(application), entry)
+    }
+}
+
+impl<BaseT: DataDirectoryResolver, FsIntT: FsInt> DesktopEntryDirectoryResolver for DesktopEntryDirectory<BaseT, FsIntT> {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let (data, _how) = self.base.resolve()?;
+
+        Ok(data.join(DESKTOP_ENTRY_DIRECTORY_NAME))
+    }
+
+    fn install_desktop_entry(&self, desktop_file_id: &str, entry: &DesktopEntry) -> Result<PathBuf, BaseDirectoryError> {
+        let directory = self.resolve()?;
+
+        install_desktop_entry_into(&self.fs, &directory, desktop_file_id, entry)
+    }
+}
+
+#[mockall::automock]
+pub trait FontsDirectoryResolver {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve_and_create(&self) -> Result<PathBuf, BaseDirectoryError>;
+}
+
+/// `$XDG_DATA_HOME/fonts`, fonts placed here are picked up by fontconfig
+pub struct FontsDirectory<BaseT: DataDirectoryResolver, VerifierT: DataVerifier, FsIntT: FsInt> {
+    base: BaseT,
+    verifier: VerifierT,
+    fs: FsIntT,
+}
+
+impl<BaseT: DataDirectoryResolver, VerifierT: DataVerifier, FsIntT: FsInt> FontsDirectory<BaseT, VerifierT, FsIntT> {
+    pub fn new(base: BaseT, verifier: VerifierT, fs: FsIntT) -> Self {
+        Self {
+            base,
+            verifier,
+            fs
+        }
+    }
+}
+
+impl<BaseT: DataDirectoryResolver, VerifierT: DataVerifier, FsIntT: FsInt> FontsDirectoryResolver for FontsDirectory<BaseT, VerifierT, FsIntT> {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let (data, _how) = self.base.resolve()?;
+
+        let fonts_path = data.join(FONTS_DIRECTORY_NAME);
+
+        self.verifier.verify(&fonts_path).map_err(|error| error.at(&fonts_path))?;
+
+        Ok(fonts_path)
+    }
+
+    fn resolve_and_create(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let (data, _how) = self.base.resolve()?;
+
+        let fonts_path = data.join(FONTS_DIRECTORY_NAME);
+
+        create_directory(&self.fs, &fonts_path, CreateOptions::default())?;
+
+        Ok(fonts_path)
+    }
+}
+
+#[mockall::automock]
+pub trait IconsDirectoryResolver {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError>;
+    fn resolve_and_create(&self) -> Result<PathBuf, BaseDirectoryError>;
+}
+
+/// `$XDG_DATA_HOME/icons`, the user icon theme base directory
+pub struct IconsDirectory<BaseT: DataDirectoryResolver, VerifierT: DataVerifier, FsIntT: FsInt> {
+    base: BaseT,
+    verifier: VerifierT,
+    fs: FsIntT,
+}
+
+impl<BaseT: DataDirectoryResolver, VerifierT: DataVerifier, FsIntT: FsInt> IconsDirectory<BaseT, VerifierT, FsIntT> {
+    pub fn new(base: BaseT, verifier: VerifierT, fs: FsIntT) -> Self {
+        Self {
+            base,
+            verifier,
+            fs
+        }
+    }
+}
+
+impl<BaseT: DataDirectoryResolver, VerifierT: DataVerifier, FsIntT: FsInt> IconsDirectoryResolver for IconsDirectory<BaseT, VerifierT, FsIntT> {
+    fn resolve(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let (data, _how) = self.base.resolve()?;
+
+        let icons_path = data.join(ICONS_DIRECTORY_NAME);
+
+        self.verifier.verify(&icons_path).map_err(|error| error.at(&icons_path))?;
+
+        Ok(icons_path)
+    }
+
+    fn resolve_and_create(&self) -> Result<PathBuf, BaseDirectoryError> {
+        let (data, _how) = self.base.resolve()?;
+
+        let icons_path = data.join(ICONS_DIRECTORY_NAME);
+
+        create_directory(&self.fs, &icons_path, CreateOptions::default())?;
+
+        Ok(icons_path)
+    }
+}
+
+#[test]
+fn test_autostart_resolve() {
+    let mut base = super::config::MockConfigDirectoryResolver::new();
+    let fs = crate::filesystem::MockFsInt::new();
+
+    base.expect_resolve()
+        .once()
+        .return_once(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/.config")).unwrap(), super::config::ConfigDirectoryResolutionMethods::FromXDG)));
+
+    let autostart = AutostartDirectory::new(base, fs);
+
+    let res = autostart.resolve();
+
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), PathBuf::from("/home/.config/autostart"));
+}
+
+#[test]
+fn test_desktop_entry_install() {
+    let mut base = super::data::MockDataDirectoryResolver::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let entry = DesktopEntry::new(String::from("Voxels"), String::from("voxels-client"));
+
+    let expected_directory = PathBuf::from("/home/.local/share/applications");
+    let expected_file = expected_directory.join("org.voxels.Client.desktop");
+
+    base.expect_resolve()
+        .once()
+        .return_once(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/.local/share")).unwrap(), super::data::DataDirectoryResolutionMethods::FromXDG)));
+
+    fs.expect_create_dir_all()
+        .once()
+        .with(mockall::predicate::eq(expected_directory.clone()))
+        .return_once(|_| Ok(()));
+
+    fs.expect_is_directory()
+        .with(mockall::predicate::eq(expected_directory))
+        .return_const(true);
+
+    fs.expect_write()
+        .once()
+        .with(mockall::predicate::eq(expected_file.clone()), mockall::predicate::eq(entry.render().unwrap()))
+        .return_once(|_, _| Ok(()));
+
+    let applications = DesktopEntryDirectory::new(base, fs);
+
+    let res = applications.install_desktop_entry("org.voxels.Client.desktop", &entry);
+
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), expected_file);
+}
+
+#[test]
+fn test_desktop_entry_render_escapes() {
+    let mut entry = DesktopEntry::new(String::from("Voxels\nExec=rm -rf ~"), String::from("voxels-client --dir C:\\voxels"));
+
+    entry.comment = Some(String::from("tab\there\r"));
+    entry.categories = vec![String::from("Game"), String::from("Odd;One")];
+
+    let rendered = entry.render().unwrap();
+
+    assert!(rendered.contains("Name=Voxels\\nExec=rm -rf ~\n"));
+    assert!(rendered.contains("Exec=voxels-client --dir C:\\\\voxels\n"));
+    assert!(rendered.contains("Comment=tab\\there\\r\n"));
+    assert!(rendered.contains("Categories=Game;Odd\\;One;\n"));
+    assert_eq!(rendered.lines().filter(|line| line.starts_with("Exec=")).count(), 1);
+
+    entry.comment = Some(String::from("bell\u{7}"));
+
+    assert!(matches!(entry.render(), Err(BaseDirectoryError::InvalidName(_))));
+}
+
+#[test]
+fn test_desktop_entry_install_refuses_bad_ids() {
+    let entry = DesktopEntry::new(String::from("Voxels"), String::from("voxels-client"));
+
+    for id in ["../autostart/evil.desktop", "nested/org.voxels.Client.desktop", "org.voxels.Client", ".desktop", "org..voxels.desktop"] {
+        // a mock with no expectations, so the refused id must not reach the filesystem
+        let fs = crate::filesystem::MockFsInt::new();
+
+        let directory = PathBuf::from("/home/.local/share/applications");
+
+        assert!(matches!(install_desktop_entry_into(&fs, &directory, id, &entry), Err(BaseDirectoryError::InvalidName(_))), "{}", id);
+    }
+}
+
+#[test]
+fn test_fonts_resolve_unverified() {
+    let mut base = super::data::MockDataDirectoryResolver::new();
+    let mut verifier = super::data::MockDataVerifier::new();
+
+    base.expect_resolve()
+        .once()
+        .return_once(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/.local/share")).unwrap(), super::data::DataDirectoryResolutionMethods::FromXDG)));
+
+    verifier.expect_verify()
+        .once()
+        .with(mockall::predicate::eq(PathBuf::from("/home/.local/share/fonts")))
+        .return_once(|_| Err(super::verifier::VerificationError::NotFound));
+
+    let fonts = FontsDirectory::new(base, verifier, crate::filesystem::MockFsInt::new());
+
+    assert!(fonts.resolve().is_err());
+}
+
+#[test]
+fn test_icons_resolve_and_create_tolerates_a_racing_creator() {
+    let mut base = super::data::MockDataDirectoryResolver::new();
+    let mut fs = crate::filesystem::MockFsInt::new();
+
+    let expected = PathBuf::from("/home/.local/share/icons");
+
+    base.expect_resolve()
+        .once()
+        .return_once(|| Ok((crate::pathcheck::AbsoluteDirPath::new(PathBuf::from("/home/.local/share")).unwrap(), super::data::DataDirectoryResolutionMethods::FromXDG)));
+
+    // another process made it between the check and the create
+    fs.expect_create_dir_all()
+        .once()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_once(|_| Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)));
+
+    fs.expect_is_directory()
+        .with(mockall::predicate::eq(expected.clone()))
+        .return_const(true);
+
+    let icons = IconsDirectory::new(base, super::data::MockDataVerifier::new(), fs);
+
+    assert_eq!(icons.resolve_and_create().unwrap(), expected);
+}
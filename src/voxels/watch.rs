@@ -0,0 +1,133 @@
+/*
+Copyright (C) 2025  Jacob Evans
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use std::path::{Path, PathBuf};
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+use tracing::warn;
+
+use super::VoxelsDirectoryError;
+
+pub use super::MANIFEST_FILE_NAME;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum ApplicationEvent {
+    AppInstalled(String),
+    AppRemoved(String),
+}
+
+/// The RDN of the application `path` belongs to, if it is an application directory or its manifest
+fn rdn_of(applications: &Path, path: &Path, manifest_only: bool) -> Option<String> {
+    let relative = path.strip_prefix(applications).ok()?;
+
+    let mut components = relative.components();
+
+    let rdn = components.next()?.as_os_str().to_str()?.to_owned();
+
+    match components.next() {
+        Some(file) if file.as_os_str() == MANIFEST_FILE_NAME && components.next().is_none() => Some(rdn),
+        None if !manifest_only => Some(rdn),
+        _ => None,
+    }
+}
+
+/// Translate a raw filesystem event beneath `applications` into install and uninstall events.
+/// Only a manifest appearing counts as an install so half copied applications are not reported.
+pub fn application_events(applications: &Path, event: &Event) -> Vec<ApplicationEvent> {
+    let installed = |path: &PathBuf| rdn_of(applications, path, true).map(ApplicationEvent::AppInstalled);
+
+    let removed = |path: &PathBuf| rdn_of(applications, path, false).map(ApplicationEvent::AppRemoved);
+
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            event.paths.iter().filter_map(installed).collect()
+        },
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            event.paths.iter().filter_map(removed).collect()
+        },
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let mut events: Vec<ApplicationEvent> = event.paths.first().and_then(removed).into_iter().collect();
+
+            events.extend(event.paths.get(1).and_then(installed));
+
+            events
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Keeps the underlying watcher alive for as long as events are being received
+pub struct ApplicationsWatcher {
+    _watcher: RecommendedWatcher,
+    events: UnboundedReceiver<ApplicationEvent>,
+}
+
+impl ApplicationsWatcher {
+    /// The next install or uninstall, `None` once the watcher has shut down
+    pub async fn next(&mut self) -> Option<ApplicationEvent> {
+        self.events.recv().await
+    }
+}
+
+/// Stream `AppInstalled` and `AppRemoved` events as manifests appear and disappear beneath `applications`
+pub fn watch(applications: &Path) -> Result<ApplicationsWatcher, VoxelsDirectoryError> {
+    let (sender, events) = unbounded_channel();
+
+    let root = applications.to_path_buf();
+
+    let mut watcher = notify::recommended_watcher(move |result: notify::Result<Event>| {
+        let Ok(event) = result else {
+            warn!("error watching applications directory: {:?}", result);
+            return;
+        };
+
+        for application_event in application_events(&root, &event) {
+            let _ = sender.send(application_event);
+        }
+    }).map_err(|_| VoxelsDirectoryError::Io(std::io::ErrorKind::Other))?;
+
+    watcher.watch(applications, RecursiveMode::Recursive)
+        .map_err(|_| VoxelsDirectoryError::Io(std::io::ErrorKind::NotFound))?;
+
+    Ok(ApplicationsWatcher {
+        _watcher: watcher,
+        events,
+    })
+}
+
+#[test]
+fn test_application_events() {
+    use notify::event::{CreateKind, RemoveKind};
+
+    let applications = PathBuf::from("/apps");
+
+    let created = Event::new(EventKind::Create(CreateKind::File))
+        .add_path(applications.join("org.voxels.Client").join(MANIFEST_FILE_NAME));
+
+    let partial = Event::new(EventKind::Create(CreateKind::Folder))
+        .add_path(applications.join("org.voxels.Client"));
+
+    let removed = Event::new(EventKind::Remove(RemoveKind::Folder))
+        .add_path(applications.join("org.voxels.Client"));
+
+    assert_eq!(application_events(&applications, &created), vec![ApplicationEvent::AppInstalled(String::from("org.voxels.Client"))]);
+    assert_eq!(application_events(&applications, &partial), vec![]);
+    assert_eq!(application_events(&applications, &removed), vec![ApplicationEvent::AppRemoved(String::from("org.voxels.Client"))]);
+}